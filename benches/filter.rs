@@ -0,0 +1,107 @@
+//! Throughput of the IAC filter against the original byte-by-byte one
+//!
+//! Run with `cargo bench --bench filter`. The in-place filter is built from
+//! `src/iac.rs` directly, since the library doesn't export it.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+// Its unit tests are compiled out of the bench, leaving their imports unused
+#[path = "../src/iac.rs"]
+#[allow(dead_code, unused_imports)]
+mod iac;
+
+/// The filter as first written, copying byte by byte
+fn filter_byte_by_byte(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::new();
+    let mut i = 0;
+
+    while i < data.len() {
+        if data[i] == 0xFF {
+            if i + 1 >= data.len() {
+                break;
+            }
+            match data[i + 1] {
+                0xFB..=0xFE => i += 3,
+                0xFA => {
+                    i += 2;
+                    while i + 1 < data.len() {
+                        if data[i] == 0xFF && data[i + 1] == 0xF0 {
+                            i += 2;
+                            break;
+                        }
+                        i += 1;
+                    }
+                }
+                0xFF => {
+                    result.push(0xFF);
+                    i += 2;
+                }
+                _ => i += 2,
+            }
+        } else {
+            result.push(data[i]);
+            i += 1;
+        }
+    }
+
+    result
+}
+
+/// 64 KiB of each kind of traffic
+fn inputs() -> Vec<(&'static str, Vec<u8>)> {
+    let mut line = vec![b'x'; 78];
+    line.extend_from_slice(b"\r\n");
+    let plain = line.repeat(64 * 1024 / line.len());
+
+    // A negotiation and a sub-negotiation every few lines, as MUD servers send
+    let mut chatty = Vec::new();
+    while chatty.len() < 64 * 1024 {
+        chatty.extend_from_slice(&line);
+        chatty.extend_from_slice(&[0xFF, 0xFB, 0x01]);
+        chatty.extend_from_slice(&line);
+        chatty.extend_from_slice(&[0xFF, 0xFA, 0x18, 0x00, b'a', b'n', b's', b'i', 0xFF, 0xF0]);
+    }
+
+    // Binary data with escaped 0xFF bytes
+    let binary: Vec<u8> = (0..64 * 1024)
+        .map(|i| (i * 7 % 256) as u8)
+        .flat_map(|byte| {
+            if byte == 0xFF {
+                vec![0xFF, 0xFF]
+            } else {
+                vec![byte]
+            }
+        })
+        .collect();
+
+    vec![("plain", plain), ("chatty", chatty), ("binary", binary)]
+}
+
+fn filter(c: &mut Criterion) {
+    let mut group = c.benchmark_group("filter");
+    for (name, input) in inputs() {
+        assert_eq!(
+            iac::filter_telnet_commands(&input),
+            filter_byte_by_byte(&input)
+        );
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_with_input(BenchmarkId::new("in_place", name), &input, |b, input| {
+            let mut buf = input.clone();
+            b.iter(|| {
+                buf.copy_from_slice(input);
+                black_box(iac::filter_telnet_commands_in_place(black_box(&mut buf)))
+            })
+        });
+        group.bench_with_input(
+            BenchmarkId::new("byte_by_byte", name),
+            &input,
+            |b, input| b.iter(|| black_box(filter_byte_by_byte(black_box(input)))),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, filter);
+criterion_main!(benches);
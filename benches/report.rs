@@ -0,0 +1,60 @@
+//! Cost of logging each delivery against the aggregated delivery report
+//!
+//! Run with `cargo bench --bench report`. Deliveries are logged the way
+//! they were before summaries, with an INFO line per message, and through
+//! [`report::DeliveryReport`]. Each is measured with no subscriber and with
+//! a subscriber formatting every enabled event into a sink. The report is
+//! built from `src/report.rs` directly, since the library doesn't export
+//! it.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tracing::info;
+
+// Its unit tests are compiled out of the bench, leaving their imports unused
+#[path = "../src/report.rs"]
+#[allow(dead_code, unused_imports)]
+mod report;
+
+/// Messages logged per iteration
+const MESSAGES: u64 = 1000;
+
+/// A successful delivery as it was logged before summaries
+fn per_message(component_id: &str) {
+    info!("Message successfully sent to component {}", component_id);
+}
+
+fn deliveries(c: &mut Criterion, subscriber: &str) {
+    let mut group = c.benchmark_group("deliveries");
+    group.throughput(Throughput::Elements(MESSAGES));
+    group.bench_function(BenchmarkId::new("per_message", subscriber), |b| {
+        b.iter(|| {
+            for _ in 0..MESSAGES {
+                per_message(black_box("component"));
+            }
+        })
+    });
+    let delivery_report = report::DeliveryReport::new("component".into());
+    group.bench_function(BenchmarkId::new("report", subscriber), |b| {
+        b.iter(|| {
+            for _ in 0..MESSAGES {
+                delivery_report.delivered(black_box(1024));
+            }
+        })
+    });
+    group.finish();
+}
+
+fn logging(c: &mut Criterion) {
+    deliveries(c, "none");
+
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(std::io::sink)
+        .with_ansi(false)
+        .finish();
+    tracing::subscriber::with_default(subscriber, || deliveries(c, "recording"));
+}
+
+criterion_group!(benches, logging);
+criterion_main!(benches);
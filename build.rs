@@ -0,0 +1,194 @@
+//! Check that the generated `wasmcloud:messaging/handler` bindings still have
+//! the shape `send_message_to_component` relies on.
+//!
+//! The provider's bindings come from `wit_bindgen_wrpc::generate!`, whose
+//! expansion isn't visible to a build script, so the same generator is run
+//! here over `wit/` and its output parsed with `syn`. If the upstream WIT
+//! changes `handle-message`, the build fails with a description of the
+//! mismatch instead of the provider breaking at runtime.
+
+use std::path::Path;
+
+use syn::visit::Visit;
+use wit_bindgen_core::Files;
+use wit_parser::Resolve;
+
+/// Module path of the generated handler interface
+const HANDLER_MODULE: &[&str] = &["wasmcloud", "messaging", "handler"];
+
+fn main() {
+    println!("cargo:rerun-if-changed=wit");
+
+    let source = generate_bindings(Path::new("wit"));
+    let file = syn::parse_file(&source).expect("failed to parse generated WIT bindings");
+
+    let mut validator = WitValidator::default();
+    validator.visit_file(&file);
+    if let Err(problem) = validator.result() {
+        panic!(
+            "wasmcloud:messaging/handler bindings no longer match what the provider expects: \
+             {problem}\nexpected: handle_message(client, context, message: &BrokerMessage) \
+             -> Result<Result<(), String>, anyhow::Error>"
+        );
+    }
+}
+
+/// Generate the Rust bindings for the `provider` world as source text
+fn generate_bindings(wit_dir: &Path) -> String {
+    let mut resolve = Resolve::default();
+    let (package, _) = resolve
+        .push_dir(wit_dir)
+        .expect("failed to parse WIT definitions");
+    let world = resolve
+        .select_world(package, Some("provider"))
+        .expect("missing `provider` world");
+
+    // Like `generate!`, which names the messaging interfaces in `with`,
+    // generate everything rather than expecting remappings
+    let mut files = Files::default();
+    wit_bindgen_wrpc_rust::Opts {
+        generate_all: true,
+        ..Default::default()
+    }
+    .build()
+    .generate(&resolve, world, &mut files)
+    .expect("failed to generate WIT bindings");
+
+    files
+        .iter()
+        .map(|(_, contents)| String::from_utf8_lossy(contents).into_owned())
+        .collect()
+}
+
+/// Finds `handle_message` in the handler module and checks its signature
+#[derive(Default)]
+struct WitValidator {
+    /// Module names from the file root to the item being visited
+    path: Vec<String>,
+    /// Outcome for `handle_message`, if it was found
+    found: Option<Result<(), String>>,
+}
+
+impl WitValidator {
+    fn result(self) -> Result<(), String> {
+        self.found.unwrap_or_else(|| {
+            Err(format!(
+                "no `{}::handle_message`",
+                HANDLER_MODULE.join("::")
+            ))
+        })
+    }
+
+    fn check_signature(sig: &syn::Signature) -> Result<(), String> {
+        let inputs: Vec<_> = sig.inputs.iter().collect();
+        let [_client, _context, message] = inputs.as_slice() else {
+            return Err(format!(
+                "handle_message takes {} arguments, expected 3",
+                inputs.len()
+            ));
+        };
+        let syn::FnArg::Typed(message) = message else {
+            return Err("handle_message has a receiver".to_string());
+        };
+        match &*message.ty {
+            syn::Type::Reference(reference)
+                if last_ident(&reference.elem).as_deref() == Some("BrokerMessage") => {}
+            _ => return Err("the message argument is not `&BrokerMessage`".to_string()),
+        }
+
+        let syn::ReturnType::Type(_, output) = &sig.output else {
+            return Err("handle_message returns nothing".to_string());
+        };
+        // wRPC generates `fn ... -> impl Future<Output = T>`; an `async fn`
+        // returning `T` is equivalent
+        let output = match sig.asyncness {
+            Some(_) => &**output,
+            None => future_output(output)
+                .ok_or("handle_message is neither async nor returns a Future")?,
+        };
+        let (ok, err) = result_args(output).ok_or("handle_message does not return a Result")?;
+        if let Some(err) = err {
+            if last_ident(err).as_deref() != Some("Error") {
+                return Err("handle_message's outer error is not anyhow::Error".to_string());
+            }
+        }
+        let inner = ok.ok_or("handle_message's Result has no value type")?;
+        match result_args(inner) {
+            Some((Some(syn::Type::Tuple(unit)), Some(err)))
+                if unit.elems.is_empty() && last_ident(err).as_deref() == Some("String") =>
+            {
+                Ok(())
+            }
+            _ => Err("handle_message does not resolve to Result<(), String>".to_string()),
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for WitValidator {
+    fn visit_item_mod(&mut self, module: &'ast syn::ItemMod) {
+        self.path.push(module.ident.to_string());
+        syn::visit::visit_item_mod(self, module);
+        self.path.pop();
+    }
+
+    fn visit_item_fn(&mut self, function: &'ast syn::ItemFn) {
+        if function.sig.ident == "handle_message"
+            && self.path.join("::").ends_with(&HANDLER_MODULE.join("::"))
+        {
+            self.found = Some(Self::check_signature(&function.sig));
+        }
+    }
+}
+
+/// Last path segment of a type such as `::wasmcloud::BrokerMessage`
+fn last_ident(ty: &syn::Type) -> Option<String> {
+    let syn::Type::Path(path) = ty else {
+        return None;
+    };
+    path.path
+        .segments
+        .last()
+        .map(|segment| segment.ident.to_string())
+}
+
+/// `T` of an `impl Future<Output = T>` type
+fn future_output(ty: &syn::Type) -> Option<&syn::Type> {
+    let syn::Type::ImplTrait(impl_trait) = ty else {
+        return None;
+    };
+    impl_trait.bounds.iter().find_map(|bound| {
+        let syn::TypeParamBound::Trait(bound) = bound else {
+            return None;
+        };
+        let segment = bound.path.segments.last()?;
+        if segment.ident != "Future" {
+            return None;
+        }
+        let syn::PathArguments::AngleBracketed(args) = &segment.arguments else {
+            return None;
+        };
+        args.args.iter().find_map(|arg| match arg {
+            syn::GenericArgument::AssocType(assoc) if assoc.ident == "Output" => Some(&assoc.ty),
+            _ => None,
+        })
+    })
+}
+
+/// Generic arguments of a `Result<T>` or `Result<T, E>` type
+fn result_args(ty: &syn::Type) -> Option<(Option<&syn::Type>, Option<&syn::Type>)> {
+    let syn::Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Result" {
+        return None;
+    }
+    let syn::PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    let mut types = args.args.iter().filter_map(|arg| match arg {
+        syn::GenericArgument::Type(ty) => Some(ty),
+        _ => None,
+    });
+    Some((types.next(), types.next()))
+}
@@ -0,0 +1,45 @@
+//! Build the component wasm before the harness so tests always run against
+//! the current component source.
+
+use std::path::PathBuf;
+use std::process::Command;
+
+fn main() {
+    let manifest_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+    let component_dir = manifest_dir.join("../component");
+
+    println!(
+        "cargo:rerun-if-changed={}",
+        component_dir.join("src").display()
+    );
+    println!(
+        "cargo:rerun-if-changed={}",
+        component_dir.join("wit").display()
+    );
+    println!(
+        "cargo:rerun-if-changed={}",
+        component_dir.join("Cargo.toml").display()
+    );
+
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let status = Command::new(cargo)
+        .args([
+            "build",
+            "--release",
+            "--target",
+            "wasm32-wasip2",
+            "--manifest-path",
+        ])
+        .arg(component_dir.join("Cargo.toml"))
+        // Don't let the outer build's settings leak into the wasm build
+        .env_remove("RUSTFLAGS")
+        .env_remove("CARGO_ENCODED_RUSTFLAGS")
+        .env_remove("CARGO_TARGET_DIR")
+        .status()
+        .expect("failed to run cargo for the component");
+    assert!(status.success(), "component build failed");
+
+    let wasm =
+        component_dir.join("target/wasm32-wasip2/release/custom_template_test_component.wasm");
+    println!("cargo:rustc-env=COMPONENT_WASM={}", wasm.display());
+}
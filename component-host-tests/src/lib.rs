@@ -0,0 +1,151 @@
+//! Host-side test harness for the test component
+//!
+//! Loads the built component with wasmtime's component model support,
+//! provides stub implementations of its imports (`wasi:logging` records and
+//! messages published through `wasmcloud:messaging/consumer` are captured
+//! for assertions), and invokes the exported `handle-message`.
+
+use anyhow::Context as _;
+use wasmtime::component::{Linker, ResourceTable};
+use wasmtime::{Config, Engine, Store};
+use wasmtime_wasi::{WasiCtx, WasiCtxBuilder, WasiView};
+
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "../component/wit",
+        world: "component",
+    });
+}
+
+pub use bindings::exports::wasmcloud::messaging::handler::BrokerMessage;
+pub use bindings::wasi::logging::logging::Level;
+
+/// Path of the component wasm built by this crate's build script
+pub const COMPONENT_WASM: &str = env!("COMPONENT_WASM");
+
+/// A log record emitted by the component through `wasi:logging`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRecord {
+    pub level: Level,
+    pub context: String,
+    pub message: String,
+}
+
+/// Store state backing the component's imports
+struct HostState {
+    wasi: WasiCtx,
+    table: ResourceTable,
+    logs: Vec<LogRecord>,
+    published: Vec<BrokerMessage>,
+}
+
+impl WasiView for HostState {
+    fn table(&mut self) -> &mut ResourceTable {
+        &mut self.table
+    }
+
+    fn ctx(&mut self) -> &mut WasiCtx {
+        &mut self.wasi
+    }
+}
+
+impl bindings::wasi::logging::logging::Host for HostState {
+    fn log(&mut self, level: Level, context: String, message: String) {
+        self.logs.push(LogRecord {
+            level,
+            context,
+            message,
+        });
+    }
+}
+
+impl bindings::wasmcloud::messaging::types::Host for HostState {}
+
+impl bindings::wasmcloud::messaging::consumer::Host for HostState {
+    fn request(
+        &mut self,
+        subject: String,
+        _body: Vec<u8>,
+        _timeout_ms: u32,
+    ) -> Result<BrokerMessage, String> {
+        Err(format!("no responder on {}", subject))
+    }
+
+    fn publish(&mut self, msg: BrokerMessage) -> Result<(), String> {
+        self.published.push(msg);
+        Ok(())
+    }
+}
+
+/// A running instance of the component with captured logs
+pub struct ComponentHarness {
+    store: Store<HostState>,
+    instance: bindings::Component,
+}
+
+impl ComponentHarness {
+    /// Instantiate the component built by the build script
+    pub fn new() -> anyhow::Result<Self> {
+        Self::from_file(COMPONENT_WASM)
+    }
+
+    /// Instantiate a component from a wasm file
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        let mut config = Config::new();
+        config.wasm_component_model(true);
+        let engine = Engine::new(&config)?;
+
+        let component = wasmtime::component::Component::from_file(&engine, path)
+            .with_context(|| format!("failed to load component from {}", path))?;
+
+        let mut linker = Linker::new(&engine);
+        wasmtime_wasi::add_to_linker_sync(&mut linker)?;
+        bindings::Component::add_to_linker(&mut linker, |state: &mut HostState| state)?;
+
+        let mut store = Store::new(
+            &engine,
+            HostState {
+                wasi: WasiCtxBuilder::new().build(),
+                table: ResourceTable::new(),
+                logs: Vec::new(),
+                published: Vec::new(),
+            },
+        );
+        let instance = bindings::Component::instantiate(&mut store, &component, &linker)
+            .context("failed to instantiate component")?;
+
+        Ok(Self { store, instance })
+    }
+
+    /// Invoke the exported `handle-message` with a synthetic broker message
+    pub fn handle_message(
+        &mut self,
+        subject: &str,
+        body: &[u8],
+        reply_to: Option<&str>,
+    ) -> anyhow::Result<Result<(), String>> {
+        let message = BrokerMessage {
+            subject: subject.to_string(),
+            body: body.to_vec(),
+            reply_to: reply_to.map(str::to_string),
+        };
+        self.instance
+            .wasmcloud_messaging_handler()
+            .call_handle_message(&mut self.store, &message)
+    }
+
+    /// Log records captured so far
+    pub fn logs(&self) -> &[LogRecord] {
+        &self.store.data().logs
+    }
+
+    /// Remove and return the log records captured so far
+    pub fn take_logs(&mut self) -> Vec<LogRecord> {
+        std::mem::take(&mut self.store.data_mut().logs)
+    }
+
+    /// Remove and return the messages the component published so far
+    pub fn take_published(&mut self) -> Vec<BrokerMessage> {
+        std::mem::take(&mut self.store.data_mut().published)
+    }
+}
@@ -0,0 +1,161 @@
+use component_host_tests::{ComponentHarness, Level};
+
+fn harness() -> ComponentHarness {
+    ComponentHarness::new().expect("component should instantiate")
+}
+
+#[test]
+fn logs_text_message_subject_and_payload() {
+    let mut harness = harness();
+
+    let result = harness
+        .handle_message("telnet.127.0.0.1:2323", b"hello world\r\n", None)
+        .unwrap();
+
+    assert_eq!(result, Ok(()));
+    let logs = harness.logs();
+    assert!(logs.iter().all(|record| record.level == Level::Info));
+    assert!(logs.iter().any(
+        |record| record.message.contains("Subject: telnet.127.0.0.1:2323")
+            && record.message.contains("Size: 13 bytes")
+    ));
+    assert!(logs
+        .iter()
+        .any(|record| record.message.contains("Message payload: hello world")));
+}
+
+#[test]
+fn logs_binary_payload_placeholder() {
+    let mut harness = harness();
+
+    harness
+        .handle_message("telnet.127.0.0.1:2323", &[0xC3, 0x28, 0xFF], None)
+        .unwrap()
+        .unwrap();
+
+    assert!(harness
+        .logs()
+        .iter()
+        .any(|record| record.message.contains("[binary data: 3 bytes]")));
+}
+
+#[test]
+fn logs_reply_to() {
+    let mut harness = harness();
+
+    harness
+        .handle_message("telnet.127.0.0.1:2323", b"data", Some("replies"))
+        .unwrap()
+        .unwrap();
+
+    assert!(harness
+        .logs()
+        .iter()
+        .any(|record| record.message == "Reply-to: replies"));
+}
+
+#[test]
+fn replies_with_supported_protocol_versions() {
+    let mut harness = harness();
+
+    for version in [1u8, 2] {
+        let result = harness
+            .handle_message(
+                "telnet.negotiate",
+                &[version],
+                Some("telnet.negotiate.reply.7"),
+            )
+            .unwrap();
+        assert_eq!(result, Ok(()), "version {version} should be accepted");
+        let published = harness.take_published();
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].subject, "telnet.negotiate.reply.7");
+        assert_eq!(published[0].body, [version]);
+    }
+}
+
+#[test]
+fn replies_to_newer_protocol_versions_with_its_own() {
+    let mut harness = harness();
+
+    let result = harness
+        .handle_message("telnet.negotiate", &[9], Some("telnet.negotiate.reply.7"))
+        .unwrap();
+
+    assert_eq!(result, Ok(()));
+    assert_eq!(harness.take_published()[0].body, [2]);
+}
+
+#[test]
+fn rejects_protocol_version_probes_it_cannot_answer() {
+    let mut harness = harness();
+
+    let result = harness
+        .handle_message("telnet.negotiate", &[2], None)
+        .unwrap();
+    assert!(result.is_err());
+    let result = harness
+        .handle_message("telnet.negotiate", &[0], Some("telnet.negotiate.reply.7"))
+        .unwrap();
+    assert_eq!(result, Err("supported protocol version: 2".to_string()));
+    assert!(harness.take_published().is_empty());
+}
+
+#[test]
+fn answers_ping_without_logging() {
+    let mut harness = harness();
+
+    let result = harness.handle_message("telnet.ping", &[], None).unwrap();
+
+    assert_eq!(result, Ok(()));
+    assert!(harness.take_logs().is_empty());
+}
+
+fn payload_log(harness: &ComponentHarness) -> String {
+    harness
+        .logs()
+        .iter()
+        .find_map(|record| record.message.strip_prefix("Message payload: "))
+        .expect("payload should be logged")
+        .to_string()
+}
+
+#[test]
+fn logs_text_payload_at_preview_limit_in_full() {
+    let mut harness = harness();
+    let body = "a".repeat(100);
+
+    harness
+        .handle_message("telnet.127.0.0.1:2323", body.as_bytes(), None)
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(payload_log(&harness), body);
+}
+
+#[test]
+fn truncates_text_payload_over_preview_limit() {
+    let mut harness = harness();
+    let body = "a".repeat(101);
+
+    harness
+        .handle_message("telnet.127.0.0.1:2323", body.as_bytes(), None)
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(payload_log(&harness), format!("{}...", "a".repeat(100)));
+}
+
+#[test]
+fn truncates_multibyte_payload_on_char_boundary() {
+    let mut harness = harness();
+    // 99 ASCII bytes then a 2-byte character straddling the limit
+    let body = format!("{}é tail", "a".repeat(99));
+
+    let result = harness
+        .handle_message("telnet.127.0.0.1:2323", body.as_bytes(), None)
+        .unwrap();
+
+    assert_eq!(result, Ok(()));
+    assert_eq!(payload_log(&harness), format!("{}...", "a".repeat(99)));
+}
@@ -2,11 +2,86 @@ wit_bindgen::generate!({ generate_all });
 
 use crate::exports::wasmcloud::messaging::handler::{BrokerMessage, Guest};
 use crate::wasi::logging::logging::*;
+use crate::wasmcloud::messaging::consumer;
+
+/// Highest message envelope protocol version this component understands.
+/// Envelopes (v2) are logged like any other payload.
+const PROTOCOL_VERSION: u8 = 2;
+
+/// Subject of the provider's version probe
+const NEGOTIATE_SUBJECT: &str = "telnet.negotiate";
+
+/// Subject of the provider's reachability ping
+const PING_SUBJECT: &str = "telnet.ping";
+
+/// Bytes of a text payload included in its log line
+const PREVIEW_LEN: usize = 100;
+
+/// Least severe level this component logs; payload previews below it are
+/// never built
+const MIN_LOG_LEVEL: Level = Level::Info;
+
+/// Whether records at `level` are logged
+fn log_enabled(level: Level) -> bool {
+    level >= MIN_LOG_LEVEL
+}
+
+/// The longest prefix of `text` of at most `max` bytes that ends on a
+/// character boundary
+fn truncate(text: &str, max: usize) -> &str {
+    let mut end = max.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
 
 struct TelnetComponent;
 
+impl TelnetComponent {
+    /// Answer a protocol version probe from the provider
+    ///
+    /// Publishes the version this component will use to the probe's
+    /// `reply-to`: the requested one if supported, otherwise the highest
+    /// version this component supports, which the provider may fall back to.
+    fn handle_negotiation(msg: &BrokerMessage) -> Result<(), String> {
+        let requested = msg.body.first().copied().unwrap_or(0);
+        log(
+            Level::Info,
+            "",
+            &format!("Protocol version probe: requested v{}", requested),
+        );
+
+        let Some(reply_to) = &msg.reply_to else {
+            return Err("protocol version probe without reply-to".to_string());
+        };
+        if requested == 0 {
+            return Err(format!("supported protocol version: {}", PROTOCOL_VERSION));
+        }
+        consumer::publish(&BrokerMessage {
+            subject: reply_to.clone(),
+            body: vec![requested.min(PROTOCOL_VERSION)],
+            reply_to: None,
+        })
+    }
+}
+
 impl Guest for TelnetComponent {
     fn handle_message(msg: BrokerMessage) -> Result<(), String> {
+        if msg.subject == NEGOTIATE_SUBJECT {
+            return Self::handle_negotiation(&msg);
+        }
+
+        // Reachability pings only need an answer
+        if msg.subject == PING_SUBJECT {
+            return Ok(());
+        }
+
+        // Nothing below is formatted unless it will be logged
+        if !log_enabled(Level::Info) {
+            return Ok(());
+        }
+
         // Log the received broker message
         log(
             Level::Info,
@@ -18,24 +93,15 @@ impl Guest for TelnetComponent {
             ),
         );
 
-        // Try to interpret body as UTF-8 text for logging
-        let payload_text = match String::from_utf8(msg.body.clone()) {
-            Ok(text) => text,
-            Err(_) => format!("[binary data: {} bytes]", msg.body.len()),
-        };
-
-        // Log the payload (truncated if too long)
-        let payload_preview = if payload_text.len() > 100 {
-            format!("{}...", &payload_text[..100])
-        } else {
-            payload_text
+        // Log the payload (truncated if too long), borrowing the body
+        let payload = match std::str::from_utf8(&msg.body) {
+            Ok(text) if text.len() > PREVIEW_LEN => {
+                format!("Message payload: {}...", truncate(text, PREVIEW_LEN))
+            }
+            Ok(text) => format!("Message payload: {}", text),
+            Err(_) => format!("Message payload: [binary data: {} bytes]", msg.body.len()),
         };
-
-        log(
-            Level::Info,
-            "",
-            &format!("Message payload: {}", payload_preview),
-        );
+        log(Level::Info, "", &payload);
 
         if let Some(reply_to) = &msg.reply_to {
             log(Level::Info, "", &format!("Reply-to: {}", reply_to));
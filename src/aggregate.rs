@@ -0,0 +1,327 @@
+//! Sliding-window summaries of numeric feeds
+//!
+//! With `aggregate_numeric_window_ms` set, each line of a record is read as
+//! a numeric reading instead of being forwarded. Every reading is added to a
+//! window of the readings from the last `aggregate_numeric_window_ms`, and
+//! the window's min/max/avg/count is forwarded in its place. Lines without a
+//! readable number are forwarded unchanged on the `.unparsed` subject.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::dedupe::extract_key;
+
+/// Where the number is read from in each line
+#[derive(Debug, Clone)]
+pub enum NumericSource {
+    /// The whole line
+    Line,
+    /// The first capture group of a pattern (or the whole match if it has
+    /// none)
+    Pattern(Regex),
+    /// A top-level field of a JSON object line, or a `name: value` line
+    Field(String),
+}
+
+/// Link config for numeric aggregation; present when
+/// `aggregate_numeric_window_ms` is set
+#[derive(Debug, Clone)]
+pub struct AggregateConfig {
+    /// Length of the sliding window in milliseconds
+    pub window_ms: u64,
+    /// How each line's reading is found
+    pub source: NumericSource,
+}
+
+impl AggregateConfig {
+    /// Parse aggregation settings, returning `None` if no window is set
+    pub fn from_values(config: &HashMap<String, String>) -> anyhow::Result<Option<Self>> {
+        let Some(window_ms) = config
+            .get("aggregate_numeric_window_ms")
+            .and_then(|v| v.parse().ok())
+            .filter(|v| *v > 0)
+        else {
+            return Ok(None);
+        };
+
+        let pattern = config
+            .get("aggregate_numeric_pattern")
+            .filter(|v| !v.is_empty());
+        let field = config
+            .get("aggregate_numeric_field")
+            .filter(|v| !v.is_empty());
+        let source = match (pattern, field) {
+            (Some(_), Some(_)) => anyhow::bail!(
+                "Set only one of aggregate_numeric_pattern and aggregate_numeric_field"
+            ),
+            (Some(pattern), None) => NumericSource::Pattern(
+                Regex::new(pattern)
+                    .map_err(|e| anyhow::anyhow!("Invalid aggregate_numeric_pattern: {}", e))?,
+            ),
+            (None, Some(field)) => NumericSource::Field(field.clone()),
+            (None, None) => NumericSource::Line,
+        };
+
+        Ok(Some(Self { window_ms, source }))
+    }
+
+    /// Get the window length as Duration
+    pub fn window(&self) -> Duration {
+        Duration::from_millis(self.window_ms)
+    }
+}
+
+/// Summary of the readings in the window
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct NumericSummary {
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub count: usize,
+    pub window_ms: u64,
+}
+
+/// What one line of a record turned into
+#[derive(Debug)]
+pub enum Aggregated {
+    /// The window's summary after adding the line's reading
+    Summary(NumericSummary),
+    /// A line without a readable number, as received
+    Unparsed(Bytes),
+}
+
+/// Readings of one connection within the sliding window
+#[derive(Debug)]
+pub struct NumericAggregator {
+    config: AggregateConfig,
+    readings: VecDeque<(Instant, f64)>,
+}
+
+impl NumericAggregator {
+    pub fn new(config: AggregateConfig) -> Self {
+        Self {
+            config,
+            readings: VecDeque::new(),
+        }
+    }
+
+    /// Add every line of a record, in order
+    ///
+    /// Blank lines are skipped.
+    pub fn push(&mut self, data: &Bytes, now: Instant) -> Vec<Aggregated> {
+        data.split(|b| *b == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .filter(|line| !line.trim_ascii().is_empty())
+            .map(|line| match self.reading(line) {
+                Some(value) => Aggregated::Summary(self.add(value, now)),
+                None => Aggregated::Unparsed(data.slice_ref(line)),
+            })
+            .collect()
+    }
+
+    /// The number in a line, if it has one
+    fn reading(&self, line: &[u8]) -> Option<f64> {
+        let text = std::str::from_utf8(line).ok()?;
+        let value = match &self.config.source {
+            NumericSource::Line => text.trim().to_string(),
+            NumericSource::Pattern(pattern) => {
+                let captures = pattern.captures(text)?;
+                captures
+                    .get(1)
+                    .or_else(|| captures.get(0))?
+                    .as_str()
+                    .to_string()
+            }
+            NumericSource::Field(field) => extract_key(line, field)?,
+        };
+        value.trim().parse().ok().filter(|v: &f64| v.is_finite())
+    }
+
+    /// Add a reading and summarize the window ending at `now`
+    fn add(&mut self, value: f64, now: Instant) -> NumericSummary {
+        let window = self.config.window();
+        while self
+            .readings
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) >= window)
+        {
+            self.readings.pop_front();
+        }
+        self.readings.push_back((now, value));
+
+        let (min, max, sum) = self.readings.iter().fold(
+            (f64::INFINITY, f64::NEG_INFINITY, 0.0),
+            |(min, max, sum), (_, v)| (min.min(*v), max.max(*v), sum + v),
+        );
+        let count = self.readings.len();
+        NumericSummary {
+            min,
+            max,
+            avg: sum / count as f64,
+            count,
+            window_ms: self.config.window_ms,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(values: &[(&str, &str)]) -> anyhow::Result<Option<AggregateConfig>> {
+        let values = values
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        AggregateConfig::from_values(&values)
+    }
+
+    fn aggregator(values: &[(&str, &str)]) -> NumericAggregator {
+        let mut values = values.to_vec();
+        values.push(("aggregate_numeric_window_ms", "1000"));
+        NumericAggregator::new(config(&values).unwrap().unwrap())
+    }
+
+    /// The summaries in `results`, panicking on unparsed lines
+    fn summaries(results: Vec<Aggregated>) -> Vec<NumericSummary> {
+        results
+            .into_iter()
+            .map(|result| match result {
+                Aggregated::Summary(summary) => summary,
+                Aggregated::Unparsed(line) => panic!("unparsed line {:?}", line),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn disabled_without_a_positive_window() {
+        assert!(config(&[]).unwrap().is_none());
+        assert!(config(&[("aggregate_numeric_window_ms", "0")])
+            .unwrap()
+            .is_none());
+        assert!(config(&[("aggregate_numeric_window_ms", "soon")])
+            .unwrap()
+            .is_none());
+        let config = config(&[("aggregate_numeric_window_ms", "250")])
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.window(), Duration::from_millis(250));
+        assert!(matches!(config.source, NumericSource::Line));
+    }
+
+    #[test]
+    fn rejects_both_sources_and_invalid_patterns() {
+        let window = ("aggregate_numeric_window_ms", "1000");
+        assert!(config(&[
+            window,
+            ("aggregate_numeric_pattern", "(\\d+)"),
+            ("aggregate_numeric_field", "temp"),
+        ])
+        .is_err());
+        assert!(config(&[window, ("aggregate_numeric_pattern", "(")]).is_err());
+        // An empty setting counts as unset
+        assert!(matches!(
+            config(&[
+                window,
+                ("aggregate_numeric_pattern", ""),
+                ("aggregate_numeric_field", "temp")
+            ])
+            .unwrap()
+            .unwrap()
+            .source,
+            NumericSource::Field(_)
+        ));
+    }
+
+    #[test]
+    fn summarizes_each_line_of_a_record() {
+        let mut aggregator = aggregator(&[]);
+        let now = Instant::now();
+        let results = summaries(aggregator.push(&Bytes::from_static(b"4\r\n\n 2 \n6"), now));
+        let last = NumericSummary {
+            min: 2.0,
+            max: 6.0,
+            avg: 4.0,
+            count: 3,
+            window_ms: 1000,
+        };
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].count, 1);
+        assert_eq!(results[1].min, 2.0);
+        assert_eq!(results[2], last);
+    }
+
+    #[test]
+    fn readings_leave_the_window_once_it_has_passed() {
+        let mut aggregator = aggregator(&[]);
+        let start = Instant::now();
+        aggregator.push(&Bytes::from_static(b"10"), start);
+        aggregator.push(
+            &Bytes::from_static(b"20"),
+            start + Duration::from_millis(600),
+        );
+        let summary = summaries(aggregator.push(
+            &Bytes::from_static(b"30"),
+            start + Duration::from_millis(1000),
+        ));
+        assert_eq!(summary[0].count, 2);
+        assert_eq!(summary[0].min, 20.0);
+        assert_eq!(summary[0].avg, 25.0);
+    }
+
+    #[test]
+    fn lines_without_a_number_are_passed_through() {
+        let mut aggregator = aggregator(&[]);
+        let results = aggregator.push(
+            &Bytes::from_static(b"12\nerror\r\nNaN\ninf\n"),
+            Instant::now(),
+        );
+        assert_eq!(results.len(), 4);
+        assert!(matches!(results[0], Aggregated::Summary(_)));
+        let unparsed: Vec<&[u8]> = results[1..]
+            .iter()
+            .map(|result| match result {
+                Aggregated::Unparsed(line) => &line[..],
+                Aggregated::Summary(_) => panic!("parsed a non-number"),
+            })
+            .collect();
+        assert_eq!(unparsed, [&b"error"[..], b"NaN", b"inf"]);
+    }
+
+    #[test]
+    fn reads_the_number_from_a_pattern() {
+        let mut with_group = aggregator(&[("aggregate_numeric_pattern", r"temp=(-?\d+)C")]);
+        let summary = summaries(with_group.push(&Bytes::from_static(b"temp=-5C"), Instant::now()));
+        assert_eq!(summary[0].max, -5.0);
+
+        let mut whole_match = aggregator(&[("aggregate_numeric_pattern", r"\d+\.\d+")]);
+        let summary =
+            summaries(whole_match.push(&Bytes::from_static(b"load 0.75 now"), Instant::now()));
+        assert_eq!(summary[0].max, 0.75);
+        assert!(matches!(
+            whole_match.push(&Bytes::from_static(b"load unknown"), Instant::now())[..],
+            [Aggregated::Unparsed(_)]
+        ));
+    }
+
+    #[test]
+    fn reads_the_number_from_a_field() {
+        let mut aggregator = aggregator(&[("aggregate_numeric_field", "temp")]);
+        let now = Instant::now();
+        let summary = summaries(aggregator.push(&Bytes::from_static(br#"{"temp": 21.5}"#), now));
+        assert_eq!(summary[0].max, 21.5);
+        let summary = summaries(aggregator.push(&Bytes::from_static(br#"{"temp": "18.5"}"#), now));
+        assert_eq!(summary[0].min, 18.5);
+        let summary = summaries(aggregator.push(&Bytes::from_static(b"Temp: 20"), now));
+        assert_eq!(summary[0].avg, 20.0);
+        assert_eq!(summary[0].count, 3);
+        assert!(matches!(
+            aggregator.push(&Bytes::from_static(br#"{"humidity": 40}"#), now)[..],
+            [Aggregated::Unparsed(_)]
+        ));
+    }
+}
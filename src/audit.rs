@@ -0,0 +1,270 @@
+//! Append-only JSON-lines audit log of negotiations and connection events
+//!
+//! Kept apart from operational logs for security review. Records are queued
+//! on a bounded channel and written by a background task, so a stalled disk
+//! never blocks a connection; records that don't fit are dropped and
+//! counted.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context as _;
+use serde::Serialize;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, warn};
+
+use crate::instrument::spawn_named;
+
+/// Records queued before new ones are dropped
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Interval between flushes of buffered records to disk
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What happened
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// TCP connection established
+    Connected { address: String, peer: String },
+    /// Option negotiation received from the server, and what we answered
+    Negotiation {
+        direction: &'static str,
+        command: &'static str,
+        option: u8,
+        response: Option<&'static str>,
+    },
+    /// Automatic login completed
+    Login { username: String },
+    /// Connection ended
+    Disconnected { reason: &'static str },
+}
+
+/// One line of the audit log
+#[derive(Serialize)]
+struct AuditRecord {
+    ts_ms: u64,
+    source_id: String,
+    #[serde(flatten)]
+    event: AuditEvent,
+}
+
+/// Handle for queueing audit records; disabled by default
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    sender: Option<mpsc::Sender<AuditRecord>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl AuditLog {
+    /// Open `path` for appending and start the writer task
+    pub async fn open(path: PathBuf, cancel: CancellationToken) -> anyhow::Result<Self> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("failed to open audit log {}", path.display()))?;
+
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        let log = Self {
+            sender: Some(sender),
+            dropped: Arc::default(),
+        };
+        spawn_named(
+            "audit",
+            "writer",
+            write_records(BufWriter::new(file), receiver, log.dropped.clone(), cancel),
+        );
+        Ok(log)
+    }
+
+    /// Audit handle recording events for one link
+    pub fn for_link(&self, source_id: &str) -> LinkAudit {
+        LinkAudit {
+            log: self.clone(),
+            source_id: source_id.to_string(),
+        }
+    }
+}
+
+/// Audit handle for one link
+#[derive(Clone)]
+pub struct LinkAudit {
+    log: AuditLog,
+    source_id: String,
+}
+
+impl LinkAudit {
+    /// Whether records are actually written
+    pub fn is_enabled(&self) -> bool {
+        self.log.sender.is_some()
+    }
+
+    /// Queue an event without waiting; counts it as lost if the queue is full
+    pub fn record(&self, event: AuditEvent) {
+        let Some(sender) = &self.log.sender else {
+            return;
+        };
+        let record = AuditRecord {
+            ts_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            source_id: self.source_id.clone(),
+            event,
+        };
+        if sender.try_send(record).is_err() {
+            self.log.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Write queued records as JSON lines until `cancel` is triggered
+async fn write_records<W: AsyncWriteExt + Unpin>(
+    mut writer: W,
+    mut receiver: mpsc::Receiver<AuditRecord>,
+    dropped: Arc<AtomicU64>,
+    cancel: CancellationToken,
+) {
+    let mut ticker = tokio::time::interval(FLUSH_INTERVAL);
+    let mut reported = 0;
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            Some(record) = receiver.recv() => {
+                let mut line = match serde_json::to_vec(&record) {
+                    Ok(line) => line,
+                    Err(e) => {
+                        error!("Failed to serialize audit record: {}", e);
+                        continue;
+                    }
+                };
+                line.push(b'\n');
+                if let Err(e) = writer.write_all(&line).await {
+                    error!("Failed to write audit record: {}", e);
+                }
+            }
+            _ = ticker.tick() => {
+                if let Err(e) = writer.flush().await {
+                    error!("Failed to flush audit log: {}", e);
+                }
+                let lost = dropped.load(Ordering::Relaxed);
+                if lost > reported {
+                    warn!("{} audit records dropped so far (writer falling behind)", lost);
+                    reported = lost;
+                }
+            }
+        }
+    }
+
+    // Write out whatever is still queued before exiting
+    receiver.close();
+    while let Some(record) = receiver.recv().await {
+        if let Ok(mut line) = serde_json::to_vec(&record) {
+            line.push(b'\n');
+            let _ = writer.write_all(&line).await;
+        }
+    }
+    let _ = writer.flush().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lines of the file at `path` once it holds `count` of them
+    async fn wait_for_lines(path: &std::path::Path, count: usize) -> Vec<serde_json::Value> {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let text = tokio::fs::read_to_string(path).await.unwrap_or_default();
+                // A line still being written doesn't parse yet
+                let lines: Vec<_> = text
+                    .lines()
+                    .filter_map(|line| serde_json::from_str(line).ok())
+                    .collect();
+                if lines.len() >= count {
+                    return lines;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("audit records were not written")
+    }
+
+    #[tokio::test]
+    async fn writes_one_json_line_per_event() {
+        let path =
+            std::env::temp_dir().join(format!("telnet-provider-audit-{}", std::process::id()));
+        let _ = tokio::fs::remove_file(&path).await;
+        let cancel = CancellationToken::new();
+        let audit = AuditLog::open(path.clone(), cancel.clone())
+            .await
+            .unwrap()
+            .for_link("alarm-handler");
+        assert!(audit.is_enabled());
+
+        audit.record(AuditEvent::Connected {
+            address: "10.0.0.5:23".to_string(),
+            peer: "10.0.0.5:23".to_string(),
+        });
+        audit.record(AuditEvent::Negotiation {
+            direction: "received",
+            command: "DO",
+            option: 39,
+            response: Some("WILL"),
+        });
+        audit.record(AuditEvent::Login {
+            username: "admin".to_string(),
+        });
+        audit.record(AuditEvent::Disconnected {
+            reason: "server_closed",
+        });
+        // Queued records are written out when the log is stopped
+        cancel.cancel();
+
+        let lines = wait_for_lines(&path, 4).await;
+        let _ = tokio::fs::remove_file(&path).await;
+        for line in &lines {
+            assert_eq!(line["source_id"], "alarm-handler");
+            assert!(line["ts_ms"].as_u64().unwrap() > 0);
+        }
+        assert_eq!(lines[0]["event"], "connected");
+        assert_eq!(lines[0]["address"], "10.0.0.5:23");
+        assert_eq!(lines[1]["event"], "negotiation");
+        assert_eq!(lines[1]["command"], "DO");
+        assert_eq!(lines[1]["option"], 39);
+        assert_eq!(lines[1]["response"], "WILL");
+        assert_eq!(lines[2]["event"], "login");
+        assert_eq!(lines[2]["username"], "admin");
+        assert_eq!(lines[3]["event"], "disconnected");
+        assert_eq!(lines[3]["reason"], "server_closed");
+    }
+
+    #[test]
+    fn records_nothing_when_disabled() {
+        let audit = AuditLog::default().for_link("alarm-handler");
+        assert!(!audit.is_enabled());
+        audit.record(AuditEvent::Disconnected { reason: "io" });
+        assert_eq!(audit.log.dropped.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn counts_records_that_do_not_fit_in_the_queue() {
+        let (sender, _receiver) = mpsc::channel(1);
+        let log = AuditLog {
+            sender: Some(sender),
+            dropped: Arc::default(),
+        };
+        let audit = log.for_link("alarm-handler");
+        for _ in 0..3 {
+            audit.record(AuditEvent::Disconnected { reason: "io" });
+        }
+        assert_eq!(log.dropped.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,155 @@
+//! `max_message_size` tuned to the sizes a link actually sees
+//!
+//! A fixed limit is either too high for a feed of short text lines, or so
+//! low that it silently drops large binary records. With
+//! `auto_tune_max_message_size` set, the sizes of the last [`WINDOW`]
+//! records are kept in a ring. Once the first window is full, which
+//! calibrates the limit, every further record moves the window along by one
+//! and the limit becomes its 99th percentile times `auto_tune_max_factor`,
+//! so it follows the feed as it changes. Records dropped for exceeding the
+//! limit count as one byte over it, so a feed that outgrows the limit
+//! raises it again once they make up more than 1% of the window.
+
+use std::collections::{BTreeMap, VecDeque};
+
+/// Records per tuning window
+pub const WINDOW: usize = 1000;
+
+/// Percentile of record sizes the limit is based on
+const PERCENTILE: usize = 99;
+
+/// Tracks the sizes of recent records and proposes a `max_message_size`
+/// for them
+pub struct SizeTuner {
+    /// Sizes of the last [`WINDOW`] records, oldest first
+    recent: VecDeque<usize>,
+    /// How many records of `recent` have each size
+    counts: BTreeMap<usize, usize>,
+    /// Margin above the percentile
+    factor: f64,
+}
+
+impl SizeTuner {
+    pub fn new(factor: f64) -> Self {
+        Self {
+            recent: VecDeque::with_capacity(WINDOW),
+            counts: BTreeMap::new(),
+            factor,
+        }
+    }
+
+    /// Record a record of `size` bytes, returning the limit for the last
+    /// [`WINDOW`] records once there are that many
+    pub fn observe(&mut self, size: usize) -> Option<usize> {
+        if self.recent.len() == WINDOW {
+            if let Some(oldest) = self.recent.pop_front() {
+                self.forget(oldest);
+            }
+        }
+        self.recent.push_back(size);
+        *self.counts.entry(size).or_default() += 1;
+        if self.recent.len() < WINDOW {
+            return None;
+        }
+        Some(((self.percentile() as f64 * self.factor).ceil() as usize).max(1))
+    }
+
+    /// How many of the recorded records are longer than `limit`
+    pub fn longer_than(&self, limit: usize) -> usize {
+        self.counts
+            .range(limit.saturating_add(1)..)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    fn forget(&mut self, size: usize) {
+        if let Some(count) = self.counts.get_mut(&size) {
+            *count -= 1;
+            if *count == 0 {
+                self.counts.remove(&size);
+            }
+        }
+    }
+
+    /// The smallest size at least [`PERCENTILE`]% of the recorded records
+    /// don't exceed
+    fn percentile(&self) -> usize {
+        let rank = (self.recent.len() * PERCENTILE).div_ceil(100);
+        // Counted from the largest, the record at `rank` is this one
+        let from_top = self.recent.len() - rank + 1;
+        let mut seen = 0;
+        for (&size, &count) in self.counts.iter().rev() {
+            seen += count;
+            if seen >= from_top {
+                return size;
+            }
+        }
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed a window of 990 records of `small` bytes and 10 of `large`
+    fn window(tuner: &mut SizeTuner, small: usize, large: usize) -> Vec<Option<usize>> {
+        (0..WINDOW)
+            .map(|i| tuner.observe(if i < 990 { small } else { large }))
+            .collect()
+    }
+
+    #[test]
+    fn calibrates_after_the_first_window() {
+        let mut tuner = SizeTuner::new(2.0);
+        let proposals = window(&mut tuner, 100, 1500);
+        assert!(proposals[..999].iter().all(Option::is_none));
+        // 99% of the records are 100 bytes
+        assert_eq!(proposals[999], Some(200));
+    }
+
+    #[test]
+    fn percentile_includes_the_top_percent() {
+        let mut tuner = SizeTuner::new(2.0);
+        // 11 large records push them into the 99th percentile
+        let proposals: Vec<_> = (0..WINDOW)
+            .map(|i| tuner.observe(if i < 989 { 100 } else { 1500 }))
+            .collect();
+        assert_eq!(proposals[999], Some(3000));
+    }
+
+    #[test]
+    fn adapts_to_each_following_record() {
+        let mut tuner = SizeTuner::new(2.0);
+        assert_eq!(window(&mut tuner, 100, 100)[999], Some(200));
+        let proposals = window(&mut tuner, 40, 40);
+        // While 11 of the last 1000 records are 100 bytes, they make up the
+        // 99th percentile
+        assert!(proposals[..989].iter().all(|p| *p == Some(200)));
+        assert_eq!(proposals[989], Some(80));
+        assert_eq!(proposals[999], Some(80));
+    }
+
+    #[test]
+    fn keeps_only_the_last_window() {
+        let mut tuner = SizeTuner::new(2.0);
+        window(&mut tuner, 100, 1500);
+        assert_eq!(tuner.longer_than(200), 10);
+        window(&mut tuner, 100, 100);
+        assert_eq!(tuner.recent.len(), WINDOW);
+        assert_eq!(tuner.longer_than(200), 0);
+        assert_eq!(tuner.counts, BTreeMap::from([(100, WINDOW)]));
+    }
+
+    #[test]
+    fn applies_the_factor() {
+        let mut tuner = SizeTuner::new(1.5);
+        assert_eq!(window(&mut tuner, 101, 101)[999], Some(152));
+    }
+
+    #[test]
+    fn never_proposes_zero() {
+        let mut tuner = SizeTuner::new(2.0);
+        assert_eq!(window(&mut tuner, 0, 0)[999], Some(1));
+    }
+}
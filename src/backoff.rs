@@ -0,0 +1,241 @@
+//! Reconnect delays with optional jitter
+//!
+//! Delays double from `initial_reconnect_delay_ms` up to
+//! `max_reconnect_delay_ms`. When many links lose the same server at once,
+//! identical delays make them all reconnect at the same moment. The jitter
+//! modes (as described in the AWS Architecture Blog's "Exponential Backoff
+//! And Jitter") spread them out:
+//!
+//! - `full`: uniformly between zero and the exponential delay
+//! - `equal`: half the exponential delay plus up to half again
+//! - `decorrelated`: between the initial delay and three times the previous
+//!   delay, capped at the maximum
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+/// How reconnect delays are randomized
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum JitterMode {
+    /// Plain exponential backoff
+    #[default]
+    None,
+    Full,
+    Equal,
+    Decorrelated,
+}
+
+impl std::str::FromStr for JitterMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(JitterMode::None),
+            "full" => Ok(JitterMode::Full),
+            "equal" => Ok(JitterMode::Equal),
+            "decorrelated" => Ok(JitterMode::Decorrelated),
+            other => Err(anyhow::anyhow!("Invalid backoff_jitter_mode: {}", other)),
+        }
+    }
+}
+
+/// Source of randomness for jitter
+pub trait JitterRng: Send {
+    /// A uniformly distributed value in `[0, 1)`
+    fn next_f64(&mut self) -> f64;
+}
+
+/// SplitMix64 generator; jitter only needs to be spread out, not secure
+pub struct SplitMix64(u64);
+
+impl SplitMix64 {
+    /// Generator with a fixed seed, producing the same delays every run
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// Generator seeded differently in each process
+    pub fn from_entropy() -> Self {
+        Self::new(RandomState::new().hash_one(std::process::id()))
+    }
+}
+
+impl JitterRng for SplitMix64 {
+    fn next_f64(&mut self) -> f64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        // The top 53 bits fill an f64 mantissa exactly
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Delays between one link's reconnection attempts
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    mode: JitterMode,
+    /// Exponential delay of the next attempt, before jitter
+    exponential: Duration,
+    /// Delay of the last attempt, for decorrelated jitter
+    previous: Duration,
+    rng: Box<dyn JitterRng>,
+}
+
+impl Backoff {
+    /// Backoff from `initial` to `max` with randomly seeded jitter
+    pub fn new(initial: Duration, max: Duration, mode: JitterMode) -> Self {
+        Self::with_rng(initial, max, mode, Box::new(SplitMix64::from_entropy()))
+    }
+
+    /// Backoff drawing its jitter from `rng`
+    pub fn with_rng(
+        initial: Duration,
+        max: Duration,
+        mode: JitterMode,
+        rng: Box<dyn JitterRng>,
+    ) -> Self {
+        Self {
+            initial,
+            max,
+            mode,
+            exponential: initial,
+            previous: initial,
+            rng,
+        }
+    }
+
+    /// Delay before the next attempt; each call moves on to the next one
+    pub fn next_delay(&mut self) -> Duration {
+        let exponential = self.exponential.min(self.max);
+        self.exponential = self.exponential.saturating_mul(2).min(self.max);
+
+        let delay = match self.mode {
+            JitterMode::None => exponential,
+            JitterMode::Full => exponential.mul_f64(self.rng.next_f64()),
+            JitterMode::Equal => {
+                let half = exponential / 2;
+                half + half.mul_f64(self.rng.next_f64())
+            }
+            JitterMode::Decorrelated => {
+                let upper = self.previous.saturating_mul(3).max(self.initial);
+                let spread = upper - self.initial;
+                (self.initial + spread.mul_f64(self.rng.next_f64())).min(self.max)
+            }
+        };
+        self.previous = delay;
+        delay
+    }
+
+    /// Start over from the initial delay
+    pub fn reset(&mut self) {
+        self.exponential = self.initial;
+        self.previous = self.initial;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INITIAL: Duration = Duration::from_millis(100);
+    const MAX: Duration = Duration::from_secs(5);
+
+    /// Always draws the same value
+    struct Fixed(f64);
+
+    impl JitterRng for Fixed {
+        fn next_f64(&mut self) -> f64 {
+            self.0
+        }
+    }
+
+    fn seeded(mode: JitterMode) -> Backoff {
+        Backoff::with_rng(INITIAL, MAX, mode, Box::new(SplitMix64::new(42)))
+    }
+
+    /// The exponential delay of each of the first `count` attempts
+    fn exponential(count: usize) -> Vec<Duration> {
+        let mut backoff = Backoff::with_rng(INITIAL, MAX, JitterMode::None, Box::new(Fixed(0.0)));
+        (0..count).map(|_| backoff.next_delay()).collect()
+    }
+
+    #[test]
+    fn none_doubles_up_to_the_maximum() {
+        let delays: Vec<u64> = exponential(8)
+            .iter()
+            .map(|delay| delay.as_millis() as u64)
+            .collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1600, 3200, 5000, 5000]);
+    }
+
+    #[test]
+    fn full_stays_below_the_exponential_delay() {
+        let mut backoff = seeded(JitterMode::Full);
+        for exponential in exponential(200) {
+            assert!(backoff.next_delay() < exponential);
+        }
+    }
+
+    #[test]
+    fn equal_stays_in_the_upper_half() {
+        let mut backoff = seeded(JitterMode::Equal);
+        for exponential in exponential(200) {
+            let delay = backoff.next_delay();
+            assert!(
+                delay >= exponential / 2 && delay <= exponential,
+                "{:?}",
+                delay
+            );
+        }
+    }
+
+    #[test]
+    fn decorrelated_stays_within_three_times_the_previous_delay() {
+        let mut backoff = seeded(JitterMode::Decorrelated);
+        let mut previous = INITIAL;
+        for _ in 0..200 {
+            let delay = backoff.next_delay();
+            assert!(delay >= INITIAL && delay <= MAX, "{:?}", delay);
+            assert!(delay <= (previous * 3).max(INITIAL), "{:?}", delay);
+            previous = delay;
+        }
+    }
+
+    #[test]
+    fn jitter_reaches_its_bounds() {
+        let delay =
+            |mode, draw| Backoff::with_rng(INITIAL, MAX, mode, Box::new(Fixed(draw))).next_delay();
+        assert_eq!(delay(JitterMode::Full, 0.0), Duration::ZERO);
+        assert_eq!(delay(JitterMode::Equal, 0.0), INITIAL / 2);
+        assert_eq!(delay(JitterMode::Decorrelated, 0.0), INITIAL);
+        assert_eq!(delay(JitterMode::Decorrelated, 0.5), INITIAL * 2);
+    }
+
+    #[test]
+    fn seeded_jitter_is_reproducible() {
+        for mode in [
+            JitterMode::Full,
+            JitterMode::Equal,
+            JitterMode::Decorrelated,
+        ] {
+            let (mut a, mut b) = (seeded(mode), seeded(mode));
+            for _ in 0..20 {
+                assert_eq!(a.next_delay(), b.next_delay());
+            }
+        }
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut backoff = seeded(JitterMode::None);
+        for _ in 0..5 {
+            backoff.next_delay();
+        }
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), INITIAL);
+    }
+}
@@ -0,0 +1,361 @@
+//! Throughput benchmark of the receive and forwarding pipeline
+//!
+//! Runs one Telnet client against an in-process mock server sending a
+//! chosen traffic profile as fast as it can. Received data goes through the
+//! real connection task and [`Forwarder`], and the resulting deliveries are
+//! discarded instead of being sent to a component, so the numbers cover
+//! everything from the socket up to the wRPC call. The result is printed as
+//! one JSON object per run (and appended to `--output` if given), so runs
+//! can be compared with a script or a plain diff.
+//!
+//! Allocations are only counted when built with the `alloc-count` feature,
+//! which wraps the system allocator in a counter.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context as _;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use crate::config::LinkConfig;
+use crate::context::ConnectionContext;
+use crate::forward::Forwarder;
+use crate::instrument::spawn_named;
+use crate::socket;
+use crate::stats::ConnectionStats;
+use crate::telnet::TelnetClient;
+
+/// Traffic sent by the mock server
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// 200-byte lines, written in large batches
+    #[default]
+    Lines,
+    /// 64 KiB blocks
+    Dumps,
+    /// One byte per write
+    Trickle,
+    /// Batches of 200-byte lines, each followed by a 1 MiB block
+    Mixed,
+}
+
+impl Profile {
+    /// Stable lowercase name, used in the results
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Profile::Lines => "lines",
+            Profile::Dumps => "dumps",
+            Profile::Trickle => "trickle",
+            Profile::Mixed => "mixed",
+        }
+    }
+
+    /// Bytes the server sends per write
+    fn payload(&self) -> Vec<u8> {
+        match self {
+            Profile::Lines => {
+                let mut line = vec![b'x'; 198];
+                line.extend_from_slice(b"\r\n");
+                line.repeat(64)
+            }
+            Profile::Dumps => vec![b'x'; 64 * 1024],
+            Profile::Trickle => vec![b'x'],
+            Profile::Mixed => {
+                let mut payload = Profile::Lines.payload();
+                payload.extend(std::iter::repeat_n(b'y', 1024 * 1024));
+                payload
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Profile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "lines" => Ok(Profile::Lines),
+            "dumps" => Ok(Profile::Dumps),
+            "trickle" => Ok(Profile::Trickle),
+            "mixed" => Ok(Profile::Mixed),
+            other => Err(anyhow::anyhow!("Invalid bench profile: {}", other)),
+        }
+    }
+}
+
+/// Benchmark parameters, parsed from `--bench` command line flags
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    /// Traffic profile
+    pub profile: Profile,
+    /// Measured run time
+    pub duration: Duration,
+    /// Time before measuring starts, while the read size adapts
+    pub warmup: Duration,
+    /// File the result line is appended to, if any
+    pub output: Option<String>,
+    /// `offload_threshold_bytes` of the link, if set
+    pub offload_threshold: Option<usize>,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            profile: Profile::default(),
+            duration: Duration::from_secs(10),
+            warmup: Duration::from_secs(2),
+            output: None,
+            offload_threshold: None,
+        }
+    }
+}
+
+impl BenchOptions {
+    /// Parse `--flag value` pairs following `--bench`
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("Missing value for {}", flag))?;
+            let secs = || value.parse().map(Duration::from_secs);
+            match flag.as_str() {
+                "--profile" => options.profile = value.parse()?,
+                "--duration-secs" => options.duration = secs()?,
+                "--warmup-secs" => options.warmup = secs()?,
+                "--output" => options.output = Some(value),
+                "--offload-threshold-bytes" => options.offload_threshold = Some(value.parse()?),
+                other => anyhow::bail!("Unknown bench option: {}", other),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Messages and bytes that made it through the pipeline
+#[derive(Default)]
+struct Counters {
+    messages: AtomicU64,
+    bytes: AtomicU64,
+    /// Microseconds between one read's data leaving the pipeline and the next
+    gaps: std::sync::Mutex<Vec<u64>>,
+}
+
+impl Counters {
+    fn snapshot(&self) -> (u64, u64, Option<u64>, usize) {
+        (
+            self.messages.load(Ordering::Relaxed),
+            self.bytes.load(Ordering::Relaxed),
+            allocations(),
+            self.gaps.lock().unwrap_or_else(|e| e.into_inner()).len(),
+        )
+    }
+
+    /// 99th percentile of the gaps recorded between two snapshots, in ms
+    fn gap_p99_ms(&self, start: usize, end: usize) -> Option<f64> {
+        let mut gaps = self.gaps.lock().unwrap_or_else(|e| e.into_inner())[start..end].to_vec();
+        if gaps.is_empty() {
+            return None;
+        }
+        gaps.sort_unstable();
+        let index = (gaps.len() * 99 / 100).min(gaps.len() - 1);
+        Some(gaps[index] as f64 / 1000.0)
+    }
+}
+
+/// Run the benchmark and report its result
+pub async fn run(options: BenchOptions) -> anyhow::Result<()> {
+    info!(?options, "starting benchmark");
+
+    let cancel = CancellationToken::new();
+    let port = start_server(options.profile, cancel.clone()).await?;
+
+    let address = format!("127.0.0.1:{}", port);
+    let mut values = HashMap::from([
+        ("telnet_host".to_string(), "127.0.0.1".to_string()),
+        ("telnet_port".to_string(), port.to_string()),
+    ]);
+    if let Some(threshold) = options.offload_threshold {
+        values.insert("offload_threshold_bytes".to_string(), threshold.to_string());
+    }
+    let config = Arc::new(LinkConfig::from_values(&values)?);
+    let stats = Arc::new(ConnectionStats::default());
+    let client = TelnetClient::new(config.clone(), stats.clone());
+    let mut forwarder = Forwarder::new(
+        ConnectionContext::new("bench", &address, &format!("telnet.{}", address)),
+        config,
+        1,
+        stats.clone(),
+        cancel.clone(),
+        Arc::default(),
+        Arc::default(),
+    );
+
+    let counters = Arc::new(Counters::default());
+    let link_counters = counters.clone();
+    let link_cancel = cancel.clone();
+    spawn_named("bench", "reader", async move {
+        let mut last_handled: Option<Instant> = None;
+        let result = client
+            .run(link_cancel, move |data, received| {
+                let result = forwarder.forward(data, received).map(|deliveries| {
+                    let (messages, bytes) = deliveries.discard();
+                    link_counters
+                        .messages
+                        .fetch_add(messages, Ordering::Relaxed);
+                    link_counters.bytes.fetch_add(bytes, Ordering::Relaxed);
+                    let now = Instant::now();
+                    if let Some(last) = last_handled.replace(now) {
+                        link_counters
+                            .gaps
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .push(now.duration_since(last).as_micros() as u64);
+                    }
+                });
+                std::future::ready(result)
+            })
+            .await;
+        if let Err(e) = result {
+            error!("Benchmark link stopped: {}", e);
+        }
+    });
+
+    tokio::time::sleep(options.warmup).await;
+    let (start_messages, start_bytes, start_allocations, start_gaps) = counters.snapshot();
+    let (start_reads, start_bytes_in) = (stats.socket_reads(), stats.bytes_in());
+    let started = Instant::now();
+    tokio::time::sleep(options.duration).await;
+    let (end_messages, end_bytes, end_allocations, end_gaps) = counters.snapshot();
+    let (end_reads, end_bytes_in) = (stats.socket_reads(), stats.bytes_in());
+    let elapsed = started.elapsed().as_secs_f64();
+    cancel.cancel();
+
+    let messages = end_messages - start_messages;
+    let bytes = end_bytes - start_bytes;
+    let reads = end_reads - start_reads;
+    if messages == 0 {
+        anyhow::bail!("No messages received during the benchmark");
+    }
+    let allocations_per_message = start_allocations
+        .zip(end_allocations)
+        .map(|(start, end)| (end - start) as f64 / messages as f64);
+    let result = serde_json::json!({
+        "profile": options.profile.as_str(),
+        "offload_threshold_bytes": options.offload_threshold,
+        "backend": socket::BACKEND,
+        "duration_secs": elapsed,
+        "messages": messages,
+        "bytes": bytes,
+        "messages_per_sec": messages as f64 / elapsed,
+        "mib_per_sec": bytes as f64 / elapsed / (1024.0 * 1024.0),
+        "allocations_per_message": allocations_per_message,
+        "reads": reads,
+        "bytes_per_read": (end_bytes_in - start_bytes_in) as f64 / reads.max(1) as f64,
+        "gap_p99_ms": counters.gap_p99_ms(start_gaps, end_gaps),
+    })
+    .to_string();
+
+    println!("{}", result);
+    if let Some(output) = &options.output {
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(output)
+            .await
+            .with_context(|| format!("failed to open benchmark output {}", output))?;
+        file.write_all(format!("{}\n", result).as_bytes()).await?;
+        info!("Benchmark result appended to {}", output);
+    }
+    Ok(())
+}
+
+/// Start a mock Telnet server that sends `profile` traffic until cancelled,
+/// returning the port it listens on
+async fn start_server(profile: Profile, cancel: CancellationToken) -> anyhow::Result<u16> {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .context("failed to bind benchmark mock server")?;
+    let port = listener.local_addr()?.port();
+    let payload = profile.payload();
+
+    spawn_named("bench-server", "listener", async move {
+        loop {
+            let (mut stream, _) = tokio::select! {
+                _ = cancel.cancelled() => return,
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        error!("Benchmark mock server accept failed: {}", e);
+                        continue;
+                    }
+                },
+            };
+            // Keep one-byte writes from being coalesced by the sender
+            let _ = stream.set_nodelay(true);
+
+            let payload = payload.clone();
+            let cancel = cancel.clone();
+            spawn_named("bench-server", "connection", async move {
+                loop {
+                    tokio::select! {
+                        _ = cancel.cancelled() => return,
+                        result = stream.write_all(&payload) => {
+                            if result.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    Ok(port)
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "alloc-count")] {
+        use std::alloc::{GlobalAlloc, Layout, System};
+
+        /// System allocator counting allocations
+        struct CountingAllocator;
+
+        static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+        unsafe impl GlobalAlloc for CountingAllocator {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+                System.alloc(layout)
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                System.dealloc(ptr, layout)
+            }
+
+            unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+                ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+                System.realloc(ptr, layout, new_size)
+            }
+        }
+
+        #[global_allocator]
+        static GLOBAL: CountingAllocator = CountingAllocator;
+
+        /// Allocations made by the process so far
+        fn allocations() -> Option<u64> {
+            Some(ALLOCATIONS.load(Ordering::Relaxed))
+        }
+    } else {
+        /// Allocations aren't counted without the `alloc-count` feature
+        fn allocations() -> Option<u64> {
+            None
+        }
+    }
+}
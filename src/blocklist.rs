@@ -0,0 +1,192 @@
+//! Commands components may not send to a server
+//!
+//! Data a component sends over its connection is checked against
+//! `command_blocklist` before anything is written, so that commands such as
+//! `reload` or `delete` never reach network equipment. Like the keyword
+//! filter, all commands are searched in one Aho-Corasick pass over the raw
+//! bytes. Matching ignores ASCII case and finds a command as a word of its
+//! own on any line, so `reload` is blocked in `reload in 5` but not in
+//! `show reloads`. A line is only complete once its line break is sent: the
+//! unterminated end of each send is kept as the connection's [`SentLine`]
+//! and checked together with the next, so a command split across sends is
+//! still found.
+
+use aho_corasick::{AhoCorasick, BuildError};
+
+/// Automaton of one link's blocked commands
+#[derive(Debug, Clone)]
+pub struct CommandBlocklist {
+    automaton: AhoCorasick,
+    commands: Vec<String>,
+    /// Length of the longest command, the most of an open line worth keeping
+    longest: usize,
+}
+
+/// The unterminated end of the last line sent on a connection
+#[derive(Debug, Default)]
+pub struct SentLine(Vec<u8>);
+
+impl SentLine {
+    /// Forget the line, as on a new connection
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+impl CommandBlocklist {
+    /// Build the automaton, or `None` if no commands are blocked
+    pub fn new(commands: &[String]) -> Result<Option<Self>, BuildError> {
+        if commands.is_empty() {
+            return Ok(None);
+        }
+        let automaton = AhoCorasick::builder()
+            .ascii_case_insensitive(true)
+            .build(commands)?;
+        Ok(Some(Self {
+            automaton,
+            commands: commands.to_vec(),
+            longest: commands.iter().map(String::len).max().unwrap_or(0),
+        }))
+    }
+
+    /// The first blocked command found in `data` sent after `line`, if any
+    ///
+    /// If there is none, `line` moves on to the unterminated end of `data`,
+    /// keeping only as much of it as a command can span.
+    pub fn check(&self, line: &mut SentLine, data: &[u8]) -> Option<&str> {
+        let mut text = std::mem::take(&mut line.0);
+        let sent_before = text.len();
+        text.extend_from_slice(data);
+        if let Some(command) = self.find_from(&text, sent_before) {
+            text.truncate(sent_before);
+            line.0 = text;
+            return Some(command);
+        }
+
+        let line_start = text
+            .iter()
+            .rposition(|&b| b == b'\r' || b == b'\n')
+            .map_or(0, |i| i + 1);
+        text.drain(..line_start.max(text.len().saturating_sub(self.longest)));
+        line.0 = text;
+        None
+    }
+
+    /// The first blocked command standing as a word of its own in `text`
+    /// that ends past `from`, so matches already checked aren't reported
+    /// again
+    ///
+    /// A line kept by [`check`](Self::check) is cut to the longest command,
+    /// so no match ending past it starts at its first byte, which may have
+    /// lost the byte before it.
+    fn find_from(&self, text: &[u8], from: usize) -> Option<&str> {
+        self.automaton
+            .find_overlapping_iter(text)
+            .find(|found| {
+                found.end() > from
+                    && word_boundary(text, found.start())
+                    && word_boundary(text, found.end())
+            })
+            .map(|found| self.commands[found.pattern().as_usize()].as_str())
+    }
+}
+
+/// Whether `at` isn't inside a word of `text`
+fn word_boundary(text: &[u8], at: usize) -> bool {
+    let is_word = |b: &u8| b.is_ascii_alphanumeric() || *b == b'_';
+    !(at > 0 && text.get(at - 1).is_some_and(is_word) && text.get(at).is_some_and(is_word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocklist(commands: &[&str]) -> CommandBlocklist {
+        let commands: Vec<String> = commands.iter().map(|c| c.to_string()).collect();
+        CommandBlocklist::new(&commands).unwrap().unwrap()
+    }
+
+    /// The blocked command found in `data` sent on a new connection
+    fn find<'a>(blocklist: &'a CommandBlocklist, data: &[u8]) -> Option<&'a str> {
+        blocklist.check(&mut SentLine::default(), data)
+    }
+
+    #[test]
+    fn nothing_to_block_builds_nothing() {
+        assert!(CommandBlocklist::new(&[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn blocks_an_exact_match() {
+        let blocklist = blocklist(&["reload", "delete"]);
+        assert_eq!(find(&blocklist, b"reload"), Some("reload"));
+        assert_eq!(find(&blocklist, b"delete flash:config\r\n"), Some("delete"));
+        assert_eq!(find(&blocklist, b"show version\r\n"), None);
+    }
+
+    #[test]
+    fn ignores_case() {
+        let blocklist = blocklist(&["reload"]);
+        assert_eq!(find(&blocklist, b"RELOAD in 5\r\n"), Some("reload"));
+        assert_eq!(find(&blocklist, b"ReLoAd"), Some("reload"));
+    }
+
+    #[test]
+    fn finds_a_command_on_any_line() {
+        let blocklist = blocklist(&["reload", "write erase"]);
+        assert_eq!(
+            find(
+                &blocklist,
+                b"show clock\r\nconfigure terminal\r\nreload\r\n"
+            ),
+            Some("reload")
+        );
+        assert_eq!(
+            find(&blocklist, b"end\r\nWRITE ERASE\r\nshow run\r\n"),
+            Some("write erase")
+        );
+        assert_eq!(find(&blocklist, b"write\r\nerase\r\n"), None);
+    }
+
+    #[test]
+    fn matches_whole_words_only() {
+        let blocklist = blocklist(&["reload", "write erase"]);
+        assert_eq!(find(&blocklist, b"show reloads\r\n"), None);
+        assert_eq!(find(&blocklist, b"unreload\r\n"), None);
+        assert_eq!(find(&blocklist, b"rewrite erased\r\n"), None);
+        assert_eq!(find(&blocklist, b"do reload;\r\n"), Some("reload"));
+        assert_eq!(find(&blocklist, b"write erase"), Some("write erase"));
+    }
+
+    #[test]
+    fn finds_a_command_split_across_sends() {
+        let blocklist = blocklist(&["reload"]);
+        let mut line = SentLine::default();
+        assert_eq!(blocklist.check(&mut line, b"show clock\r\nre"), None);
+        assert_eq!(blocklist.check(&mut line, b"lo"), None);
+        assert_eq!(blocklist.check(&mut line, b"ad\r\n"), Some("reload"));
+        // Refused data isn't part of the line
+        assert_eq!(blocklist.check(&mut line, b"ad\r\n"), Some("reload"));
+        assert_eq!(blocklist.check(&mut line, b"x\r\n"), None);
+
+        // A line break in between splits the command
+        assert_eq!(blocklist.check(&mut line, b"rel"), None);
+        assert_eq!(blocklist.check(&mut line, b"\r\noad\r\n"), None);
+        // A command ending a send is refused without waiting for more; the
+        // start of one waits for the rest
+        assert_eq!(blocklist.check(&mut line, b"show reload"), Some("reload"));
+        assert_eq!(blocklist.check(&mut line, b"show relo"), None);
+        assert_eq!(blocklist.check(&mut line, b"ads\r\n"), None);
+    }
+
+    #[test]
+    fn keeps_no_more_of_a_line_than_a_command_spans() {
+        let blocklist = blocklist(&["reload"]);
+        let mut line = SentLine::default();
+        assert_eq!(blocklist.check(&mut line, &[b'x'; 4096]), None);
+        assert_eq!(line.0, b"xxxxxx");
+        // The kept bytes are still context for the next send
+        assert_eq!(blocklist.check(&mut line, b"reload\r\n"), None);
+        assert_eq!(blocklist.check(&mut line, b" reload\r\n"), Some("reload"));
+    }
+}
@@ -0,0 +1,193 @@
+//! wRPC client for a link's component, built once and reused
+//!
+//! Building a client looks up the host connection and sets up the transport,
+//! which is wasted work when repeated for every message. Each link keeps one
+//! client for all deliveries; if a call fails at the transport level the
+//! client may be stale, so it is rebuilt once and the call retried.
+//!
+//! A client can also wedge without failing at the transport level, with
+//! every call ending in an error or timing out. With
+//! `component_client_recreate_after` set, that many failed calls in a row
+//! (including watchdog pings that time out) drop the cached client, and the
+//! next call acquires a fresh one from the host connection.
+//!
+//! A provider built with a [`MessageSink`] delivers through it instead, and
+//! never builds a wRPC client.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use anyhow::Context as _;
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+use wasmcloud_provider_sdk::provider::WrpcClient;
+
+use crate::provider::{handler, types};
+use crate::stats::ConnectionStats;
+
+/// Handles messages for components in the provider's process, in place of
+/// their `handle-message` export
+///
+/// Lets a provider run without a wasmCloud host, as embedders and
+/// integration tests do. An error counts as the component failing the
+/// message.
+pub trait MessageSink: Send + Sync {
+    /// Handle `body`, published on `subject`, for `component_id`
+    fn handle_message<'a>(
+        &'a self,
+        component_id: &'a str,
+        subject: &'a str,
+        body: &'a [u8],
+        reply_to: Option<&'a str>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send + 'a>>;
+}
+
+/// When to recreate a wedged client, and where recreations are counted
+#[derive(Clone)]
+struct Recovery {
+    /// Consecutive failed calls that drop the cached client
+    after: u32,
+    stats: Arc<ConnectionStats>,
+}
+
+/// Sends messages to one component over a cached wRPC client
+pub struct ComponentClient {
+    component_id: Arc<str>,
+    client: Mutex<Option<Arc<WrpcClient>>>,
+    /// Failed calls since the last successful one
+    consecutive_failures: AtomicU32,
+    recovery: Option<Recovery>,
+    /// Where messages go instead of over wRPC, if anywhere
+    sink: Option<Arc<dyn MessageSink>>,
+}
+
+impl ComponentClient {
+    /// Client for `component_id`; nothing is built until the first send
+    pub fn new(component_id: Arc<str>) -> Self {
+        Self {
+            component_id,
+            client: Mutex::new(None),
+            consecutive_failures: AtomicU32::new(0),
+            recovery: None,
+            sink: None,
+        }
+    }
+
+    /// Recreate the client after `failures` failed calls in a row, counting
+    /// recreations in `stats`; 0 never does
+    pub fn with_recovery(mut self, failures: u32, stats: Arc<ConnectionStats>) -> Self {
+        self.recovery = (failures > 0).then_some(Recovery {
+            after: failures,
+            stats,
+        });
+        self
+    }
+
+    /// Deliver to `sink` instead of over wRPC, if given
+    pub fn with_sink(mut self, sink: Option<Arc<dyn MessageSink>>) -> Self {
+        self.sink = sink;
+        self
+    }
+
+    /// Client for another component of the same link, recovering and
+    /// delivering the same way
+    pub fn sibling(&self, component_id: Arc<str>) -> Self {
+        Self {
+            recovery: self.recovery.clone(),
+            sink: self.sink.clone(),
+            ..Self::new(component_id)
+        }
+    }
+
+    /// Deliver `message` to the component's `handle-message` export
+    pub async fn send(&self, message: &types::BrokerMessage) -> anyhow::Result<()> {
+        let result = match &self.sink {
+            Some(sink) => {
+                sink.handle_message(
+                    &self.component_id,
+                    &message.subject,
+                    &message.body,
+                    message.reply_to.as_deref(),
+                )
+                .await
+            }
+            None => self.call(message).await,
+        };
+        match &result {
+            Ok(()) => self.consecutive_failures.store(0, Ordering::Relaxed),
+            Err(_) => self.record_failure().await,
+        }
+        result
+    }
+
+    /// Call the component's `handle-message` export over wRPC
+    async fn call(&self, message: &types::BrokerMessage) -> anyhow::Result<()> {
+        let client = self.get().await?;
+        let result = match handler::handle_message(&*client, None, message).await {
+            Err(e) => {
+                debug!(
+                    "Call to component {} failed, rebuilding wRPC client: {}",
+                    self.component_id, e
+                );
+                self.invalidate(&client).await;
+                let client = self.get().await?;
+                handler::handle_message(&*client, None, message).await
+            }
+            result => result,
+        };
+
+        // Callers log failures; at high message rates they throttle them
+        match result {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(e)) => Err(anyhow::anyhow!("Component error: {}", e)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Count a failed call, such as a send the caller gave up waiting for,
+    /// recreating the client once too many failed in a row
+    pub async fn record_failure(&self) {
+        let Some(recovery) = &self.recovery else {
+            return;
+        };
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures < recovery.after {
+            return;
+        }
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+        warn!(
+            "{} consecutive calls to component {} failed, recreating wRPC client",
+            failures, self.component_id
+        );
+        self.client.lock().await.take();
+        recovery.stats.record_client_recreation();
+    }
+
+    /// The cached client, building it if there is none
+    async fn get(&self) -> anyhow::Result<Arc<WrpcClient>> {
+        let mut cached = self.client.lock().await;
+        if let Some(client) = &*cached {
+            return Ok(client.clone());
+        }
+        let client = wasmcloud_provider_sdk::get_connection()
+            .get_wrpc_client(&self.component_id)
+            .await
+            .context("failed to get wrpc client")?;
+        let client = Arc::new(client);
+        *cached = Some(client.clone());
+        Ok(client)
+    }
+
+    /// Drop `stale` from the cache, unless another send already replaced it
+    async fn invalidate(&self, stale: &Arc<WrpcClient>) {
+        let mut cached = self.client.lock().await;
+        if cached
+            .as_ref()
+            .is_some_and(|client| Arc::ptr_eq(client, stale))
+        {
+            *cached = None;
+        }
+    }
+}
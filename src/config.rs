@@ -1,7 +1,31 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
+use thiserror::Error;
+use tracing::warn;
+
+use crate::aggregate::AggregateConfig;
+use crate::backoff::JitterMode;
+use crate::blocklist::CommandBlocklist;
+use crate::controls::ControlTokenStyle;
+use crate::dispatch::DeliveryPriority;
+use crate::effective::{EffectiveValue, ProvidedValues};
+use crate::enrich::{parse_enrichment_fields, EnrichmentField};
+use crate::envelope::EnvelopeFormat;
+use crate::environ::{self, Environ};
+use crate::login::LoginConfig;
+use crate::multiplex::ConnectionRuntime;
+use crate::ordering::DeliveryOrdering;
+use crate::policy::{parse_host_patterns, HostPattern};
+use crate::priority::parse_priority_byte_map;
+use crate::route::{parse_routing_rules, RoutingRule};
+use crate::sample::SampleRate;
+use crate::schedule::DailySchedule;
+use crate::server_type::ServerType;
+use crate::subject::{self, SourceIdTransform, DEFAULT_SUBJECT_TEMPLATE};
+use crate::throttle::DEFAULT_MAX_CONCURRENT_RECONNECTS;
+use crate::unstuff::UnstuffConfig;
 
 /// Configuration for the Telnet provider
 #[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
@@ -18,6 +42,325 @@ impl From<&HashMap<String, String>> for ProviderConfig {
     }
 }
 
+impl ProviderConfig {
+    /// UDP address of a StatsD server to send metrics to
+    pub fn statsd_addr(&self) -> Option<&str> {
+        self.values
+            .get("statsd_addr")
+            .map(String::as_str)
+            .filter(|v| !v.is_empty())
+    }
+
+    /// Prefix for StatsD metric names
+    pub fn statsd_prefix(&self) -> &str {
+        self.values
+            .get("statsd_prefix")
+            .map(String::as_str)
+            .unwrap_or("telnet")
+    }
+
+    /// Whether failing to set up metrics export stops the provider
+    pub fn metrics_required(&self) -> bool {
+        self.values
+            .get("metrics_required")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false)
+    }
+
+    /// Maximum deliveries to components in flight at once (0 = unlimited)
+    pub fn max_inflight_deliveries(&self) -> usize {
+        self.values
+            .get("max_inflight_deliveries")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// Maximum links served at once; further links are rejected
+    pub fn max_connections(&self) -> Option<usize> {
+        self.values
+            .get("max_connections")
+            .and_then(|v| v.parse().ok())
+            .filter(|v| *v > 0)
+    }
+
+    /// Hosts that may only be reached over TLS; links to them are rejected
+    pub fn require_tls_hosts(&self) -> anyhow::Result<Vec<HostPattern>> {
+        self.values
+            .get("require_tls_hosts")
+            .map(|v| parse_host_patterns(v))
+            .transpose()
+            .map(Option::unwrap_or_default)
+    }
+
+    /// Maximum connection attempts in progress at once across all links
+    /// (0 = unlimited)
+    pub fn max_concurrent_reconnects(&self) -> usize {
+        self.values
+            .get("max_concurrent_reconnects")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_CONCURRENT_RECONNECTS)
+    }
+
+    /// Number of tasks shared by all connections (0 = one task per link)
+    pub fn shared_reader_tasks(&self) -> usize {
+        self.values
+            .get("shared_reader_tasks")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// Commands blocked on every connection opened by a component, on top
+    /// of any it sets itself
+    pub fn command_blocklist(&self) -> Vec<String> {
+        self.values
+            .get("command_blocklist")
+            .map(|v| keyword_list(v))
+            .unwrap_or_default()
+    }
+
+    /// Hosts components may open connections to; none unless set
+    pub fn connect_allowed_hosts(&self) -> anyhow::Result<Vec<HostPattern>> {
+        self.values
+            .get("connect_allowed_hosts")
+            .map(|v| parse_host_patterns(v))
+            .transpose()
+            .map(Option::unwrap_or_default)
+    }
+
+    /// Ports components may open connections to (empty = any port of an
+    /// allowed host)
+    pub fn connect_allowed_ports(&self) -> anyhow::Result<Vec<u16>> {
+        self.values
+            .get("connect_allowed_ports")
+            .map(|v| keyword_list(v).iter().map(|port| port.parse()).collect())
+            .transpose()
+            .map(Option::unwrap_or_default)
+            .map_err(|e| anyhow::anyhow!("Invalid connect_allowed_ports: {}", e))
+    }
+
+    /// Whether to run each link's pipeline against sample input before
+    /// connecting
+    pub fn self_test(&self) -> bool {
+        self.values
+            .get("self_test")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false)
+    }
+
+    /// How long connections get to stop on their own at shutdown before
+    /// they are aborted
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_millis(
+            self.values
+                .get("shutdown_timeout_ms")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(10000),
+        )
+    }
+
+    /// File to append the JSON-lines audit log to
+    pub fn audit_log_path(&self) -> Option<std::path::PathBuf> {
+        self.values
+            .get("audit_log_path")
+            .filter(|v| !v.is_empty())
+            .map(std::path::PathBuf::from)
+    }
+
+    /// Interval between StatsD flushes
+    pub fn statsd_flush_interval(&self) -> Duration {
+        Duration::from_millis(
+            self.values
+                .get("statsd_flush_interval_ms")
+                .and_then(|v| v.parse().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(10000),
+        )
+    }
+}
+
+/// Invalid link configuration
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    /// `telnet_port` is not a port number between 1 and 65535
+    #[error("Invalid telnet_port {0:?}: must be between 1 and 65535")]
+    InvalidPort(String),
+    /// `ndjson` combined with a binary `envelope_format`
+    #[error("ndjson requires raw text or json envelopes, not {0:?}")]
+    NdjsonBinaryEnvelope(EnvelopeFormat),
+    /// `envelope_format` without `protocol_version` 2
+    #[error("envelope_format {0:?} requires protocol_version=2")]
+    EnvelopeWithoutVersion(EnvelopeFormat),
+    /// A login `#len:<n>` read longer than `max_message_size`
+    #[error("Login expect length {length} exceeds max_message_size {max}")]
+    LoginLengthTooLarge { length: usize, max: usize },
+}
+
+/// Keys a running connection adopts without reconnecting
+pub const LIVE_KEYS: &[&str] = &["max_message_size", "debug_hexdump", "hexdump_max_bytes"];
+
+/// Keys a component may set on a connection it opens through a handle
+///
+/// Anything else (routing, subjects, groups, runtimes, files) reaches
+/// beyond the connection itself and is left to the operator's links.
+pub const COMPONENT_OPTIONS: &[&str] = &[
+    "ip_family",
+    "max_message_size",
+    "command_blocklist",
+    "initial_reconnect_delay_ms",
+    "max_reconnect_delay_ms",
+    "max_reconnect_attempts",
+    "backoff_jitter_mode",
+    "idle_reconnect_after_ms",
+    "write_timeout_ms",
+    "negotiation_timeout_ms",
+    "negotiation_timeout_action",
+    "reachability_probe",
+    "protocol_version",
+    "envelope_format",
+    "ndjson",
+    "unstuff",
+    "unstuff_cr",
+    "tokenize_controls",
+    "body_blocked_keywords",
+    "body_required_keywords",
+    "login_username",
+    "login_password",
+    "login_prompt",
+    "password_prompt",
+    "login_timeout_ms",
+    "login_step_delay_ms",
+    "login_username_delay_ms",
+    "login_password_delay_ms",
+];
+
+/// Whether a component may set `key` on a connection it opens
+pub fn is_component_option(key: &str) -> bool {
+    COMPONENT_OPTIONS.contains(&key) || key.starts_with(environ::KEY_PREFIX)
+}
+
+/// One value changed between two configs of a link
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigChange {
+    pub key: String,
+    pub old: String,
+    pub new: String,
+}
+
+impl ConfigChange {
+    /// Whether the connection has to be restarted for the change to apply
+    pub fn is_disruptive(&self) -> bool {
+        !LIVE_KEYS.contains(&self.key.as_str())
+    }
+}
+
+/// Role of a link within a redundant connection group
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum GroupRole {
+    /// Preferred source; forwards whenever it is connected
+    #[default]
+    Primary,
+    /// Takes over only while no primary is connected
+    Standby,
+}
+
+impl std::str::FromStr for GroupRole {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "primary" => Ok(GroupRole::Primary),
+            "standby" => Ok(GroupRole::Standby),
+            other => Err(anyhow::anyhow!("Invalid connection_group_role: {}", other)),
+        }
+    }
+}
+
+/// What to do with the Telnet connection while the component is unreachable
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ComponentDownAction {
+    /// Only report the component as unreachable
+    #[default]
+    None,
+    /// Stay connected but stop forwarding messages
+    Pause,
+    /// Close the Telnet connection until the component returns
+    Disconnect,
+}
+
+impl std::str::FromStr for ComponentDownAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(ComponentDownAction::None),
+            "pause" => Ok(ComponentDownAction::Pause),
+            "disconnect" => Ok(ComponentDownAction::Disconnect),
+            other => Err(anyhow::anyhow!("Invalid component_down_action: {}", other)),
+        }
+    }
+}
+
+/// What to do when option negotiation runs past `negotiation_timeout_ms`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NegotiationTimeoutAction {
+    /// Stop answering negotiation and keep forwarding data
+    #[default]
+    Continue,
+    /// Close the connection and reconnect with backoff
+    Fail,
+}
+
+impl std::str::FromStr for NegotiationTimeoutAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "continue" => Ok(NegotiationTimeoutAction::Continue),
+            "fail" => Ok(NegotiationTimeoutAction::Fail),
+            other => Err(anyhow::anyhow!(
+                "Invalid negotiation_timeout_action: {}",
+                other
+            )),
+        }
+    }
+}
+
+/// IP family used when connecting to the Telnet server
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum IpFamily {
+    /// Use whatever order the OS resolver returns
+    #[default]
+    Any,
+    /// Connect over IPv4 only (A records)
+    Ipv4Only,
+    /// Connect over IPv6 only (AAAA records)
+    Ipv6Only,
+}
+
+impl IpFamily {
+    /// Whether an address belongs to this family
+    pub fn allows(&self, ip: &std::net::IpAddr) -> bool {
+        match self {
+            IpFamily::Any => true,
+            IpFamily::Ipv4Only => ip.is_ipv4(),
+            IpFamily::Ipv6Only => ip.is_ipv6(),
+        }
+    }
+}
+
+impl std::str::FromStr for IpFamily {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "any" => Ok(IpFamily::Any),
+            "ipv4" | "ipv4only" | "ipv4_only" => Ok(IpFamily::Ipv4Only),
+            "ipv6" | "ipv6only" | "ipv6_only" => Ok(IpFamily::Ipv6Only),
+            other => Err(anyhow::anyhow!("Invalid ip_family: {}", other)),
+        }
+    }
+}
+
 /// Link-specific configuration for Telnet connections
 #[derive(Debug, Clone)]
 pub struct LinkConfig {
@@ -27,6 +370,9 @@ pub struct LinkConfig {
     /// Telnet server port to connect to
     pub telnet_port: u16,
 
+    /// IP family to connect over
+    pub ip_family: IpFamily,
+
     /// Maximum reconnection attempts (0 for infinite)
     pub max_reconnect_attempts: u32,
 
@@ -36,8 +382,262 @@ pub struct LinkConfig {
     /// Maximum reconnection delay in milliseconds
     pub max_reconnect_delay_ms: u64,
 
+    /// How reconnect delays are randomized
+    pub backoff_jitter_mode: JitterMode,
+
+    /// Daily UTC windows during which no reconnection is attempted
+    pub reconnect_blackout: Option<DailySchedule>,
+
     /// Maximum message size in bytes
     pub max_message_size: usize,
+
+    /// Initial bytes requested per socket read, adapted to the feed after
+    pub read_buffer_size: usize,
+
+    /// Messages read ahead of the one being handled
+    pub read_ahead: usize,
+
+    /// Size in bytes from which a message is forwarded without holding up
+    /// the runtime worker's other tasks, if set
+    pub offload_threshold_bytes: Option<usize>,
+
+    /// Share of the provider's delivery capacity this link may use
+    pub delivery_priority: DeliveryPriority,
+
+    /// Whether the connection runs on the provider's runtime or one of its own
+    pub runtime: ConnectionRuntime,
+
+    /// Optional group name used to stop related connections together
+    pub group: Option<String>,
+
+    /// Subject data is forwarded on, with `{host}`, `{port}` and
+    /// `{source_id}` placeholders
+    pub subject_template: String,
+
+    /// Adjustment of the source ID for `{source_id}` in the subject
+    pub source_id_transform: SourceIdTransform,
+
+    /// Subject messages the component failed to handle are republished on,
+    /// with the same placeholders as `subject_template`
+    pub dead_letter_subject: Option<String>,
+
+    /// Message envelope protocol version requested from the component
+    pub protocol_version: u8,
+
+    /// Envelope serialization format (None forwards raw bytes)
+    pub envelope_format: Option<EnvelopeFormat>,
+
+    /// How long after receipt forwarded data counts as stale in
+    /// milliseconds, stamped on each message (0 disables)
+    pub message_ttl_ms: u64,
+
+    /// Total bytes the link may forward across all reconnects (None for unlimited)
+    pub lifetime_byte_quota: Option<u64>,
+
+    /// Whether to notify the component when the lifetime byte quota is reached
+    pub quota_exceeded_event: bool,
+
+    /// Data messages the link may forward across all reconnects (None for unlimited)
+    pub max_messages: Option<u64>,
+
+    /// Whether to send a summary to the component when `max_messages` is reached
+    pub message_limit_event: bool,
+
+    /// Redundant connection group carrying the same feed as other links
+    pub connection_group: Option<String>,
+
+    /// Role of this link within its connection group
+    pub connection_group_role: GroupRole,
+
+    /// Byte-stuffed framing to de-frame and unescape before forwarding
+    pub unstuff: Option<UnstuffConfig>,
+
+    /// Turn Telnet's `CR NUL` back into a bare carriage return
+    pub unstuff_cr: bool,
+
+    /// Automatic login after connecting, if configured
+    pub login: Option<LoginConfig>,
+
+    /// Environment variables sent via NEW-ENVIRON when the server asks
+    pub environ: Environ,
+
+    /// Data that makes the client drop and re-establish the connection
+    pub reconnect_sentinel: Option<String>,
+
+    /// Whether data up to and including the sentinel is forwarded
+    pub forward_reconnect_sentinel: bool,
+
+    /// Detect lost messages from `x-telnet-seq` header lines
+    pub sequence_gap_detection: bool,
+
+    /// Whether numbered records are delivered in `x-telnet-seq` order
+    pub delivery_ordering: DeliveryOrdering,
+
+    /// How long a missing sequence number is waited for in ordered mode
+    pub ordering_max_wait_ms: u64,
+
+    /// Replace control characters in text records with named tokens
+    pub tokenize_controls: bool,
+
+    /// How control characters are written when tokenized
+    pub control_token_style: ControlTokenStyle,
+
+    /// Keywords of which a message body must contain at least one (empty
+    /// for no requirement)
+    pub body_required_keywords: Vec<String>,
+
+    /// Keywords a message body must not contain
+    pub body_blocked_keywords: Vec<String>,
+
+    /// Components receiving messages by subject, checked in order before
+    /// the linked component
+    pub routing_rules: Vec<RoutingRule>,
+
+    /// How long a forwarded message suppresses identical ones in
+    /// milliseconds (0 disables)
+    pub debounce_window_ms: u64,
+
+    /// Which messages are forwarded on high-rate feeds, if sampled
+    pub sample_rate: Option<SampleRate>,
+
+    /// Field identifying records for deduplication, if enabled
+    pub dedupe_key: Option<String>,
+
+    /// How long a forwarded key value suppresses repeats in milliseconds
+    pub dedupe_window_ms: u64,
+
+    /// Forward only the changes between consecutive JSON object records
+    pub delta_only: bool,
+
+    /// Field identifying records to diff against each other, if any
+    pub delta_key: Option<String>,
+
+    /// Interval between full snapshots in delta mode in milliseconds (0 for
+    /// only the first record)
+    pub delta_snapshot_interval_ms: u64,
+
+    /// Sliding-window numeric summary forwarded instead of readings
+    pub aggregate_numeric: Option<AggregateConfig>,
+
+    /// Forward each read's records as one newline-delimited JSON message
+    pub ndjson: bool,
+
+    /// Provider-computed header lines added to each data message, in order
+    pub enrichment_fields: Vec<EnrichmentField>,
+
+    /// Offset of the byte holding each message's priority level, if any
+    pub priority_byte_offset: Option<usize>,
+
+    /// Priority levels of priority bytes; unmapped bytes are their own level
+    pub priority_byte_map: HashMap<u8, u8>,
+
+    /// Accept MSDP (option 69) and forward its variables as JSON
+    pub msdp_enabled: bool,
+
+    /// Accept MCCP2 compression (option 86) and decompress what is read
+    pub mccp_enabled: bool,
+
+    /// Fail the connection if the server doesn't offer MCCP2 compression
+    pub compression_required: bool,
+
+    /// Log raw socket bytes as hexdumps at TRACE level
+    pub debug_hexdump: bool,
+
+    /// Maximum bytes included in each hexdump
+    pub hexdump_max_bytes: usize,
+
+    /// Interval between component reachability pings in milliseconds (0 disables)
+    pub component_ping_interval_ms: u64,
+
+    /// How long the component must be unreachable before it is considered down
+    pub component_down_threshold_ms: u64,
+
+    /// What to do with the Telnet connection while the component is down
+    pub component_down_action: ComponentDownAction,
+
+    /// Consecutive failed calls after which a component's wRPC client is
+    /// recreated (0 disables)
+    pub component_client_recreate_after: u32,
+
+    /// Reconnect after this long connected without a message or error in
+    /// milliseconds (0 disables)
+    pub watchdog_timeout_ms: u64,
+
+    /// Reconnect after this long without reading any bytes from the socket
+    /// in milliseconds (0 disables)
+    pub idle_reconnect_after_ms: u64,
+
+    /// How long a write to the server may take in milliseconds (0 for no
+    /// limit)
+    pub write_timeout_ms: u64,
+
+    /// Seconds a connection may be idle before TCP keepalive probes are
+    /// sent (0 leaves keepalive off)
+    pub tcp_keepalive_secs: u64,
+
+    /// Seconds between TCP keepalive probes (0 for the OS default)
+    pub tcp_keepalive_interval_secs: u64,
+
+    /// Unanswered TCP keepalive probes before the connection is dropped
+    /// (0 for the OS default)
+    pub tcp_keepalive_retries: u32,
+
+    /// How long sent data may stay unacknowledged before the connection is
+    /// dropped, in milliseconds (0 for the OS default)
+    pub tcp_user_timeout_ms: u64,
+
+    /// How long option negotiation may go on before any data arrives in
+    /// milliseconds (0 disables)
+    pub negotiation_timeout_ms: u64,
+
+    /// What to do when negotiation runs past its timeout
+    pub negotiation_timeout_action: NegotiationTimeoutAction,
+
+    /// Log whether the target is reachable when the link is created
+    pub reachability_probe: bool,
+
+    /// Commands that data sent to the server must not contain
+    pub command_blocklist: Option<CommandBlocklist>,
+
+    /// How long the connection of a deleted link is kept open for a
+    /// recreated link to reuse, in milliseconds (0 closes it at once)
+    pub reuse_grace_ms: u64,
+
+    /// Latency from read to handled above which a message is reported as
+    /// slow, in milliseconds (0 disables)
+    pub slow_consumer_threshold_ms: u64,
+
+    /// Guess the kind of server from its banner when the link is created,
+    /// and fill in settings suited to it that the link leaves unset
+    pub auto_detect_server_type: bool,
+
+    /// Kind of server detected when the link was created, which reloads of
+    /// the link reuse instead of asking the server again
+    pub server_type: Option<ServerType>,
+
+    /// Adjust `max_message_size` to the sizes of the records received
+    pub auto_tune_max_message_size: bool,
+
+    /// Multiple of the 99th percentile record size that auto-tuning sets
+    /// `max_message_size` to
+    pub auto_tune_max_factor: f64,
+
+    /// How long a resolution of `telnet_host` is reused by reconnection
+    /// attempts, in milliseconds (0 resolves for every attempt)
+    pub dns_cache_ms: u64,
+
+    /// JSON or TOML file whose values override the link config, reloaded
+    /// whenever it changes
+    pub config_file: Option<String>,
+
+    /// Directory where the raw bytes of each connection are recorded
+    pub session_record_dir: Option<String>,
+
+    /// Compress session recordings with zstd
+    pub session_record_compress: bool,
+
+    /// Values set explicitly for this link, for the effective config export
+    pub provided: ProvidedValues,
 }
 
 impl LinkConfig {
@@ -48,10 +648,25 @@ impl LinkConfig {
             .ok_or_else(|| anyhow::anyhow!("Missing required config: telnet_host"))?
             .clone();
 
-        let telnet_port = config
-            .get("telnet_port")
-            .and_then(|v| v.parse().ok())
-            .unwrap_or(23);
+        let telnet_port = match config.get("telnet_port") {
+            Some(v) => match v.parse::<u16>() {
+                Ok(port) if port != 0 => port,
+                _ => return Err(ConfigError::InvalidPort(v.clone()).into()),
+            },
+            None => 23,
+        };
+        if telnet_port < 1024 && telnet_port != 23 {
+            warn!(
+                "Connecting to privileged port {} — ensure this is intentional",
+                telnet_port
+            );
+        }
+
+        let ip_family = config
+            .get("ip_family")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or_default();
 
         let max_reconnect_attempts = config
             .get("max_reconnect_attempts")
@@ -68,21 +683,527 @@ impl LinkConfig {
             .and_then(|v| v.parse().ok())
             .unwrap_or(60000);
 
+        let backoff_jitter_mode = config
+            .get("backoff_jitter_mode")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or_default();
+
+        let reconnect_blackout = config
+            .get("reconnect_blackout")
+            .filter(|v| !v.is_empty())
+            .map(|v| v.parse())
+            .transpose()?;
+
         let max_message_size = config
             .get("max_message_size")
             .and_then(|v| v.parse().ok())
             .unwrap_or(1024 * 1024);
 
+        let read_buffer_size = config
+            .get("read_buffer_size")
+            .and_then(|v| v.parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(4096);
+
+        let read_ahead = config
+            .get("read_ahead")
+            .and_then(|v| v.parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(4);
+
+        let offload_threshold_bytes = config
+            .get("offload_threshold_bytes")
+            .and_then(|v| v.parse().ok())
+            .filter(|v| *v > 0);
+
+        let delivery_priority = config
+            .get("delivery_priority")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or_default();
+
+        let runtime = config
+            .get("runtime")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or_default();
+
+        let group = config.get("group").filter(|v| !v.is_empty()).cloned();
+
+        let envelope_format = config
+            .get("envelope_format")
+            .map(|v| v.parse())
+            .transpose()?;
+
+        let subject_template = config
+            .get("subject_template")
+            .filter(|v| !v.is_empty())
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_SUBJECT_TEMPLATE.to_string());
+
+        let source_id_transform = config
+            .get("source_id_transform")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or_default();
+
+        let dead_letter_subject = config
+            .get("dead_letter_subject")
+            .filter(|v| !v.is_empty())
+            .cloned();
+
+        let protocol_version = config
+            .get("protocol_version")
+            .and_then(|v| v.parse().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(1);
+        // Envelopes require protocol version 2, which has to be asked for
+        if let Some(format) = envelope_format.filter(|_| protocol_version < 2) {
+            return Err(ConfigError::EnvelopeWithoutVersion(format).into());
+        }
+
+        let message_ttl_ms = config
+            .get("message_ttl_ms")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        let lifetime_byte_quota = config
+            .get("lifetime_byte_quota")
+            .and_then(|v| v.parse().ok())
+            .filter(|v| *v > 0);
+
+        let quota_exceeded_event = config
+            .get("quota_exceeded_event")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+
+        let max_messages = config
+            .get("max_messages")
+            .and_then(|v| v.parse().ok())
+            .filter(|v| *v > 0);
+
+        let message_limit_event = config
+            .get("message_limit_event")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+
+        let connection_group = config
+            .get("connection_group")
+            .filter(|v| !v.is_empty())
+            .cloned();
+
+        let connection_group_role = config
+            .get("connection_group_role")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or_default();
+
+        let unstuff = config.get("unstuff").map(|v| v.parse()).transpose()?;
+
+        let unstuff_cr = config
+            .get("unstuff_cr")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+
+        let login = LoginConfig::from_values(config)?;
+        if let Some(length) = login
+            .as_ref()
+            .and_then(LoginConfig::longest_read)
+            .filter(|length| *length > max_message_size)
+        {
+            return Err(ConfigError::LoginLengthTooLarge {
+                length,
+                max: max_message_size,
+            }
+            .into());
+        }
+
+        let environ = Environ::from_values(config);
+
+        let reconnect_sentinel = config
+            .get("reconnect_sentinel")
+            .filter(|v| !v.is_empty())
+            .cloned();
+
+        let forward_reconnect_sentinel = config
+            .get("forward_reconnect_sentinel")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+
+        let sequence_gap_detection = config
+            .get("sequence_gap_detection")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+
+        let delivery_ordering = config
+            .get("delivery_ordering")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or_default();
+
+        let ordering_max_wait_ms = config
+            .get("ordering_max_wait_ms")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1000);
+
+        let tokenize_controls = config
+            .get("tokenize_controls")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+
+        let control_token_style = config
+            .get("control_token_style")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or_default();
+
+        let body_required_keywords = config
+            .get("body_required_keywords")
+            .map(|v| keyword_list(v))
+            .unwrap_or_default();
+
+        let body_blocked_keywords = config
+            .get("body_blocked_keywords")
+            .map(|v| keyword_list(v))
+            .unwrap_or_default();
+
+        let routing_rules = config
+            .get("routing_rules")
+            .map(|v| parse_routing_rules(v))
+            .transpose()?
+            .unwrap_or_default();
+
+        let debounce_window_ms = config
+            .get("debounce_window_ms")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        let sample_rate = config
+            .get("sample_rate")
+            .filter(|v| !v.is_empty())
+            .map(|v| v.parse())
+            .transpose()?;
+
+        let dedupe_key = config.get("dedupe_key").filter(|v| !v.is_empty()).cloned();
+
+        let dedupe_window_ms = config
+            .get("dedupe_window_ms")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1000);
+
+        let delta_only = config
+            .get("delta_only")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+
+        let delta_key = config.get("delta_key").filter(|v| !v.is_empty()).cloned();
+
+        let delta_snapshot_interval_ms = config
+            .get("delta_snapshot_interval_ms")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60000);
+
+        let aggregate_numeric = AggregateConfig::from_values(config)?;
+
+        let ndjson = config
+            .get("ndjson")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+        match envelope_format {
+            Some(format @ (EnvelopeFormat::Msgpack | EnvelopeFormat::Cbor)) if ndjson => {
+                return Err(ConfigError::NdjsonBinaryEnvelope(format).into());
+            }
+            _ => {}
+        }
+
+        let enrichment_fields = config
+            .get("enrichment_fields")
+            .map(|v| parse_enrichment_fields(v))
+            .transpose()?
+            .unwrap_or_default();
+
+        let priority_byte_offset = config
+            .get("priority_byte_offset")
+            .and_then(|v| v.parse().ok());
+
+        let priority_byte_map = config
+            .get("priority_byte_map")
+            .map(|v| parse_priority_byte_map(v))
+            .transpose()?
+            .unwrap_or_default();
+
+        let msdp_enabled = config
+            .get("msdp_enabled")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+
+        let compression_required = config
+            .get("compression_required")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+
+        // Requiring compression implies accepting it
+        let mccp_enabled = compression_required
+            || config
+                .get("mccp_enabled")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(false);
+
+        let debug_hexdump = config
+            .get("debug_hexdump")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+
+        let hexdump_max_bytes = config
+            .get("hexdump_max_bytes")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(256);
+
+        let component_ping_interval_ms = config
+            .get("component_ping_interval_ms")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        let component_down_threshold_ms = config
+            .get("component_down_threshold_ms")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30000);
+
+        let component_client_recreate_after = config
+            .get("component_client_recreate_after")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        let watchdog_timeout_ms = config
+            .get("watchdog_timeout_ms")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        let idle_reconnect_after_ms = config
+            .get("idle_reconnect_after_ms")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        let write_timeout_ms = config
+            .get("write_timeout_ms")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10000);
+
+        let tcp_keepalive_secs = config
+            .get("tcp_keepalive_secs")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        let tcp_keepalive_interval_secs = config
+            .get("tcp_keepalive_interval_secs")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        let tcp_keepalive_retries = config
+            .get("tcp_keepalive_retries")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        let tcp_user_timeout_ms = config
+            .get("tcp_user_timeout_ms")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        let negotiation_timeout_ms = config
+            .get("negotiation_timeout_ms")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        let negotiation_timeout_action = config
+            .get("negotiation_timeout_action")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or_default();
+
+        let command_blocklist = config
+            .get("command_blocklist")
+            .map(|v| CommandBlocklist::new(&keyword_list(v)))
+            .transpose()?
+            .flatten();
+
+        let config_file = config.get("config_file").filter(|v| !v.is_empty()).cloned();
+
+        let session_record_dir = config
+            .get("session_record_dir")
+            .filter(|v| !v.is_empty())
+            .cloned();
+
+        let session_record_compress = config
+            .get("session_record_compress")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+
+        let reachability_probe = config
+            .get("reachability_probe")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(true);
+
+        let reuse_grace_ms = config
+            .get("reuse_grace_ms")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        let slow_consumer_threshold_ms = config
+            .get("slow_consumer_threshold_ms")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        let auto_detect_server_type = config
+            .get("auto_detect_server_type")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+
+        let auto_tune_max_message_size = config
+            .get("auto_tune_max_message_size")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false);
+
+        // Below 1 the limit would drop records as large as the percentile
+        let auto_tune_max_factor = config
+            .get("auto_tune_max_factor")
+            .and_then(|v| v.parse().ok())
+            .filter(|v: &f64| *v >= 1.0)
+            .unwrap_or(2.0);
+
+        let dns_cache_ms = config
+            .get("dns_cache_ms")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5000);
+
+        let component_down_action = config
+            .get("component_down_action")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or_default();
+
         Ok(Self {
             telnet_host,
             telnet_port,
+            ip_family,
             max_reconnect_attempts,
             initial_reconnect_delay_ms,
             max_reconnect_delay_ms,
+            backoff_jitter_mode,
+            reconnect_blackout,
             max_message_size,
+            read_buffer_size,
+            read_ahead,
+            offload_threshold_bytes,
+            delivery_priority,
+            runtime,
+            group,
+            subject_template,
+            source_id_transform,
+            dead_letter_subject,
+            protocol_version,
+            envelope_format,
+            message_ttl_ms,
+            lifetime_byte_quota,
+            quota_exceeded_event,
+            max_messages,
+            message_limit_event,
+            connection_group,
+            connection_group_role,
+            unstuff,
+            unstuff_cr,
+            login,
+            environ,
+            reconnect_sentinel,
+            forward_reconnect_sentinel,
+            sequence_gap_detection,
+            delivery_ordering,
+            ordering_max_wait_ms,
+            tokenize_controls,
+            control_token_style,
+            body_required_keywords,
+            body_blocked_keywords,
+            routing_rules,
+            debounce_window_ms,
+            sample_rate,
+            dedupe_key,
+            dedupe_window_ms,
+            delta_only,
+            delta_key,
+            delta_snapshot_interval_ms,
+            aggregate_numeric,
+            ndjson,
+            enrichment_fields,
+            priority_byte_offset,
+            priority_byte_map,
+            msdp_enabled,
+            mccp_enabled,
+            compression_required,
+            debug_hexdump,
+            hexdump_max_bytes,
+            component_ping_interval_ms,
+            component_down_threshold_ms,
+            component_down_action,
+            component_client_recreate_after,
+            watchdog_timeout_ms,
+            idle_reconnect_after_ms,
+            write_timeout_ms,
+            tcp_keepalive_secs,
+            tcp_keepalive_interval_secs,
+            tcp_keepalive_retries,
+            tcp_user_timeout_ms,
+            negotiation_timeout_ms,
+            negotiation_timeout_action,
+            reachability_probe,
+            command_blocklist,
+            reuse_grace_ms,
+            slow_consumer_threshold_ms,
+            auto_detect_server_type,
+            server_type: None,
+            auto_tune_max_message_size,
+            auto_tune_max_factor,
+            dns_cache_ms,
+            config_file,
+            session_record_dir,
+            session_record_compress,
+            provided: ProvidedValues::from_config(config),
         })
     }
 
+    /// Keys whose effective values differ between two configs of a link,
+    /// including keys set in only one of them
+    ///
+    /// Values are compared as set, so a changed password is detected, but
+    /// sensitive values are redacted in the changes returned.
+    pub fn diff(old: &LinkConfig, new: &LinkConfig) -> Vec<ConfigChange> {
+        let by_key = |config: &LinkConfig| -> BTreeMap<String, EffectiveValue> {
+            config
+                .provided
+                .actual()
+                .into_iter()
+                .map(|value| (value.key.clone(), value))
+                .collect()
+        };
+        let (old_values, new_values) = (by_key(old), by_key(new));
+        let keys: BTreeSet<&String> = old_values.keys().chain(new_values.keys()).collect();
+        keys.into_iter()
+            .filter_map(|key| {
+                let (old, new) = (old_values.get(key), new_values.get(key));
+                if old.map(|v| &v.value) == new.map(|v| &v.value) {
+                    return None;
+                }
+                let shown = |value: Option<&EffectiveValue>| {
+                    value.map(|v| v.shown().to_string()).unwrap_or_default()
+                };
+                Some(ConfigChange {
+                    key: key.clone(),
+                    old: shown(old),
+                    new: shown(new),
+                })
+            })
+            .collect()
+    }
+
     /// Get the initial reconnection delay as Duration
     pub fn initial_reconnect_delay(&self) -> Duration {
         Duration::from_millis(self.initial_reconnect_delay_ms)
@@ -93,8 +1214,324 @@ impl LinkConfig {
         Duration::from_millis(self.max_reconnect_delay_ms)
     }
 
+    /// Get the component ping interval as Duration
+    pub fn component_ping_interval(&self) -> Duration {
+        Duration::from_millis(self.component_ping_interval_ms)
+    }
+
+    /// Get the component down threshold as Duration
+    pub fn component_down_threshold(&self) -> Duration {
+        Duration::from_millis(self.component_down_threshold_ms)
+    }
+
+    /// Get the message TTL as Duration, if expiry stamping is enabled
+    pub fn message_ttl(&self) -> Option<Duration> {
+        (self.message_ttl_ms > 0).then(|| Duration::from_millis(self.message_ttl_ms))
+    }
+
+    /// Get the debounce window as Duration, if debouncing is enabled
+    pub fn debounce_window(&self) -> Option<Duration> {
+        (self.debounce_window_ms > 0).then(|| Duration::from_millis(self.debounce_window_ms))
+    }
+
+    /// Get the ordered-mode wait for missing sequence numbers as Duration
+    pub fn ordering_max_wait(&self) -> Duration {
+        Duration::from_millis(self.ordering_max_wait_ms)
+    }
+
+    /// Get the deduplication window as Duration
+    pub fn dedupe_window(&self) -> Duration {
+        Duration::from_millis(self.dedupe_window_ms)
+    }
+
+    /// Get the delta snapshot interval as Duration, if snapshots repeat
+    pub fn delta_snapshot_interval(&self) -> Option<Duration> {
+        (self.delta_snapshot_interval_ms > 0)
+            .then(|| Duration::from_millis(self.delta_snapshot_interval_ms))
+    }
+
+    /// Get the write timeout as Duration, if writes are limited
+    pub fn write_timeout(&self) -> Option<Duration> {
+        (self.write_timeout_ms > 0).then(|| Duration::from_millis(self.write_timeout_ms))
+    }
+
+    /// Get the reuse grace window as Duration, if deleted links are kept open
+    pub fn reuse_grace(&self) -> Option<Duration> {
+        (self.reuse_grace_ms > 0).then(|| Duration::from_millis(self.reuse_grace_ms))
+    }
+
+    /// Get how long a resolution of the host is reused as Duration
+    pub fn dns_cache(&self) -> Duration {
+        Duration::from_millis(self.dns_cache_ms)
+    }
+
+    /// Get the slow consumer threshold as Duration, if slow deliveries are
+    /// reported
+    pub fn slow_consumer_threshold(&self) -> Option<Duration> {
+        (self.slow_consumer_threshold_ms > 0)
+            .then(|| Duration::from_millis(self.slow_consumer_threshold_ms))
+    }
+
+    /// Get the negotiation timeout as Duration, if negotiation is limited
+    pub fn negotiation_timeout(&self) -> Option<Duration> {
+        (self.negotiation_timeout_ms > 0)
+            .then(|| Duration::from_millis(self.negotiation_timeout_ms))
+    }
+
+    /// Get the stall watchdog timeout as Duration
+    pub fn watchdog_timeout(&self) -> Duration {
+        Duration::from_millis(self.watchdog_timeout_ms)
+    }
+
+    /// Get the idle reconnect timeout as Duration, if enabled
+    pub fn idle_reconnect_after(&self) -> Option<Duration> {
+        (self.idle_reconnect_after_ms > 0)
+            .then(|| Duration::from_millis(self.idle_reconnect_after_ms))
+    }
+
     /// Get the full address string
     pub fn address(&self) -> String {
         format!("{}:{}", self.telnet_host, self.telnet_port)
     }
+
+    /// Get the subject data from this link is forwarded on to `source_id`
+    pub fn subject(&self, source_id: &str) -> String {
+        subject::render(
+            &self.subject_template,
+            &self.telnet_host,
+            self.telnet_port,
+            source_id,
+            &self.source_id_transform,
+        )
+    }
+
+    /// Get the subject messages to `source_id` are dead-lettered on, if any
+    pub fn dead_letter_subject(&self, source_id: &str) -> Option<String> {
+        self.dead_letter_subject.as_ref().map(|template| {
+            subject::render(
+                template,
+                &self.telnet_host,
+                self.telnet_port,
+                source_id,
+                &self.source_id_transform,
+            )
+        })
+    }
+}
+
+/// Comma-separated keywords, trimmed, without empty entries
+fn keyword_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|keyword| !keyword.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    fn config(values: &[(&str, &str)]) -> LinkConfig {
+        let mut values: HashMap<String, String> = values
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        values.insert("telnet_host".to_string(), "localhost".to_string());
+        LinkConfig::from_values(&values).unwrap()
+    }
+
+    /// Parse a link to `port`, returning the result and what was logged
+    fn with_port(port: &str) -> (anyhow::Result<LinkConfig>, String) {
+        let logged = Arc::new(Mutex::new(Vec::new()));
+        let writer = logged.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || LogWriter(writer.clone()))
+            .with_ansi(false)
+            .without_time()
+            .finish();
+        let values = HashMap::from([
+            ("telnet_host".to_string(), "localhost".to_string()),
+            ("telnet_port".to_string(), port.to_string()),
+        ]);
+        let result =
+            tracing::subscriber::with_default(subscriber, || LinkConfig::from_values(&values));
+        let logged = String::from_utf8(logged.lock().unwrap().clone()).unwrap();
+        (result, logged)
+    }
+
+    /// Appends log output to a shared buffer
+    struct LogWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn rejects_ports_outside_1_to_65535() {
+        for port in ["0", "65536", "-1", "telnet", ""] {
+            let (result, _) = with_port(port);
+            let e = result.unwrap_err();
+            assert!(
+                matches!(e.downcast_ref(), Some(ConfigError::InvalidPort(value)) if value == port),
+                "{:?} gave {:#}",
+                port,
+                e
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_the_whole_port_range() {
+        for port in [1, 2323, 65535] {
+            let (result, _) = with_port(&port.to_string());
+            assert_eq!(result.unwrap().telnet_port, port);
+        }
+        // And defaults to the Telnet port
+        assert_eq!(config(&[]).telnet_port, 23);
+    }
+
+    #[test]
+    fn warns_about_privileged_ports_other_than_telnet() {
+        let (result, logged) = with_port("22");
+        assert_eq!(result.unwrap().telnet_port, 22);
+        assert!(
+            logged.contains("WARN") && logged.contains("privileged port 22"),
+            "{}",
+            logged
+        );
+
+        for port in ["23", "1024"] {
+            let (result, logged) = with_port(port);
+            assert!(result.is_ok());
+            assert!(logged.is_empty(), "{}: {}", port, logged);
+        }
+    }
+
+    fn changed_keys(old: &LinkConfig, new: &LinkConfig) -> Vec<String> {
+        LinkConfig::diff(old, new)
+            .into_iter()
+            .map(|change| change.key)
+            .collect()
+    }
+
+    #[test]
+    fn diff_is_empty_for_equal_configs() {
+        let old = config(&[("max_message_size", "1024"), ("environ.USER", "me")]);
+        assert!(LinkConfig::diff(&old, &old.clone()).is_empty());
+    }
+
+    #[test]
+    fn diff_reports_live_and_disruptive_changes() {
+        let old = config(&[("max_message_size", "1024")]);
+        let changes = LinkConfig::diff(&old, &config(&[("max_message_size", "2048")]));
+        assert_eq!(
+            changes,
+            [ConfigChange {
+                key: "max_message_size".to_string(),
+                old: "1024".to_string(),
+                new: "2048".to_string(),
+            }]
+        );
+        assert!(!changes[0].is_disruptive());
+
+        let changes = LinkConfig::diff(
+            &old,
+            &config(&[("max_message_size", "1024"), ("telnet_port", "2323")]),
+        );
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0].is_disruptive());
+    }
+
+    #[test]
+    fn diff_detects_a_rotated_password_without_revealing_it() {
+        let old = config(&[("login_username", "admin"), ("login_password", "old")]);
+        let new = config(&[("login_username", "admin"), ("login_password", "new")]);
+        assert_eq!(
+            LinkConfig::diff(&old, &new),
+            [ConfigChange {
+                key: "login_password".to_string(),
+                old: "<redacted>".to_string(),
+                new: "<redacted>".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn diff_detects_added_and_removed_keys() {
+        let without = config(&[]);
+        let with = config(&[("environ.USER", "operator")]);
+        assert_eq!(changed_keys(&without, &with), ["environ.USER"]);
+        assert_eq!(changed_keys(&with, &without), ["environ.USER"]);
+        let change = &LinkConfig::diff(&with, &without)[0];
+        assert_eq!(change.new, "");
+        assert!(change.is_disruptive());
+    }
+
+    #[test]
+    fn envelopes_need_protocol_version_2_asked_for() {
+        assert_eq!(config(&[]).protocol_version, 1);
+        let config = config(&[("envelope_format", "json"), ("protocol_version", "2")]);
+        assert_eq!(config.envelope_format, Some(EnvelopeFormat::Json));
+
+        for version in [None, Some("1")] {
+            let mut values = HashMap::from([
+                ("telnet_host".to_string(), "localhost".to_string()),
+                ("envelope_format".to_string(), "cbor".to_string()),
+            ]);
+            if let Some(version) = version {
+                values.insert("protocol_version".to_string(), version.to_string());
+            }
+            let e = LinkConfig::from_values(&values).unwrap_err();
+            assert!(matches!(
+                e.downcast_ref(),
+                Some(ConfigError::EnvelopeWithoutVersion(EnvelopeFormat::Cbor))
+            ));
+        }
+    }
+
+    #[test]
+    fn login_lengths_must_fit_a_message() {
+        let config = config(&[
+            ("login_username", "admin"),
+            ("login_prompt", "#len:4"),
+            ("max_message_size", "4"),
+        ]);
+        assert_eq!(
+            config.login.unwrap().login_prompt,
+            crate::login::Expect::Bytes(4)
+        );
+
+        for (prompt, max) in [("#len:0", "1024"), ("#len:x", "1024"), ("#len:5", "4")] {
+            let values = HashMap::from([
+                ("telnet_host".to_string(), "localhost".to_string()),
+                ("login_username".to_string(), "admin".to_string()),
+                ("password_prompt".to_string(), prompt.to_string()),
+                ("login_password".to_string(), "secret".to_string()),
+                ("max_message_size".to_string(), max.to_string()),
+            ]);
+            assert!(LinkConfig::from_values(&values).is_err(), "{}", prompt);
+        }
+        let values = HashMap::from([
+            ("telnet_host".to_string(), "localhost".to_string()),
+            ("login_username".to_string(), "admin".to_string()),
+            ("login_prompt".to_string(), "#len:5".to_string()),
+            ("max_message_size".to_string(), "4".to_string()),
+        ]);
+        let e = LinkConfig::from_values(&values).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref(),
+            Some(ConfigError::LoginLengthTooLarge { length: 5, max: 4 })
+        ));
+    }
 }
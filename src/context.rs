@@ -0,0 +1,91 @@
+//! Identifiers and component client of one connection, set up once per link
+//!
+//! Every message from a connection carries the same subject and goes to the
+//! same component, so these are shared rather than rebuilt per message.
+
+use std::sync::Arc;
+
+use crate::client::ComponentClient;
+
+/// Component, address and subjects of one connection
+#[derive(Clone)]
+pub struct ConnectionContext {
+    /// Component receiving the connection's messages
+    pub source_id: Arc<str>,
+    /// Telnet server address ("<host>:<port>")
+    pub address: Arc<str>,
+    /// Subject of data messages (`subject_template`, by default
+    /// "telnet.<host>:<port>")
+    pub subject: Arc<str>,
+    /// Subject of sequence gap events
+    pub gap_subject: Arc<str>,
+    /// Subject of the quota exceeded event
+    pub quota_exceeded_subject: Arc<str>,
+    /// Subject of the message limit summary
+    pub message_limit_subject: Arc<str>,
+    /// Subject of lines numeric aggregation couldn't read
+    pub unparsed_subject: Arc<str>,
+    /// Subject of MSDP variables
+    pub msdp_subject: Arc<str>,
+    /// wRPC client for the component, shared by all deliveries
+    pub client: Arc<ComponentClient>,
+}
+
+impl ConnectionContext {
+    /// Context of the connection to `address` serving `source_id`, with
+    /// data forwarded on `subject`
+    pub fn new(source_id: &str, address: &str, subject: &str) -> Self {
+        let source_id: Arc<str> = source_id.into();
+        Self {
+            client: Arc::new(ComponentClient::new(source_id.clone())),
+            source_id,
+            address: address.into(),
+            gap_subject: format!("{}.gap", subject).into(),
+            quota_exceeded_subject: format!("{}.quota_exceeded", subject).into(),
+            message_limit_subject: format!("{}.message_limit_reached", subject).into(),
+            unparsed_subject: format!("{}.unparsed", subject).into(),
+            msdp_subject: format!("{}.msdp", subject).into(),
+            subject: subject.into(),
+        }
+    }
+
+    /// Deliver to the component through `client`, set up for `source_id`
+    pub fn with_client(mut self, client: ComponentClient) -> Self {
+        self.client = Arc::new(client);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_event_subjects_from_the_data_subject() {
+        let context = ConnectionContext::new("weather", "localhost:23", "feeds.weather");
+        assert_eq!(&*context.source_id, "weather");
+        assert_eq!(&*context.address, "localhost:23");
+        assert_eq!(&*context.subject, "feeds.weather");
+        assert_eq!(&*context.gap_subject, "feeds.weather.gap");
+        assert_eq!(
+            &*context.quota_exceeded_subject,
+            "feeds.weather.quota_exceeded"
+        );
+        assert_eq!(
+            &*context.message_limit_subject,
+            "feeds.weather.message_limit_reached"
+        );
+        assert_eq!(&*context.unparsed_subject, "feeds.weather.unparsed");
+        assert_eq!(&*context.msdp_subject, "feeds.weather.msdp");
+    }
+
+    #[test]
+    fn clones_share_the_subjects_and_client() {
+        let context = ConnectionContext::new("weather", "localhost:23", "feeds.weather");
+        let clone = context.clone();
+        assert!(Arc::ptr_eq(&context.subject, &clone.subject));
+        assert!(Arc::ptr_eq(&context.msdp_subject, &clone.msdp_subject));
+        assert!(Arc::ptr_eq(&context.source_id, &clone.source_id));
+        assert!(Arc::ptr_eq(&context.client, &clone.client));
+    }
+}
@@ -0,0 +1,160 @@
+//! Named tokens for control characters in text feeds
+//!
+//! Terminal-oriented feeds carry control characters (bells, backspaces,
+//! escape sequences) that are invisible or garble the output once a
+//! log-consuming component stores them. With `tokenize_controls` set, each
+//! C0 control character and DEL in a text record is replaced by a readable
+//! token such as `<BEL>`, in the style set by `control_token_style`. Tabs
+//! and line breaks (HT, LF, CR) are kept as they are, and records that
+//! aren't valid UTF-8 are forwarded unchanged, so binary feeds are
+//! unaffected.
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// ASCII names of the C0 control characters, by byte value
+const NAMES: [&str; 32] = [
+    "NUL", "SOH", "STX", "ETX", "EOT", "ENQ", "ACK", "BEL", "BS", "HT", "LF", "VT", "FF", "CR",
+    "SO", "SI", "DLE", "DC1", "DC2", "DC3", "DC4", "NAK", "SYN", "ETB", "CAN", "EM", "SUB", "ESC",
+    "FS", "GS", "RS", "US",
+];
+
+/// How control characters are written
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ControlTokenStyle {
+    /// ASCII name in angle brackets, e.g. `<BEL>`
+    #[default]
+    Angle,
+    /// Caret notation, e.g. `^G`
+    Caret,
+    /// Escaped hex byte, e.g. `\x07`
+    Hex,
+}
+
+impl std::str::FromStr for ControlTokenStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "angle" => Ok(ControlTokenStyle::Angle),
+            "caret" => Ok(ControlTokenStyle::Caret),
+            "hex" => Ok(ControlTokenStyle::Hex),
+            other => Err(anyhow::anyhow!("Invalid control_token_style: {}", other)),
+        }
+    }
+}
+
+/// Replaces control characters in text records with tokens
+pub struct ControlTokenizer {
+    style: ControlTokenStyle,
+}
+
+impl ControlTokenizer {
+    pub fn new(style: ControlTokenStyle) -> Self {
+        Self { style }
+    }
+
+    /// `data` with its control characters replaced, or unchanged if it has
+    /// none or isn't text
+    pub fn tokenize(&self, data: Bytes) -> Bytes {
+        let controls = data.iter().filter(|&&byte| is_tokenized(byte)).count();
+        if controls == 0 || std::str::from_utf8(&data).is_err() {
+            return data;
+        }
+
+        let mut tokenized = BytesMut::with_capacity(data.len() + controls * 4);
+        for &byte in data.iter() {
+            if is_tokenized(byte) {
+                self.put_token(byte, &mut tokenized);
+            } else {
+                tokenized.put_u8(byte);
+            }
+        }
+        tokenized.freeze()
+    }
+
+    fn put_token(&self, byte: u8, out: &mut BytesMut) {
+        match self.style {
+            ControlTokenStyle::Angle => {
+                let name = NAMES.get(byte as usize).copied().unwrap_or("DEL");
+                out.put_u8(b'<');
+                out.put_slice(name.as_bytes());
+                out.put_u8(b'>');
+            }
+            // DEL is `^?`, the others are `^@` to `^_`
+            ControlTokenStyle::Caret => out.put_slice(&[b'^', byte ^ 0x40]),
+            ControlTokenStyle::Hex => out.put_slice(format!("\\x{:02X}", byte).as_bytes()),
+        }
+    }
+}
+
+/// Whether `byte` is replaced: C0 controls other than HT, LF and CR, and DEL
+fn is_tokenized(byte: u8) -> bool {
+    (byte < 0x20 && !matches!(byte, b'\t' | b'\n' | b'\r')) || byte == 0x7F
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokenize(style: ControlTokenStyle, data: &'static [u8]) -> Bytes {
+        ControlTokenizer::new(style).tokenize(Bytes::from_static(data))
+    }
+
+    #[test]
+    fn parses_each_style_case_insensitively() {
+        assert_eq!(
+            "angle".parse::<ControlTokenStyle>().unwrap(),
+            ControlTokenStyle::Angle
+        );
+        assert_eq!(
+            "Caret".parse::<ControlTokenStyle>().unwrap(),
+            ControlTokenStyle::Caret
+        );
+        assert_eq!(
+            "HEX".parse::<ControlTokenStyle>().unwrap(),
+            ControlTokenStyle::Hex
+        );
+        assert!("octal".parse::<ControlTokenStyle>().is_err());
+        assert_eq!(ControlTokenStyle::default(), ControlTokenStyle::Angle);
+    }
+
+    #[test]
+    fn names_every_control_character_in_each_style() {
+        let data = b"\x00a\x07b\x08c\x1B[0m\x1F\x7F";
+        assert_eq!(
+            tokenize(ControlTokenStyle::Angle, data),
+            &b"<NUL>a<BEL>b<BS>c<ESC>[0m<US><DEL>"[..]
+        );
+        assert_eq!(
+            tokenize(ControlTokenStyle::Caret, data),
+            &b"^@a^Gb^Hc^[[0m^_^?"[..]
+        );
+        assert_eq!(
+            tokenize(ControlTokenStyle::Hex, data),
+            &b"\\x00a\\x07b\\x08c\\x1B[0m\\x1F\\x7F"[..]
+        );
+    }
+
+    #[test]
+    fn keeps_tabs_and_line_breaks() {
+        let data = b"name\tvalue\r\nnext\n";
+        for style in [
+            ControlTokenStyle::Angle,
+            ControlTokenStyle::Caret,
+            ControlTokenStyle::Hex,
+        ] {
+            assert_eq!(tokenize(style, data), &data[..]);
+        }
+    }
+
+    #[test]
+    fn forwards_records_without_controls_or_text_unchanged() {
+        let plain = Bytes::from_static("caf\u{e9} \u{2603}".as_bytes());
+        let tokenized = ControlTokenizer::new(ControlTokenStyle::Angle).tokenize(plain.clone());
+        // Returned as is, not copied
+        assert_eq!(tokenized.as_ptr(), plain.as_ptr());
+
+        let binary = b"\xFF\xFE\x07\x00";
+        assert_eq!(tokenize(ControlTokenStyle::Angle, binary), &binary[..]);
+    }
+}
@@ -0,0 +1,56 @@
+//! Suppression of identical messages repeated in quick succession
+//!
+//! Some equipment repeats the same status line every few hundred
+//! milliseconds for as long as a condition lasts. With `debounce_window_ms`
+//! set, a message whose content is identical to one forwarded less than the
+//! window ago is dropped; once the window has passed, the next occurrence is
+//! forwarded again, so a persisting condition is still reported once per
+//! window. Unlike key deduplication this compares whole messages, and needs
+//! no structure in them.
+
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::time::{Duration, Instant};
+
+/// Number of tracked messages above which expired ones are pruned
+const PRUNE_THRESHOLD: usize = 4096;
+
+/// Tracks when each distinct message was last forwarded on a connection
+#[derive(Debug)]
+pub struct Debouncer {
+    window: Duration,
+    /// Content hash to the time that content was last forwarded
+    last_forwarded: HashMap<u64, Instant>,
+}
+
+impl Debouncer {
+    /// Suppress repeats within `window`
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            last_forwarded: HashMap::new(),
+        }
+    }
+
+    /// Whether the message repeats one forwarded within the window;
+    /// otherwise it is recorded as forwarded at `now`
+    pub fn is_repeat(&mut self, data: &[u8], now: Instant) -> bool {
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        if let Some(forwarded) = self.last_forwarded.get(&hash) {
+            if now.duration_since(*forwarded) < self.window {
+                return true;
+            }
+        }
+
+        if self.last_forwarded.len() >= PRUNE_THRESHOLD {
+            let window = self.window;
+            self.last_forwarded
+                .retain(|_, forwarded| now.duration_since(*forwarded) < window);
+        }
+        self.last_forwarded.insert(hash, now);
+        false
+    }
+}
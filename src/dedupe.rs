@@ -0,0 +1,158 @@
+//! Suppression of repeated records by a key field
+//!
+//! With `dedupe_key` set, each record's value for that field is looked up
+//! (a top-level field of a JSON object record, or otherwise a leading
+//! `name: value` header line). A record whose key value was already
+//! forwarded within `dedupe_window_ms` is dropped, so e.g. repeated status
+//! reports for the same device ID reach the component at most once per
+//! window. Records without the field are always forwarded.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Number of tracked key values above which expired ones are pruned
+const PRUNE_THRESHOLD: usize = 4096;
+
+/// Tracks when each key value was last forwarded on a connection
+#[derive(Debug)]
+pub struct KeyDeduplicator {
+    key: String,
+    window: Duration,
+    last_seen: HashMap<String, Instant>,
+}
+
+impl KeyDeduplicator {
+    /// Deduplicate on the field named `key` within `window`
+    pub fn new(key: String, window: Duration) -> Self {
+        Self {
+            key,
+            window,
+            last_seen: HashMap::new(),
+        }
+    }
+
+    /// Whether the record is a repeat to suppress; otherwise its key value
+    /// (if any) is recorded as forwarded now
+    pub fn is_duplicate(&mut self, data: &[u8]) -> bool {
+        self.is_duplicate_at(data, Instant::now())
+    }
+
+    /// Like [`is_duplicate`](Self::is_duplicate), for a record received at
+    /// `now`
+    fn is_duplicate_at(&mut self, data: &[u8], now: Instant) -> bool {
+        let Some(value) = extract_key(data, &self.key) else {
+            return false;
+        };
+
+        if let Some(seen) = self.last_seen.get(&value) {
+            if now.duration_since(*seen) < self.window {
+                return true;
+            }
+        }
+
+        if self.last_seen.len() >= PRUNE_THRESHOLD {
+            let window = self.window;
+            self.last_seen
+                .retain(|_, seen| now.duration_since(*seen) < window);
+        }
+        self.last_seen.insert(value, now);
+        false
+    }
+}
+
+/// The value of field `key` in a record
+///
+/// JSON object records are looked up by top-level field (strings as-is,
+/// other values as JSON text). Other records are looked up by header line
+/// (`key: value`, case-insensitive name) up to the first blank line.
+pub fn extract_key(data: &[u8], key: &str) -> Option<String> {
+    if data.trim_ascii_start().first() == Some(&b'{') {
+        let record: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_slice(data).ok()?;
+        return match record.get(key)? {
+            serde_json::Value::String(value) => Some(value.clone()),
+            value => Some(value.to_string()),
+        };
+    }
+
+    data.split(|b| *b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| std::str::from_utf8(line).ok()?.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case(key))
+        .map(|(_, value)| value.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(5);
+
+    fn deduplicator() -> KeyDeduplicator {
+        KeyDeduplicator::new("device".to_string(), WINDOW)
+    }
+
+    #[test]
+    fn drops_repeats_within_the_window() {
+        let mut dedupe = deduplicator();
+        let start = Instant::now();
+        let record = br#"{"device":"sw1","status":"up"}"#;
+        assert!(!dedupe.is_duplicate_at(record, start));
+        assert!(dedupe.is_duplicate_at(record, start + Duration::from_secs(1)));
+        // Same key, different contents
+        assert!(dedupe.is_duplicate_at(
+            br#"{"device":"sw1","status":"down"}"#,
+            start + WINDOW - Duration::from_millis(1)
+        ));
+    }
+
+    #[test]
+    fn forwards_again_once_the_window_has_passed() {
+        let mut dedupe = deduplicator();
+        let start = Instant::now();
+        let record = b"device: sw1\r\n\r\nstatus up";
+        assert!(!dedupe.is_duplicate_at(record, start));
+        assert!(!dedupe.is_duplicate_at(record, start + WINDOW));
+        // The window restarts from the last forwarded record
+        assert!(dedupe.is_duplicate_at(record, start + WINDOW + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn dropped_repeats_do_not_extend_the_window() {
+        let mut dedupe = deduplicator();
+        let start = Instant::now();
+        let record = br#"{"device":"sw1"}"#;
+        assert!(!dedupe.is_duplicate_at(record, start));
+        assert!(dedupe.is_duplicate_at(record, start + Duration::from_secs(4)));
+        assert!(!dedupe.is_duplicate_at(record, start + WINDOW));
+    }
+
+    #[test]
+    fn different_keys_and_keyless_records_are_forwarded() {
+        let mut dedupe = deduplicator();
+        let now = Instant::now();
+        assert!(!dedupe.is_duplicate_at(br#"{"device":"sw1"}"#, now));
+        assert!(!dedupe.is_duplicate_at(br#"{"device":"sw2"}"#, now));
+        for _ in 0..3 {
+            assert!(!dedupe.is_duplicate_at(br#"{"status":"up"}"#, now));
+            assert!(!dedupe.is_duplicate_at(b"plain text", now));
+        }
+    }
+
+    #[test]
+    fn extracts_json_fields_and_header_lines() {
+        assert_eq!(
+            extract_key(br#" {"id":"a","n":1}"#, "id").as_deref(),
+            Some("a")
+        );
+        assert_eq!(extract_key(br#"{"id":42}"#, "id").as_deref(), Some("42"));
+        assert_eq!(
+            extract_key(b"Type: alarm\r\nID:  7 \r\n\r\nbody", "id").as_deref(),
+            Some("7")
+        );
+        // Header lines end at the first blank line
+        assert_eq!(extract_key(b"Type: alarm\n\nid: 7", "id"), None);
+        assert_eq!(extract_key(b"{not json", "id"), None);
+    }
+}
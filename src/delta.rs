@@ -0,0 +1,259 @@
+//! Forwarding only what changed between structured records
+//!
+//! With `delta_only` set, JSON object records are compared with the previous
+//! record (per value of `delta_key`, if set). The first record, and one
+//! every `delta_snapshot_interval_ms`, is forwarded in full; the others are
+//! forwarded as a JSON Merge Patch (RFC 7396) against the previous record:
+//! changed fields with their new value, removed fields as `null`, plus the
+//! key field. Records identical to the previous one are dropped. Records
+//! that aren't JSON objects are forwarded unchanged.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use serde_json::{Map, Value};
+
+/// Number of tracked key values above which tracking starts over
+const MAX_TRACKED_KEYS: usize = 4096;
+
+/// Last record forwarded for one key value
+struct Previous {
+    record: Map<String, Value>,
+    snapshot_at: Instant,
+}
+
+/// Reduces each structured record to its changes since the previous one
+pub struct DeltaEncoder {
+    key: Option<String>,
+    snapshot_interval: Option<Duration>,
+    previous: HashMap<Option<String>, Previous>,
+}
+
+impl DeltaEncoder {
+    /// Track records per value of field `key` (or all together), sending a
+    /// full snapshot every `snapshot_interval`
+    pub fn new(key: Option<String>, snapshot_interval: Option<Duration>) -> Self {
+        Self {
+            key,
+            snapshot_interval,
+            previous: HashMap::new(),
+        }
+    }
+
+    /// What to forward for a record, or `None` if nothing changed
+    pub fn encode(&mut self, data: Bytes, now: Instant) -> Option<Bytes> {
+        if data.trim_ascii_start().first() != Some(&b'{') {
+            return Some(data);
+        }
+        let Ok(record) = serde_json::from_slice::<Map<String, Value>>(&data) else {
+            return Some(data);
+        };
+
+        let key_value = self
+            .key
+            .as_ref()
+            .and_then(|key| record.get(key))
+            .map(Value::to_string);
+        if self.previous.len() >= MAX_TRACKED_KEYS && !self.previous.contains_key(&key_value) {
+            self.previous.clear();
+        }
+
+        let snapshot_due = |previous: &Previous| {
+            self.snapshot_interval
+                .is_some_and(|interval| now.duration_since(previous.snapshot_at) >= interval)
+        };
+        let (body, snapshot_at) = match self.previous.get(&key_value) {
+            Some(previous) if !snapshot_due(previous) => {
+                let mut patch = diff(&previous.record, &record);
+                if patch.is_empty() {
+                    return None;
+                }
+                if let Some((key, value)) = self
+                    .key
+                    .as_ref()
+                    .and_then(|key| Some((key, record.get(key)?)))
+                {
+                    patch.insert(key.clone(), value.clone());
+                }
+                (
+                    serde_json::to_vec(&patch).ok()?.into(),
+                    previous.snapshot_at,
+                )
+            }
+            // First record for this key, or time for a full snapshot
+            _ => (data, now),
+        };
+
+        self.previous.insert(
+            key_value,
+            Previous {
+                record,
+                snapshot_at,
+            },
+        );
+        Some(body)
+    }
+}
+
+/// JSON Merge Patch turning `old` into `new`
+fn diff(old: &Map<String, Value>, new: &Map<String, Value>) -> Map<String, Value> {
+    let mut patch = Map::new();
+    for (field, value) in new {
+        match (old.get(field), value) {
+            (Some(old_value), _) if old_value == value => {}
+            (Some(Value::Object(old_object)), Value::Object(new_object)) => {
+                patch.insert(field.clone(), Value::Object(diff(old_object, new_object)));
+            }
+            _ => {
+                patch.insert(field.clone(), value.clone());
+            }
+        }
+    }
+    for field in old.keys() {
+        if !new.contains_key(field) {
+            patch.insert(field.clone(), Value::Null);
+        }
+    }
+    patch
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn record(value: Value) -> Bytes {
+        serde_json::to_vec(&value).unwrap().into()
+    }
+
+    fn parsed(body: Option<Bytes>) -> Value {
+        serde_json::from_slice(&body.expect("record was dropped")).unwrap()
+    }
+
+    /// Apply a JSON Merge Patch as RFC 7396 defines it
+    fn merge(target: &mut Value, patch: &Value) {
+        let Value::Object(patch) = patch else {
+            *target = patch.clone();
+            return;
+        };
+        if !target.is_object() {
+            *target = Value::Object(Map::new());
+        }
+        let target = target.as_object_mut().unwrap();
+        for (field, value) in patch {
+            if value.is_null() {
+                target.remove(field);
+            } else {
+                merge(target.entry(field.clone()).or_insert(Value::Null), value);
+            }
+        }
+    }
+
+    #[test]
+    fn forwards_the_first_record_then_merge_patches() {
+        let mut encoder = DeltaEncoder::new(None, None);
+        let now = Instant::now();
+        let first = json!({"temp": 20, "state": "ok", "pos": {"x": 1, "y": 2}, "note": "hi"});
+        assert_eq!(parsed(encoder.encode(record(first.clone()), now)), first);
+
+        let second = json!({"temp": 21, "state": "ok", "pos": {"x": 1, "y": 3}});
+        let patch = parsed(encoder.encode(record(second.clone()), now));
+        assert_eq!(patch, json!({"temp": 21, "pos": {"y": 3}, "note": null}));
+        let mut merged = first;
+        merge(&mut merged, &patch);
+        assert_eq!(merged, second);
+    }
+
+    #[test]
+    fn drops_records_that_did_not_change() {
+        let mut encoder = DeltaEncoder::new(None, None);
+        let now = Instant::now();
+        encoder
+            .encode(record(json!({"a": 1, "b": 2})), now)
+            .unwrap();
+        // Field order doesn't matter
+        assert_eq!(encoder.encode(record(json!({"b": 2, "a": 1})), now), None);
+    }
+
+    #[test]
+    fn tracks_each_key_value_separately() {
+        let mut encoder = DeltaEncoder::new(Some("device".to_string()), None);
+        let now = Instant::now();
+        let pump = json!({"device": "pump", "rpm": 100, "on": true});
+        let fan = json!({"device": "fan", "rpm": 900, "on": true});
+        assert_eq!(parsed(encoder.encode(record(pump.clone()), now)), pump);
+        assert_eq!(parsed(encoder.encode(record(fan.clone()), now)), fan);
+        // The patch names the device it applies to
+        assert_eq!(
+            parsed(encoder.encode(
+                record(json!({"device": "pump", "rpm": 120, "on": true})),
+                now
+            )),
+            json!({"device": "pump", "rpm": 120})
+        );
+        assert_eq!(encoder.encode(record(fan), now), None);
+    }
+
+    #[test]
+    fn sends_a_full_snapshot_every_interval() {
+        let interval = Duration::from_secs(60);
+        let mut encoder = DeltaEncoder::new(None, Some(interval));
+        let start = Instant::now();
+        encoder
+            .encode(record(json!({"a": 1, "b": 1})), start)
+            .unwrap();
+        let later = start + Duration::from_secs(30);
+        assert_eq!(
+            parsed(encoder.encode(record(json!({"a": 2, "b": 1})), later)),
+            json!({"a": 2})
+        );
+        // Patches don't restart the interval
+        let due = start + interval;
+        assert_eq!(
+            parsed(encoder.encode(record(json!({"a": 3, "b": 1})), due)),
+            json!({"a": 3, "b": 1})
+        );
+        assert_eq!(
+            parsed(encoder.encode(
+                record(json!({"a": 4, "b": 1})),
+                due + Duration::from_secs(1)
+            )),
+            json!({"a": 4})
+        );
+    }
+
+    #[test]
+    fn records_that_are_not_json_objects_pass_through() {
+        let mut encoder = DeltaEncoder::new(None, None);
+        let now = Instant::now();
+        for data in [&b"plain text\r\n"[..], b"[1, 2]", b"{not json", b""] {
+            let data = Bytes::copy_from_slice(data);
+            assert_eq!(encoder.encode(data.clone(), now), Some(data.clone()));
+            // And aren't compared with anything
+            assert_eq!(encoder.encode(data.clone(), now), Some(data));
+        }
+    }
+
+    #[test]
+    fn starts_over_when_too_many_keys_are_tracked() {
+        let mut encoder = DeltaEncoder::new(Some("id".to_string()), None);
+        let now = Instant::now();
+        for id in 0..MAX_TRACKED_KEYS {
+            encoder
+                .encode(record(json!({"id": id, "v": 0})), now)
+                .unwrap();
+        }
+        assert_eq!(encoder.encode(record(json!({"id": 0, "v": 0})), now), None);
+        // A new key clears the others, so the next record for one is in full
+        encoder
+            .encode(record(json!({"id": "new", "v": 0})), now)
+            .unwrap();
+        assert_eq!(encoder.previous.len(), 1);
+        assert_eq!(
+            parsed(encoder.encode(record(json!({"id": 0, "v": 0})), now)),
+            json!({"id": 0, "v": 0})
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Provider-wide limit on in-flight deliveries, shared by priority
+//!
+//! Every link's deliveries to its component count against one capacity
+//! (`max_inflight_deliveries`). Lower-priority links may only use part of
+//! it, so when components fall behind, `low` links are shed first, then
+//! `normal` ones, while `high` links can still use the remaining headroom.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Share of the delivery capacity a link may use
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryPriority {
+    /// May use the whole capacity
+    High,
+    /// May use up to 75% of the capacity
+    #[default]
+    Normal,
+    /// May use up to 50% of the capacity; shed first under load
+    Low,
+}
+
+impl DeliveryPriority {
+    /// Priority of messages with a priority level read from their content
+    /// (see `priority_byte_offset`): lower levels are more urgent, so `0`
+    /// and `1` are `high`, `2` is `normal` and anything above is `low`
+    pub fn from_level(level: u8) -> Self {
+        match level {
+            0 | 1 => DeliveryPriority::High,
+            2 => DeliveryPriority::Normal,
+            _ => DeliveryPriority::Low,
+        }
+    }
+
+    /// Maximum in-flight deliveries (across all links) at which a link of
+    /// this priority may still start one
+    fn limit(&self, capacity: usize) -> usize {
+        match self {
+            DeliveryPriority::High => capacity,
+            DeliveryPriority::Normal => capacity * 3 / 4,
+            DeliveryPriority::Low => capacity / 2,
+        }
+        .max(1)
+    }
+}
+
+impl std::str::FromStr for DeliveryPriority {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "high" => Ok(DeliveryPriority::High),
+            "normal" => Ok(DeliveryPriority::Normal),
+            "low" => Ok(DeliveryPriority::Low),
+            other => Err(anyhow::anyhow!("Invalid delivery_priority: {}", other)),
+        }
+    }
+}
+
+/// Counts in-flight deliveries against the provider's capacity
+#[derive(Debug, Default)]
+pub struct DeliveryLimiter {
+    /// Maximum in-flight deliveries (0 = unlimited)
+    capacity: AtomicUsize,
+    inflight: AtomicUsize,
+}
+
+impl DeliveryLimiter {
+    /// Set the maximum in-flight deliveries (0 = unlimited)
+    pub fn set_capacity(&self, capacity: usize) {
+        self.capacity.store(capacity, Ordering::Relaxed);
+    }
+
+    /// Reserve a delivery slot, or `None` if the link's share is used up
+    pub fn try_acquire(self: &Arc<Self>, priority: DeliveryPriority) -> Option<DeliveryPermit> {
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        if capacity == 0 {
+            self.inflight.fetch_add(1, Ordering::Relaxed);
+        } else {
+            let limit = priority.limit(capacity);
+            self.inflight
+                .try_update(Ordering::Relaxed, Ordering::Relaxed, |inflight| {
+                    (inflight < limit).then_some(inflight + 1)
+                })
+                .ok()?;
+        }
+        Some(DeliveryPermit {
+            limiter: self.clone(),
+        })
+    }
+}
+
+/// A reserved delivery slot, released on drop
+pub struct DeliveryPermit {
+    limiter: Arc<DeliveryLimiter>,
+}
+
+impl Drop for DeliveryPermit {
+    fn drop(&mut self) {
+        self.limiter.inflight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,218 @@
+//! Effective link configuration with the provenance of every value
+//!
+//! Exported through `wasmcloud:telnet/control.effective-config` so operators
+//! can see exactly what a link runs with and which values they set.
+
+use std::collections::BTreeMap;
+
+/// Placeholder shown instead of sensitive values
+const REDACTED: &str = "<redacted>";
+
+/// Keys whose values are never shown, even from plain link config
+const SENSITIVE_KEYS: &[&str] = &["login_password"];
+
+/// Default of every link config key, as written in link config
+const DEFAULTS: &[(&str, &str)] = &[
+    ("telnet_port", "23"),
+    ("ip_family", "any"),
+    ("max_reconnect_attempts", "0"),
+    ("initial_reconnect_delay_ms", "1000"),
+    ("max_reconnect_delay_ms", "60000"),
+    ("backoff_jitter_mode", "none"),
+    ("reconnect_blackout", ""),
+    ("max_message_size", "1048576"),
+    ("read_buffer_size", "4096"),
+    ("read_ahead", "4"),
+    ("offload_threshold_bytes", "0"),
+    ("delivery_priority", "normal"),
+    ("runtime", "shared"),
+    ("group", ""),
+    ("subject_template", "telnet.{host}:{port}"),
+    ("source_id_transform", "none"),
+    ("dead_letter_subject", ""),
+    ("protocol_version", "1"),
+    ("envelope_format", ""),
+    ("message_ttl_ms", "0"),
+    ("lifetime_byte_quota", "0"),
+    ("quota_exceeded_event", "false"),
+    ("max_messages", "0"),
+    ("message_limit_event", "false"),
+    ("connection_group", ""),
+    ("connection_group_role", "primary"),
+    ("unstuff", ""),
+    ("unstuff_cr", "false"),
+    ("login_username", ""),
+    ("login_password", ""),
+    ("login_prompt", "login:"),
+    ("password_prompt", "password:"),
+    ("login_timeout_ms", "10000"),
+    ("login_step_delay_ms", "0"),
+    ("login_username_delay_ms", ""),
+    ("login_password_delay_ms", ""),
+    ("reconnect_sentinel", ""),
+    ("forward_reconnect_sentinel", "false"),
+    ("sequence_gap_detection", "false"),
+    ("delivery_ordering", "best_effort"),
+    ("ordering_max_wait_ms", "1000"),
+    ("tokenize_controls", "false"),
+    ("control_token_style", "angle"),
+    ("body_required_keywords", ""),
+    ("body_blocked_keywords", ""),
+    ("routing_rules", ""),
+    ("debounce_window_ms", "0"),
+    ("sample_rate", ""),
+    ("dedupe_key", ""),
+    ("dedupe_window_ms", "1000"),
+    ("delta_only", "false"),
+    ("delta_key", ""),
+    ("delta_snapshot_interval_ms", "60000"),
+    ("aggregate_numeric_window_ms", "0"),
+    ("aggregate_numeric_pattern", ""),
+    ("aggregate_numeric_field", ""),
+    ("ndjson", "false"),
+    ("enrichment_fields", ""),
+    ("priority_byte_offset", ""),
+    ("priority_byte_map", ""),
+    ("msdp_enabled", "false"),
+    ("mccp_enabled", "false"),
+    ("compression_required", "false"),
+    ("debug_hexdump", "false"),
+    ("hexdump_max_bytes", "256"),
+    ("component_ping_interval_ms", "0"),
+    ("component_down_threshold_ms", "30000"),
+    ("component_down_action", "none"),
+    ("component_client_recreate_after", "0"),
+    ("watchdog_timeout_ms", "0"),
+    ("idle_reconnect_after_ms", "0"),
+    ("write_timeout_ms", "10000"),
+    ("tcp_keepalive_secs", "0"),
+    ("tcp_keepalive_interval_secs", "0"),
+    ("tcp_keepalive_retries", "0"),
+    ("tcp_user_timeout_ms", "0"),
+    ("negotiation_timeout_ms", "0"),
+    ("negotiation_timeout_action", "continue"),
+    ("reachability_probe", "true"),
+    ("command_blocklist", ""),
+    ("reuse_grace_ms", "0"),
+    ("slow_consumer_threshold_ms", "0"),
+    ("auto_detect_server_type", "false"),
+    ("auto_tune_max_message_size", "false"),
+    ("auto_tune_max_factor", "2.0"),
+    ("dns_cache_ms", "5000"),
+    ("config_file", ""),
+    ("session_record_dir", ""),
+    ("session_record_compress", "false"),
+];
+
+/// Where an effective value came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// Not set; the built-in default applies
+    Default,
+    /// Set in the link's config
+    LinkConfig,
+    /// Set in the link's secrets
+    Secret,
+}
+
+/// One effective configuration value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EffectiveValue {
+    pub key: String,
+    pub value: String,
+    pub source: ConfigSource,
+}
+
+impl EffectiveValue {
+    /// The value as it may be shown or logged
+    pub fn shown(&self) -> &str {
+        if self.source == ConfigSource::Secret || SENSITIVE_KEYS.contains(&self.key.as_str()) {
+            REDACTED
+        } else {
+            &self.value
+        }
+    }
+}
+
+/// Values a link set explicitly
+///
+/// Config values are kept as set, so changes to sensitive ones can be
+/// detected, and only redacted when shown. Secret values are never kept.
+#[derive(Clone, Default)]
+pub struct ProvidedValues {
+    values: BTreeMap<String, (String, ConfigSource)>,
+}
+
+impl std::fmt::Debug for ProvidedValues {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map()
+            .entries(self.values.iter().map(|(key, (value, source))| {
+                let value = EffectiveValue {
+                    key: key.clone(),
+                    value: value.clone(),
+                    source: *source,
+                };
+                (key, value.shown().to_string())
+            }))
+            .finish()
+    }
+}
+
+impl ProvidedValues {
+    /// Record the keys and values of a link's config
+    pub fn from_config<'a>(config: impl IntoIterator<Item = (&'a String, &'a String)>) -> Self {
+        let values = config
+            .into_iter()
+            .map(|(key, value)| (key.clone(), (value.clone(), ConfigSource::LinkConfig)))
+            .collect();
+        Self { values }
+    }
+
+    /// Record keys set through secrets; their values are never kept
+    pub fn add_secrets<'a>(&mut self, keys: impl IntoIterator<Item = &'a String>) {
+        for key in keys {
+            self.values
+                .insert(key.clone(), (REDACTED.to_string(), ConfigSource::Secret));
+        }
+    }
+
+    /// Every known key plus any other set ones, with its effective value,
+    /// sensitive ones redacted
+    pub fn effective(&self) -> Vec<EffectiveValue> {
+        let mut effective = self.actual();
+        for value in &mut effective {
+            value.value = value.shown().to_string();
+        }
+        effective
+    }
+
+    /// Like [`effective`](Self::effective), with config values as set; for
+    /// comparing configs, never for showing them
+    pub fn actual(&self) -> Vec<EffectiveValue> {
+        let mut effective: BTreeMap<&str, EffectiveValue> = DEFAULTS
+            .iter()
+            .copied()
+            .map(|(key, value)| {
+                (
+                    key,
+                    EffectiveValue {
+                        key: key.to_string(),
+                        value: value.to_string(),
+                        source: ConfigSource::Default,
+                    },
+                )
+            })
+            .collect();
+        for (key, (value, source)) in &self.values {
+            effective.insert(
+                key,
+                EffectiveValue {
+                    key: key.clone(),
+                    value: value.clone(),
+                    source: *source,
+                },
+            );
+        }
+        effective.into_values().collect()
+    }
+}
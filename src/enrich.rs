@@ -0,0 +1,212 @@
+//! Provider-computed fields added to forwarded messages
+//!
+//! wasmcloud:messaging broker messages have no headers, so like
+//! `x-telnet-seq` the fields are sent as header lines: with
+//! `enrichment_fields` set, each message body is prefixed with one
+//! `x-telnet-<field>: <value>\r\n` line per field, in the configured order,
+//! followed by the data unchanged. Values are computed when the message is
+//! created, after filtering and transformations.
+
+use std::time::{Instant, SystemTime};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::stats::ConnectionStats;
+
+/// A field that can be added to each message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnrichmentField {
+    /// XXH3 64-bit hash of the data, as 16 hex digits
+    MessageHash,
+    /// Length of the data in bytes
+    BodyLength,
+    /// Nanoseconds from the socket read to creating the message
+    ArrivalLatencyNanos,
+    /// Seconds the current connection has been up
+    ConnectionUptimeSecs,
+    /// Connections established after the first one
+    ReconnectCount,
+}
+
+impl EnrichmentField {
+    /// Name in `enrichment_fields` and, prefixed with `x-telnet-`, of the
+    /// header line
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EnrichmentField::MessageHash => "message_hash",
+            EnrichmentField::BodyLength => "body_length",
+            EnrichmentField::ArrivalLatencyNanos => "arrival_latency_nanos",
+            EnrichmentField::ConnectionUptimeSecs => "connection_uptime_secs",
+            EnrichmentField::ReconnectCount => "reconnect_count",
+        }
+    }
+}
+
+impl std::str::FromStr for EnrichmentField {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "message_hash" => Ok(EnrichmentField::MessageHash),
+            "body_length" => Ok(EnrichmentField::BodyLength),
+            "arrival_latency_nanos" => Ok(EnrichmentField::ArrivalLatencyNanos),
+            "connection_uptime_secs" => Ok(EnrichmentField::ConnectionUptimeSecs),
+            "reconnect_count" => Ok(EnrichmentField::ReconnectCount),
+            other => Err(anyhow::anyhow!("Invalid enrichment field: {}", other)),
+        }
+    }
+}
+
+/// Parse comma-separated enrichment fields, keeping their order
+pub fn parse_enrichment_fields(s: &str) -> anyhow::Result<Vec<EnrichmentField>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// Adds the configured header lines to a connection's messages
+pub struct MessageEnricher {
+    fields: Vec<EnrichmentField>,
+}
+
+impl MessageEnricher {
+    pub fn new(fields: Vec<EnrichmentField>) -> Self {
+        Self { fields }
+    }
+
+    /// `data` prefixed with the header lines, for data read at `received`
+    pub fn enrich(&self, data: &[u8], received: Instant, stats: &ConnectionStats) -> Bytes {
+        let mut body = BytesMut::with_capacity(data.len() + 48 * self.fields.len());
+        for field in &self.fields {
+            let value = match field {
+                EnrichmentField::MessageHash => format!("{:016x}", xxh3_64(data)),
+                EnrichmentField::BodyLength => data.len().to_string(),
+                EnrichmentField::ArrivalLatencyNanos => received.elapsed().as_nanos().to_string(),
+                EnrichmentField::ConnectionUptimeSecs => stats
+                    .connected_at()
+                    .and_then(|at| SystemTime::now().duration_since(at).ok())
+                    .unwrap_or_default()
+                    .as_secs()
+                    .to_string(),
+                EnrichmentField::ReconnectCount => {
+                    stats.connections().saturating_sub(1).to_string()
+                }
+            };
+            body.put_slice(b"x-telnet-");
+            body.put_slice(field.as_str().as_bytes());
+            body.put_slice(b": ");
+            body.put_slice(value.as_bytes());
+            body.put_slice(b"\r\n");
+        }
+        body.put_slice(data);
+        body.freeze()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// The header lines of `body` as name/value pairs, and the data after
+    /// them
+    fn headers(body: &[u8]) -> (Vec<(String, String)>, &[u8]) {
+        let mut rest = body;
+        let mut headers = Vec::new();
+        while let Some(line) = rest.strip_prefix(b"x-telnet-") {
+            let end = line.windows(2).position(|w| w == b"\r\n").unwrap();
+            let (name, value) = std::str::from_utf8(&line[..end])
+                .unwrap()
+                .split_once(": ")
+                .unwrap();
+            headers.push((name.to_string(), value.to_string()));
+            rest = &line[end + 2..];
+        }
+        (headers, rest)
+    }
+
+    #[test]
+    fn parses_fields_in_order() {
+        assert_eq!(
+            parse_enrichment_fields(" Body_Length,,message_hash , reconnect_count").unwrap(),
+            [
+                EnrichmentField::BodyLength,
+                EnrichmentField::MessageHash,
+                EnrichmentField::ReconnectCount,
+            ]
+        );
+        assert!(parse_enrichment_fields("").unwrap().is_empty());
+        assert!(parse_enrichment_fields("body_length,checksum").is_err());
+        for field in [
+            EnrichmentField::MessageHash,
+            EnrichmentField::BodyLength,
+            EnrichmentField::ArrivalLatencyNanos,
+            EnrichmentField::ConnectionUptimeSecs,
+            EnrichmentField::ReconnectCount,
+        ] {
+            assert_eq!(field.as_str().parse::<EnrichmentField>().unwrap(), field);
+        }
+    }
+
+    #[test]
+    fn leaves_data_unchanged_without_fields() {
+        let enricher = MessageEnricher::new(Vec::new());
+        let body = enricher.enrich(b"data\r\n", Instant::now(), &ConnectionStats::default());
+        assert_eq!(body, &b"data\r\n"[..]);
+    }
+
+    #[test]
+    fn prefixes_a_header_line_per_field() {
+        let stats = ConnectionStats::default();
+        stats.next_epoch();
+        stats.next_epoch();
+        stats.next_epoch();
+        stats.set_connected(true);
+        let enricher = MessageEnricher::new(vec![
+            EnrichmentField::ReconnectCount,
+            EnrichmentField::BodyLength,
+            EnrichmentField::MessageHash,
+            EnrichmentField::ConnectionUptimeSecs,
+            EnrichmentField::ArrivalLatencyNanos,
+        ]);
+        let received = Instant::now() - Duration::from_millis(5);
+        let body = enricher.enrich(b"temp: 21\r\n", received, &stats);
+
+        let (headers, data) = headers(&body);
+        assert_eq!(data, b"temp: 21\r\n");
+        let names: Vec<&str> = headers.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "reconnect_count",
+                "body_length",
+                "message_hash",
+                "connection_uptime_secs",
+                "arrival_latency_nanos",
+            ]
+        );
+        assert_eq!(headers[0].1, "2");
+        assert_eq!(headers[1].1, "10");
+        assert_eq!(headers[2].1, format!("{:016x}", xxh3_64(b"temp: 21\r\n")));
+        assert_eq!(headers[2].1.len(), 16);
+        assert_eq!(headers[3].1, "0");
+        assert!(headers[4].1.parse::<u64>().unwrap() >= 5_000_000);
+    }
+
+    #[test]
+    fn reports_no_uptime_or_reconnects_before_connecting() {
+        let enricher = MessageEnricher::new(vec![
+            EnrichmentField::ConnectionUptimeSecs,
+            EnrichmentField::ReconnectCount,
+        ]);
+        let body = enricher.enrich(b"", Instant::now(), &ConnectionStats::default());
+        assert_eq!(
+            body,
+            &b"x-telnet-connection_uptime_secs: 0\r\nx-telnet-reconnect_count: 0\r\n"[..]
+        );
+    }
+}
@@ -0,0 +1,246 @@
+//! Message envelopes (protocol version 2)
+//!
+//! In envelope mode the broker-message body is not the raw Telnet bytes but a
+//! serialized envelope carrying the data alongside metadata about where and
+//! when it was received. The serialization is pluggable through
+//! [`EnvelopeEncoder`] so new formats only need a new implementation.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+
+/// Name of the header line carrying the expiry of raw (unenveloped) data
+pub const EXPIRY_HEADER: &str = "x-telnet-expires_at_ms";
+
+/// Serialization format for message envelopes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnvelopeFormat {
+    /// JSON, with the data base64-encoded
+    Json,
+    /// MessagePack, with the data as a binary value
+    Msgpack,
+    /// CBOR, with the data as a byte string
+    Cbor,
+}
+
+impl EnvelopeFormat {
+    /// Create the encoder for this format
+    pub fn encoder(&self) -> Box<dyn EnvelopeEncoder> {
+        match self {
+            EnvelopeFormat::Json => Box::new(JsonEncoder),
+            EnvelopeFormat::Msgpack => Box::new(MsgpackEncoder),
+            EnvelopeFormat::Cbor => Box::new(CborEncoder),
+        }
+    }
+}
+
+impl std::str::FromStr for EnvelopeFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(EnvelopeFormat::Json),
+            "msgpack" => Ok(EnvelopeFormat::Msgpack),
+            "cbor" => Ok(EnvelopeFormat::Cbor),
+            other => Err(anyhow::anyhow!("Invalid envelope_format: {}", other)),
+        }
+    }
+}
+
+/// Metadata describing a received Telnet message
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EnvelopeMetadata {
+    /// Envelope serialization format
+    pub format: EnvelopeFormat,
+    /// Telnet server address ("<host>:<port>")
+    pub address: String,
+    /// Time the data was received, in milliseconds since the Unix epoch
+    pub received_at_ms: u64,
+    /// Time after which the data is stale, in milliseconds since the Unix
+    /// epoch; advisory, set only with `message_ttl_ms`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at_ms: Option<u64>,
+}
+
+/// A Telnet message wrapped with its metadata
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    /// Envelope protocol version
+    pub version: u8,
+    /// Where and when the data was received
+    pub metadata: EnvelopeMetadata,
+    /// Filtered Telnet data
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+}
+
+impl Envelope {
+    /// Wrap data received now from `address`
+    pub fn new(format: EnvelopeFormat, address: &str, data: Vec<u8>) -> Self {
+        Self {
+            version: 2,
+            metadata: EnvelopeMetadata {
+                format,
+                address: address.to_string(),
+                received_at_ms: now_ms(),
+                expires_at_ms: None,
+            },
+            data,
+        }
+    }
+
+    /// Mark the data as stale `ttl` after it was received
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.metadata.expires_at_ms = Some(self.metadata.received_at_ms + ttl.as_millis() as u64);
+        self
+    }
+}
+
+/// Current time in milliseconds since the Unix epoch
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Serializes envelopes into message bodies
+pub trait EnvelopeEncoder: Send + Sync {
+    /// Format produced by this encoder
+    fn format(&self) -> EnvelopeFormat;
+
+    /// Serialize an envelope, appending it to `out`
+    fn encode(&self, envelope: &Envelope, out: &mut Vec<u8>) -> anyhow::Result<()>;
+}
+
+/// JSON representation, with the data base64-encoded since JSON has no bytes type
+#[derive(Serialize)]
+struct JsonEnvelope {
+    version: u8,
+    metadata: EnvelopeMetadata,
+    data: String,
+}
+
+/// JSON envelope encoder
+pub struct JsonEncoder;
+
+impl EnvelopeEncoder for JsonEncoder {
+    fn format(&self) -> EnvelopeFormat {
+        EnvelopeFormat::Json
+    }
+
+    fn encode(&self, envelope: &Envelope, out: &mut Vec<u8>) -> anyhow::Result<()> {
+        Ok(serde_json::to_writer(
+            out,
+            &JsonEnvelope {
+                version: envelope.version,
+                metadata: envelope.metadata.clone(),
+                data: base64::engine::general_purpose::STANDARD.encode(&envelope.data),
+            },
+        )?)
+    }
+}
+
+/// MessagePack envelope encoder
+pub struct MsgpackEncoder;
+
+impl EnvelopeEncoder for MsgpackEncoder {
+    fn format(&self) -> EnvelopeFormat {
+        EnvelopeFormat::Msgpack
+    }
+
+    fn encode(&self, envelope: &Envelope, out: &mut Vec<u8>) -> anyhow::Result<()> {
+        Ok(rmp_serde::encode::write_named(out, envelope)?)
+    }
+}
+
+/// CBOR envelope encoder
+pub struct CborEncoder;
+
+impl EnvelopeEncoder for CborEncoder {
+    fn format(&self) -> EnvelopeFormat {
+        EnvelopeFormat::Cbor
+    }
+
+    fn encode(&self, envelope: &Envelope, out: &mut Vec<u8>) -> anyhow::Result<()> {
+        Ok(ciborium::into_writer(envelope, out)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An envelope holding every byte value, with a TTL
+    fn envelope(format: EnvelopeFormat) -> Envelope {
+        Envelope::new(format, "10.0.0.5:23", (0..=255).collect()).with_ttl(Duration::from_secs(30))
+    }
+
+    fn encode(envelope: &Envelope) -> Vec<u8> {
+        let mut out = Vec::new();
+        envelope
+            .metadata
+            .format
+            .encoder()
+            .encode(envelope, &mut out)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn json_round_trips_with_base64_data() {
+        let envelope = envelope(EnvelopeFormat::Json);
+        let json: serde_json::Value = serde_json::from_slice(&encode(&envelope)).unwrap();
+        let metadata: EnvelopeMetadata = serde_json::from_value(json["metadata"].clone()).unwrap();
+        let data = base64::engine::general_purpose::STANDARD
+            .decode(json["data"].as_str().unwrap())
+            .unwrap();
+
+        assert_eq!(json["version"], 2);
+        assert_eq!(json["metadata"]["format"], "json");
+        assert_eq!(metadata, envelope.metadata);
+        assert_eq!(data, envelope.data);
+    }
+
+    #[test]
+    fn msgpack_round_trips() {
+        let envelope = envelope(EnvelopeFormat::Msgpack);
+        let decoded: Envelope = rmp_serde::from_slice(&encode(&envelope)).unwrap();
+        assert_eq!(decoded, envelope);
+    }
+
+    #[test]
+    fn cbor_round_trips() {
+        let envelope = envelope(EnvelopeFormat::Cbor);
+        let decoded: Envelope = ciborium::from_reader(encode(&envelope).as_slice()).unwrap();
+        assert_eq!(decoded, envelope);
+    }
+
+    #[test]
+    fn omits_the_expiry_without_a_ttl() {
+        let envelope = Envelope::new(EnvelopeFormat::Json, "10.0.0.5:23", b"data".to_vec());
+        let json: serde_json::Value = serde_json::from_slice(&encode(&envelope)).unwrap();
+        assert!(json["metadata"].get("expires_at_ms").is_none(), "{}", json);
+
+        let ttl = envelope.clone().with_ttl(Duration::from_millis(1500));
+        assert_eq!(
+            ttl.metadata.expires_at_ms,
+            Some(envelope.metadata.received_at_ms + 1500)
+        );
+    }
+
+    #[test]
+    fn parses_format_names() {
+        for (name, format) in [
+            ("json", EnvelopeFormat::Json),
+            ("MsgPack", EnvelopeFormat::Msgpack),
+            ("CBOR", EnvelopeFormat::Cbor),
+        ] {
+            assert_eq!(name.parse::<EnvelopeFormat>().unwrap(), format);
+            assert_eq!(format.encoder().format(), format);
+        }
+        assert!("protobuf".parse::<EnvelopeFormat>().is_err());
+    }
+}
@@ -0,0 +1,437 @@
+//! NEW-ENVIRON Telnet option (RFC 1572)
+//!
+//! When `environ.*` values are configured the client agrees to the server's
+//! `DO NEW-ENVIRON` and answers each `SEND` request with an `IS` response
+//! carrying the requested variables.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+
+const IAC: u8 = 0xFF;
+const SB: u8 = 0xFA;
+const SE: u8 = 0xF0;
+const WILL: u8 = 0xFB;
+const DO: u8 = 0xFD;
+
+/// Telnet option code of NEW-ENVIRON
+const NEW_ENVIRON: u8 = 39;
+
+const IS: u8 = 0;
+const SEND: u8 = 1;
+const VAR: u8 = 0;
+const VALUE: u8 = 1;
+const ESC: u8 = 2;
+const USERVAR: u8 = 3;
+
+/// Variables RFC 1572 defines as well-known; everything else is a USERVAR
+const WELL_KNOWN: &[&str] = &["USER", "JOB", "ACCT", "PRINTER", "SYSTEMTYPE", "DISPLAY"];
+
+/// Prefix of link config keys and secrets holding environment variables
+pub const KEY_PREFIX: &str = "environ.";
+
+/// One configured variable
+#[derive(Clone)]
+struct Variable {
+    value: String,
+    /// Came from a link secret, so never logged
+    secret: bool,
+}
+
+/// Environment variables offered to the server
+#[derive(Clone, Default)]
+pub struct Environ {
+    vars: BTreeMap<String, Variable>,
+}
+
+impl fmt::Debug for Environ {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.vars.iter().map(|(name, var)| {
+                let value = if var.secret { "<redacted>" } else { &var.value };
+                (name, value)
+            }))
+            .finish()
+    }
+}
+
+impl Environ {
+    /// Collect `environ.<NAME>` entries from link config values
+    pub fn from_values<'a>(values: impl IntoIterator<Item = (&'a String, &'a String)>) -> Self {
+        let mut environ = Self::default();
+        for (key, value) in values {
+            if let Some(name) = key.strip_prefix(KEY_PREFIX) {
+                environ.insert(name, value, false);
+            }
+        }
+        environ
+    }
+
+    /// Add `environ.<NAME>` entries from link secrets, overriding config values
+    pub fn extend_secrets<'a>(&mut self, secrets: impl IntoIterator<Item = (&'a String, &'a str)>) {
+        for (key, value) in secrets {
+            if let Some(name) = key.strip_prefix(KEY_PREFIX) {
+                self.insert(name, value, true);
+            }
+        }
+    }
+
+    /// Whether no variables are configured, in which case NEW-ENVIRON is ignored
+    pub fn is_empty(&self) -> bool {
+        self.vars.is_empty()
+    }
+
+    /// What the client answers to a negotiation command for `option`, if anything
+    pub fn response_to(&self, command: u8, option: u8) -> Option<&'static str> {
+        if option != NEW_ENVIRON || self.is_empty() {
+            return None;
+        }
+        match command {
+            DO => Some("WILL"),
+            SB => Some("IS"),
+            _ => None,
+        }
+    }
+
+    /// Responses to any NEW-ENVIRON negotiation in raw received data
+    ///
+    /// Returns an empty buffer if there is nothing to send.
+    pub fn replies(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        if self.is_empty() {
+            return out;
+        }
+
+        let mut i = 0;
+        while i + 2 < data.len() {
+            if data[i] != IAC {
+                i += 1;
+                continue;
+            }
+            match (data[i + 1], data[i + 2]) {
+                (IAC, _) => i += 2,
+                (DO, NEW_ENVIRON) => {
+                    out.extend_from_slice(&[IAC, WILL, NEW_ENVIRON]);
+                    i += 3;
+                }
+                (SB, NEW_ENVIRON) => {
+                    let (payload, len) = subnegotiation(&data[i + 3..]);
+                    if payload.first() == Some(&SEND) {
+                        out.extend(self.is_response(&parse_send(&payload[1..])));
+                    }
+                    i += 3 + len;
+                }
+                _ => i += 2,
+            }
+        }
+        out
+    }
+
+    /// Build the `IS` subnegotiation answering a `SEND` request
+    ///
+    /// An empty request, or an entry with an empty name, asks for every
+    /// variable of that type. Requested variables that aren't configured are
+    /// sent without a value, marking them undefined.
+    fn is_response(&self, requested: &[(u8, String)]) -> Vec<u8> {
+        let mut out = vec![IAC, SB, NEW_ENVIRON, IS];
+        let mut push = |kind: u8, name: &str, value: Option<&str>| {
+            out.push(kind);
+            escape_into(&mut out, name.as_bytes());
+            if let Some(value) = value {
+                out.push(VALUE);
+                escape_into(&mut out, value.as_bytes());
+            }
+        };
+
+        for (kind, name) in requested {
+            if name.is_empty() {
+                for (name, var) in self.vars.iter().filter(|(name, _)| var_kind(name) == *kind) {
+                    push(*kind, name, Some(&var.value));
+                }
+            } else {
+                push(
+                    *kind,
+                    name,
+                    self.vars.get(name).map(|var| var.value.as_str()),
+                );
+            }
+        }
+        if requested.is_empty() {
+            for (name, var) in &self.vars {
+                push(var_kind(name), name, Some(&var.value));
+            }
+        }
+
+        out.extend_from_slice(&[IAC, SE]);
+        out
+    }
+
+    fn insert(&mut self, name: &str, value: &str, secret: bool) {
+        if name.is_empty() {
+            return;
+        }
+        self.vars.insert(
+            name.to_string(),
+            Variable {
+                value: value.to_string(),
+                secret,
+            },
+        );
+    }
+}
+
+/// Whether `name` is sent as a VAR or a USERVAR
+fn var_kind(name: &str) -> u8 {
+    if WELL_KNOWN.contains(&name) {
+        VAR
+    } else {
+        USERVAR
+    }
+}
+
+/// Unescaped payload of a subnegotiation up to `IAC SE`, and the number of
+/// input bytes it spanned (including `IAC SE` if present)
+///
+/// The payload is borrowed from `data` unless it contains IAC sequences.
+fn subnegotiation(data: &[u8]) -> (Cow<'_, [u8]>, usize) {
+    let mut plain = true;
+    let mut i = 0;
+    let end = loop {
+        if i >= data.len() {
+            break i;
+        }
+        if data[i] == IAC && i + 1 < data.len() {
+            if data[i + 1] == SE {
+                break i;
+            }
+            plain = false;
+            i += 2;
+        } else {
+            i += 1;
+        }
+    };
+    let body = &data[..end];
+    let payload = if plain {
+        Cow::Borrowed(body)
+    } else {
+        Cow::Owned(unescape(body))
+    };
+    (payload, if end < data.len() { end + 2 } else { end })
+}
+
+/// Subnegotiation body with `IAC IAC` unescaped and other IAC commands
+/// dropped, in one allocation
+fn unescape(body: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(body.len());
+    let mut i = 0;
+    while i < body.len() {
+        if body[i] == IAC && i + 1 < body.len() {
+            if body[i + 1] == IAC {
+                payload.push(IAC);
+            }
+            i += 2;
+        } else {
+            payload.push(body[i]);
+            i += 1;
+        }
+    }
+    payload
+}
+
+/// Parse the variable list of a `SEND` request into (type, name) pairs
+fn parse_send(list: &[u8]) -> Vec<(u8, String)> {
+    let mut requested = Vec::new();
+    let mut current: Option<(u8, Vec<u8>)> = None;
+    let mut i = 0;
+    while i < list.len() {
+        match list[i] {
+            kind @ (VAR | USERVAR) => {
+                requested.extend(current.take());
+                current = Some((kind, Vec::new()));
+            }
+            ESC if i + 1 < list.len() => {
+                i += 1;
+                if let Some((_, name)) = &mut current {
+                    name.push(list[i]);
+                }
+            }
+            byte => {
+                if let Some((_, name)) = &mut current {
+                    name.push(byte);
+                }
+            }
+        }
+        i += 1;
+    }
+    requested.extend(current);
+
+    requested
+        .into_iter()
+        .map(|(kind, name)| (kind, String::from_utf8_lossy(&name).into_owned()))
+        .collect()
+}
+
+/// Append `bytes`, escaping NEW-ENVIRON type codes and doubling IAC
+fn escape_into(out: &mut Vec<u8>, bytes: &[u8]) {
+    for &byte in bytes {
+        match byte {
+            VAR | VALUE | ESC | USERVAR => out.extend_from_slice(&[ESC, byte]),
+            IAC => out.extend_from_slice(&[IAC, IAC]),
+            _ => out.push(byte),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn environ(vars: &[(&str, &str)]) -> Environ {
+        let values: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (format!("{}{}", KEY_PREFIX, name), value.to_string()))
+            .collect();
+        Environ::from_values(&values)
+    }
+
+    /// `IAC SB NEW-ENVIRON SEND <list> IAC SE`
+    fn send_request(list: &[u8]) -> Vec<u8> {
+        let mut request = vec![IAC, SB, NEW_ENVIRON, SEND];
+        request.extend_from_slice(list);
+        request.extend_from_slice(&[IAC, SE]);
+        request
+    }
+
+    /// `IAC SB NEW-ENVIRON IS <list> IAC SE`
+    fn is_response(list: &[u8]) -> Vec<u8> {
+        let mut response = vec![IAC, SB, NEW_ENVIRON, IS];
+        response.extend_from_slice(list);
+        response.extend_from_slice(&[IAC, SE]);
+        response
+    }
+
+    #[test]
+    fn agrees_to_new_environ_only_with_variables() {
+        let request = [IAC, DO, NEW_ENVIRON];
+        assert_eq!(
+            environ(&[("USER", "alice")]).replies(&request),
+            [IAC, WILL, NEW_ENVIRON]
+        );
+        assert!(Environ::default().replies(&request).is_empty());
+        assert!(environ(&[("USER", "alice")])
+            .replies(&[IAC, DO, 24])
+            .is_empty());
+    }
+
+    #[test]
+    fn sends_every_variable_for_an_empty_request() {
+        let environ = environ(&[("USER", "alice"), ("TERM_ID", "7")]);
+        // In name order
+        let mut expected = vec![USERVAR];
+        expected.extend_from_slice(b"TERM_ID");
+        expected.push(VALUE);
+        expected.extend_from_slice(b"7");
+        expected.push(VAR);
+        expected.extend_from_slice(b"USER");
+        expected.push(VALUE);
+        expected.extend_from_slice(b"alice");
+        assert_eq!(environ.replies(&send_request(&[])), is_response(&expected));
+    }
+
+    #[test]
+    fn sends_requested_variables_and_marks_unknown_ones_undefined() {
+        let environ = environ(&[("USER", "alice"), ("TERM_ID", "7")]);
+        let mut request = vec![VAR];
+        request.extend_from_slice(b"USER");
+        request.push(USERVAR);
+        request.extend_from_slice(b"MISSING");
+
+        let mut expected = vec![VAR];
+        expected.extend_from_slice(b"USER");
+        expected.push(VALUE);
+        expected.extend_from_slice(b"alice");
+        expected.push(USERVAR);
+        expected.extend_from_slice(b"MISSING");
+        assert_eq!(
+            environ.replies(&send_request(&request)),
+            is_response(&expected)
+        );
+    }
+
+    #[test]
+    fn an_empty_name_asks_for_every_variable_of_its_type() {
+        let environ = environ(&[("USER", "alice"), ("TERM_ID", "7")]);
+        let mut expected = vec![USERVAR];
+        expected.extend_from_slice(b"TERM_ID");
+        expected.push(VALUE);
+        expected.extend_from_slice(b"7");
+        assert_eq!(
+            environ.replies(&send_request(&[USERVAR])),
+            is_response(&expected)
+        );
+    }
+
+    #[test]
+    fn escapes_type_codes_in_values() {
+        let environ = environ(&[("USER", "a\u{0}b\u{1}c\u{2}d\u{3}e")]);
+        let mut expected = vec![VAR];
+        expected.extend_from_slice(b"USER");
+        expected.push(VALUE);
+        expected.extend_from_slice(&[
+            b'a', ESC, VAR, b'b', ESC, VALUE, b'c', ESC, ESC, b'd', ESC, USERVAR, b'e',
+        ]);
+        assert_eq!(
+            environ.replies(&send_request(&[VAR])),
+            is_response(&expected)
+        );
+    }
+
+    #[test]
+    fn doubles_iac_bytes() {
+        let mut out = Vec::new();
+        escape_into(&mut out, &[b'a', IAC, b'b', VALUE]);
+        assert_eq!(out, [b'a', IAC, IAC, b'b', ESC, VALUE]);
+    }
+
+    #[test]
+    fn parses_escaped_and_iac_escaped_names() {
+        // "A<VALUE>B" escaped with ESC, then "C<IAC>" with the IAC doubled
+        // on the wire
+        let request = send_request(&[USERVAR, b'A', ESC, VALUE, b'B', USERVAR, b'C', IAC, IAC]);
+        let (payload, len) = subnegotiation(&request[3..]);
+        assert_eq!(len, request.len() - 3);
+        assert_eq!(
+            parse_send(&payload[1..]),
+            [
+                (USERVAR, "A\u{1}B".to_string()),
+                (USERVAR, String::from_utf8_lossy(&[b'C', IAC]).into_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn secrets_override_config_and_are_redacted() {
+        let mut environ = environ(&[("USER", "alice"), ("ACCT", "public")]);
+        let password = "hunter2".to_string();
+        let key = format!("{}ACCT", KEY_PREFIX);
+        environ.extend_secrets([(&key, password.as_str())]);
+
+        let debug = format!("{:?}", environ);
+        assert!(debug.contains("alice"), "{}", debug);
+        assert!(!debug.contains("hunter2"), "{}", debug);
+        assert!(!debug.contains("public"), "{}", debug);
+
+        let mut request = vec![VAR];
+        request.extend_from_slice(b"ACCT");
+        let mut expected = vec![VAR];
+        expected.extend_from_slice(b"ACCT");
+        expected.push(VALUE);
+        expected.extend_from_slice(b"hunter2");
+        assert_eq!(
+            environ.replies(&send_request(&request)),
+            is_response(&expected)
+        );
+    }
+}
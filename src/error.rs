@@ -0,0 +1,241 @@
+//! Typed errors for the Telnet connection task
+//!
+//! anyhow is only used at the provider trait boundary; inside the connection
+//! task failures carry a [`TelnetErrorKind`] so reconnect decisions, logs and
+//! metrics can tell them apart.
+
+use std::io;
+
+use thiserror::Error;
+
+use crate::mccp::MccpError;
+
+/// Why a Telnet connection failed or ended
+#[derive(Debug, Error)]
+pub enum TelnetError {
+    /// The host name could not be resolved to a usable address
+    #[error("failed to resolve {host}: {reason}")]
+    Dns { host: String, reason: String },
+    /// The server actively refused the connection
+    #[error("connection refused: {0}")]
+    Refused(#[source] io::Error),
+    /// Connecting or reading timed out
+    #[error("connection timed out")]
+    Timeout,
+    /// The server closed the connection
+    #[error("connection closed by server")]
+    ServerClosed,
+    /// The server sent the configured reconnect sentinel, or the stall
+    /// watchdog asked for a fresh connection
+    #[error("reconnect requested")]
+    ReconnectRequested,
+    /// Nothing at all was read from the socket for `idle_reconnect_after_ms`
+    #[error("no data received for {0:?}")]
+    Idle(std::time::Duration),
+    /// The server kept negotiating options past `negotiation_timeout_ms`
+    /// without sending data
+    #[error("option negotiation did not finish within {0:?}")]
+    NegotiationTimeout(std::time::Duration),
+    /// Any other socket error
+    #[error("I/O error: {0}")]
+    Io(#[source] io::Error),
+    /// The message handler rejected received data
+    #[error("message handler failed: {0:#}")]
+    Handler(anyhow::Error),
+    /// `compression_required` is set and the server didn't offer MCCP
+    /// compression by its first data
+    #[error("server did not offer MCCP compression, which compression_required demands")]
+    CompressionNotOffered,
+    /// The server's compressed data could not be decompressed
+    #[error("{0}")]
+    Compression(#[source] MccpError),
+    /// Data to send contained a command from `command_blocklist`
+    #[error("blocked command: {0}")]
+    Blocked(String),
+}
+
+/// Category of a [`TelnetError`], used for reconnect decisions and metric labels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TelnetErrorKind {
+    Dns,
+    Refused,
+    Timeout,
+    ServerClosed,
+    ReconnectRequested,
+    Idle,
+    NegotiationTimeout,
+    Io,
+    Handler,
+    Compression,
+    Blocked,
+}
+
+impl TelnetError {
+    /// Category of this error
+    pub fn kind(&self) -> TelnetErrorKind {
+        match self {
+            TelnetError::Dns { .. } => TelnetErrorKind::Dns,
+            TelnetError::Refused(_) => TelnetErrorKind::Refused,
+            TelnetError::Timeout => TelnetErrorKind::Timeout,
+            TelnetError::ServerClosed => TelnetErrorKind::ServerClosed,
+            TelnetError::ReconnectRequested => TelnetErrorKind::ReconnectRequested,
+            TelnetError::Idle(_) => TelnetErrorKind::Idle,
+            TelnetError::NegotiationTimeout(_) => TelnetErrorKind::NegotiationTimeout,
+            TelnetError::Io(_) => TelnetErrorKind::Io,
+            TelnetError::Handler(_) => TelnetErrorKind::Handler,
+            TelnetError::CompressionNotOffered | TelnetError::Compression(_) => {
+                TelnetErrorKind::Compression
+            }
+            TelnetError::Blocked(_) => TelnetErrorKind::Blocked,
+        }
+    }
+}
+
+impl TelnetErrorKind {
+    /// Stable lowercase name, used as a log field and metric label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TelnetErrorKind::Dns => "dns",
+            TelnetErrorKind::Refused => "refused",
+            TelnetErrorKind::Timeout => "timeout",
+            TelnetErrorKind::ServerClosed => "server_closed",
+            TelnetErrorKind::ReconnectRequested => "reconnect_requested",
+            TelnetErrorKind::Idle => "idle",
+            TelnetErrorKind::NegotiationTimeout => "negotiation_timeout",
+            TelnetErrorKind::Io => "io",
+            TelnetErrorKind::Handler => "handler",
+            TelnetErrorKind::Compression => "compression",
+            TelnetErrorKind::Blocked => "blocked",
+        }
+    }
+
+    /// Whether the connection ended cleanly rather than failing
+    ///
+    /// A clean close means the previous connection worked, so the reconnect
+    /// backoff starts over instead of growing.
+    pub fn is_clean_close(&self) -> bool {
+        matches!(
+            self,
+            TelnetErrorKind::ServerClosed
+                | TelnetErrorKind::ReconnectRequested
+                | TelnetErrorKind::Idle
+        )
+    }
+}
+
+impl From<io::Error> for TelnetError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::ConnectionRefused => TelnetError::Refused(e),
+            io::ErrorKind::TimedOut => TelnetError::Timeout,
+            io::ErrorKind::UnexpectedEof => TelnetError::ServerClosed,
+            _ => TelnetError::Io(e),
+        }
+    }
+}
+
+impl From<tokio::time::error::Elapsed> for TelnetError {
+    fn from(_: tokio::time::error::Elapsed) -> Self {
+        TelnetError::Timeout
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn every_error_maps_to_its_kind_and_reconnect_policy() {
+        use TelnetErrorKind::*;
+
+        let io = || io::Error::other("broken");
+        // error, kind, label, clean close (backoff starts over)
+        let table = [
+            (
+                TelnetError::Dns {
+                    host: "nowhere".to_string(),
+                    reason: "no such host".to_string(),
+                },
+                Dns,
+                "dns",
+                false,
+            ),
+            (TelnetError::Refused(io()), Refused, "refused", false),
+            (TelnetError::Timeout, Timeout, "timeout", false),
+            (
+                TelnetError::ServerClosed,
+                ServerClosed,
+                "server_closed",
+                true,
+            ),
+            (
+                TelnetError::ReconnectRequested,
+                ReconnectRequested,
+                "reconnect_requested",
+                true,
+            ),
+            (
+                TelnetError::Idle(Duration::from_secs(60)),
+                Idle,
+                "idle",
+                true,
+            ),
+            (
+                TelnetError::NegotiationTimeout(Duration::from_secs(5)),
+                NegotiationTimeout,
+                "negotiation_timeout",
+                false,
+            ),
+            (TelnetError::Io(io()), Io, "io", false),
+            (
+                TelnetError::Handler(anyhow::anyhow!("rejected")),
+                Handler,
+                "handler",
+                false,
+            ),
+            (
+                TelnetError::CompressionNotOffered,
+                Compression,
+                "compression",
+                false,
+            ),
+            (
+                TelnetError::Blocked("reload".to_string()),
+                Blocked,
+                "blocked",
+                false,
+            ),
+        ];
+        for (error, kind, label, clean) in table {
+            assert_eq!(error.kind(), kind, "{}", error);
+            assert_eq!(kind.as_str(), label);
+            assert_eq!(kind.is_clean_close(), clean, "{}", label);
+        }
+    }
+
+    #[test]
+    fn io_errors_map_by_kind() {
+        let kind_of = |kind| TelnetError::from(io::Error::from(kind)).kind();
+        assert_eq!(
+            kind_of(io::ErrorKind::ConnectionRefused),
+            TelnetErrorKind::Refused
+        );
+        assert_eq!(kind_of(io::ErrorKind::TimedOut), TelnetErrorKind::Timeout);
+        assert_eq!(
+            kind_of(io::ErrorKind::UnexpectedEof),
+            TelnetErrorKind::ServerClosed
+        );
+        assert_eq!(kind_of(io::ErrorKind::ConnectionReset), TelnetErrorKind::Io);
+        assert_eq!(kind_of(io::ErrorKind::BrokenPipe), TelnetErrorKind::Io);
+    }
+
+    #[tokio::test]
+    async fn an_elapsed_timeout_is_a_timeout() {
+        let elapsed = tokio::time::timeout(Duration::ZERO, std::future::pending::<()>())
+            .await
+            .unwrap_err();
+        assert_eq!(TelnetError::from(elapsed).kind(), TelnetErrorKind::Timeout);
+    }
+}
@@ -0,0 +1,1241 @@
+//! Per-connection forwarding of received Telnet data to a component
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use tokio::runtime::RuntimeFlavor;
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, trace, warn};
+
+use crate::aggregate::{Aggregated, NumericAggregator};
+use crate::autotune::{SizeTuner, WINDOW};
+use crate::client::ComponentClient;
+use crate::config::{ComponentDownAction, LinkConfig};
+use crate::context::ConnectionContext;
+use crate::controls::ControlTokenizer;
+use crate::debounce::Debouncer;
+use crate::dedupe::KeyDeduplicator;
+use crate::delta::DeltaEncoder;
+use crate::dispatch::{DeliveryLimiter, DeliveryPermit, DeliveryPriority};
+use crate::enrich::MessageEnricher;
+use crate::envelope::{now_ms, Envelope, EnvelopeEncoder, EXPIRY_HEADER};
+use crate::group::ConnectionGroups;
+use crate::keywords::{KeywordFilter, KeywordVerdict};
+use crate::metrics::{LinkMetrics, Registry};
+use crate::ndjson::NdjsonBatch;
+use crate::ordering::{DeliveryOrdering, Resequencer};
+use crate::pool::BufferPool;
+use crate::priority::{with_priority_header, PriorityExtractor};
+use crate::provider::{create_broker_message, types};
+use crate::report::DeliveryReport;
+use crate::route::Router;
+use crate::sample::Sampler;
+use crate::sequence::{extract_sequence, SequenceGap, SequenceGapDetector};
+use crate::stats::{ConnectionStats, LinkState};
+use crate::transform::TransformRules;
+use crate::unstuff::{CrUnstuffer, Unstuffer};
+
+/// Forwarding pipeline run for every message received on one connection
+pub struct Forwarder {
+    /// Component, address and subjects of the connection
+    context: ConnectionContext,
+    /// Link configuration
+    config: Arc<LinkConfig>,
+    /// Runtime state shared with the provider
+    stats: Arc<ConnectionStats>,
+    /// Token stopping this connection
+    cancel: CancellationToken,
+    /// Redundant connection groups, for standby suppression
+    connection_groups: Arc<ConnectionGroups>,
+    /// Transformation rules registered by components
+    transform_rules: Arc<TransformRules>,
+    /// Component receiving each subject's messages
+    router: Router,
+    /// Envelope encoder, if envelope mode was negotiated
+    encoder: Option<Box<dyn EnvelopeEncoder>>,
+    /// Reusable buffers for encoded envelopes
+    buffers: Arc<BufferPool>,
+    /// Removal of Telnet's `CR NUL` stuffing, if configured
+    cr_unstuffer: Option<CrUnstuffer>,
+    /// De-framer for byte-stuffed records, if configured
+    unstuffer: Option<Unstuffer>,
+    /// Sequence number tracking, if gap detection is enabled
+    gap_detector: Option<SequenceGapDetector>,
+    /// Reordering of numbered records, if ordered delivery is configured
+    resequencer: Option<Resequencer>,
+    /// Named tokens for control characters, if configured
+    control_tokens: Option<ControlTokenizer>,
+    /// Required and blocked body keywords, if configured
+    keywords: Option<KeywordFilter>,
+    /// Suppression of identical messages in quick succession, if configured
+    debouncer: Option<Debouncer>,
+    /// Selection of the messages forwarded from a high-rate feed, if configured
+    sampler: Option<Sampler>,
+    /// Repeat suppression by key field, if configured
+    deduplicator: Option<KeyDeduplicator>,
+    /// Reduction of structured records to their changes, if configured
+    delta: Option<DeltaEncoder>,
+    /// Sliding-window summary of numeric readings, if configured
+    aggregator: Option<NumericAggregator>,
+    /// Records of the current read, if NDJSON output is enabled
+    ndjson: Option<NdjsonBatch>,
+    /// Provider-computed header lines, if configured
+    enricher: Option<MessageEnricher>,
+    /// Per-message priority read from the content, if configured
+    prioritizer: Option<PriorityExtractor>,
+    /// Message size and delivery latency instruments
+    metrics: Arc<LinkMetrics>,
+    /// Periodic delivery summary and throttled failure logging
+    report: Arc<DeliveryReport>,
+    /// Provider-wide in-flight delivery limit
+    delivery_limiter: Arc<DeliveryLimiter>,
+    /// Messages produced by the data being forwarded, not yet handed out
+    pending: Vec<Delivery>,
+    /// Whether to stop the connection once the pending messages are sent
+    stop_after_sending: bool,
+    /// Reloaded config, of which the live keys are adopted between reads
+    updates: Option<watch::Receiver<Arc<LinkConfig>>>,
+    /// Record size tracking, if `max_message_size` is auto-tuned
+    size_tuner: Option<SizeTuner>,
+    /// Where an auto-tuned `max_message_size` is published for the reader
+    tuned: Option<watch::Sender<Arc<LinkConfig>>>,
+    /// Where messages the component failed to handle are republished, if
+    /// configured
+    dead_letter: Option<DeadLetter>,
+}
+
+impl Forwarder {
+    /// Create a forwarder for a connection using the negotiated protocol version
+    pub fn new(
+        context: ConnectionContext,
+        config: Arc<LinkConfig>,
+        protocol_version: u8,
+        stats: Arc<ConnectionStats>,
+        cancel: CancellationToken,
+        connection_groups: Arc<ConnectionGroups>,
+        transform_rules: Arc<TransformRules>,
+    ) -> Self {
+        let encoder = config
+            .envelope_format
+            .filter(|_| protocol_version >= 2)
+            .map(|format| format.encoder());
+
+        let unstuffer = config
+            .unstuff
+            .map(|unstuff| Unstuffer::new(unstuff, config.max_message_size));
+        let cr_unstuffer = config.unstuff_cr.then(CrUnstuffer::default);
+
+        let keywords = KeywordFilter::new(
+            &config.body_required_keywords,
+            &config.body_blocked_keywords,
+        )
+        .unwrap_or_else(|e| {
+            error!("Keyword filter disabled, failed to build it: {}", e);
+            None
+        });
+
+        let control_tokens = config
+            .tokenize_controls
+            .then(|| ControlTokenizer::new(config.control_token_style));
+
+        let debouncer = config.debounce_window().map(Debouncer::new);
+
+        let sampler = config.sample_rate.map(Sampler::new);
+
+        let deduplicator = config
+            .dedupe_key
+            .clone()
+            .map(|key| KeyDeduplicator::new(key, config.dedupe_window()));
+
+        let gap_detector = config
+            .sequence_gap_detection
+            .then(SequenceGapDetector::default);
+
+        let resequencer = (config.delivery_ordering == DeliveryOrdering::Ordered)
+            .then(|| Resequencer::new(config.ordering_max_wait()));
+
+        let delta = config
+            .delta_only
+            .then(|| DeltaEncoder::new(config.delta_key.clone(), config.delta_snapshot_interval()));
+
+        let aggregator = config.aggregate_numeric.clone().map(NumericAggregator::new);
+
+        let ndjson = config.ndjson.then(NdjsonBatch::default);
+
+        let enricher = (!config.enrichment_fields.is_empty())
+            .then(|| MessageEnricher::new(config.enrichment_fields.clone()));
+
+        let prioritizer = config
+            .priority_byte_offset
+            .map(|offset| PriorityExtractor::new(offset, config.priority_byte_map.clone()));
+
+        let size_tuner = config
+            .auto_tune_max_message_size
+            .then(|| SizeTuner::new(config.auto_tune_max_factor));
+
+        let router = Router::new(&config.routing_rules, context.client.clone());
+
+        let dead_letter = config
+            .dead_letter_subject(&context.source_id)
+            .map(|subject| DeadLetter {
+                client: router.client_for(&subject).clone(),
+                subject: subject.into(),
+            });
+
+        Self {
+            metrics: Arc::new(LinkMetrics::new(&context.source_id)),
+            report: Arc::new(DeliveryReport::new(context.source_id.clone())),
+            context,
+            config,
+            stats,
+            cancel,
+            connection_groups,
+            transform_rules,
+            router,
+            encoder,
+            buffers: Arc::default(),
+            cr_unstuffer,
+            unstuffer,
+            control_tokens,
+            keywords,
+            debouncer,
+            sampler,
+            deduplicator,
+            delta,
+            aggregator,
+            ndjson,
+            enricher,
+            prioritizer,
+            gap_detector,
+            resequencer,
+            delivery_limiter: Arc::default(),
+            pending: Vec::new(),
+            stop_after_sending: false,
+            updates: None,
+            size_tuner,
+            tuned: None,
+            dead_letter,
+        }
+    }
+
+    /// Share `limiter`'s delivery capacity with other links
+    pub fn with_delivery_limiter(mut self, limiter: Arc<DeliveryLimiter>) -> Self {
+        self.delivery_limiter = limiter;
+        self
+    }
+
+    /// Record delivery metrics into `registry` rather than the global one
+    pub fn with_metrics_registry(mut self, registry: Arc<Registry>) -> Self {
+        self.metrics = Arc::new(LinkMetrics::with_registry(
+            registry,
+            &self.context.source_id,
+        ));
+        self
+    }
+
+    /// Adopt changes to live keys sent to `updates`
+    pub fn with_config_updates(mut self, updates: watch::Receiver<Arc<LinkConfig>>) -> Self {
+        self.updates = Some(updates);
+        self
+    }
+
+    /// Publish an auto-tuned `max_message_size` to `tuned`, so the
+    /// connection's reader adopts it like a reloaded live key
+    pub fn with_tuned_config(mut self, tuned: watch::Sender<Arc<LinkConfig>>) -> Self {
+        self.tuned = Some(tuned);
+        self
+    }
+
+    /// Prepare filtered Telnet data for the component
+    ///
+    /// With byte-stuffed framing configured, each completed record is
+    /// forwarded as its own message; otherwise the data is one message.
+    /// With ordered delivery, numbered records may be held back and
+    /// forwarded by a later call, once the records before them arrive.
+    /// With NDJSON output, all messages from the data are sent as one.
+    /// `received` is when the data was read from the socket, which is
+    /// earlier than this call when reading has run ahead.
+    /// The returned deliveries (including any events) are sent, in order,
+    /// by awaiting [`Deliveries::send`].
+    ///
+    /// Data of at least `offload_threshold_bytes` is forwarded in
+    /// [`tokio::task::block_in_place`] on a multi-threaded runtime, so the
+    /// worker's other tasks (including other connections' reads) move to
+    /// another worker instead of waiting for it.
+    pub fn forward(&mut self, data: Bytes, received: Instant) -> anyhow::Result<Deliveries> {
+        let offload = self
+            .config
+            .offload_threshold_bytes
+            .is_some_and(|threshold| data.len() >= threshold);
+        if offload && on_multi_thread_runtime() {
+            return tokio::task::block_in_place(|| self.forward_data(data, received));
+        }
+        self.forward_data(data, received)
+    }
+
+    fn forward_data(&mut self, data: Bytes, received: Instant) -> anyhow::Result<Deliveries> {
+        if let Some(updates) = self
+            .updates
+            .as_mut()
+            .filter(|rx| rx.has_changed().unwrap_or(false))
+        {
+            self.config = updates.borrow_and_update().clone();
+            if let Some(unstuffer) = &mut self.unstuffer {
+                unstuffer.set_max_record_size(self.config.max_message_size);
+            }
+        }
+        let data = match &mut self.cr_unstuffer {
+            Some(cr_unstuffer) => cr_unstuffer.push(data),
+            None => data,
+        };
+        let (records, dropped) = match &mut self.unstuffer {
+            None => (vec![data], 0),
+            Some(unstuffer) => {
+                let (records, dropped) = unstuffer.push(&data);
+                if dropped > 0 {
+                    warn!(
+                        "Dropped {} byte-stuffed record(s) exceeding limit {}",
+                        dropped, self.config.max_message_size
+                    );
+                }
+                (records, dropped)
+            }
+        };
+        self.tune_max_message_size(&records, dropped);
+        for record in records {
+            match &mut self.resequencer {
+                None => self.forward_message(record, received)?,
+                Some(resequencer) => {
+                    for (record, received) in resequencer.push(record, received) {
+                        self.forward_message(record, received)?;
+                    }
+                }
+            }
+        }
+
+        if let Some(body) = self.ndjson.as_mut().and_then(NdjsonBatch::take) {
+            self.publish(body, received, None);
+        }
+
+        // The summary follows the message that reached the limit
+        if self.stop_after_sending
+            && self.config.message_limit_event
+            && self.stats.state() == LinkState::MessageLimitReached
+        {
+            let max_messages = self.config.max_messages.unwrap_or_default();
+            self.send(create_message_limit_message(
+                &self.context.message_limit_subject,
+                max_messages,
+                self.stats.bytes_forwarded(),
+            ));
+        }
+
+        Ok(Deliveries {
+            report: self.report.clone(),
+            stats: self.stats.clone(),
+            metrics: self.metrics.clone(),
+            items: std::mem::take(&mut self.pending),
+            dead_letter: self.dead_letter.clone(),
+            slow_threshold: self.config.slow_consumer_threshold(),
+            stop: std::mem::take(&mut self.stop_after_sending).then(|| self.cancel.clone()),
+        })
+    }
+
+    /// Record the sizes of `records` and of `dropped` oversized records,
+    /// adopting the `max_message_size` proposed for the last window of
+    /// records
+    fn tune_max_message_size(&mut self, records: &[Bytes], dropped: usize) {
+        let Some(tuner) = &mut self.size_tuner else {
+            return;
+        };
+        let oversized = self.config.max_message_size.saturating_add(1);
+        let sizes = records
+            .iter()
+            .map(Bytes::len)
+            .chain(std::iter::repeat_n(oversized, dropped));
+        let Some(max) = sizes.filter_map(|size| tuner.observe(size)).last() else {
+            return;
+        };
+        if max == self.config.max_message_size {
+            return;
+        }
+
+        info!(
+            "Auto-tuned max_message_size from {} to {} bytes",
+            self.config.max_message_size, max
+        );
+        // Warn once when tuning down starts dropping records, not on every
+        // adjustment while they are being dropped
+        let longer = tuner.longer_than(max);
+        if longer > 0 && tuner.longer_than(self.config.max_message_size) == 0 {
+            warn!(
+                "Auto-tuned max_message_size {} is below {} of the last {} records; records that long are dropped from now on",
+                max, longer, WINDOW
+            );
+        }
+        let tuned = Arc::new(LinkConfig {
+            max_message_size: max,
+            ..(*self.config).clone()
+        });
+        if let Some(unstuffer) = &mut self.unstuffer {
+            unstuffer.set_max_record_size(max);
+        }
+        if let Some(sender) = &self.tuned {
+            sender.send_replace(tuned.clone());
+        }
+        self.config = tuned;
+    }
+
+    /// Forward one message to the component
+    fn forward_message(&mut self, data: Bytes, received: Instant) -> anyhow::Result<()> {
+        self.detect_gap(&data);
+        let priority = self
+            .prioritizer
+            .as_ref()
+            .and_then(|prioritizer| prioritizer.extract(&data));
+
+        // Standby members of a connection group stay silent until needed
+        if let Some(group) = &self.config.connection_group {
+            if !self
+                .connection_groups
+                .is_active(group, &self.context.source_id)
+            {
+                return Ok(());
+            }
+        }
+
+        // Don't forward to a component the watchdog has marked down
+        if self.config.component_down_action != ComponentDownAction::None
+            && !self.stats.is_component_reachable()
+        {
+            return Ok(());
+        }
+
+        // Forward only the sample of a high-rate feed, before any work is
+        // spent on the rest
+        if let Some(sampler) = &mut self.sampler {
+            if !sampler.keep(received) {
+                self.stats.record_sampled_out();
+                return Ok(());
+            }
+        }
+
+        // Make control characters in text readable
+        let data = match &self.control_tokens {
+            Some(control_tokens) => control_tokens.tokenize(data),
+            None => data,
+        };
+
+        // Apply component-registered transformations
+        let Some(data) = self.transform_rules.apply(&self.context.subject, data) else {
+            return Ok(());
+        };
+
+        // Forward only bodies with a required and without a blocked keyword
+        if let Some(keywords) = &self.keywords {
+            let verdict = keywords.check(&data);
+            if verdict != KeywordVerdict::Forward {
+                trace!(
+                    "Dropping record from {} by keyword filter: {:?}",
+                    self.context.address,
+                    verdict
+                );
+                self.stats.record_keyword_verdict(verdict);
+                return Ok(());
+            }
+        }
+
+        // Drop identical messages repeated within the debounce window
+        if let Some(debouncer) = &mut self.debouncer {
+            if debouncer.is_repeat(&data, received) {
+                trace!("Debouncing repeated message from {}", self.context.address);
+                self.stats.record_dropped();
+                return Ok(());
+            }
+        }
+
+        // Drop repeats of a recently forwarded key value
+        if let Some(deduplicator) = &mut self.deduplicator {
+            if deduplicator.is_duplicate(&data) {
+                trace!("Suppressing duplicate record for {}", self.context.address);
+                return Ok(());
+            }
+        }
+
+        // Reduce structured records to what changed since the previous one
+        let data = match &mut self.delta {
+            None => data,
+            Some(delta) => match delta.encode(data, received) {
+                Some(data) => data,
+                None => {
+                    trace!("Dropping unchanged record for {}", self.context.address);
+                    return Ok(());
+                }
+            },
+        };
+
+        // Stop forwarding once the message limit is reached, after sending
+        // the message that reaches it
+        let max_messages = self.config.max_messages;
+        let Some(count) = self.stats.try_count_message(max_messages) else {
+            self.message_limit_reached();
+            return Ok(());
+        };
+        if Some(count) == max_messages {
+            self.message_limit_reached();
+        }
+
+        // Stop forwarding once the lifetime byte quota is used up
+        let quota = self.config.lifetime_byte_quota;
+        if !self.stats.try_forward(data.len() as u64, quota) {
+            self.quota_exceeded();
+            return Ok(());
+        }
+
+        // Replace numeric readings with the window's summary
+        match &mut self.aggregator {
+            None => self.emit(data, received, priority),
+            Some(aggregator) => {
+                for output in aggregator.push(&data, received) {
+                    match output {
+                        Aggregated::Summary(summary) => {
+                            let body = serde_json::to_vec(&summary).unwrap_or_default();
+                            self.emit(body.into(), received, None);
+                        }
+                        Aggregated::Unparsed(line) => {
+                            self.deliver(
+                                create_broker_message(line, &self.context.unparsed_subject),
+                                received,
+                                priority,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Encode data as configured and queue it on the data subject
+    fn emit(&mut self, data: Bytes, received: Instant, priority: Option<u8>) {
+        let body = match &self.encoder {
+            Some(encoder) => {
+                let mut envelope =
+                    Envelope::new(encoder.format(), &self.context.address, Vec::from(data));
+                if let Some(ttl) = self.config.message_ttl() {
+                    envelope = envelope.with_ttl(ttl);
+                }
+                let mut body = self.buffers.take();
+                match encoder.encode(&envelope, &mut body) {
+                    Ok(()) => body.freeze(),
+                    Err(e) => {
+                        error!("Failed to encode {:?} envelope: {}", encoder.format(), e);
+                        return;
+                    }
+                }
+            }
+            None => data,
+        };
+
+        if let Some(batch) = &mut self.ndjson {
+            if let Err(e) = batch.push(&body, self.encoder.is_some()) {
+                warn!(
+                    "Dropping non-text record from {} in NDJSON output: {}",
+                    self.context.address, e
+                );
+            }
+            return;
+        }
+
+        self.publish(body, received, priority);
+    }
+
+    /// Queue an encoded body on the data subject, with any expiry, priority
+    /// and enrichment header lines
+    fn publish(&mut self, body: Bytes, received: Instant, priority: Option<u8>) {
+        // Envelopes carry the expiry in their metadata instead
+        let body = match self.config.message_ttl().filter(|_| self.encoder.is_none()) {
+            Some(ttl) => with_expiry_header(body, received, ttl),
+            None => body,
+        };
+        let body = match priority {
+            Some(level) => with_priority_header(body, level),
+            None => body,
+        };
+        let body = match &self.enricher {
+            Some(enricher) => enricher.enrich(&body, received, &self.stats),
+            None => body,
+        };
+
+        // Convert Telnet message to a standard broker-message
+        self.metrics.message_size(body.len());
+        self.deliver(
+            create_broker_message(body, &self.context.subject),
+            received,
+            priority,
+        );
+    }
+
+    /// Report lost messages if the record's sequence number skips ahead
+    fn detect_gap(&mut self, data: &[u8]) {
+        let Some(detector) = &mut self.gap_detector else {
+            return;
+        };
+        let Some(gap) = extract_sequence(data).and_then(|seq| detector.observe(seq)) else {
+            return;
+        };
+
+        warn!(
+            gap_size = gap.gap,
+            "Sequence gap on {}: expected {}, received {}",
+            self.context.address,
+            gap.expected,
+            gap.received
+        );
+        self.stats.set_gap_count(detector.gap_count());
+        self.send(create_gap_message(&self.context.gap_subject, gap));
+    }
+
+    /// Move to the quota-exceeded state and stop the connection once the
+    /// queued messages are sent
+    fn quota_exceeded(&mut self) {
+        if self.stats.state() == LinkState::QuotaExceeded {
+            return;
+        }
+
+        warn!(
+            "Lifetime byte quota reached for component {}, stopping link",
+            self.context.source_id
+        );
+        self.stats.set_state(LinkState::QuotaExceeded);
+        if self.config.quota_exceeded_event {
+            self.send(create_quota_exceeded_message(
+                &self.context.quota_exceeded_subject,
+                self.stats.bytes_forwarded(),
+                self.config.lifetime_byte_quota.unwrap_or_default(),
+            ));
+        }
+        self.stop_after_sending = true;
+    }
+
+    /// Move to the message-limit-reached state and stop the connection once
+    /// the queued messages are sent
+    fn message_limit_reached(&mut self) {
+        if self.stats.state() == LinkState::MessageLimitReached {
+            return;
+        }
+
+        warn!(
+            "Message limit reached for component {}, stopping link",
+            self.context.source_id
+        );
+        self.stats.set_state(LinkState::MessageLimitReached);
+        self.stop_after_sending = true;
+    }
+
+    /// Queue received data for the component, recording its latency
+    ///
+    /// The message is dropped if the share of the provider's delivery
+    /// capacity for its priority (the link's, unless the message has its
+    /// own priority level) is used up.
+    fn deliver(&mut self, message: types::BrokerMessage, received: Instant, level: Option<u8>) {
+        let priority = level
+            .map(DeliveryPriority::from_level)
+            .unwrap_or(self.config.delivery_priority);
+        let Some(permit) = self.delivery_limiter.try_acquire(priority) else {
+            self.stats.record_shed();
+            trace!(
+                "Delivery capacity for {:?} messages used up, dropping message for {}",
+                priority,
+                self.context.source_id
+            );
+            return;
+        };
+
+        self.stats.record_forwarded_message();
+        self.metrics.delivery_started();
+        self.pending.push(Delivery {
+            client: self.router.client_for(&message.subject).clone(),
+            message,
+            timing: Some((permit, received)),
+        });
+    }
+
+    /// Queue an event for the component
+    fn send(&mut self, message: types::BrokerMessage) {
+        self.pending.push(Delivery {
+            client: self.router.client_for(&message.subject).clone(),
+            message,
+            timing: None,
+        });
+    }
+}
+
+/// A message waiting to be sent to the component
+struct Delivery {
+    /// Component the message is routed to
+    client: Arc<ComponentClient>,
+    message: types::BrokerMessage,
+    /// For data messages, the delivery slot held until sent and the time
+    /// the data was read
+    timing: Option<(DeliveryPermit, Instant)>,
+}
+
+/// Subject and component that messages failing delivery are republished to
+#[derive(Clone)]
+struct DeadLetter {
+    subject: Arc<str>,
+    client: Arc<ComponentClient>,
+}
+
+/// Messages produced by one call to [`Forwarder::forward`]
+pub struct Deliveries {
+    report: Arc<DeliveryReport>,
+    stats: Arc<ConnectionStats>,
+    metrics: Arc<LinkMetrics>,
+    items: Vec<Delivery>,
+    dead_letter: Option<DeadLetter>,
+    /// Latency from read to handled above which the component is reported
+    /// as slow
+    slow_threshold: Option<Duration>,
+    /// Token to trigger once everything is sent, stopping the connection
+    stop: Option<CancellationToken>,
+}
+
+impl Deliveries {
+    /// Send every message in order, waiting for each to be handled
+    ///
+    /// A message the component fails to handle is logged (throttled) and
+    /// republished on the dead-letter subject if one is configured, or
+    /// skipped; it doesn't affect the Telnet connection.
+    pub async fn send(mut self) -> anyhow::Result<()> {
+        let items = std::mem::take(&mut self.items);
+        for delivery in items {
+            match delivery.client.send(&delivery.message).await {
+                Ok(()) => {
+                    self.report.delivered(delivery.message.body.len());
+                    self.check_latency(&delivery);
+                }
+                Err(e) => {
+                    self.stats.record_delivery_failure();
+                    self.report.failed(&e);
+                    if let Some(dead_letter) = &self.dead_letter {
+                        let message =
+                            create_dead_letter_message(&dead_letter.subject, &delivery.message, &e);
+                        match dead_letter.client.send(&message).await {
+                            Ok(()) => self.stats.record_dead_lettered(),
+                            Err(e) => self.report.failed(&e.context(format!(
+                                "failed to dead-letter message on {}",
+                                dead_letter.subject
+                            ))),
+                        }
+                    }
+                }
+            }
+            if let Some((permit, received)) = delivery.timing {
+                self.metrics.delivery_finished(received);
+                drop(permit);
+            }
+        }
+        if let Some(stop) = self.stop {
+            stop.cancel();
+        }
+        Ok(())
+    }
+
+    /// Report a data message the component took longer than the slow
+    /// consumer threshold to handle
+    fn check_latency(&self, delivery: &Delivery) {
+        let (Some(threshold), Some((_, received))) = (self.slow_threshold, &delivery.timing) else {
+            return;
+        };
+        let latency = received.elapsed();
+        if latency > threshold {
+            self.stats.record_slow_delivery();
+            self.report.slow(latency, threshold);
+        }
+    }
+
+    /// Take the messages as if the component had handled them instantly
+    ///
+    /// Used by the startup self-test to inspect the pipeline's output.
+    pub fn into_messages(self) -> Vec<types::BrokerMessage> {
+        self.items
+            .into_iter()
+            .map(|delivery| {
+                if let Some((_, received)) = delivery.timing {
+                    self.metrics.delivery_finished(received);
+                }
+                delivery.message
+            })
+            .collect()
+    }
+
+    /// Drop every message as if the component had handled it instantly,
+    /// returning the number of messages and their body bytes
+    ///
+    /// Used by the benchmark to measure the pipeline without a component.
+    pub fn discard(self) -> (u64, u64) {
+        let mut bytes = 0;
+        for delivery in &self.items {
+            bytes += delivery.message.body.len() as u64;
+            if let Some((_, received)) = delivery.timing {
+                self.metrics.delivery_finished(received);
+            }
+        }
+        if let Some(stop) = self.stop {
+            stop.cancel();
+        }
+        (self.items.len() as u64, bytes)
+    }
+}
+
+/// Create the event sent when a link's lifetime byte quota is reached
+///
+/// The subject is "telnet.<host>:<port>.quota_exceeded" and the body is a
+/// JSON object with the bytes forwarded and the configured quota.
+fn create_quota_exceeded_message(
+    subject: &str,
+    bytes_forwarded: u64,
+    quota: u64,
+) -> types::BrokerMessage {
+    let body = serde_json::json!({
+        "bytes_forwarded": bytes_forwarded,
+        "quota": quota,
+    });
+    types::BrokerMessage {
+        subject: subject.to_string(),
+        body: body.to_string().into_bytes().into(),
+        reply_to: None,
+    }
+}
+
+/// Create the summary sent when a link's message limit is reached
+///
+/// The subject is "telnet.<host>:<port>.message_limit_reached" and the body
+/// is a JSON object with the messages and bytes forwarded.
+fn create_message_limit_message(
+    subject: &str,
+    max_messages: u64,
+    bytes_forwarded: u64,
+) -> types::BrokerMessage {
+    let body = serde_json::json!({
+        "messages_forwarded": max_messages,
+        "bytes_forwarded": bytes_forwarded,
+    });
+    types::BrokerMessage {
+        subject: subject.to_string(),
+        body: body.to_string().into_bytes().into(),
+        reply_to: None,
+    }
+}
+
+/// Create the message republishing `failed` on the dead-letter subject
+///
+/// Like other provider metadata, the failure is described in header lines
+/// before the original body: `x-telnet-original_subject`, `x-telnet-error`
+/// (on one line) and `x-telnet-failed_at_ms`.
+fn create_dead_letter_message(
+    subject: &str,
+    failed: &types::BrokerMessage,
+    e: &anyhow::Error,
+) -> types::BrokerMessage {
+    let error = format!("{:#}", e).replace(['\r', '\n'], " ");
+    let header = format!(
+        "x-telnet-original_subject: {}\r\nx-telnet-error: {}\r\nx-telnet-failed_at_ms: {}\r\n",
+        failed.subject,
+        error,
+        now_ms()
+    );
+    let mut body = BytesMut::with_capacity(header.len() + failed.body.len());
+    body.put_slice(header.as_bytes());
+    body.put_slice(&failed.body);
+    types::BrokerMessage {
+        subject: subject.to_string(),
+        body: body.freeze(),
+        reply_to: failed.reply_to.clone(),
+    }
+}
+
+/// Create the event sent when a sequence number gap is detected
+///
+/// The subject is "telnet.<host>:<port>.gap" and the body is a JSON object
+/// with the expected and received sequence numbers and the gap size.
+fn create_gap_message(subject: &str, gap: SequenceGap) -> types::BrokerMessage {
+    let body = serde_json::json!({
+        "expected": gap.expected,
+        "received": gap.received,
+        "gap": gap.gap,
+    });
+    types::BrokerMessage {
+        subject: subject.to_string(),
+        body: body.to_string().into_bytes().into(),
+        reply_to: None,
+    }
+}
+
+/// `body` prefixed with an `x-telnet-expires_at_ms` header line, `ttl` after
+/// it was received
+fn with_expiry_header(body: Bytes, received: Instant, ttl: Duration) -> Bytes {
+    let received_at_ms = now_ms().saturating_sub(received.elapsed().as_millis() as u64);
+    let header = format!(
+        "{}: {}\r\n",
+        EXPIRY_HEADER,
+        received_at_ms + ttl.as_millis() as u64
+    );
+    let mut stamped = BytesMut::with_capacity(header.len() + body.len());
+    stamped.put_slice(header.as_bytes());
+    stamped.put_slice(&body);
+    stamped.freeze()
+}
+
+/// Whether this is a worker of a multi-threaded runtime, the only kind on
+/// which [`tokio::task::block_in_place`] doesn't panic
+fn on_multi_thread_runtime() -> bool {
+    tokio::runtime::Handle::try_current()
+        .is_ok_and(|handle| handle.runtime_flavor() == RuntimeFlavor::MultiThread)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::transform::Transform;
+
+    /// A forwarder for a link to localhost with `values`
+    fn forwarder(values: &[(&str, &str)]) -> Forwarder {
+        let mut config: HashMap<String, String> = values
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        config.insert("telnet_host".to_string(), "localhost".to_string());
+        let config = Arc::new(LinkConfig::from_values(&config).unwrap());
+        let context = ConnectionContext::new("test", &config.address(), &config.subject("test"));
+        Forwarder::new(
+            context,
+            config.clone(),
+            config.protocol_version,
+            Arc::default(),
+            CancellationToken::new(),
+            Arc::default(),
+            Arc::default(),
+        )
+    }
+
+    #[test]
+    fn shares_the_link_config() {
+        let config = Arc::new(
+            LinkConfig::from_values(&HashMap::from([(
+                "telnet_host".to_string(),
+                "localhost".to_string(),
+            )]))
+            .unwrap(),
+        );
+        let forwarder = Forwarder::new(
+            ConnectionContext::new("test", &config.address(), &config.subject("test")),
+            config.clone(),
+            config.protocol_version,
+            Arc::default(),
+            CancellationToken::new(),
+            Arc::default(),
+            Arc::default(),
+        );
+        // The link's config itself, not a copy of it
+        assert!(Arc::ptr_eq(&forwarder.config, &config));
+    }
+
+    #[tokio::test]
+    async fn sends_every_message_on_the_subject_rendered_for_the_link() {
+        let mut forwarder = forwarder(&[("subject_template", "feeds.{source_id}.{port}")]);
+        let subject = forwarder.context.subject.clone();
+        assert_eq!(&*subject, "feeds.test.23");
+        for read in [&b"first"[..], b"second"] {
+            let deliveries = forwarder
+                .forward(Bytes::from_static(read), Instant::now())
+                .unwrap();
+            assert_eq!(deliveries.items[0].message.subject, &*subject);
+            deliveries.discard();
+        }
+        // Rendered once, when the link was set up
+        assert!(Arc::ptr_eq(&forwarder.context.subject, &subject));
+    }
+
+    /// Bodies of the messages `data` is forwarded as
+    fn bodies(forwarder: &mut Forwarder, data: Bytes) -> Vec<Bytes> {
+        let deliveries = forwarder.forward(data, Instant::now()).unwrap();
+        let bodies = deliveries
+            .items
+            .iter()
+            .map(|delivery| delivery.message.body.clone())
+            .collect();
+        deliveries.discard();
+        bodies
+    }
+
+    #[tokio::test]
+    async fn forwards_plain_data_without_copying() {
+        let mut forwarder = forwarder(&[]);
+        forwarder
+            .transform_rules
+            .register("elsewhere.*", Transform::Uppercase)
+            .unwrap();
+        let data = Bytes::from(b"reading 42\r\n".to_vec());
+        let bodies = bodies(&mut forwarder, data.clone());
+        assert_eq!(bodies, std::slice::from_ref(&data));
+        // The read buffer itself, not a copy of it
+        assert_eq!(bodies[0].as_ptr(), data.as_ptr());
+    }
+
+    #[tokio::test]
+    async fn copies_only_to_apply_a_matching_transform() {
+        let mut forwarder = forwarder(&[]);
+        forwarder
+            .transform_rules
+            .register("telnet.*", Transform::Uppercase)
+            .unwrap();
+        let data = Bytes::from(b"reading 42\r\n".to_vec());
+        let bodies = bodies(&mut forwarder, data.clone());
+        assert_eq!(bodies, [Bytes::from_static(b"READING 42\r\n")]);
+        assert_ne!(bodies[0].as_ptr(), data.as_ptr());
+    }
+
+    #[tokio::test]
+    async fn stops_at_max_messages_framed_records() {
+        use tokio::io::AsyncWriteExt;
+
+        use crate::telnet::TelnetClient;
+
+        // Three reads of four records each
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            for read in 0..3 {
+                let mut data = Vec::new();
+                for record in 0..4 {
+                    // Each record between SLIP END bytes
+                    let record = format!("record {}", read * 4 + record);
+                    data.push(0xC0);
+                    data.extend_from_slice(record.as_bytes());
+                    data.push(0xC0);
+                }
+                stream.write_all(&data).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            // Hold the connection open until the client leaves
+            let _ = tokio::io::AsyncReadExt::read(&mut stream, &mut [0; 1]).await;
+        });
+
+        let config = Arc::new(
+            LinkConfig::from_values(&HashMap::from([
+                ("telnet_host".to_string(), "127.0.0.1".to_string()),
+                ("telnet_port".to_string(), port.to_string()),
+                ("unstuff".to_string(), "slip".to_string()),
+                ("max_messages".to_string(), "5".to_string()),
+            ]))
+            .unwrap(),
+        );
+        let stats = Arc::new(ConnectionStats::default());
+        let cancel = CancellationToken::new();
+        let mut forwarder = Forwarder::new(
+            ConnectionContext::new("test", &config.address(), &config.subject("test")),
+            config.clone(),
+            config.protocol_version,
+            stats.clone(),
+            cancel.clone(),
+            Arc::default(),
+            Arc::default(),
+        );
+
+        let mut bodies = Vec::new();
+        let client = TelnetClient::new(config, stats.clone());
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            client.run(cancel, |data, received| {
+                let result = forwarder.forward(data, received).map(|deliveries| {
+                    bodies.extend(deliveries.items.iter().map(|delivery| {
+                        String::from_utf8_lossy(&delivery.message.body).into_owned()
+                    }));
+                    deliveries.discard();
+                });
+                std::future::ready(result)
+            }),
+        )
+        .await
+        .expect("the link didn't stop at max_messages")
+        .unwrap();
+        server.abort();
+
+        let expected: Vec<String> = (0..5).map(|i| format!("record {}", i)).collect();
+        assert_eq!(bodies, expected);
+        assert_eq!(stats.state(), LinkState::MessageLimitReached);
+    }
+
+    /// A forwarder for a link to localhost with `values`, and the receiver
+    /// of the configs it publishes
+    fn tuned_forwarder(values: &[(&str, &str)]) -> (Forwarder, watch::Receiver<Arc<LinkConfig>>) {
+        let forwarder = forwarder(values);
+        let (tuned, tuned_rx) = watch::channel(forwarder.config.clone());
+        (forwarder.with_tuned_config(tuned), tuned_rx)
+    }
+
+    fn forward(forwarder: &mut Forwarder, data: Vec<u8>) {
+        forwarder
+            .forward(data.into(), Instant::now())
+            .unwrap()
+            .discard();
+    }
+
+    #[tokio::test]
+    async fn shares_the_link_config_until_a_reload_replaces_it() {
+        let forwarder = forwarder(&[("unstuff", "slip")]);
+        let link_config = forwarder.config.clone();
+        let (updates_tx, updates) = watch::channel(link_config.clone());
+        let mut forwarder = forwarder.with_config_updates(updates);
+        // One record between SLIP END bytes
+        let record = [&[0xC0][..], b"eight ch", &[0xC0]].concat();
+        forward(&mut forwarder, record.clone());
+        // The same config, not a copy of it
+        assert!(Arc::ptr_eq(&forwarder.config, &link_config));
+
+        let mut reloaded = (*link_config).clone();
+        reloaded.max_message_size = 4;
+        let reloaded = Arc::new(reloaded);
+        updates_tx.send(reloaded.clone()).unwrap();
+        let (forwarded, _) = forwarder
+            .forward(record.into(), Instant::now())
+            .unwrap()
+            .discard();
+        assert!(Arc::ptr_eq(&forwarder.config, &reloaded));
+        // Held to the reloaded limit
+        assert_eq!(forwarded, 0);
+    }
+
+    #[tokio::test]
+    async fn publishes_the_tuned_max_message_size() {
+        let (mut forwarder, tuned) = tuned_forwarder(&[("auto_tune_max_message_size", "true")]);
+        for _ in 0..WINDOW - 1 {
+            forward(&mut forwarder, vec![b'x'; 100]);
+        }
+        assert_eq!(tuned.borrow().max_message_size, 1024 * 1024);
+        forward(&mut forwarder, vec![b'x'; 100]);
+        assert_eq!(tuned.borrow().max_message_size, 200);
+    }
+
+    #[tokio::test]
+    async fn dropped_records_raise_the_limit() {
+        let (mut forwarder, tuned) = tuned_forwarder(&[
+            ("auto_tune_max_message_size", "true"),
+            ("unstuff", "slip"),
+            ("max_message_size", "100"),
+        ]);
+        // 2% of the records are dropped for exceeding the limit
+        let mut data = Vec::new();
+        for i in 0..WINDOW {
+            let len = if i % 50 == 0 { 500 } else { 50 };
+            // Each record between SLIP END bytes
+            data.push(0xC0);
+            data.extend(std::iter::repeat_n(b'x', len));
+            data.push(0xC0);
+        }
+        forward(&mut forwarder, data);
+        assert_eq!(tuned.borrow().max_message_size, 202);
+    }
+
+    #[tokio::test]
+    async fn warns_once_when_tuning_starts_dropping_records() {
+        let logged = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let writer = logged.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || LogWriter(writer.clone()))
+            .with_ansi(false)
+            .without_time()
+            .finish();
+        let (mut forwarder, tuned) = tuned_forwarder(&[("auto_tune_max_message_size", "true")]);
+        tracing::subscriber::with_default(subscriber, || {
+            // 1% of the records are too long for the tuned limit
+            for i in 0..WINDOW {
+                forward(
+                    &mut forwarder,
+                    vec![b'x'; if i % 100 == 0 { 1500 } else { 100 }],
+                );
+            }
+            assert_eq!(tuned.borrow().max_message_size, 200);
+            // A lower limit while records are already being dropped
+            for _ in 0..WINDOW {
+                forward(&mut forwarder, vec![b'x'; 50]);
+            }
+            assert_eq!(tuned.borrow().max_message_size, 100);
+        });
+        let logged = String::from_utf8(logged.lock().unwrap().clone()).unwrap();
+        let warnings: Vec<_> = logged
+            .lines()
+            .filter(|line| line.contains("WARN"))
+            .collect();
+        assert_eq!(warnings.len(), 1, "{}", logged);
+        assert!(warnings[0].contains("max_message_size 200 is below 10 of the last 1000 records"));
+    }
+
+    /// Appends log output to a shared buffer
+    struct LogWriter(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_max_message_size_without_auto_tuning() {
+        let (mut forwarder, tuned) = tuned_forwarder(&[]);
+        for _ in 0..WINDOW {
+            forward(&mut forwarder, vec![b'x'; 100]);
+        }
+        assert!(!tuned.has_changed().unwrap());
+    }
+
+    #[tokio::test]
+    async fn removes_cr_nul_before_framing() {
+        let mut forwarder = forwarder(&[("unstuff_cr", "true"), ("unstuff", "slip")]);
+        // The NUL of the first record's CR NUL arrives in the second read
+        let mut bodies = Vec::new();
+        for read in [&b"\xc0a\r"[..], b"\0b\xc0\xc0c\r\n\xc0"] {
+            let deliveries = forwarder
+                .forward(Bytes::from_static(read), Instant::now())
+                .unwrap();
+            bodies.extend(
+                deliveries
+                    .items
+                    .iter()
+                    .map(|delivery| delivery.message.body.to_vec()),
+            );
+            deliveries.discard();
+        }
+        assert_eq!(bodies, [b"a\rb".to_vec(), b"c\r\n".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn counts_messages_left_out_of_the_sample() {
+        let mut forwarder = forwarder(&[("sample_rate", "1/4")]);
+        let forwarded: u64 = (0..10)
+            .map(|_| {
+                forwarder
+                    .forward(Bytes::from_static(b"reading"), Instant::now())
+                    .unwrap()
+                    .discard()
+                    .0
+            })
+            .sum();
+        assert_eq!(forwarded, 3);
+        assert_eq!(forwarder.stats.messages_sampled_out(), 7);
+    }
+}
@@ -0,0 +1,102 @@
+//! Redundant connection groups
+//!
+//! Links in the same connection group carry an identical feed from different
+//! Telnet servers. Every member stays connected and receives data, but only
+//! the active member forwards messages: the first connected primary, or if no
+//! primary is connected, the first connected standby. When a primary comes
+//! back it takes over again.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tracing::info;
+
+use crate::config::GroupRole;
+use crate::stats::ConnectionStats;
+
+/// A single link participating in a connection group
+struct GroupMember {
+    source_id: String,
+    role: GroupRole,
+    stats: Arc<ConnectionStats>,
+}
+
+/// Membership and current active member of one connection group
+#[derive(Default)]
+pub struct GroupState {
+    /// Members in the order their links were established
+    members: Vec<GroupMember>,
+    /// Source ID of the member currently forwarding messages
+    active: Option<String>,
+}
+
+impl GroupState {
+    /// Pick the member that should forward, logging any change of role
+    fn elect(&mut self, group: &str) -> Option<&str> {
+        let elected = self
+            .members
+            .iter()
+            .filter(|member| member.stats.is_connected())
+            .min_by_key(|member| member.role);
+
+        let elected_id = elected.map(|member| member.source_id.clone());
+        if elected_id != self.active {
+            match elected {
+                Some(member) => info!(
+                    "Connection group {}: {} ({:?}) is now active",
+                    group, member.source_id, member.role
+                ),
+                None => info!("Connection group {}: no connected member", group),
+            }
+            self.active = elected_id;
+        }
+
+        self.active.as_deref()
+    }
+}
+
+/// All connection groups known to the provider
+#[derive(Default)]
+pub struct ConnectionGroups {
+    groups: Mutex<HashMap<String, GroupState>>,
+}
+
+impl ConnectionGroups {
+    /// Add a link to a group
+    pub fn join(&self, group: &str, source_id: &str, role: GroupRole, stats: Arc<ConnectionStats>) {
+        let mut groups = self.groups.lock().unwrap_or_else(|e| e.into_inner());
+        let state = groups.entry(group.to_string()).or_default();
+        state.members.retain(|member| member.source_id != source_id);
+        state.members.push(GroupMember {
+            source_id: source_id.to_string(),
+            role,
+            stats,
+        });
+        info!(
+            "Connection group {}: {} joined as {:?}",
+            group, source_id, role
+        );
+    }
+
+    /// Remove a link from a group, dropping the group once it is empty
+    pub fn leave(&self, group: &str, source_id: &str) {
+        let mut groups = self.groups.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(state) = groups.get_mut(group) {
+            state.members.retain(|member| member.source_id != source_id);
+            if state.members.is_empty() {
+                groups.remove(group);
+            } else {
+                state.elect(group);
+            }
+        }
+    }
+
+    /// Whether the given link should forward messages for its group
+    pub fn is_active(&self, group: &str, source_id: &str) -> bool {
+        let mut groups = self.groups.lock().unwrap_or_else(|e| e.into_inner());
+        groups
+            .get_mut(group)
+            .and_then(|state| state.elect(group))
+            .is_some_and(|active| active == source_id)
+    }
+}
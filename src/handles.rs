@@ -0,0 +1,100 @@
+//! Handles given to components for resources the provider holds for them
+//!
+//! wRPC passes a resource as an opaque handle. The provider hands out
+//! handles of the form `handle-<n>` and keeps what each one stands for in a
+//! [`ResourceTable`], together with the component it was handed to. A
+//! handle is only honoured for the component that owns it, so guessing or
+//! copying another component's handle gets nothing.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use bytes::Bytes;
+
+/// An entry and the component it belongs to
+struct Owned<T> {
+    owner: String,
+    value: T,
+}
+
+/// Resources handed out to components, by handle
+pub struct ResourceTable<T> {
+    entries: Mutex<HashMap<Bytes, Owned<T>>>,
+    next_id: AtomicU64,
+}
+
+impl<T> Default for ResourceTable<T> {
+    fn default() -> Self {
+        Self {
+            entries: Mutex::default(),
+            next_id: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<T> ResourceTable<T> {
+    /// Add the value `value` makes from a new handle for `owner`, returning
+    /// the handle
+    pub fn insert(&self, owner: &str, value: impl FnOnce(&str) -> T) -> Bytes {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let handle = format!("handle-{}", id);
+        let value = value(&handle);
+        let handle = Bytes::from(handle);
+        self.entries().insert(
+            handle.clone(),
+            Owned {
+                owner: owner.to_string(),
+                value,
+            },
+        );
+        handle
+    }
+
+    /// Remove the entry of `handle` if it belongs to `owner`
+    pub fn remove(&self, owner: &str, handle: &[u8]) -> Result<T, String> {
+        let mut entries = self.entries();
+        match entries.get(handle) {
+            Some(entry) if entry.owner == owner => {}
+            _ => return Err(unknown(handle)),
+        }
+        entries
+            .remove(handle)
+            .map(|entry| entry.value)
+            .ok_or_else(|| unknown(handle))
+    }
+
+    /// Remove every entry of `owner`, returning how many there were
+    pub fn remove_owner(&self, owner: &str) -> usize {
+        let mut entries = self.entries();
+        let before = entries.len();
+        entries.retain(|_, entry| entry.owner != owner);
+        before - entries.len()
+    }
+
+    /// Remove every entry, e.g. on shutdown
+    pub fn clear(&self) {
+        self.entries().clear();
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<Bytes, Owned<T>>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T: Clone> ResourceTable<T> {
+    /// The entry of `handle` if it belongs to `owner`
+    pub fn get(&self, owner: &str, handle: &[u8]) -> Result<T, String> {
+        self.entries()
+            .get(handle)
+            .filter(|entry| entry.owner == owner)
+            .map(|entry| entry.value.clone())
+            .ok_or_else(|| unknown(handle))
+    }
+}
+
+/// Error for a handle that doesn't exist or belongs to another component;
+/// the two aren't told apart
+fn unknown(handle: &[u8]) -> String {
+    format!("no such handle: {}", String::from_utf8_lossy(handle))
+}
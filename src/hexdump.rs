@@ -0,0 +1,101 @@
+//! Hexdump and text preview formatting for protocol debugging
+//!
+//! [`HexDump`] and [`Preview`] format lazily through `Display`, so a
+//! disabled log statement never pays for the rendering.
+
+use std::fmt;
+
+/// Bytes shown per hexdump line
+const BYTES_PER_LINE: usize = 16;
+
+/// Canonical hexdump of a byte slice, truncated to a maximum length
+///
+/// Each line shows the offset, up to 16 bytes in hex, and their printable
+/// ASCII representation:
+///
+/// ```text
+/// 00000000  ff fb 01 48 65 6c 6c 6f  0d 0a                    |...Hello..|
+/// ```
+pub struct HexDump<'a> {
+    data: &'a [u8],
+    max_bytes: usize,
+}
+
+impl<'a> HexDump<'a> {
+    /// Dump at most `max_bytes` of `data`
+    pub fn new(data: &'a [u8], max_bytes: usize) -> Self {
+        Self { data, max_bytes }
+    }
+}
+
+impl fmt::Display for HexDump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shown = &self.data[..self.data.len().min(self.max_bytes)];
+
+        for (line, chunk) in shown.chunks(BYTES_PER_LINE).enumerate() {
+            if line > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{:08x} ", line * BYTES_PER_LINE)?;
+            for i in 0..BYTES_PER_LINE {
+                if i == BYTES_PER_LINE / 2 {
+                    write!(f, " ")?;
+                }
+                match chunk.get(i) {
+                    Some(byte) => write!(f, " {:02x}", byte)?,
+                    None => write!(f, "   ")?,
+                }
+            }
+            write!(f, "  |")?;
+            for &byte in chunk {
+                let c = if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                };
+                write!(f, "{}", c)?;
+            }
+            write!(f, "|")?;
+        }
+
+        if self.data.len() > shown.len() {
+            if !shown.is_empty() {
+                writeln!(f)?;
+            }
+            write!(f, "... {} more bytes", self.data.len() - shown.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// Escaped text of the start of a payload, for one-line log messages
+///
+/// Invalid UTF-8 is shown as U+FFFD and control characters are escaped, so
+/// `Hello\r\n` previews as `Hello\r\n`. Text past `max_bytes` is elided.
+pub struct Preview<'a> {
+    data: &'a [u8],
+    max_bytes: usize,
+}
+
+impl<'a> Preview<'a> {
+    /// Preview at most `max_bytes` of `data`
+    pub fn new(data: &'a [u8], max_bytes: usize) -> Self {
+        Self { data, max_bytes }
+    }
+}
+
+impl fmt::Display for Preview<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shown = &self.data[..self.data.len().min(self.max_bytes)];
+        for chunk in shown.utf8_chunks() {
+            write!(f, "{}", chunk.valid().escape_debug())?;
+            if !chunk.invalid().is_empty() {
+                write!(f, "{}", char::REPLACEMENT_CHARACTER)?;
+            }
+        }
+        if self.data.len() > shown.len() {
+            write!(f, "...")?;
+        }
+        Ok(())
+    }
+}
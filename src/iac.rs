@@ -0,0 +1,216 @@
+//! Removal of Telnet commands from received data
+//!
+//! Everything the server sends passes through here, so the filter works in
+//! place and skips over plain data with `memchr` instead of looking at each
+//! byte. It has no other dependencies, so the filter benchmark
+//! (`benches/filter.rs`) builds it on its own.
+
+use memchr::memchr;
+
+/// Filter out Telnet IAC (Interpret As Command) sequences from raw data.
+///
+/// See [`filter_telnet_commands_in_place`]; this copies `data` first.
+pub fn filter_telnet_commands(data: &[u8]) -> Vec<u8> {
+    let mut result = data.to_vec();
+    let len = filter_telnet_commands_in_place(&mut result);
+    result.truncate(len);
+    result
+}
+
+/// Filter out Telnet IAC (Interpret As Command) sequences in place,
+/// returning the length of the filtered data at the start of `data`.
+///
+/// Telnet protocol uses IAC (0xFF) as an escape byte. Common sequences:
+/// - IAC WILL/WONT/DO/DONT <option>: 3 bytes (0xFF, 0xFB-0xFE, <option>)
+/// - IAC SB ... IAC SE: Sub-negotiation (variable length)
+/// - IAC <command>: 2 bytes for other commands
+///
+/// Output never gets ahead of input, so the data is compacted in place
+/// without allocating; without any IAC byte it is left untouched. Runs of
+/// plain data between IAC bytes are located with `memchr` and moved
+/// wholesale rather than byte by byte.
+pub fn filter_telnet_commands_in_place(data: &mut [u8]) -> usize {
+    let Some(first) = memchr(0xFF, data) else {
+        return data.len();
+    };
+    let mut i = first;
+    let mut len = first;
+
+    while i < data.len() {
+        if data[i] != 0xFF {
+            // Plain data - move everything up to the next IAC at once
+            let end = memchr(0xFF, &data[i..]).map_or(data.len(), |p| i + p);
+            data.copy_within(i..end, len);
+            len += end - i;
+            i = end;
+            continue;
+        }
+
+        // IAC byte
+        if i + 1 >= data.len() {
+            break;
+        }
+        match data[i + 1] {
+            0xFB..=0xFE => {
+                // WILL, WONT, DO, DONT - skip 3 bytes
+                i += 3;
+            }
+            0xFA => {
+                // SB (sub-negotiation) - skip until IAC SE
+                i = skip_subnegotiation(data, i + 2);
+            }
+            0xFF => {
+                // Escaped 0xFF - output single 0xFF
+                data[len] = 0xFF;
+                len += 1;
+                i += 2;
+            }
+            _ => {
+                // Other IAC command - skip 2 bytes
+                i += 2;
+            }
+        }
+    }
+
+    len
+}
+
+/// Find where the sub-negotiation body starting at `start` ends.
+///
+/// Returns the offset just past its IAC SE. If the buffer ends first, it
+/// returns the offset of the buffer's last byte, which the caller then
+/// treats as ordinary input.
+fn skip_subnegotiation(data: &[u8], start: usize) -> usize {
+    let mut i = start;
+    while i + 1 < data.len() {
+        match memchr(0xFF, &data[i..data.len() - 1]) {
+            Some(p) if data[i + p + 1] == 0xF0 => return i + p + 2,
+            Some(p) => i += p + 1,
+            None => return data.len() - 1,
+        }
+    }
+    i
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn keeps_an_escaped_iac_as_one_data_byte() {
+        assert_eq!(filter_telnet_commands(b"a\xff\xffb"), b"a\xffb");
+        assert_eq!(filter_telnet_commands(b"\xff\xff\xff\xff"), b"\xff\xff");
+        assert_eq!(filter_telnet_commands(b"\xff\xff\xff\xfb\x01c"), b"\xffc");
+    }
+
+    #[test]
+    fn drops_commands_and_sub_negotiations() {
+        assert_eq!(filter_telnet_commands(b"a\xff\xfb\x01b\xff\xf1c"), b"abc");
+        assert_eq!(
+            filter_telnet_commands(b"a\xff\xfa\x18\x00ansi\xff\xf0b"),
+            b"ab"
+        );
+    }
+
+    #[test]
+    fn unterminated_sub_negotiation_keeps_only_the_last_byte() {
+        // Without IAC SE the body runs to the end of the read; its last
+        // byte is kept as data, as the original filter did
+        assert_eq!(filter_telnet_commands(b"a\xff\xfa\x18\x00ansi"), b"ai");
+        assert_eq!(filter_telnet_commands(b"a\xff\xfa\x18\x00\xff"), b"a");
+        assert_eq!(filter_telnet_commands(b"a\xff\xfa"), b"a");
+    }
+
+    #[test]
+    fn drops_a_trailing_lone_iac() {
+        assert_eq!(filter_telnet_commands(b"abc\xff"), b"abc");
+        assert_eq!(filter_telnet_commands(b"\xff"), b"");
+        assert_eq!(filter_telnet_commands(b"abc\xff\xfd"), b"abc");
+    }
+
+    #[test]
+    fn in_place_compacts_into_the_same_buffer() {
+        let mut data = b"\xff\xfb\x01hello\xff\xff world".to_vec();
+        let len = filter_telnet_commands_in_place(&mut data);
+        assert_eq!(&data[..len], b"hello\xff world");
+    }
+
+    /// The filter as first written, copying byte by byte
+    fn filter_byte_by_byte(data: &[u8]) -> Vec<u8> {
+        let mut result = Vec::new();
+        let mut i = 0;
+
+        while i < data.len() {
+            if data[i] == 0xFF {
+                if i + 1 >= data.len() {
+                    break;
+                }
+                match data[i + 1] {
+                    0xFB..=0xFE => i += 3,
+                    0xFA => {
+                        i += 2;
+                        while i + 1 < data.len() {
+                            if data[i] == 0xFF && data[i + 1] == 0xF0 {
+                                i += 2;
+                                break;
+                            }
+                            i += 1;
+                        }
+                    }
+                    0xFF => {
+                        result.push(0xFF);
+                        i += 2;
+                    }
+                    _ => i += 2,
+                }
+            } else {
+                result.push(data[i]);
+                i += 1;
+            }
+        }
+
+        result
+    }
+
+    /// Bytes weighted towards IAC, SB, SE and the negotiation verbs, so
+    /// commands, escapes and sub-negotiations occur often
+    fn telnet_bytes() -> impl Strategy<Value = Vec<u8>> {
+        let byte = prop_oneof![
+            4 => any::<u8>(),
+            3 => Just(0xFF),
+            1 => Just(0xFA),
+            1 => Just(0xF0),
+            1 => 0xFBu8..=0xFE,
+        ];
+        prop::collection::vec(byte, 0..256)
+    }
+
+    proptest! {
+        #[test]
+        fn matches_the_byte_by_byte_filter(data in telnet_bytes()) {
+            prop_assert_eq!(filter_telnet_commands(&data), filter_byte_by_byte(&data));
+        }
+
+        #[test]
+        fn matches_with_a_command_split_at_the_end(
+            data in telnet_bytes(),
+            tail in prop_oneof![
+                Just(vec![0xFF]),
+                Just(vec![0xFF, 0xFB]),
+                Just(vec![0xFF, 0xFA]),
+                Just(vec![0xFF, 0xFA, 0x18]),
+                Just(vec![0xFF, 0xFA, 0x18, 0x00, b'x', 0xFF]),
+            ],
+        ) {
+            let data = [data, tail].concat();
+            prop_assert_eq!(filter_telnet_commands(&data), filter_byte_by_byte(&data));
+        }
+
+        #[test]
+        fn leaves_data_without_iac_untouched(data in prop::collection::vec(0u8..0xFF, 0..256)) {
+            prop_assert_eq!(filter_telnet_commands(&data), data);
+        }
+    }
+}
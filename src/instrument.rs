@@ -0,0 +1,149 @@
+//! Span instrumentation, compiled out without the `tokio-tracing` feature,
+//! and named task spawning for tokio-console
+//!
+//! Every task of a link runs inside a `telnet_link` span with the link's
+//! `source_id`, `host` and `port`, so all of its log lines carry them without
+//! repeating them in each call. Tasks spawned from inside a link's task
+//! inherit the span.
+
+use std::future::Future;
+
+use tokio::task::{AbortHandle, JoinHandle, JoinSet};
+
+#[cfg(all(feature = "tokio-console", not(tokio_unstable)))]
+compile_error!("the `tokio-console` feature requires building with `--cfg tokio_unstable` (use `cargo console`)");
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "tokio-tracing")] {
+        use tracing::Instrument as _;
+
+        /// Run a connection task inside a span identifying its link
+        pub fn link_task<F: Future>(
+            task: F,
+            source_id: &str,
+            host: &str,
+            port: u16,
+        ) -> impl Future<Output = F::Output> {
+            task.instrument(tracing::info_span!("telnet_link", source_id, host, port))
+        }
+    } else {
+        /// Run a connection task as-is (span instrumentation disabled)
+        pub fn link_task<F: Future>(task: F, _source_id: &str, _host: &str, _port: u16) -> F {
+            task
+        }
+    }
+}
+
+/// Log to stderr in place of the SDK's `initialize_observability!`, which
+/// only exists with OpenTelemetry support (`tokio-tracing`)
+#[cfg(not(feature = "tokio-tracing"))]
+pub fn init_logging() {
+    // A subscriber set up by an embedder takes precedence
+    let _ = tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .try_init();
+}
+
+/// Spawn a task named `<owner>/<role>` (e.g. the link's source ID and
+/// `reader`), so it is identifiable in tokio-console and in panics
+///
+/// All provider tasks go through here; names are only recorded with the
+/// `tokio-console` feature. The task runs in the caller's span.
+pub fn spawn_named<F>(owner: &str, role: &str, task: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(feature = "tokio-tracing")]
+    let task = task.in_current_span();
+
+    cfg_if::cfg_if! {
+        if #[cfg(all(feature = "tokio-console", tokio_unstable))] {
+            tokio::task::Builder::new()
+                .name(&format!("{}/{}", owner, role))
+                .spawn(task)
+                .expect("failed to spawn task")
+        } else {
+            let _ = (owner, role);
+            tokio::spawn(task)
+        }
+    }
+}
+
+/// Spawn a task named `<owner>/<role>` into `set`, like [`spawn_named`]
+pub fn spawn_named_in<F>(
+    set: &mut JoinSet<F::Output>,
+    owner: &str,
+    role: &str,
+    task: F,
+) -> AbortHandle
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(feature = "tokio-tracing")]
+    let task = task.in_current_span();
+
+    cfg_if::cfg_if! {
+        if #[cfg(all(feature = "tokio-console", tokio_unstable))] {
+            set.build_task()
+                .name(&format!("{}/{}", owner, role))
+                .spawn(task)
+                .expect("failed to spawn task")
+        } else {
+            let _ = (owner, role);
+            set.spawn(task)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn named_tasks_run_to_completion() {
+        assert_eq!(
+            spawn_named("alarm-handler", "reader", async { 7 })
+                .await
+                .unwrap(),
+            7
+        );
+
+        let mut set = JoinSet::new();
+        for i in 0..3 {
+            spawn_named_in(&mut set, "alarm-handler", "delivery", async move { i });
+        }
+        let mut results = set.join_all().await;
+        results.sort();
+        assert_eq!(results, [0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn link_tasks_return_their_output() {
+        assert_eq!(
+            link_task(async { "done" }, "alarm-handler", "10.0.0.5", 23).await,
+            "done"
+        );
+    }
+
+    #[cfg(feature = "tokio-tracing")]
+    #[tokio::test]
+    async fn spawned_tasks_inherit_the_link_span() {
+        let _subscriber = tracing::subscriber::set_default(tracing_subscriber::registry());
+        let span_of_task = link_task(
+            async {
+                spawn_named("alarm-handler", "reader", async {
+                    tracing::Span::current().metadata().map(|m| m.name())
+                })
+                .await
+                .unwrap()
+            },
+            "alarm-handler",
+            "10.0.0.5",
+            23,
+        )
+        .await;
+        assert_eq!(span_of_task, Some("telnet_link"));
+    }
+}
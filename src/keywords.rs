@@ -0,0 +1,57 @@
+//! Fixed-string keyword filtering of message bodies
+//!
+//! For a handful of alert keywords or error codes, a multi-pattern
+//! Aho-Corasick automaton finds all of them in one pass over the body,
+//! much faster than a regex alternation. A message is forwarded only if it
+//! contains at least one of `body_required_keywords` (when any are set) and
+//! none of `body_blocked_keywords`. Matching is case-sensitive and on raw
+//! bytes, so keywords may appear anywhere, including inside longer words.
+
+use aho_corasick::{AhoCorasick, BuildError};
+
+/// Outcome of checking a message body against the keyword lists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeywordVerdict {
+    Forward,
+    /// None of the required keywords appear
+    MissingRequired,
+    /// A blocked keyword appears
+    Blocked,
+}
+
+/// Required and blocked keyword automata of one connection
+pub struct KeywordFilter {
+    required: Option<AhoCorasick>,
+    blocked: Option<AhoCorasick>,
+}
+
+impl KeywordFilter {
+    /// Build the automata, or `None` if both lists are empty
+    pub fn new(required: &[String], blocked: &[String]) -> Result<Option<Self>, BuildError> {
+        let automaton = |keywords: &[String]| {
+            (!keywords.is_empty())
+                .then(|| AhoCorasick::new(keywords))
+                .transpose()
+        };
+        let filter = Self {
+            required: automaton(required)?,
+            blocked: automaton(blocked)?,
+        };
+        Ok((filter.required.is_some() || filter.blocked.is_some()).then_some(filter))
+    }
+
+    /// Whether a message with this body should be forwarded
+    pub fn check(&self, body: &[u8]) -> KeywordVerdict {
+        if let Some(blocked) = &self.blocked {
+            if blocked.is_match(body) {
+                return KeywordVerdict::Blocked;
+            }
+        }
+        if let Some(required) = &self.required {
+            if !required.is_match(body) {
+                return KeywordVerdict::MissingRequired;
+            }
+        }
+        KeywordVerdict::Forward
+    }
+}
@@ -0,0 +1,79 @@
+//! Telnet capability provider for wasmCloud
+//!
+//! This provider connects to remote Telnet servers and forwards received messages
+//! to wasmCloud components via wRPC. It implements unidirectional communication
+//! (receiving only) with automatic reconnection and message size limits.
+//!
+//! The provider binary runs [`provider::TelnetProvider`] under a wasmCloud
+//! host. Integration tests and embedders build one with
+//! [`provider::TelnetProviderBuilder`] instead and drive it through the
+//! SDK's `Provider` trait.
+
+mod aggregate;
+mod audit;
+mod autotune;
+mod backoff;
+pub mod bench;
+mod blocklist;
+mod client;
+pub mod config;
+mod context;
+mod controls;
+mod debounce;
+mod dedupe;
+mod delta;
+mod dispatch;
+mod effective;
+mod enrich;
+mod envelope;
+mod environ;
+mod error;
+mod forward;
+mod group;
+mod handles;
+mod hexdump;
+mod iac;
+mod instrument;
+mod keywords;
+mod login;
+mod mccp;
+mod metrics;
+#[cfg(target_os = "linux")]
+pub mod migrate;
+mod msdp;
+mod multiplex;
+mod ndjson;
+mod negotiate;
+mod ordering;
+mod platform;
+mod policy;
+mod pool;
+mod priority;
+mod probe;
+pub mod provider;
+mod rate;
+mod readbuf;
+pub mod record;
+mod reload;
+mod report;
+mod resolve;
+mod route;
+mod sample;
+mod schedule;
+mod selftest;
+mod sentinel;
+mod sequence;
+mod server_type;
+pub mod soak;
+pub mod socket;
+mod stall;
+mod stats;
+mod statsd;
+mod subject;
+mod tasks;
+mod telnet;
+mod throttle;
+mod transform;
+mod unstuff;
+mod verbosity;
+mod watchdog;
@@ -0,0 +1,153 @@
+//! Scripted username/password login performed right after connecting
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+/// What to wait for before sending a login step's response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expect {
+    /// Text appearing in the server's output (matched ignoring ASCII case)
+    Prompt(String),
+    /// Exactly this many bytes, for fixed-length handshake fields; never 0
+    Bytes(usize),
+}
+
+impl std::str::FromStr for Expect {
+    type Err = anyhow::Error;
+
+    /// Parse `#len:<n>` as [`Expect::Bytes`] and anything else as a prompt
+    ///
+    /// A length of 0 is rejected: it would wait for nothing, so the response
+    /// would go out without anything from the server.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("#len:") {
+            Some(len) => len
+                .trim()
+                .parse()
+                .ok()
+                .filter(|n| *n > 0)
+                .map(Expect::Bytes)
+                .ok_or_else(|| anyhow::anyhow!("Invalid login expect length: {}", s)),
+            None => Ok(Expect::Prompt(s.to_lowercase())),
+        }
+    }
+}
+
+/// Link config for automatic login; present when `login_username` is set
+#[derive(Clone)]
+pub struct LoginConfig {
+    pub username: String,
+    /// Sent at the password prompt; may come from the `login_password` secret
+    pub password: Option<String>,
+    /// What precedes the username
+    pub login_prompt: Expect,
+    /// What precedes the password
+    pub password_prompt: Expect,
+    /// How long to wait for each prompt in milliseconds
+    pub timeout_ms: u64,
+    /// Pause after a prompt is seen before responding, in milliseconds
+    pub step_delay_ms: u64,
+    /// Per-step overrides of `step_delay_ms`
+    pub username_delay_ms: Option<u64>,
+    pub password_delay_ms: Option<u64>,
+}
+
+impl fmt::Debug for LoginConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoginConfig")
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("login_prompt", &self.login_prompt)
+            .field("password_prompt", &self.password_prompt)
+            .field("timeout_ms", &self.timeout_ms)
+            .field("step_delay_ms", &self.step_delay_ms)
+            .field("username_delay_ms", &self.username_delay_ms)
+            .field("password_delay_ms", &self.password_delay_ms)
+            .finish()
+    }
+}
+
+/// One expect/send exchange of the login script
+pub struct LoginStep<'a> {
+    /// What to wait for
+    pub expect: &'a Expect,
+    /// Line sent once the prompt is seen
+    pub response: &'a str,
+    /// Pause between seeing the prompt and responding
+    pub delay: Duration,
+}
+
+impl LoginConfig {
+    /// Parse login settings, returning `None` if no `login_username` is set
+    pub fn from_values(config: &HashMap<String, String>) -> anyhow::Result<Option<Self>> {
+        let Some(username) = config.get("login_username").filter(|v| !v.is_empty()) else {
+            return Ok(None);
+        };
+
+        let delay = |key: &str| -> anyhow::Result<Option<u64>> {
+            config
+                .get(key)
+                .map(|v| {
+                    v.parse()
+                        .map_err(|_| anyhow::anyhow!("Invalid {}: {}", key, v))
+                })
+                .transpose()
+        };
+
+        Ok(Some(Self {
+            username: username.clone(),
+            password: config.get("login_password").cloned(),
+            login_prompt: config
+                .get("login_prompt")
+                .map(String::as_str)
+                .unwrap_or("login:")
+                .parse()?,
+            password_prompt: config
+                .get("password_prompt")
+                .map(String::as_str)
+                .unwrap_or("password:")
+                .parse()?,
+            timeout_ms: config
+                .get("login_timeout_ms")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(10000),
+            step_delay_ms: delay("login_step_delay_ms")?.unwrap_or(0),
+            username_delay_ms: delay("login_username_delay_ms")?,
+            password_delay_ms: delay("login_password_delay_ms")?,
+        }))
+    }
+
+    /// How long to wait for each prompt
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    /// The longest `#len:<n>` read of the script, if it has one
+    pub fn longest_read(&self) -> Option<usize> {
+        self.steps()
+            .iter()
+            .filter_map(|step| match step.expect {
+                Expect::Bytes(n) => Some(*n),
+                Expect::Prompt(_) => None,
+            })
+            .max()
+    }
+
+    /// The expect/send steps to run, in order
+    pub fn steps(&self) -> Vec<LoginStep<'_>> {
+        let mut steps = vec![LoginStep {
+            expect: &self.login_prompt,
+            response: &self.username,
+            delay: Duration::from_millis(self.username_delay_ms.unwrap_or(self.step_delay_ms)),
+        }];
+        if let Some(password) = &self.password {
+            steps.push(LoginStep {
+                expect: &self.password_prompt,
+                response: password,
+                delay: Duration::from_millis(self.password_delay_ms.unwrap_or(self.step_delay_ms)),
+            });
+        }
+        steps
+    }
+}
@@ -1,18 +1,51 @@
 //! Telnet capability provider for wasmCloud
 //!
-//! This provider connects to remote Telnet servers and forwards received messages
-//! to wasmCloud components via wRPC. It implements unidirectional communication
-//! (receiving only) with automatic reconnection and message size limits.
+//! Runs the provider under a wasmCloud host, or one of its developer modes
+//! (`--soak`, `--bench`, `--decompress-session`).
 
-mod config;
-mod provider;
-mod telnet;
+use std::time::Duration;
 
-use provider::TelnetProvider;
+use wasmcloud_provider_telnet::provider::TelnetProvider;
+use wasmcloud_provider_telnet::{bench, record, soak};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    TelnetProvider::run().await?;
+    let mut args = std::env::args().skip(1);
+    let mut wait_for_connections = None;
+    match args.next().as_deref() {
+        Some("--soak") => {
+            tracing_subscriber::fmt::init();
+            return soak::run(soak::SoakOptions::from_args(args)?).await;
+        }
+        Some("--bench") => {
+            // Logs go to stderr so stdout holds only the result
+            tracing_subscriber::fmt()
+                .with_writer(std::io::stderr)
+                .init();
+            return bench::run(bench::BenchOptions::from_args(args)?).await;
+        }
+        Some("--decompress-session") => {
+            let path = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("Missing file for --decompress-session"))?;
+            // Only the recorded bytes go to stdout, for hexdump and the like
+            record::decompress(std::path::Path::new(&path), &mut tokio::io::stdout()).await?;
+            return Ok(());
+        }
+        Some("--wait-for-connections") => {
+            let secs = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("Missing value for --wait-for-connections"))?;
+            wait_for_connections = Some(Duration::from_secs(secs.parse()?));
+        }
+        _ => {}
+    }
+
+    // The console layer takes the place of the provider's own subscriber
+    #[cfg(feature = "tokio-console")]
+    console_subscriber::init();
+
+    TelnetProvider::run(wait_for_connections).await?;
     eprintln!("Telnet provider exiting");
     Ok(())
 }
@@ -0,0 +1,377 @@
+//! MUD Client Compression Protocol, version 2 (Telnet option 86)
+//!
+//! With `mccp_enabled` set, the client agrees to the server's `WILL
+//! COMPRESS2`. The server then sends `IAC SB COMPRESS2 IAC SE`, and
+//! everything after it is a zlib stream until that stream ends, at which
+//! point the server is back to sending uncompressed data. It may start
+//! compressing again later with another `IAC SB COMPRESS2 IAC SE`.
+//!
+//! [`MccpReader`] turns what is read from the socket back into the plain
+//! Telnet stream, so negotiation, filtering and framing never see
+//! compressed bytes. Version 1 (option 85) is not supported and never
+//! accepted.
+
+use flate2::{Decompress, FlushDecompress, Status};
+use thiserror::Error;
+
+const IAC: u8 = 0xFF;
+const SB: u8 = 0xFA;
+const SE: u8 = 0xF0;
+const WILL: u8 = 0xFB;
+const DO: u8 = 0xFD;
+
+/// Telnet option code of MCCP2
+const COMPRESS2: u8 = 86;
+
+/// Marks the start of compressed data
+const START: &[u8] = &[IAC, SB, COMPRESS2, IAC, SE];
+
+/// Decompressed bytes reserved per compressed byte before inflating; the
+/// buffer grows further as needed
+const INFLATE_RATIO: usize = 4;
+
+/// What the client answers to a negotiation command for `option`, if anything
+pub fn response_to(command: u8, option: u8) -> Option<&'static str> {
+    match (command, option) {
+        (WILL, COMPRESS2) => Some("DO"),
+        _ => None,
+    }
+}
+
+/// The server's compressed data could not be inflated
+#[derive(Debug, Error)]
+#[error("invalid MCCP compressed data: {0}")]
+pub struct MccpError(#[from] flate2::DecompressError);
+
+/// A change between compressed and uncompressed reading
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MccpTransition {
+    /// The server started compressing
+    Started,
+    /// The server's compressed stream ended; what follows is uncompressed
+    Ended,
+}
+
+/// Decompresses successive reads of one connection
+#[derive(Default)]
+pub struct MccpReader {
+    /// Inflater of the current compressed stream, while there is one
+    inflate: Option<Decompress>,
+    /// Bytes of earlier reads still to decode: uncompressed ones that may
+    /// be the start of [`START`], or compressed ones the inflater didn't take
+    pending: Vec<u8>,
+    /// The server sent `WILL COMPRESS2`
+    offered: bool,
+    /// `DO COMPRESS2` has been handed out to send
+    accepted: bool,
+}
+
+impl MccpReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Note an offer of compression in uncompressed Telnet data
+    ///
+    /// Offers are only noted here; [`accept`](Self::accept) hands out the
+    /// answer, so it can be put off while the login script reads.
+    pub fn note_offers(&mut self, data: &[u8]) {
+        let mut i = 0;
+        while i + 2 < data.len() {
+            if data[i] != IAC {
+                i += 1;
+                continue;
+            }
+            match (data[i + 1], data[i + 2]) {
+                (IAC, _) => i += 2,
+                (WILL, COMPRESS2) => {
+                    self.offered = true;
+                    i += 3;
+                }
+                _ => i += 2,
+            }
+        }
+    }
+
+    /// Whether the server has offered compression on this connection
+    pub fn offered(&self) -> bool {
+        self.offered
+    }
+
+    /// The answer accepting the server's offer, once it has been made;
+    /// `None` before that and after the answer was handed out
+    pub fn accept(&mut self) -> Option<&'static [u8]> {
+        if !self.offered || self.accepted {
+            return None;
+        }
+        self.accepted = true;
+        Some(&[IAC, DO, COMPRESS2])
+    }
+
+    /// Append the uncompressed Telnet stream of the read `data` to `out`
+    ///
+    /// The start marker itself is passed on, for negotiation logging.
+    /// Returns the transitions `data` contained, in order.
+    pub fn decode(
+        &mut self,
+        data: &[u8],
+        out: &mut Vec<u8>,
+    ) -> Result<Vec<MccpTransition>, MccpError> {
+        let mut transitions = Vec::new();
+        let held;
+        let data = if self.pending.is_empty() {
+            data
+        } else {
+            self.pending.extend_from_slice(data);
+            held = std::mem::take(&mut self.pending);
+            &held[..]
+        };
+
+        let mut pos = 0;
+        while pos < data.len() {
+            if let Some(inflate) = &mut self.inflate {
+                let (consumed, ended) = inflate_into(inflate, &data[pos..], out)?;
+                pos += consumed;
+                if !ended {
+                    // Anything the inflater didn't take goes in first next time
+                    self.pending.extend_from_slice(&data[pos..]);
+                    break;
+                }
+                self.inflate = None;
+                transitions.push(MccpTransition::Ended);
+                continue;
+            }
+
+            match find_start(&data[pos..]) {
+                Start::Found(at) => {
+                    let end = pos + at + START.len();
+                    out.extend_from_slice(&data[pos..end]);
+                    pos = end;
+                    self.inflate = Some(Decompress::new(true));
+                    transitions.push(MccpTransition::Started);
+                }
+                Start::Partial(at) => {
+                    out.extend_from_slice(&data[pos..pos + at]);
+                    self.pending.extend_from_slice(&data[pos + at..]);
+                    break;
+                }
+                Start::None => {
+                    out.extend_from_slice(&data[pos..]);
+                    break;
+                }
+            }
+        }
+        Ok(transitions)
+    }
+}
+
+/// Inflate `data` into `out`; returns the bytes consumed and whether the
+/// compressed stream ended
+fn inflate_into(
+    inflate: &mut Decompress,
+    data: &[u8],
+    out: &mut Vec<u8>,
+) -> Result<(usize, bool), MccpError> {
+    let start_in = inflate.total_in();
+    loop {
+        let consumed = (inflate.total_in() - start_in) as usize;
+        out.reserve((data.len() - consumed) * INFLATE_RATIO + 64);
+        let before_out = inflate.total_out();
+        let status = inflate.decompress_vec(&data[consumed..], out, FlushDecompress::None)?;
+        let consumed = (inflate.total_in() - start_in) as usize;
+        match status {
+            Status::StreamEnd => return Ok((consumed, true)),
+            // Room ran out before the input did
+            _ if out.len() == out.capacity() => {}
+            // Everything available has been inflated
+            _ if consumed == data.len() || inflate.total_out() == before_out => {
+                return Ok((consumed, false));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Where [`START`] is in uncompressed data
+enum Start {
+    Found(usize),
+    /// The data ends with the beginning of it
+    Partial(usize),
+    None,
+}
+
+fn find_start(data: &[u8]) -> Start {
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] != IAC {
+            i += 1;
+            continue;
+        }
+        let rest = &data[i..];
+        if rest.starts_with(START) {
+            return Start::Found(i);
+        }
+        if START.starts_with(rest) {
+            return Start::Partial(i);
+        }
+        // Skips escaped 0xFF data bytes along with commands
+        i += 2;
+    }
+    Start::None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{Compress, Compression, FlushCompress};
+
+    /// `data` as one complete zlib stream
+    fn compressed(data: &[u8]) -> Vec<u8> {
+        let mut compress = Compress::new(Compression::default(), true);
+        let mut out = Vec::with_capacity(data.len() + 64);
+        compress
+            .compress_vec(data, &mut out, FlushCompress::Finish)
+            .unwrap();
+        out
+    }
+
+    /// `data` as the beginning of a zlib stream that hasn't ended
+    fn compressed_unfinished(data: &[u8]) -> Vec<u8> {
+        let mut compress = Compress::new(Compression::default(), true);
+        let mut out = Vec::with_capacity(data.len() + 64);
+        compress
+            .compress_vec(data, &mut out, FlushCompress::Sync)
+            .unwrap();
+        out
+    }
+
+    /// Decode `reads` in turn, returning the output and transitions
+    fn decode_all(reader: &mut MccpReader, reads: &[&[u8]]) -> (Vec<u8>, Vec<MccpTransition>) {
+        let mut out = Vec::new();
+        let mut transitions = Vec::new();
+        for read in reads {
+            transitions.extend(reader.decode(read, &mut out).unwrap());
+        }
+        (out, transitions)
+    }
+
+    #[test]
+    fn uncompressed_data_passes_through() {
+        let mut reader = MccpReader::new();
+        let data = b"plain \xff\xff data \xff\xfb\x01";
+        let (out, transitions) = decode_all(&mut reader, &[data]);
+        assert_eq!(out, data);
+        assert!(transitions.is_empty());
+    }
+
+    #[test]
+    fn data_after_the_start_marker_is_inflated() {
+        let mut reader = MccpReader::new();
+        let read = [
+            b"before".as_slice(),
+            START,
+            &compressed_unfinished(b"after"),
+        ]
+        .concat();
+        let (out, transitions) = decode_all(&mut reader, &[&read]);
+        assert_eq!(out, [b"before".as_slice(), START, b"after"].concat());
+        assert_eq!(transitions, [MccpTransition::Started]);
+    }
+
+    #[test]
+    fn reading_turns_uncompressed_when_the_stream_ends_mid_read() {
+        let mut reader = MccpReader::new();
+        let read = [
+            START,
+            &compressed(b"compressed\r\n"),
+            b"plain again\r\n".as_slice(),
+        ]
+        .concat();
+        let (out, transitions) = decode_all(&mut reader, &[&read]);
+        assert_eq!(
+            out,
+            [START, b"compressed\r\n".as_slice(), b"plain again\r\n"].concat()
+        );
+        assert_eq!(
+            transitions,
+            [MccpTransition::Started, MccpTransition::Ended]
+        );
+    }
+
+    #[test]
+    fn compression_can_be_turned_on_and_off_repeatedly() {
+        let mut reader = MccpReader::new();
+        let first = compressed(b"one ");
+        let second = compressed(b"three ");
+        // Split at arbitrary points, start marker and zlib stream included
+        let stream = [START, &first, b"two ".as_slice(), START, &second, b"four"].concat();
+        for split in [1, 2, 3, 4, 7, first.len() + 5, first.len() + 11] {
+            let mut reader_split = MccpReader::new();
+            let (out, transitions) =
+                decode_all(&mut reader_split, &[&stream[..split], &stream[split..]]);
+            assert_eq!(
+                out,
+                [START, b"one two ".as_slice(), START, b"three four"].concat(),
+                "split at {}",
+                split
+            );
+            assert_eq!(
+                transitions,
+                [
+                    MccpTransition::Started,
+                    MccpTransition::Ended,
+                    MccpTransition::Started,
+                    MccpTransition::Ended
+                ],
+                "split at {}",
+                split
+            );
+        }
+
+        // One byte at a time
+        let reads: Vec<&[u8]> = stream.chunks(1).collect();
+        let (out, _) = decode_all(&mut reader, &reads);
+        assert_eq!(
+            out,
+            [START, b"one two ".as_slice(), START, b"three four"].concat()
+        );
+    }
+
+    #[test]
+    fn highly_compressed_data_is_inflated_in_full() {
+        let mut reader = MccpReader::new();
+        let data = vec![b'x'; 1 << 20];
+        let read = [START, &compressed(&data)].concat();
+        let (out, _) = decode_all(&mut reader, &[&read]);
+        assert_eq!(&out[START.len()..], data);
+    }
+
+    #[test]
+    fn an_escaped_0xff_is_not_taken_for_the_marker() {
+        let mut reader = MccpReader::new();
+        // Data byte 0xFF followed by bytes that look like the rest of START
+        let read = [IAC, IAC, SB, COMPRESS2, IAC, SE];
+        let (out, transitions) = decode_all(&mut reader, &[&read]);
+        assert_eq!(out, read);
+        assert!(transitions.is_empty());
+    }
+
+    #[test]
+    fn corrupt_compressed_data_is_an_error() {
+        let mut reader = MccpReader::new();
+        let read = [START, b"not zlib at all".as_slice()].concat();
+        assert!(reader.decode(&read, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn offers_are_accepted_once() {
+        let mut reader = MccpReader::new();
+        assert_eq!(reader.accept(), None);
+        reader.note_offers(&[IAC, WILL, 85]);
+        assert!(!reader.offered());
+        reader.note_offers(&[IAC, WILL, COMPRESS2]);
+        assert!(reader.offered());
+        assert_eq!(reader.accept(), Some(&[IAC, DO, COMPRESS2][..]));
+        assert_eq!(reader.accept(), None);
+    }
+}
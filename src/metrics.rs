@@ -0,0 +1,279 @@
+//! OpenTelemetry instruments for the delivery path, compiled out without the
+//! `tokio-tracing` feature
+//!
+//! | Instrument | Type | Unit |
+//! |------------|------|------|
+//! | `telnet.message.size` | histogram | bytes forwarded per message |
+//! | `telnet.delivery.latency` | histogram | seconds from socket read to `handle_message` completion |
+//! | `telnet.delivery.queue_depth` | up-down counter | deliveries in flight |
+//!
+//! All are labeled with the link's `source_id`. The attribute set is built
+//! once per link so recording doesn't allocate. Instruments live in a
+//! [`Registry`], by default that of the global meter provider.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "tokio-tracing")] {
+        use std::sync::OnceLock;
+
+        use opentelemetry::metrics::{Histogram, Meter, UpDownCounter};
+        use opentelemetry::KeyValue;
+
+        /// Message size buckets, in bytes (up to the default `max_message_size`)
+        const SIZE_BUCKETS: &[f64] = &[
+            16.0, 64.0, 256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0,
+        ];
+
+        /// Delivery latency buckets, in seconds
+        const LATENCY_BUCKETS: &[f64] = &[
+            0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+        ];
+
+        /// Instruments shared by every link
+        struct Instruments {
+            message_size: Histogram<u64>,
+            delivery_latency: Histogram<f64>,
+            queue_depth: UpDownCounter<i64>,
+        }
+
+        impl Instruments {
+            fn new(meter: &Meter) -> Self {
+                Self {
+                    message_size: meter
+                        .u64_histogram("telnet.message.size")
+                        .with_description("Size of messages forwarded to components")
+                        .with_unit("By")
+                        .with_boundaries(SIZE_BUCKETS.to_vec())
+                        .build(),
+                    delivery_latency: meter
+                        .f64_histogram("telnet.delivery.latency")
+                        .with_description("Time from socket read to handle_message completion")
+                        .with_unit("s")
+                        .with_boundaries(LATENCY_BUCKETS.to_vec())
+                        .build(),
+                    queue_depth: meter
+                        .i64_up_down_counter("telnet.delivery.queue_depth")
+                        .with_description("Deliveries to components currently in flight")
+                        .build(),
+                }
+            }
+        }
+
+        /// Instruments links record into, created from one meter
+        pub struct Registry {
+            instruments: Instruments,
+        }
+
+        impl Registry {
+            /// Instruments created from `meter`
+            pub fn new(meter: &Meter) -> Self {
+                Self {
+                    instruments: Instruments::new(meter),
+                }
+            }
+
+            /// Instruments of the global meter provider, created on first use
+            pub fn global() -> Arc<Self> {
+                static GLOBAL: OnceLock<Arc<Registry>> = OnceLock::new();
+                GLOBAL
+                    .get_or_init(|| {
+                        Arc::new(Self::new(&opentelemetry::global::meter(env!(
+                            "CARGO_PKG_NAME"
+                        ))))
+                    })
+                    .clone()
+            }
+        }
+
+        /// Delivery metrics for one link
+        pub struct LinkMetrics {
+            registry: Arc<Registry>,
+            attributes: [KeyValue; 1],
+        }
+
+        impl LinkMetrics {
+            pub fn new(source_id: &str) -> Self {
+                Self::with_registry(Registry::global(), source_id)
+            }
+
+            /// Metrics recorded into `registry` rather than the global one
+            pub fn with_registry(registry: Arc<Registry>, source_id: &str) -> Self {
+                Self {
+                    registry,
+                    attributes: [KeyValue::new("source_id", source_id.to_string())],
+                }
+            }
+
+            /// Record the size of a message about to be delivered
+            pub fn message_size(&self, len: usize) {
+                self.registry
+                    .instruments
+                    .message_size
+                    .record(len as u64, &self.attributes);
+            }
+
+            /// Record that a delivery started
+            pub fn delivery_started(&self) {
+                self.registry.instruments.queue_depth.add(1, &self.attributes);
+            }
+
+            /// Record that a delivery of data read at `received` finished
+            pub fn delivery_finished(&self, received: Instant) {
+                self.registry.instruments.queue_depth.add(-1, &self.attributes);
+                self.registry
+                    .instruments
+                    .delivery_latency
+                    .record(received.elapsed().as_secs_f64(), &self.attributes);
+            }
+        }
+    } else {
+        /// Instruments links record into (metrics export disabled)
+        pub struct Registry;
+
+        impl Registry {
+            pub fn global() -> Arc<Self> {
+                Arc::new(Self)
+            }
+        }
+
+        /// Delivery metrics for one link (metrics export disabled)
+        pub struct LinkMetrics;
+
+        impl LinkMetrics {
+            pub fn new(source_id: &str) -> Self {
+                Self::with_registry(Registry::global(), source_id)
+            }
+
+            pub fn with_registry(_registry: Arc<Registry>, _source_id: &str) -> Self {
+                Self
+            }
+
+            pub fn message_size(&self, _len: usize) {}
+
+            pub fn delivery_started(&self) {}
+
+            pub fn delivery_finished(&self, _received: Instant) {}
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tokio-tracing"))]
+mod tests {
+    use std::sync::Weak;
+    use std::time::Duration;
+
+    use opentelemetry::metrics::MeterProvider as _;
+    use opentelemetry_sdk::metrics::data::{self, ResourceMetrics};
+    use opentelemetry_sdk::metrics::reader::MetricReader;
+    use opentelemetry_sdk::metrics::{
+        InstrumentKind, ManualReader, MetricResult, Pipeline, SdkMeterProvider, Temporality,
+    };
+    use opentelemetry_sdk::Resource;
+
+    use super::*;
+
+    /// A [`ManualReader`] the test keeps a handle to after giving it to the
+    /// meter provider
+    #[derive(Debug, Clone, Default)]
+    struct SharedReader(Arc<ManualReader>);
+
+    impl MetricReader for SharedReader {
+        fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+            self.0.register_pipeline(pipeline)
+        }
+
+        fn collect(&self, rm: &mut ResourceMetrics) -> MetricResult<()> {
+            self.0.collect(rm)
+        }
+
+        fn force_flush(&self) -> MetricResult<()> {
+            self.0.force_flush()
+        }
+
+        fn shutdown(&self) -> MetricResult<()> {
+            self.0.shutdown()
+        }
+
+        fn temporality(&self, kind: InstrumentKind) -> Temporality {
+            self.0.temporality(kind)
+        }
+    }
+
+    /// Link metrics recording into a private meter provider, and its reader
+    fn link_metrics(source_id: &str) -> (LinkMetrics, SharedReader, SdkMeterProvider) {
+        let reader = SharedReader::default();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(reader.clone())
+            .build();
+        let registry = Arc::new(Registry::new(&provider.meter("test")));
+        (
+            LinkMetrics::with_registry(registry, source_id),
+            reader,
+            provider,
+        )
+    }
+
+    fn collect(reader: &SharedReader) -> ResourceMetrics {
+        let mut metrics = ResourceMetrics {
+            resource: Resource::empty(),
+            scope_metrics: Vec::new(),
+        };
+        reader.collect(&mut metrics).unwrap();
+        metrics
+    }
+
+    /// Data of the metric called `name`, as `T`
+    fn data<'a, T: 'static>(metrics: &'a ResourceMetrics, name: &str) -> &'a T {
+        let metric = metrics
+            .scope_metrics
+            .iter()
+            .flat_map(|scope| &scope.metrics)
+            .find(|metric| metric.name == name)
+            .unwrap_or_else(|| panic!("no metric {}", name));
+        metric.data.as_any().downcast_ref().unwrap()
+    }
+
+    #[test]
+    fn records_message_sizes_into_byte_buckets() {
+        let (metrics, reader, _provider) = link_metrics("alarm-handler");
+        for len in [10, 100, 5000] {
+            metrics.message_size(len);
+        }
+
+        let collected = collect(&reader);
+        let histogram: &data::Histogram<u64> = data(&collected, "telnet.message.size");
+        let point = &histogram.data_points[0];
+        assert_eq!(point.count, 3);
+        assert_eq!(point.sum, 5110);
+        assert_eq!(point.bounds, SIZE_BUCKETS);
+        // <= 16, <= 256 and <= 16384
+        assert_eq!(point.bucket_counts[0], 1);
+        assert_eq!(point.bucket_counts[2], 1);
+        assert_eq!(point.bucket_counts[5], 1);
+        assert_eq!(
+            point.attributes,
+            [KeyValue::new("source_id", "alarm-handler")]
+        );
+    }
+
+    #[test]
+    fn tracks_deliveries_in_flight_and_their_latency() {
+        let (metrics, reader, _provider) = link_metrics("alarm-handler");
+        let received = Instant::now() - Duration::from_millis(20);
+        metrics.delivery_started();
+        metrics.delivery_started();
+        metrics.delivery_finished(received);
+
+        let collected = collect(&reader);
+        let depth: &data::Sum<i64> = data(&collected, "telnet.delivery.queue_depth");
+        assert_eq!(depth.data_points[0].value, 1);
+
+        let latency: &data::Histogram<f64> = data(&collected, "telnet.delivery.latency");
+        let point = &latency.data_points[0];
+        assert_eq!(point.count, 1);
+        assert!(point.sum >= 0.02, "{}", point.sum);
+        assert_eq!(point.bounds, LATENCY_BUCKETS);
+    }
+}
@@ -0,0 +1,216 @@
+//! Handing a live Telnet connection to another process on the same host
+//!
+//! The old process sends the connection's socket descriptor over a UNIX
+//! datagram socket as `SCM_RIGHTS` ancillary data, together with the state
+//! the new process needs to carry on without renegotiating, serialized as
+//! JSON. Each hand-over is one datagram, so the descriptor and its state
+//! can't be taken apart. The kernel gives the receiver its own descriptor
+//! for the same socket, so the sender closing its copy afterwards doesn't
+//! close the connection.
+//!
+//! Only the transfer itself lives here: the provider doesn't migrate its
+//! links yet (see the README's Upgrades section).
+
+use std::io::{self, IoSlice, IoSliceMut};
+use std::mem::MaybeUninit;
+use std::net::SocketAddr;
+use std::os::fd::{AsFd, OwnedFd};
+
+use rustix::net::{
+    recvmsg, sendmsg, RecvAncillaryBuffer, RecvAncillaryMessage, RecvFlags, ReturnFlags,
+    SendAncillaryBuffer, SendAncillaryMessage, SendFlags,
+};
+use serde::{Deserialize, Serialize};
+use tokio::io::Interest;
+use tokio::net::{TcpStream, UnixDatagram};
+
+/// Largest serialized state a hand-over can carry
+pub const MAX_STATE_SIZE: usize = 64 * 1024;
+
+/// What a new process needs to carry on a connection it takes over
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConnectionMigration {
+    /// Component the connection's link belongs to
+    pub source_id: String,
+    /// Address of the server
+    pub peer: SocketAddr,
+    /// Telnet options the server agreed to
+    pub options: Vec<u8>,
+    /// Last sequence number seen, if the server numbers its records
+    pub last_sequence: Option<u64>,
+}
+
+/// Send `stream`'s descriptor and `state` to the process reading `channel`
+///
+/// The caller still owns `stream` and should drop it without shutting it
+/// down once this returns, since the connection now belongs to the
+/// receiver as well.
+pub async fn hand_over(
+    channel: &UnixDatagram,
+    stream: &impl AsFd,
+    state: &ConnectionMigration,
+) -> io::Result<()> {
+    let body = serde_json::to_vec(state)?;
+    if body.len() > MAX_STATE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "migration state of {} bytes exceeds {}",
+                body.len(),
+                MAX_STATE_SIZE
+            ),
+        ));
+    }
+
+    let fds = [stream.as_fd()];
+    channel
+        .async_io(Interest::WRITABLE, || {
+            let mut space = [MaybeUninit::uninit(); rustix::cmsg_space!(ScmRights(1))];
+            let mut control = SendAncillaryBuffer::new(&mut space);
+            control.push(SendAncillaryMessage::ScmRights(&fds));
+            sendmsg(
+                channel,
+                &[IoSlice::new(&body)],
+                &mut control,
+                SendFlags::empty(),
+            )?;
+            Ok(())
+        })
+        .await
+}
+
+/// Receive a connection handed over on `channel`
+///
+/// Fails with [`io::ErrorKind::InvalidData`], closing anything received,
+/// if the datagram carries no descriptor, more than one, or state that
+/// doesn't parse, or if the descriptor isn't a socket connected to the
+/// state's `peer`.
+pub async fn take_over(channel: &UnixDatagram) -> io::Result<(TcpStream, ConnectionMigration)> {
+    let mut body = vec![0; MAX_STATE_SIZE];
+    let (len, fds) = channel
+        .async_io(Interest::READABLE, || {
+            let mut space = [MaybeUninit::uninit(); rustix::cmsg_space!(ScmRights(1))];
+            let mut control = RecvAncillaryBuffer::new(&mut space);
+            let received = recvmsg(
+                channel,
+                &mut [IoSliceMut::new(&mut body)],
+                &mut control,
+                RecvFlags::CMSG_CLOEXEC,
+            )?;
+            let mut fds: Vec<OwnedFd> = Vec::new();
+            for message in control.drain() {
+                if let RecvAncillaryMessage::ScmRights(received) = message {
+                    fds.extend(received);
+                }
+            }
+            if received
+                .flags
+                .intersects(ReturnFlags::TRUNC | ReturnFlags::CTRUNC)
+            {
+                return Err(invalid("hand-over message was truncated"));
+            }
+            Ok((received.bytes, fds))
+        })
+        .await?;
+
+    let fd = match <[OwnedFd; 1]>::try_from(fds) {
+        Ok([fd]) => fd,
+        Err(fds) => {
+            return Err(invalid(format!(
+                "hand-over carried {} descriptors instead of one",
+                fds.len()
+            )))
+        }
+    };
+    let state: ConnectionMigration =
+        serde_json::from_slice(&body[..len]).map_err(|e| invalid(e.to_string()))?;
+
+    let stream = std::net::TcpStream::from(fd);
+    let peer = stream
+        .peer_addr()
+        .map_err(|e| invalid(format!("handed-over descriptor is not connected: {}", e)))?;
+    if peer != state.peer {
+        return Err(invalid(format!(
+            "handed-over socket is connected to {}, not {}",
+            peer, state.peer
+        )));
+    }
+    stream.set_nonblocking(true)?;
+    Ok((TcpStream::from_std(stream)?, state))
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// A connection to a local server: the client end and the server end
+    async fn connection() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (client, server)
+    }
+
+    fn state(peer: SocketAddr) -> ConnectionMigration {
+        ConnectionMigration {
+            source_id: "weather".to_string(),
+            peer,
+            options: vec![1, 3],
+            last_sequence: Some(41),
+        }
+    }
+
+    async fn read_some(stream: &mut TcpStream) -> Vec<u8> {
+        let mut buf = [0; 64];
+        let n = stream.read(&mut buf).await.unwrap();
+        buf[..n].to_vec()
+    }
+
+    #[tokio::test]
+    async fn a_handed_over_connection_stays_open_for_the_new_owner() {
+        let (mut old, mut server) = connection().await;
+        let peer = old.peer_addr().unwrap();
+        old.write_all(b"before").await.unwrap();
+        assert_eq!(read_some(&mut server).await, b"before");
+
+        let (old_channel, new_channel) = UnixDatagram::pair().unwrap();
+        hand_over(&old_channel, &old, &state(peer)).await.unwrap();
+        drop(old);
+        let (mut new, migrated) = take_over(&new_channel).await.unwrap();
+        assert_eq!(migrated, state(peer));
+
+        // The server sees neither a close nor a new connection
+        server.write_all(b"after").await.unwrap();
+        assert_eq!(read_some(&mut new).await, b"after");
+        new.write_all(b"again").await.unwrap();
+        assert_eq!(read_some(&mut server).await, b"again");
+    }
+
+    #[tokio::test]
+    async fn a_hand_over_without_a_descriptor_is_rejected() {
+        let (old_channel, new_channel) = UnixDatagram::pair().unwrap();
+        let body = serde_json::to_vec(&state("127.0.0.1:23".parse().unwrap())).unwrap();
+        old_channel.send(&body).await.unwrap();
+        let e = take_over(&new_channel).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn a_socket_connected_elsewhere_is_rejected() {
+        let (old, _server) = connection().await;
+        let (old_channel, new_channel) = UnixDatagram::pair().unwrap();
+        hand_over(&old_channel, &old, &state("127.0.0.1:23".parse().unwrap()))
+            .await
+            .unwrap();
+        let e = take_over(&new_channel).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,404 @@
+//! MUD Server Data Protocol (Telnet option 69)
+//!
+//! With `msdp_enabled` set, the client agrees to the server's MSDP offer and
+//! parses each `IAC SB MSDP ... IAC SE` frame into variables, which are
+//! forwarded to the component as JSON on `telnet.<host>:<port>.msdp`.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::provider::types;
+
+const IAC: u8 = 0xFF;
+const SB: u8 = 0xFA;
+const SE: u8 = 0xF0;
+const WILL: u8 = 0xFB;
+const DO: u8 = 0xFD;
+
+/// Telnet option code of MSDP
+const MSDP: u8 = 69;
+
+const MSDP_VAR: u8 = 1;
+const MSDP_VAL: u8 = 2;
+const MSDP_TABLE_OPEN: u8 = 3;
+const MSDP_TABLE_CLOSE: u8 = 4;
+const MSDP_ARRAY_OPEN: u8 = 5;
+const MSDP_ARRAY_CLOSE: u8 = 6;
+
+/// Deepest table/array nesting parsed; anything deeper is read as text
+const MAX_DEPTH: usize = 32;
+
+/// A value sent by the server
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum MsdpValue {
+    Scalar(String),
+    Table(BTreeMap<String, MsdpValue>),
+    Array(Vec<MsdpValue>),
+}
+
+/// The variables of one MSDP frame
+pub type MsdpVariables = BTreeMap<String, MsdpValue>;
+
+/// What the client answers to a negotiation command for `option`, if anything
+pub fn response_to(command: u8, option: u8) -> Option<&'static str> {
+    match (command, option) {
+        (WILL, MSDP) => Some("DO"),
+        (DO, MSDP) => Some("WILL"),
+        _ => None,
+    }
+}
+
+/// Responses to any MSDP option negotiation in raw received data
+///
+/// Returns an empty buffer if there is nothing to send.
+pub fn replies(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i + 2 < data.len() {
+        if data[i] != IAC {
+            i += 1;
+            continue;
+        }
+        match (data[i + 1], data[i + 2]) {
+            (IAC, _) => i += 2,
+            (WILL, MSDP) => {
+                out.extend_from_slice(&[IAC, DO, MSDP]);
+                i += 3;
+            }
+            (DO, MSDP) => {
+                out.extend_from_slice(&[IAC, WILL, MSDP]);
+                i += 3;
+            }
+            _ => i += 2,
+        }
+    }
+    out
+}
+
+/// Collects MSDP frames from successive reads
+///
+/// A frame cut off by the end of a read is kept until the rest arrives, up
+/// to `max_frame_size` bytes.
+pub struct MsdpReader {
+    pending: Vec<u8>,
+    max_frame_size: usize,
+}
+
+impl MsdpReader {
+    pub fn new(max_frame_size: usize) -> Self {
+        Self {
+            pending: Vec::new(),
+            max_frame_size,
+        }
+    }
+
+    /// Change the largest frame kept from now on
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size;
+    }
+
+    /// Parse every MSDP frame completed by `data`
+    pub fn push(&mut self, data: &[u8]) -> Vec<MsdpVariables> {
+        let mut frames = Vec::new();
+        let mut carried = std::mem::take(&mut self.pending);
+        let data = if carried.is_empty() {
+            data
+        } else {
+            carried.extend_from_slice(data);
+            &carried[..]
+        };
+
+        let mut i = 0;
+        while i + 2 < data.len() {
+            if data[i] != IAC {
+                i += 1;
+                continue;
+            }
+            match (data[i + 1], data[i + 2]) {
+                (IAC, _) => i += 2,
+                (SB, MSDP) => match subnegotiation(&data[i + 3..]) {
+                    Some((payload, len)) => {
+                        frames.push(parse(&payload));
+                        i += 3 + len;
+                    }
+                    None => {
+                        if data.len() - i <= self.max_frame_size {
+                            self.pending = data[i..].to_vec();
+                        }
+                        break;
+                    }
+                },
+                _ => i += 2,
+            }
+        }
+        frames
+    }
+}
+
+/// Unescaped payload of a subnegotiation up to `IAC SE`, and the number of
+/// input bytes it spanned, or `None` if `IAC SE` hasn't arrived yet
+///
+/// The payload is borrowed from `data` unless it contains IAC sequences.
+fn subnegotiation(data: &[u8]) -> Option<(Cow<'_, [u8]>, usize)> {
+    let mut plain = true;
+    let mut i = 0;
+    while i + 1 < data.len() {
+        if data[i] == IAC {
+            if data[i + 1] == SE {
+                let body = &data[..i];
+                let payload = if plain {
+                    Cow::Borrowed(body)
+                } else {
+                    Cow::Owned(unescape(body))
+                };
+                return Some((payload, i + 2));
+            }
+            plain = false;
+            i += 2;
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+/// Subnegotiation body with `IAC IAC` unescaped and other IAC commands
+/// dropped, in one allocation
+fn unescape(body: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(body.len());
+    let mut i = 0;
+    while i < body.len() {
+        if body[i] == IAC && i + 1 < body.len() {
+            if body[i + 1] == IAC {
+                payload.push(IAC);
+            }
+            i += 2;
+        } else {
+            payload.push(body[i]);
+            i += 1;
+        }
+    }
+    payload
+}
+
+/// Parse the payload of an MSDP frame into its variables
+///
+/// A variable followed by several values holds them as an array.
+pub fn parse(payload: &[u8]) -> MsdpVariables {
+    Parser {
+        data: payload,
+        pos: 0,
+    }
+    .table(None, 0)
+}
+
+struct Parser<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).copied()
+    }
+
+    /// `VAR name VAL value...` entries up to `close` (or the end)
+    fn table(&mut self, close: Option<u8>, depth: usize) -> MsdpVariables {
+        let mut table = BTreeMap::new();
+        while let Some(byte) = self.peek() {
+            self.pos += 1;
+            if Some(byte) == close {
+                break;
+            }
+            if byte != MSDP_VAR {
+                continue;
+            }
+            let name = self.text();
+            let mut values = Vec::new();
+            while self.peek() == Some(MSDP_VAL) {
+                self.pos += 1;
+                values.push(self.value(depth));
+            }
+            let value = match values.len() {
+                0 => MsdpValue::Scalar(String::new()),
+                1 => values.remove(0),
+                _ => MsdpValue::Array(values),
+            };
+            table.insert(name, value);
+        }
+        table
+    }
+
+    /// `VAL value...` elements up to `ARRAY_CLOSE` (or the end)
+    fn array(&mut self, depth: usize) -> Vec<MsdpValue> {
+        let mut array = Vec::new();
+        while let Some(byte) = self.peek() {
+            self.pos += 1;
+            match byte {
+                MSDP_ARRAY_CLOSE => break,
+                MSDP_VAL => array.push(self.value(depth)),
+                _ => {}
+            }
+        }
+        array
+    }
+
+    fn value(&mut self, depth: usize) -> MsdpValue {
+        if depth < MAX_DEPTH {
+            match self.peek() {
+                Some(MSDP_TABLE_OPEN) => {
+                    self.pos += 1;
+                    return MsdpValue::Table(self.table(Some(MSDP_TABLE_CLOSE), depth + 1));
+                }
+                Some(MSDP_ARRAY_OPEN) => {
+                    self.pos += 1;
+                    return MsdpValue::Array(self.array(depth + 1));
+                }
+                _ => {}
+            }
+        }
+        MsdpValue::Scalar(self.text())
+    }
+
+    /// Bytes up to the next MSDP control byte
+    fn text(&mut self) -> String {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|byte| !(MSDP_VAR..=MSDP_ARRAY_CLOSE).contains(&byte))
+        {
+            self.pos += 1;
+        }
+        String::from_utf8_lossy(&self.data[start..self.pos]).into_owned()
+    }
+}
+
+/// Create the message carrying one MSDP frame
+///
+/// The subject is "telnet.<host>:<port>.msdp" and the body is a JSON object
+/// of the frame's variables.
+pub fn create_msdp_message(subject: &str, variables: &MsdpVariables) -> types::BrokerMessage {
+    let body = serde_json::to_vec(variables).unwrap_or_default();
+    types::BrokerMessage {
+        subject: subject.to_string(),
+        body: body.into(),
+        reply_to: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scalar(value: &str) -> MsdpValue {
+        MsdpValue::Scalar(value.to_string())
+    }
+
+    /// `payload` wrapped in `IAC SB MSDP ... IAC SE`
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![IAC, SB, MSDP];
+        frame.extend_from_slice(payload);
+        frame.extend_from_slice(&[IAC, SE]);
+        frame
+    }
+
+    #[test]
+    fn parses_scalars() {
+        let variables = parse(b"\x01HEALTH\x02100\x01NAME\x02Ada");
+        assert_eq!(
+            variables,
+            MsdpVariables::from([
+                ("HEALTH".to_string(), scalar("100")),
+                ("NAME".to_string(), scalar("Ada")),
+            ])
+        );
+    }
+
+    #[test]
+    fn parses_several_values_as_an_array() {
+        let variables = parse(b"\x01EXITS\x02north\x02south");
+        assert_eq!(
+            variables["EXITS"],
+            MsdpValue::Array(vec![scalar("north"), scalar("south")])
+        );
+    }
+
+    #[test]
+    fn parses_nested_tables() {
+        let variables = parse(
+            b"\x01ROOM\x02\x03\x01VNUM\x026008\x01AREA\x02\x03\x01NAME\x02Moria\x04\x04\x01HP\x029",
+        );
+        let area = MsdpValue::Table(MsdpVariables::from([("NAME".to_string(), scalar("Moria"))]));
+        let room = MsdpValue::Table(MsdpVariables::from([
+            ("VNUM".to_string(), scalar("6008")),
+            ("AREA".to_string(), area),
+        ]));
+        assert_eq!(
+            variables,
+            MsdpVariables::from([("ROOM".to_string(), room), ("HP".to_string(), scalar("9")),])
+        );
+    }
+
+    #[test]
+    fn parses_arrays() {
+        let variables = parse(b"\x01LIST\x02\x05\x02a\x02\x05\x02b\x06\x02c\x06\x01NEXT\x02x");
+        assert_eq!(
+            variables["LIST"],
+            MsdpValue::Array(vec![
+                scalar("a"),
+                MsdpValue::Array(vec![scalar("b")]),
+                scalar("c"),
+            ])
+        );
+        assert_eq!(variables["NEXT"], scalar("x"));
+    }
+
+    #[test]
+    fn parses_a_truncated_payload_up_to_its_end() {
+        let variables = parse(b"\x01ROOM\x02\x03\x01VNUM\x026008\x01EXITS\x02\x05\x02north");
+        assert_eq!(
+            variables["ROOM"],
+            MsdpValue::Table(MsdpVariables::from([
+                ("VNUM".to_string(), scalar("6008")),
+                ("EXITS".to_string(), MsdpValue::Array(vec![scalar("north")])),
+            ]))
+        );
+    }
+
+    #[test]
+    fn keeps_a_truncated_frame_until_it_completes() {
+        let data = frame(b"\x01HEALTH\x02100");
+        let (first, rest) = data.split_at(8);
+
+        let mut reader = MsdpReader::new(1024);
+        assert!(reader.push(first).is_empty());
+        assert_eq!(
+            reader.push(rest),
+            vec![MsdpVariables::from([("HEALTH".to_string(), scalar("100"))])]
+        );
+    }
+
+    #[test]
+    fn drops_a_truncated_frame_over_the_size_limit() {
+        let data = frame(b"\x01HEALTH\x02100");
+        let (first, rest) = data.split_at(8);
+
+        let mut reader = MsdpReader::new(4);
+        assert!(reader.push(first).is_empty());
+        assert!(reader.push(rest).is_empty());
+    }
+
+    #[test]
+    fn unescapes_iac_in_values() {
+        let mut reader = MsdpReader::new(1024);
+        let frames = reader.push(&frame(b"\x01RAW\x02a\xff\xffb"));
+        assert_eq!(frames.len(), 1);
+        assert_eq!(
+            frames[0]["RAW"],
+            MsdpValue::Scalar(String::from_utf8_lossy(b"a\xffb").into_owned())
+        );
+    }
+}
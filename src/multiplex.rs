@@ -0,0 +1,252 @@
+//! Shared reader tasks for deployments with many mostly-idle connections
+//!
+//! By default every link's connection runs on its own tokio task. With
+//! `shared_reader_tasks` set, connections are instead assigned round-robin
+//! to that many worker tasks, each polling all of its connections together
+//! through a `FuturesUnordered`. A connection runs exactly the same future
+//! either way (reconnects, login, cancellation, delivery), only polled by a
+//! shared task instead of a dedicated one.
+//!
+//! A link with `runtime=dedicated` is taken out of the provider's runtime
+//! altogether: its connection runs on a single-threaded runtime of its own,
+//! on its own thread, so the scheduling of other links can't delay it.
+
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use futures::future::{abortable, AbortHandle};
+use futures::stream::{FuturesUnordered, StreamExt};
+use futures::FutureExt;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio_util::sync::{CancellationToken, DropGuard};
+use tracing::error;
+
+use crate::instrument::spawn_named;
+
+/// A connection's task, boxed to be handed to a worker
+type ConnectionFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Worker tasks shared by all connections (none by default)
+#[derive(Default)]
+pub struct ReaderPool {
+    workers: Mutex<Vec<mpsc::UnboundedSender<ConnectionFuture>>>,
+    next: AtomicUsize,
+}
+
+impl ReaderPool {
+    /// Start `workers` shared tasks, running until `shutdown` is cancelled
+    pub fn start(&self, workers: usize, shutdown: CancellationToken) {
+        let senders = (0..workers)
+            .map(|i| {
+                let (tx, rx) = mpsc::unbounded_channel();
+                spawn_named(
+                    "readers",
+                    &format!("worker-{}", i),
+                    run(rx, shutdown.clone()),
+                );
+                tx
+            })
+            .collect();
+        *self.workers.lock().unwrap_or_else(|e| e.into_inner()) = senders;
+    }
+
+    /// Run a connection task on the next shared worker, or on its own task
+    /// named `<owner>/<role>` if no workers were started
+    pub fn spawn<F>(&self, owner: &str, role: &str, task: F) -> ReaderTask
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let (task, done) = track(task);
+
+        let workers = self.workers.lock().unwrap_or_else(|e| e.into_inner());
+        if workers.is_empty() {
+            return ReaderTask {
+                handle: TaskHandle::Task(spawn_named(owner, role, task)),
+                done,
+            };
+        }
+
+        let (task, handle) = abortable(task);
+        // Keep a panicking connection from taking its worker's other
+        // connections down with it, as a dedicated task would
+        let task = catch_panic(task, owner);
+        let worker = &workers[self.next.fetch_add(1, Ordering::Relaxed) % workers.len()];
+        // Only fails once the workers have stopped for shutdown
+        let _ = worker.send(Box::pin(task));
+        ReaderTask {
+            handle: TaskHandle::Shared(handle),
+            done,
+        }
+    }
+
+    /// Run a connection task where `runtime` says
+    ///
+    /// A dedicated runtime is kept until the returned handle is dropped, so
+    /// background deliveries the task started can still be drained after it
+    /// has finished. If its thread can't be started, the task runs as
+    /// [`spawn`](Self::spawn) would run it.
+    pub fn spawn_on<F>(
+        &self,
+        runtime: ConnectionRuntime,
+        owner: &str,
+        role: &str,
+        task: F,
+    ) -> ReaderTask
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        if runtime == ConnectionRuntime::Shared {
+            return self.spawn(owner, role, task);
+        }
+        let worker = match start_dedicated(format!("{}/{}", owner, role)) {
+            Ok(worker) => worker,
+            Err(e) => {
+                error!(
+                    "Failed to start a dedicated runtime for component {}, using the shared one: {}",
+                    owner, e
+                );
+                return self.spawn(owner, role, task);
+            }
+        };
+
+        let (task, done) = track(task);
+        let (task, handle) = abortable(task);
+        let task = catch_panic(task, owner);
+        let release = CancellationToken::new();
+        let released = release.clone();
+        // Only fails if the runtime's thread has already died
+        let _ = worker.send(Box::pin(async move {
+            task.await;
+            released.cancelled().await;
+        }));
+        ReaderTask {
+            handle: TaskHandle::Runtime {
+                abort: handle,
+                _release: release.drop_guard(),
+            },
+            done,
+        }
+    }
+}
+
+/// Where a link's connection task runs
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionRuntime {
+    /// On the provider's runtime, with every other link
+    #[default]
+    Shared,
+    /// On a single-threaded runtime and thread of its own
+    Dedicated,
+}
+
+impl std::str::FromStr for ConnectionRuntime {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "shared" => Ok(ConnectionRuntime::Shared),
+            "dedicated" => Ok(ConnectionRuntime::Dedicated),
+            other => Err(anyhow::anyhow!("Invalid runtime: {}", other)),
+        }
+    }
+}
+
+/// Start a thread named `name` with a single-threaded runtime, which runs
+/// the connection future sent to it
+fn start_dedicated(name: String) -> std::io::Result<oneshot::Sender<ConnectionFuture>> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let (tx, rx) = oneshot::channel::<ConnectionFuture>();
+    std::thread::Builder::new().name(name).spawn(move || {
+        runtime.block_on(async move {
+            if let Ok(task) = rx.await {
+                task.await;
+            }
+        })
+    })?;
+    Ok(tx)
+}
+
+/// `task`, and a token cancelled when it completes or is dropped
+fn track<F>(task: F) -> (impl Future<Output = ()>, CancellationToken)
+where
+    F: Future<Output = ()>,
+{
+    let done = CancellationToken::new();
+    let guard = done.clone().drop_guard();
+    let task = async move {
+        let _guard = guard;
+        task.await
+    };
+    (task, done)
+}
+
+/// `task` with a panic logged instead of unwinding into whatever polls it
+fn catch_panic<F>(task: F, owner: &str) -> impl Future<Output = ()>
+where
+    F: Future,
+{
+    let owner = owner.to_string();
+    async move {
+        if AssertUnwindSafe(task).catch_unwind().await.is_err() {
+            error!("Connection task for component {} panicked", owner);
+        }
+    }
+}
+
+/// Handle to a connection task, wherever it runs
+pub struct ReaderTask {
+    handle: TaskHandle,
+    /// Cancelled once the task has finished
+    done: CancellationToken,
+}
+
+enum TaskHandle {
+    /// Running on its own tokio task
+    Task(JoinHandle<()>),
+    /// Running on a shared worker
+    Shared(AbortHandle),
+    /// Running on a runtime of its own, which is shut down when this is
+    /// dropped
+    Runtime {
+        abort: AbortHandle,
+        _release: DropGuard,
+    },
+}
+
+impl ReaderTask {
+    /// Stop the task at its next await point
+    pub fn abort(&self) {
+        match &self.handle {
+            TaskHandle::Task(handle) => handle.abort(),
+            TaskHandle::Shared(handle) | TaskHandle::Runtime { abort: handle, .. } => {
+                handle.abort()
+            }
+        }
+    }
+
+    /// Wait until the task has finished, by returning or being aborted
+    pub async fn finished(&self) {
+        self.done.cancelled().await
+    }
+}
+
+/// Poll every connection assigned to this worker until shutdown
+async fn run(mut tasks: mpsc::UnboundedReceiver<ConnectionFuture>, shutdown: CancellationToken) {
+    let mut connections = FuturesUnordered::new();
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            task = tasks.recv() => match task {
+                Some(task) => connections.push(task),
+                None => break,
+            },
+            Some(()) = connections.next(), if !connections.is_empty() => {}
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Newline-delimited JSON output
+//!
+//! With `ndjson` set, every record a read produces becomes one JSON value on
+//! its own line, and all of them go to the component as a single message.
+//! With JSON envelopes the value is the envelope; otherwise it is the
+//! record's text as a JSON string.
+
+use bytes::Bytes;
+
+/// Records from one read, joined as NDJSON
+#[derive(Debug, Default)]
+pub struct NdjsonBatch {
+    buf: Vec<u8>,
+    records: usize,
+}
+
+impl NdjsonBatch {
+    /// Append a record, either an encoded JSON envelope or raw text
+    ///
+    /// Raw records that aren't UTF-8 text can't be represented and are
+    /// rejected.
+    pub fn push(&mut self, record: &[u8], enveloped: bool) -> Result<(), std::str::Utf8Error> {
+        if enveloped {
+            // Compact JSON never contains a raw newline
+            self.buf.extend_from_slice(record);
+        } else {
+            let text = std::str::from_utf8(record)?.trim_end_matches(['\r', '\n']);
+            serde_json::to_writer(&mut self.buf, text)
+                .expect("serializing a string to memory can't fail");
+        }
+        self.buf.push(b'\n');
+        self.records += 1;
+        Ok(())
+    }
+
+    /// The batch as a message body, leaving it empty; `None` if it has no
+    /// records
+    pub fn take(&mut self) -> Option<Bytes> {
+        if self.records == 0 {
+            return None;
+        }
+        self.records = 0;
+        Some(Bytes::from(std::mem::take(&mut self.buf)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each line of `body`, parsed as JSON
+    fn values(body: &[u8]) -> Vec<serde_json::Value> {
+        let text = std::str::from_utf8(body).unwrap();
+        assert!(text.ends_with('\n'));
+        text.lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn raw_records_become_json_strings_one_per_line() {
+        let mut batch = NdjsonBatch::default();
+        batch.push(b"login: \r\n", false).unwrap();
+        batch.push(b"say \"hi\"\n", false).unwrap();
+        batch.push(b"no newline", false).unwrap();
+        let body = batch.take().unwrap();
+        assert_eq!(
+            values(&body),
+            [
+                serde_json::json!("login: "),
+                serde_json::json!("say \"hi\""),
+                serde_json::json!("no newline"),
+            ]
+        );
+    }
+
+    #[test]
+    fn envelopes_pass_through_unchanged() {
+        let mut batch = NdjsonBatch::default();
+        batch.push(br#"{"seq":1,"data":"a"}"#, true).unwrap();
+        batch.push(br#"{"seq":2,"data":"b"}"#, true).unwrap();
+        assert_eq!(
+            batch.take().unwrap(),
+            &b"{\"seq\":1,\"data\":\"a\"}\n{\"seq\":2,\"data\":\"b\"}\n"[..]
+        );
+    }
+
+    #[test]
+    fn raw_records_that_are_not_utf8_are_rejected() {
+        let mut batch = NdjsonBatch::default();
+        assert!(batch.push(b"\xff\xfe", false).is_err());
+        assert_eq!(batch.take(), None);
+        // An envelope is already JSON, so its bytes aren't checked
+        batch.push(b"\xff", true).unwrap();
+        assert!(batch.take().is_some());
+    }
+
+    #[test]
+    fn take_empties_the_batch() {
+        let mut batch = NdjsonBatch::default();
+        assert_eq!(batch.take(), None);
+        batch.push(b"one", false).unwrap();
+        assert_eq!(batch.take().unwrap(), &b"\"one\"\n"[..]);
+        assert_eq!(batch.take(), None);
+        batch.push(b"two", false).unwrap();
+        assert_eq!(batch.take().unwrap(), &b"\"two\"\n"[..]);
+    }
+}
@@ -0,0 +1,272 @@
+//! Envelope protocol version negotiation with components
+//!
+//! Before forwarding any data, a link asking for a protocol version above 1
+//! sends its component a probe on `telnet.negotiate`: a one-byte body
+//! holding the requested version, with `reply_to` set to a subject made up
+//! for that probe. The component answers by publishing the version it will
+//! use, as a one-byte body, to that subject through the provider's
+//! `wasmcloud:messaging/consumer` export. Only the probed component can
+//! answer.
+//!
+//! Returning `Ok` from `handle-message` is not an answer: a component that
+//! doesn't know about negotiation returns `Ok` for any message. So a probe
+//! the component accepts without replying, a reply that isn't a single byte
+//! between 1 and the requested version, and no reply within
+//! [`REPLY_TIMEOUT`] all leave the link on version 1 (raw bytes), which
+//! every component understands.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use bytes::Bytes;
+use tokio::sync::oneshot;
+use tracing::{error, warn};
+
+use crate::provider::types;
+
+/// Highest message envelope protocol version this provider can produce
+pub const PROTOCOL_VERSION: u8 = 2;
+
+/// Subject of the version probe sent to components during negotiation
+pub const NEGOTIATE_SUBJECT: &str = "telnet.negotiate";
+
+/// How long a component has to publish its reply to a probe
+pub const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Probes waiting for their component's reply, by reply subject
+#[derive(Default)]
+pub struct PendingReplies {
+    next_id: AtomicU64,
+    waiting: Mutex<HashMap<String, Waiting>>,
+}
+
+/// A probe waiting for its reply
+struct Waiting {
+    /// The only component whose reply is taken
+    component: Arc<str>,
+    reply: oneshot::Sender<Bytes>,
+}
+
+impl PendingReplies {
+    /// Wait for a reply from `component` on a subject of its own
+    fn expect(self: &Arc<Self>, component: &Arc<str>) -> ExpectedReply {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let subject = format!("{}.reply.{}", NEGOTIATE_SUBJECT, id);
+        let (reply, receiver) = oneshot::channel();
+        self.waiting().insert(
+            subject.clone(),
+            Waiting {
+                component: component.clone(),
+                reply,
+            },
+        );
+        ExpectedReply {
+            subject,
+            receiver,
+            replies: self.clone(),
+        }
+    }
+
+    /// Hand `body`, published by `component` on `subject`, to the probe
+    /// waiting for it
+    ///
+    /// Returns `false` if no probe of `component` waits on `subject`, such
+    /// as one that already timed out.
+    pub fn reply(&self, component: &str, subject: &str, body: Bytes) -> bool {
+        let mut waiting = self.waiting();
+        if !waiting
+            .get(subject)
+            .is_some_and(|waiting| &*waiting.component == component)
+        {
+            return false;
+        }
+        if let Some(waiting) = waiting.remove(subject) {
+            let _ = waiting.reply.send(body);
+        }
+        true
+    }
+
+    fn waiting(&self) -> MutexGuard<'_, HashMap<String, Waiting>> {
+        self.waiting.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A reply subject registered with [`PendingReplies`], unregistered on drop
+struct ExpectedReply {
+    subject: String,
+    receiver: oneshot::Receiver<Bytes>,
+    replies: Arc<PendingReplies>,
+}
+
+impl ExpectedReply {
+    /// The reply, once the component publishes it
+    async fn receive(&mut self) -> Option<Bytes> {
+        (&mut self.receiver).await.ok()
+    }
+}
+
+impl Drop for ExpectedReply {
+    fn drop(&mut self) {
+        self.replies.waiting().remove(&self.subject);
+    }
+}
+
+/// The version a component replied with, if it is one of `1..=requested`
+fn replied_version(requested: u8, body: &[u8]) -> Option<u8> {
+    match body {
+        [version] if (1..=requested).contains(version) => Some(*version),
+        _ => None,
+    }
+}
+
+/// Negotiate the envelope protocol version with `component`
+///
+/// `send` delivers the probe to the component. Version 1 needs no
+/// negotiation, so no probe is sent for it; any failure, and a missing or
+/// unusable reply, falls back to it.
+pub async fn negotiate<F, Fut>(
+    replies: &Arc<PendingReplies>,
+    component: &Arc<str>,
+    requested: u8,
+    timeout: Duration,
+    send: F,
+) -> u8
+where
+    F: FnOnce(types::BrokerMessage) -> Fut,
+    Fut: Future<Output = anyhow::Result<()>>,
+{
+    if requested <= 1 {
+        return 1;
+    }
+    if requested > PROTOCOL_VERSION {
+        error!(
+            "Protocol version {} is not supported by this provider (max {}), falling back to v1",
+            requested, PROTOCOL_VERSION
+        );
+        return 1;
+    }
+
+    let mut expected = replies.expect(component);
+    let probe = types::BrokerMessage {
+        subject: NEGOTIATE_SUBJECT.to_string(),
+        body: vec![requested].into(),
+        reply_to: Some(expected.subject.clone()),
+    };
+    if let Err(e) = send(probe).await {
+        error!(
+            "Component {} did not accept protocol version {}, falling back to v1: {}",
+            component, requested, e
+        );
+        return 1;
+    }
+
+    match tokio::time::timeout(timeout, expected.receive()).await {
+        Ok(Some(body)) => replied_version(requested, &body).unwrap_or_else(|| {
+            warn!(
+                "Component {} replied {:?} to protocol version {}, which is not a version it can be given, falling back to v1",
+                component, body, requested
+            );
+            1
+        }),
+        _ => {
+            warn!(
+                "Component {} did not reply to the protocol version {} probe within {:?}, falling back to v1",
+                component, requested, timeout
+            );
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Negotiate `requested` with a component that handles the probe with
+    /// `component`, given the pending replies and the probe
+    async fn negotiate_with(
+        requested: u8,
+        component: impl FnOnce(&PendingReplies, types::BrokerMessage) -> anyhow::Result<()>,
+    ) -> u8 {
+        let replies = Arc::new(PendingReplies::default());
+        let version = negotiate(&replies, &"weather".into(), requested, REPLY_TIMEOUT, {
+            let replies = replies.clone();
+            move |probe| std::future::ready(component(&replies, probe))
+        })
+        .await;
+        assert!(replies.waiting().is_empty());
+        version
+    }
+
+    /// A component publishing `body` as its reply to the probe
+    fn replying(
+        body: &'static [u8],
+    ) -> impl FnOnce(&PendingReplies, types::BrokerMessage) -> anyhow::Result<()> {
+        move |replies, probe| {
+            assert_eq!(probe.subject, NEGOTIATE_SUBJECT);
+            let reply_to = probe.reply_to.unwrap();
+            assert!(replies.reply("weather", &reply_to, Bytes::from_static(body)));
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn agrees_on_the_version_the_component_replies_with() {
+        assert_eq!(negotiate_with(2, replying(&[2])).await, 2);
+        assert_eq!(negotiate_with(2, replying(&[1])).await, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_component_returning_ok_without_replying_gets_v1() {
+        let started = tokio::time::Instant::now();
+        let version = negotiate_with(2, |_, probe| {
+            assert_eq!(&probe.body[..], [2]);
+            Ok(())
+        })
+        .await;
+        assert_eq!(version, 1);
+        assert_eq!(started.elapsed(), REPLY_TIMEOUT);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unusable_replies_fall_back_to_v1() {
+        for body in [&b""[..], &[0], &[3], &[2, 2], b"2"] {
+            let body: &'static [u8] = body;
+            assert_eq!(negotiate_with(2, replying(body)).await, 1, "{:?}", body);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_failed_probe_falls_back_to_v1() {
+        let version =
+            negotiate_with(2, |_, _| anyhow::bail!("supported protocol version: 1")).await;
+        assert_eq!(version, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn only_the_probed_component_can_reply() {
+        let version = negotiate_with(2, |replies, probe| {
+            let reply_to = probe.reply_to.unwrap();
+            assert!(!replies.reply("intruder", &reply_to, Bytes::from_static(&[2])));
+            assert!(!replies.reply(
+                "weather",
+                "telnet.negotiate.reply.99",
+                Bytes::from_static(&[2])
+            ));
+            Ok(())
+        })
+        .await;
+        assert_eq!(version, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn no_probe_is_sent_for_v1_or_unsupported_versions() {
+        for requested in [0, 1, PROTOCOL_VERSION + 1] {
+            let version = negotiate_with(requested, |_, _| panic!("probe sent")).await;
+            assert_eq!(version, 1);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Delivery of numbered records in sequence order
+//!
+//! Data is delivered in the order it is read, which is the order the
+//! server sent it. Some servers interleave several producers and send
+//! numbered records (`x-telnet-seq: <n>` header lines, as used for
+//! sequence gap detection) slightly out of order. With
+//! `delivery_ordering=ordered`, records are held back until the records
+//! numbered before them have arrived, and passed on in sequence order.
+//! A missing number is waited for up to `ordering_max_wait_ms`, then
+//! skipped. Waits are checked as data arrives, so held records are passed
+//! on by the first read after the wait ends.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tracing::{debug, trace};
+
+use crate::sequence::extract_sequence;
+
+/// Records held at most while waiting for a missing number; beyond this the
+/// number is skipped without waiting out `ordering_max_wait_ms`
+const MAX_HELD: usize = 1024;
+
+/// Order in which a link's records are delivered
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryOrdering {
+    /// In the order they are read
+    #[default]
+    BestEffort,
+    /// In `x-telnet-seq` order, waiting for missing numbers
+    Ordered,
+}
+
+impl std::str::FromStr for DeliveryOrdering {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "best_effort" => Ok(DeliveryOrdering::BestEffort),
+            "ordered" => Ok(DeliveryOrdering::Ordered),
+            other => Err(anyhow::anyhow!("Invalid delivery_ordering: {}", other)),
+        }
+    }
+}
+
+/// A record waiting for the records numbered before it
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Held {
+    seq: u64,
+    received: Instant,
+    record: Bytes,
+}
+
+/// Puts one connection's numbered records back in sequence order
+#[derive(Debug)]
+pub struct Resequencer {
+    max_wait: Duration,
+    /// Sequence number to pass on next, once the first record has arrived
+    next: Option<u64>,
+    /// Records numbered after `next`, lowest number first
+    held: BinaryHeap<Reverse<Held>>,
+    /// When the wait for `next` started, while records are held
+    waiting_since: Option<Instant>,
+}
+
+impl Resequencer {
+    pub fn new(max_wait: Duration) -> Self {
+        Self {
+            max_wait,
+            next: None,
+            held: BinaryHeap::new(),
+            waiting_since: None,
+        }
+    }
+
+    /// Add a record read at `received`, returning the records (with their
+    /// read times) that can now be passed on, in order
+    ///
+    /// Records without a sequence number, and records numbered below the
+    /// next one expected (late arrivals after a skip, or a restarted
+    /// server), are passed on as they arrive.
+    pub fn push(&mut self, record: Bytes, received: Instant) -> Vec<(Bytes, Instant)> {
+        let mut ready = Vec::new();
+        match extract_sequence(&record) {
+            Some(seq) if seq >= *self.next.get_or_insert(seq) => {
+                self.held.push(Reverse(Held {
+                    seq,
+                    received,
+                    record,
+                }));
+                self.waiting_since.get_or_insert(received);
+            }
+            _ => ready.push((record, received)),
+        }
+        self.release(received, &mut ready);
+        ready
+    }
+
+    /// Pass on held records that are next in sequence, skipping missing
+    /// numbers that have been waited for long enough
+    fn release(&mut self, now: Instant, ready: &mut Vec<(Bytes, Instant)>) {
+        let Some(mut next) = self.next else {
+            return;
+        };
+        loop {
+            while self
+                .held
+                .peek()
+                .is_some_and(|Reverse(held)| held.seq <= next)
+            {
+                let Some(Reverse(held)) = self.held.pop() else {
+                    break;
+                };
+                next = next.max(held.seq + 1);
+                // The wait for the following number starts now
+                self.waiting_since = Some(now);
+                ready.push((held.record, held.received));
+            }
+
+            let Some(Reverse(lowest)) = self.held.peek() else {
+                self.waiting_since = None;
+                break;
+            };
+            let waited = self
+                .waiting_since
+                .map_or(Duration::ZERO, |since| now.saturating_duration_since(since));
+            if waited < self.max_wait && self.held.len() <= MAX_HELD {
+                trace!(
+                    "Holding {} record(s) until sequence number {} arrives",
+                    self.held.len(),
+                    next
+                );
+                break;
+            }
+
+            debug!(
+                "Skipping sequence numbers {} to {} after waiting {:?}",
+                next,
+                lowest.seq - 1,
+                waited
+            );
+            next = lowest.seq;
+        }
+        self.next = Some(next);
+    }
+}
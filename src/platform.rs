@@ -0,0 +1,118 @@
+//! Socket options that differ between operating systems
+//!
+//! TCP keepalive timing and `TCP_USER_TIMEOUT` are set through `socket2`,
+//! but not every OS has every knob: Windows has no keepalive probe count,
+//! and only Linux has a user timeout. Each option is compiled in only where
+//! the OS supports it. A link setting one that isn't supported gets a
+//! warning naming the key when it is created, and the option is skipped,
+//! so the same link config works on Linux, macOS and Windows hosts.
+
+use std::io;
+use std::time::Duration;
+
+use socket2::{SockRef, TcpKeepalive};
+
+use crate::config::LinkConfig;
+
+/// Whether the interval between keepalive probes can be set
+const KEEPALIVE_INTERVAL: bool = cfg!(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    windows
+));
+
+/// Whether the number of unanswered keepalive probes can be set
+const KEEPALIVE_RETRIES: bool = cfg!(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd"
+));
+
+/// Whether `TCP_USER_TIMEOUT` can be set
+const USER_TIMEOUT: bool = cfg!(any(target_os = "linux", target_os = "android"));
+
+/// Socket options of a link, applied to each connection it makes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SocketTuning {
+    /// Idle time before keepalive probes start; keepalive is off without it
+    keepalive_time: Option<Duration>,
+    keepalive_interval: Option<Duration>,
+    keepalive_retries: Option<u32>,
+    /// How long sent data may stay unacknowledged before the connection
+    /// is dropped
+    user_timeout: Option<Duration>,
+}
+
+impl SocketTuning {
+    pub fn from_config(config: &LinkConfig) -> Self {
+        let secs = |secs| (secs > 0).then(|| Duration::from_secs(secs));
+        Self {
+            keepalive_time: secs(config.tcp_keepalive_secs),
+            keepalive_interval: secs(config.tcp_keepalive_interval_secs),
+            keepalive_retries: (config.tcp_keepalive_retries > 0)
+                .then_some(config.tcp_keepalive_retries),
+            user_timeout: (config.tcp_user_timeout_ms > 0)
+                .then(|| Duration::from_millis(config.tcp_user_timeout_ms)),
+        }
+    }
+
+    /// Config keys that are set but have no effect on this platform
+    pub fn unsupported(&self) -> Vec<&'static str> {
+        let mut unsupported = Vec::new();
+        if self.keepalive_interval.is_some() && !KEEPALIVE_INTERVAL {
+            unsupported.push("tcp_keepalive_interval_secs");
+        }
+        if self.keepalive_retries.is_some() && !KEEPALIVE_RETRIES {
+            unsupported.push("tcp_keepalive_retries");
+        }
+        if self.user_timeout.is_some() && !USER_TIMEOUT {
+            unsupported.push("tcp_user_timeout_ms");
+        }
+        unsupported
+    }
+
+    /// Set the supported options on `socket`
+    pub fn apply(&self, socket: SockRef<'_>) -> io::Result<()> {
+        if let Some(time) = self.keepalive_time {
+            let keepalive = TcpKeepalive::new().with_time(time);
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "android",
+                target_os = "macos",
+                target_os = "ios",
+                target_os = "freebsd",
+                target_os = "netbsd",
+                windows
+            ))]
+            let keepalive = match self.keepalive_interval {
+                Some(interval) => keepalive.with_interval(interval),
+                None => keepalive,
+            };
+            #[cfg(any(
+                target_os = "linux",
+                target_os = "android",
+                target_os = "macos",
+                target_os = "ios",
+                target_os = "freebsd",
+                target_os = "netbsd"
+            ))]
+            let keepalive = match self.keepalive_retries {
+                Some(retries) => keepalive.with_retries(retries),
+                None => keepalive,
+            };
+            socket.set_tcp_keepalive(&keepalive)?;
+        }
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if self.user_timeout.is_some() {
+            socket.set_tcp_user_timeout(self.user_timeout)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+//! Provider-level policy on which hosts links may connect to
+//!
+//! `require_tls_hosts` lists hosts that must only be reached over TLS, as
+//! comma-separated glob patterns on the host name (`*.secure.example.com`)
+//! or CIDR ranges (`10.20.0.0/16`, `fd00::/8`). The provider connects in
+//! plain Telnet only, so links to a listed host are rejected when they are
+//! created rather than silently sending data unencrypted. CIDR ranges are
+//! checked against the host itself if it is an IP address, and otherwise
+//! against the addresses it resolves to.
+//!
+//! `connect_allowed_hosts` uses the same patterns for the opposite purpose:
+//! it lists the only hosts components may open connections to through
+//! handles, optionally narrowed to `connect_allowed_ports`. Nothing is
+//! allowed unless it is set. These patterns are only checked against the
+//! host as the component gave it, never against what a name resolves to,
+//! so a CIDR range only admits hosts given as IP addresses.
+
+use std::net::IpAddr;
+
+use crate::config::IpFamily;
+use crate::resolve::resolve;
+use crate::transform::subject_matches;
+
+/// A host name glob or an IP address range
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostPattern {
+    /// Glob on the host name, compared case-insensitively
+    Glob(String),
+    /// Network address and prefix length
+    Cidr(IpAddr, u8),
+}
+
+impl HostPattern {
+    /// Whether `host` (as configured on the link) matches the pattern
+    pub fn matches_host(&self, host: &str) -> bool {
+        match self {
+            HostPattern::Glob(pattern) => subject_matches(pattern, &host.to_ascii_lowercase()),
+            HostPattern::Cidr(..) => host.parse().is_ok_and(|ip| self.matches_ip(ip)),
+        }
+    }
+
+    /// Whether `ip` lies in the pattern's range; globs never match addresses
+    pub fn matches_ip(&self, ip: IpAddr) -> bool {
+        let HostPattern::Cidr(network, prefix) = *self else {
+            return false;
+        };
+        match (network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the pattern is an address range
+    pub fn is_cidr(&self) -> bool {
+        matches!(self, HostPattern::Cidr(..))
+    }
+}
+
+impl std::fmt::Display for HostPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HostPattern::Glob(pattern) => f.write_str(pattern),
+            HostPattern::Cidr(network, prefix) => write!(f, "{}/{}", network, prefix),
+        }
+    }
+}
+
+impl std::str::FromStr for HostPattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((network, prefix)) = s.split_once('/') else {
+            return Ok(HostPattern::Glob(s.to_ascii_lowercase()));
+        };
+        let invalid = || anyhow::anyhow!("Invalid CIDR range: {}", s);
+        let network: IpAddr = network.parse().map_err(|_| invalid())?;
+        let prefix: u8 = prefix.parse().map_err(|_| invalid())?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        if prefix > max {
+            return Err(invalid());
+        }
+        Ok(HostPattern::Cidr(network, prefix))
+    }
+}
+
+/// Parse comma-separated host patterns, skipping empty entries
+pub fn parse_host_patterns(s: &str) -> anyhow::Result<Vec<HostPattern>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// The first pattern requiring TLS for connections to `host`, if any
+///
+/// Host names are resolved for CIDR ranges; if resolution fails, only the
+/// globs are checked.
+pub async fn tls_required_by<'a>(
+    patterns: &'a [HostPattern],
+    host: &str,
+    port: u16,
+    family: IpFamily,
+) -> Option<&'a HostPattern> {
+    if let Some(pattern) = patterns.iter().find(|pattern| pattern.matches_host(host)) {
+        return Some(pattern);
+    }
+    if host.parse::<IpAddr>().is_ok() || !patterns.iter().any(HostPattern::is_cidr) {
+        return None;
+    }
+    let addrs = resolve(host, port, family).await.unwrap_or_default();
+    patterns
+        .iter()
+        .find(|pattern| addrs.iter().any(|addr| pattern.matches_ip(addr.ip())))
+}
+
+/// Whether a component may open a connection to `host:port`
+pub fn connect_allowed(hosts: &[HostPattern], ports: &[u16], host: &str, port: u16) -> bool {
+    (ports.is_empty() || ports.contains(&port))
+        && hosts.iter().any(|pattern| pattern.matches_host(host))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_globs_and_cidr_ranges() {
+        assert_eq!(
+            parse_host_patterns(" *.Secure.example.com,, 10.20.0.0/16 ,fd00::/8,").unwrap(),
+            [
+                HostPattern::Glob("*.secure.example.com".to_string()),
+                HostPattern::Cidr("10.20.0.0".parse().unwrap(), 16),
+                HostPattern::Cidr("fd00::".parse().unwrap(), 8),
+            ]
+        );
+        assert!(parse_host_patterns("").unwrap().is_empty());
+        for invalid in ["10.0.0.0/33", "fd00::/129", "host/8", "10.0.0.0/x"] {
+            assert!(parse_host_patterns(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(
+            parse_host_patterns("10.20.0.0/16").unwrap()[0].to_string(),
+            "10.20.0.0/16"
+        );
+    }
+
+    #[test]
+    fn cidr_ranges_match_addresses_of_their_family() {
+        let v4: HostPattern = "10.20.0.0/16".parse().unwrap();
+        assert!(v4.matches_ip("10.20.255.1".parse().unwrap()));
+        assert!(!v4.matches_ip("10.21.0.1".parse().unwrap()));
+        assert!(!v4.matches_ip("::ffff:10.20.0.1".parse().unwrap()));
+        let v6: HostPattern = "fd00::/8".parse().unwrap();
+        assert!(v6.matches_ip("fd12::1".parse().unwrap()));
+        assert!(!v6.matches_ip("fe80::1".parse().unwrap()));
+        let everything: HostPattern = "0.0.0.0/0".parse().unwrap();
+        assert!(everything.matches_ip("192.0.2.1".parse().unwrap()));
+        let single: HostPattern = "192.0.2.7/32".parse().unwrap();
+        assert!(single.matches_host("192.0.2.7"));
+        assert!(!single.matches_host("192.0.2.8"));
+        // Globs never match addresses, and ranges never match names
+        assert!(!HostPattern::Glob("*".to_string()).matches_ip("192.0.2.7".parse().unwrap()));
+        assert!(!everything.matches_host("example.com"));
+    }
+
+    #[tokio::test]
+    async fn tls_is_required_by_the_first_matching_pattern() {
+        let patterns = parse_host_patterns("*.secure.example.com, 10.20.0.0/16").unwrap();
+        let required = |host: &'static str| {
+            let patterns = patterns.clone();
+            async move {
+                tls_required_by(&patterns, host, 23, IpFamily::Any)
+                    .await
+                    .map(ToString::to_string)
+            }
+        };
+        assert_eq!(
+            required("DB.Secure.example.com").await.as_deref(),
+            Some("*.secure.example.com")
+        );
+        assert_eq!(required("10.20.3.4").await.as_deref(), Some("10.20.0.0/16"));
+        assert_eq!(required("10.30.3.4").await, None);
+        assert_eq!(required("secure.example.com").await, None);
+    }
+
+    #[tokio::test]
+    async fn host_names_are_resolved_for_cidr_ranges() {
+        let loopback = parse_host_patterns("127.0.0.0/8").unwrap();
+        assert_eq!(
+            tls_required_by(&loopback, "localhost", 23, IpFamily::Any)
+                .await
+                .map(ToString::to_string)
+                .as_deref(),
+            Some("127.0.0.0/8")
+        );
+        // A name that doesn't resolve is only checked against globs
+        assert_eq!(
+            tls_required_by(&loopback, "no-such-host.invalid", 23, IpFamily::Any).await,
+            None
+        );
+        let elsewhere = parse_host_patterns("10.0.0.0/8").unwrap();
+        assert_eq!(
+            tls_required_by(&elsewhere, "localhost", 23, IpFamily::Any).await,
+            None
+        );
+    }
+
+    #[test]
+    fn connect_allows_listed_hosts_and_ports_only() {
+        let hosts = parse_host_patterns("*.lab.example.com, 10.1.0.0/16").unwrap();
+        assert!(connect_allowed(&hosts, &[], "sw1.lab.example.com", 23));
+        assert!(connect_allowed(&hosts, &[], "SW1.LAB.example.com", 2323));
+        assert!(connect_allowed(&hosts, &[], "10.1.2.3", 23));
+        assert!(!connect_allowed(&hosts, &[], "10.2.0.1", 23));
+        assert!(!connect_allowed(
+            &hosts,
+            &[],
+            "lab.example.com.evil.org",
+            23
+        ));
+
+        assert!(connect_allowed(&hosts, &[23, 2323], "10.1.2.3", 2323));
+        assert!(!connect_allowed(&hosts, &[23, 2323], "10.1.2.3", 22));
+    }
+
+    #[test]
+    fn nothing_is_allowed_without_hosts() {
+        assert!(!connect_allowed(&[], &[], "127.0.0.1", 23));
+        assert!(!connect_allowed(&[], &[23], "127.0.0.1", 23));
+    }
+}
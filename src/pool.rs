@@ -0,0 +1,96 @@
+//! Reusable buffers for encoded message bodies
+//!
+//! Envelope encoding needs a fresh buffer for every message, which is freed
+//! as soon as the message is delivered. A connection instead takes buffers
+//! from its pool, and each returns there when the last `Bytes` referring to
+//! it is dropped. The pool keeps a bounded number of buffers; when it is
+//! empty a new buffer is allocated, and when it is full (or a buffer grew
+//! unusually large) the buffer is simply freed.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, Weak};
+
+use bytes::Bytes;
+
+/// Buffers kept per pool
+const MAX_POOLED_BUFFERS: usize = 16;
+
+/// Largest buffer capacity returned to the pool
+const MAX_POOLED_CAPACITY: usize = 64 * 1024;
+
+/// Free buffers of one connection
+#[derive(Debug, Default)]
+pub struct BufferPool {
+    free: Mutex<Vec<Vec<u8>>>,
+}
+
+impl BufferPool {
+    /// An empty buffer, reused from the pool if one is free
+    pub fn take(self: &Arc<Self>) -> PooledBuffer {
+        let buf = self
+            .free
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop()
+            .unwrap_or_default();
+        PooledBuffer {
+            buf,
+            pool: Arc::downgrade(self),
+        }
+    }
+
+    /// Keep `buf` for reuse if there is room
+    fn put(&self, mut buf: Vec<u8>) {
+        if buf.capacity() == 0 || buf.capacity() > MAX_POOLED_CAPACITY {
+            return;
+        }
+        let mut free = self.free.lock().unwrap_or_else(|e| e.into_inner());
+        if free.len() < MAX_POOLED_BUFFERS {
+            buf.clear();
+            free.push(buf);
+        }
+    }
+}
+
+/// A buffer that goes back to its pool when dropped
+pub struct PooledBuffer {
+    buf: Vec<u8>,
+    /// Doesn't keep the pool alive once its connection is gone
+    pool: Weak<BufferPool>,
+}
+
+impl PooledBuffer {
+    /// Turn the contents into message bytes without copying; the buffer
+    /// returns to the pool once they are dropped
+    pub fn freeze(self) -> Bytes {
+        Bytes::from_owner(self)
+    }
+}
+
+impl Deref for PooledBuffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buf
+    }
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.upgrade() {
+            pool.put(std::mem::take(&mut self.buf));
+        }
+    }
+}
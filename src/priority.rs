@@ -0,0 +1,71 @@
+//! Per-message priority read from the message content
+//!
+//! Some feeds mark each record's severity in a fixed byte, e.g. `0x01` to
+//! `0x05` in the first byte. With `priority_byte_offset` set, the byte at
+//! that offset of each message (as received, before transformations) is
+//! looked up in `priority_byte_map` (unmapped bytes are used as they are)
+//! to give the message's priority level. The level decides the message's
+//! share of the provider's delivery capacity in place of the link's
+//! `delivery_priority`, and is sent as an `x-telnet-priority: <level>`
+//! header line.
+
+use std::collections::HashMap;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// Header line carrying a message's priority level
+pub const PRIORITY_HEADER: &str = "x-telnet-priority";
+
+/// Parse comma-separated `<byte>=<level>` pairs, with bytes and levels
+/// written in decimal or as `0x`-prefixed hex, e.g. `0x01=1,0x02=1,0x03=2`
+pub fn parse_priority_byte_map(s: &str) -> anyhow::Result<HashMap<u8, u8>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let (byte, level) = part
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Invalid priority_byte_map entry: {}", part))?;
+            Ok((parse_byte(byte)?, parse_byte(level)?))
+        })
+        .collect()
+}
+
+fn parse_byte(value: &str) -> anyhow::Result<u8> {
+    let value = value.trim();
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|_| anyhow::anyhow!("Invalid priority_byte_map byte: {}", value))
+}
+
+/// Reads the priority level of a link's messages
+pub struct PriorityExtractor {
+    offset: usize,
+    map: HashMap<u8, u8>,
+}
+
+impl PriorityExtractor {
+    pub fn new(offset: usize, map: HashMap<u8, u8>) -> Self {
+        Self { offset, map }
+    }
+
+    /// Priority level of `data`, or `None` if it is too short to have one
+    pub fn extract(&self, data: &[u8]) -> Option<u8> {
+        let byte = *data.get(self.offset)?;
+        Some(self.map.get(&byte).copied().unwrap_or(byte))
+    }
+}
+
+/// `body` prefixed with an `x-telnet-priority` header line
+pub fn with_priority_header(body: Bytes, level: u8) -> Bytes {
+    let header = format!("{}: {}\r\n", PRIORITY_HEADER, level);
+    let mut stamped = BytesMut::with_capacity(header.len() + body.len());
+    stamped.put_slice(header.as_bytes());
+    stamped.put_slice(&body);
+    stamped.freeze()
+}
@@ -0,0 +1,56 @@
+//! One-off reachability check when a link is created
+//!
+//! The connection task retries quietly in the background, so a link to a
+//! wrong host or a firewalled port otherwise only shows up as repeated
+//! reconnect warnings. With `reachability_probe` enabled, a separate
+//! connection is opened alongside the connection task, and waits for the
+//! server's banner (see [`TelnetClient::banner_wait`]), so a server that
+//! accepts connections but doesn't serve anything shows up too. The outcome
+//! is logged once. The result is informational only; the link is created
+//! either way.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+use crate::config::LinkConfig;
+use crate::error::TelnetError;
+use crate::telnet::TelnetClient;
+
+/// How long the probe may take to connect and read the banner
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Probe the link's target and log whether it is reachable
+pub async fn run(source_id: String, config: Arc<LinkConfig>) {
+    let address = config.address();
+    let started = Instant::now();
+    let client = TelnetClient::new(config, Arc::default());
+    match client.banner_wait(PROBE_TIMEOUT.as_millis() as u64).await {
+        Ok(banner) if banner.is_empty() => warn!(
+            reachable = true,
+            "Link {}: target {} reachable but sent no data within {:?}",
+            source_id,
+            address,
+            PROBE_TIMEOUT
+        ),
+        Ok(banner) => info!(
+            reachable = true,
+            banner_bytes = banner.len(),
+            "Link {}: target {} reachable (banner after {:?})",
+            source_id,
+            address,
+            started.elapsed()
+        ),
+        Err(e) => warn!(
+            reachable = false,
+            kind = e
+                .downcast_ref::<TelnetError>()
+                .map_or("other", |e| e.kind().as_str()),
+            "Link {}: target {} unreachable: {:#}",
+            source_id,
+            address,
+            e
+        ),
+    }
+}
@@ -1,36 +1,257 @@
 use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Context as _;
-use tokio::sync::RwLock;
+use bytes::Bytes;
+use futures::future::join_all;
+use tokio::sync::{mpsc, watch, RwLock};
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
+use wasmcloud_provider_sdk::core::secrets::SecretValue;
+#[cfg(feature = "tokio-tracing")]
 use wasmcloud_provider_sdk::initialize_observability;
 use wasmcloud_provider_sdk::{
-    run_provider, LinkConfig as SdkLinkConfig, LinkDeleteInfo, Provider, ProviderInitConfig,
+    get_connection, run_provider, serve_provider_exports, Context, LinkConfig as SdkLinkConfig,
+    LinkDeleteInfo, Provider, ProviderInitConfig,
 };
+use wit_bindgen_wrpc::wrpc_transport::{ResourceBorrow, ResourceOwn};
 
-use crate::config::{LinkConfig, ProviderConfig};
-use crate::telnet::TelnetClient;
+use crate::audit::AuditLog;
+use crate::client::ComponentClient;
+use crate::config::{is_component_option, ConfigChange, LinkConfig, ProviderConfig};
+use crate::context::ConnectionContext;
+use crate::dispatch::DeliveryLimiter;
+use crate::effective::{ConfigSource, EffectiveValue};
+use crate::error::TelnetError;
+use crate::forward::Forwarder;
+use crate::group::ConnectionGroups;
+use crate::handles::ResourceTable;
+#[cfg(not(feature = "tokio-tracing"))]
+use crate::instrument::init_logging;
+use crate::instrument::{link_task, spawn_named};
+use crate::metrics::Registry;
+use crate::msdp;
+use crate::multiplex::{ReaderPool, ReaderTask};
+use crate::negotiate::{self, PendingReplies};
+use crate::platform::SocketTuning;
+use crate::policy::{connect_allowed, tls_required_by};
+use crate::probe;
+use crate::reload::{read_config_file, ConfigWatcher};
+use crate::route::Router;
+use crate::selftest;
+use crate::server_type::ServerType;
+use crate::socket::TcpConnector;
+use crate::stall::WatchdogTask;
+use crate::stats::{ConnectionStats, LinkState};
+use crate::statsd::StatsdSink;
+use crate::tasks::DeliveryTasks;
+use crate::telnet::{MsdpHandler, SharedSentLine, SharedWriter, TelnetClient};
+use crate::throttle::ConnectLimiter;
+use crate::transform::{Transform, TransformRules};
+use crate::watchdog;
+
+pub use crate::client::MessageSink;
 
 pub(crate) mod bindings {
     wit_bindgen_wrpc::generate!({
         with: {
             "wasmcloud:messaging/types@0.2.0": generate,
             "wasmcloud:messaging/handler@0.2.0": generate,
+            "wasmcloud:messaging/consumer@0.2.0": generate,
         }
     });
 }
 
 // Import the standard messaging interfaces from WIT
-use bindings::wasmcloud::messaging::handler;
-use bindings::wasmcloud::messaging::types;
+pub(crate) use bindings::wasmcloud::messaging::handler;
+pub(crate) use bindings::wasmcloud::messaging::types;
+
+// Interfaces exported by this provider
+use bindings::exports::wasmcloud::messaging::consumer;
+use bindings::exports::wasmcloud::telnet::connections;
+use bindings::exports::wasmcloud::telnet::control;
+use bindings::exports::wasmcloud::telnet::transform;
+
+/// Interval between checks of whether all links are connected
+const WARM_UP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Config values and secrets a link was created with, kept to rebuild its
+/// config when its `config_file` changes
+#[derive(Clone)]
+struct LinkValues {
+    config: HashMap<String, String>,
+    secrets: HashMap<String, SecretValue>,
+}
+
+impl LinkValues {
+    /// Whether `other` has the same config values and secrets
+    fn same_as(&self, other: &LinkValues) -> bool {
+        self.config == other.config
+            && self.secrets.len() == other.secrets.len()
+            && self.secrets.iter().all(|(key, value)| {
+                other.secrets.get(key).is_some_and(|other| {
+                    value.as_string() == other.as_string() && value.as_bytes() == other.as_bytes()
+                })
+            })
+    }
+}
+
+/// The connection of a deleted link, kept open for a recreated link to take
+/// over until its `reuse_grace_ms` runs out
+struct ParkedLink {
+    state: Arc<ConnectionState>,
+    /// Cancelled when a recreated link takes the connection over
+    reattached: CancellationToken,
+}
+
+/// A connection a component opened through `wasmcloud:telnet/connections`
+///
+/// Refers to the connection by its key in `connections` rather than
+/// holding it, so the handle follows the connection when it is restarted
+/// with a reloaded config, and fails once the connection is gone.
+#[derive(Clone)]
+struct ConnectionHandle {
+    key: String,
+}
+
+/// Separates a component's ID from a handle in the key of a connection it
+/// opened; component IDs containing it are refused
+const HANDLE_SEPARATOR: char = '#';
+
+/// Key in `connections` of the connection `source_id` opened as `handle`,
+/// kept apart from the component's link
+fn handle_key(source_id: &str, handle: &str) -> String {
+    format!("{}{}{}", source_id, HANDLE_SEPARATOR, handle)
+}
+
+/// Component served by the connection stored under `key`
+fn component_of(key: &str) -> &str {
+    key.split_once(HANDLE_SEPARATOR)
+        .map_or(key, |(source_id, _)| source_id)
+}
+
+/// Refuse a component ID that could be mistaken for another component's
+/// handle connection
+fn check_component_id(source_id: &str) -> anyhow::Result<()> {
+    if source_id.contains(HANDLE_SEPARATOR) {
+        anyhow::bail!(
+            "component ID {} contains '{}', which is reserved for handle connections",
+            source_id,
+            HANDLE_SEPARATOR
+        );
+    }
+    Ok(())
+}
+
+/// Deletion of the connection opened as a handle when the handle is
+/// dropped, stored under `0`
+struct DroppedHandle<'a>(&'a str);
+
+impl LinkDeleteInfo for DroppedHandle<'_> {
+    fn get_source_id(&self) -> &str {
+        self.0
+    }
+
+    fn get_target_id(&self) -> &str {
+        "telnet-provider"
+    }
+
+    fn get_link_name(&self) -> &str {
+        "default"
+    }
+}
 
 /// State for a single Telnet connection
 struct ConnectionState {
-    /// Configuration for this connection
-    _config: LinkConfig,
+    /// Configuration for this connection (including its group membership),
+    /// shared with its tasks; replaced when live keys are reloaded
+    config: watch::Sender<Arc<LinkConfig>>,
+    /// Token used to cooperatively stop the Telnet task
+    cancel: CancellationToken,
+    /// Runtime state shared with the Telnet task
+    stats: Arc<ConnectionStats>,
+    /// Write half of the live Telnet connection, if connected
+    writer: SharedWriter,
+    /// End of the line sent so far, for `command_blocklist`
+    sent_line: SharedSentLine,
+    /// Values the link was created with, to recognise it when recreated
+    values: LinkValues,
+    /// Deliveries sent from background tasks, drained on teardown
+    delivery_tasks: Arc<DeliveryTasks>,
     /// Handle to the Telnet task
-    _task_handle: tokio::task::JoinHandle<()>,
+    _task_handle: ReaderTask,
+}
+
+impl ConnectionState {
+    /// Current configuration
+    fn config(&self) -> Arc<LinkConfig> {
+        self.config.borrow().clone()
+    }
+
+    /// Ask the Telnet task to stop at its next await point
+    fn stop(&self) {
+        self.cancel.cancel();
+    }
+
+    /// Close our side of the connection so the server sees a clean FIN
+    /// rather than a reset when the task is aborted
+    async fn close(&self) {
+        if let Some(mut writer) = self.writer.lock().await.take() {
+            let _ = tokio::io::AsyncWriteExt::shutdown(&mut writer).await;
+        }
+    }
+
+    /// Write `data` to the server with IAC bytes escaped, if connected
+    async fn send(&self, data: &[u8]) -> Result<(), TelnetError> {
+        TelnetClient::new(self.config(), self.stats.clone())
+            .with_writer(self.writer.clone())
+            .with_sent_line(self.sent_line.clone())
+            .send_bytes(data)
+            .await
+    }
+
+    /// Snapshot of this connection's status for the control interface
+    fn status(&self) -> control::LinkStatus {
+        let config = self.config();
+        let bytes_forwarded = self.stats.bytes_forwarded();
+        control::LinkStatus {
+            state: self.stats.state().into(),
+            component_reachable: self.stats.is_component_reachable(),
+            bytes_forwarded,
+            remaining_quota: config
+                .lifetime_byte_quota
+                .map(|quota| quota.saturating_sub(bytes_forwarded)),
+        }
+    }
+}
+
+impl From<EffectiveValue> for control::ConfigEntry {
+    fn from(value: EffectiveValue) -> Self {
+        control::ConfigEntry {
+            key: value.key,
+            value: value.value,
+            source: match value.source {
+                ConfigSource::Default => control::ConfigSource::Default,
+                ConfigSource::LinkConfig => control::ConfigSource::LinkConfig,
+                ConfigSource::Secret => control::ConfigSource::Secret,
+            },
+        }
+    }
+}
+
+impl From<LinkState> for control::LinkState {
+    fn from(state: LinkState) -> Self {
+        match state {
+            LinkState::Active => control::LinkState::Active,
+            LinkState::QuotaExceeded => control::LinkState::QuotaExceeded,
+            LinkState::Blackout => control::LinkState::Blackout,
+            LinkState::MessageLimitReached => control::LinkState::MessageLimitReached,
+        }
+    }
 }
 
 /// Telnet provider implementation
@@ -38,7 +259,109 @@ struct ConnectionState {
 pub struct TelnetProvider {
     config: Arc<RwLock<ProviderConfig>>,
     /// All components linked to this provider (target) and their connections
-    connections: Arc<RwLock<HashMap<String, ConnectionState>>>,
+    ///
+    /// The lock is only held to look up, insert or remove entries; anything
+    /// slow (closing sockets, aborting tasks) happens after it is released.
+    connections: Arc<RwLock<HashMap<String, Arc<ConnectionState>>>>,
+    /// Connections of deleted links within their reuse grace window
+    parked: Arc<RwLock<HashMap<String, ParkedLink>>>,
+    /// Redundant connection groups and their active members
+    connection_groups: Arc<ConnectionGroups>,
+    /// Transformation rules registered by components
+    transform_rules: Arc<TransformRules>,
+    /// Connections opened by components through `wasmcloud:telnet/connections`
+    connection_handles: Arc<ResourceTable<ConnectionHandle>>,
+    /// Token stopping background exporters and shared readers on shutdown
+    exporters: CancellationToken,
+    /// In-flight delivery limit shared by all links
+    delivery_limiter: Arc<DeliveryLimiter>,
+    /// Limit on concurrent connection attempts shared by all links
+    connect_limiter: Arc<ConnectLimiter>,
+    /// Audit log of negotiations and connection events (disabled by default)
+    audit: Arc<RwLock<AuditLog>>,
+    /// Shared tasks running connections (one task per link by default)
+    readers: Arc<ReaderPool>,
+    /// Link limit set through the builder, taking precedence over
+    /// `max_connections` in the provider config
+    max_connections: Option<usize>,
+    /// Opens the sockets of every connection, if not the default backend
+    connector: Option<Arc<dyn TcpConnector>>,
+    /// Handles messages in place of components over wRPC, if set
+    message_sink: Option<Arc<dyn MessageSink>>,
+    /// Instruments delivery metrics are recorded into, if not the global
+    /// meter provider's
+    metrics_registry: Option<Arc<Registry>>,
+    /// Protocol version probes waiting for their component's reply
+    negotiations: Arc<PendingReplies>,
+}
+
+/// Builder for a provider with settings applied up front
+///
+/// The binary only hands it the metrics registry of the meter provider it
+/// installs. Without a host, nothing calls `init`, so tests and embedders
+/// set the config here rather than faking one, and can swap in their own
+/// sockets and take messages in place of components. StatsD export, the
+/// audit log and shared reader tasks need I/O or a runtime to set up and
+/// are still only started by `init`, which also replaces the config with
+/// the host's.
+#[derive(Default)]
+pub struct TelnetProviderBuilder {
+    config: ProviderConfig,
+    connector: Option<Arc<dyn TcpConnector>>,
+    message_sink: Option<Arc<dyn MessageSink>>,
+    max_connections: Option<u32>,
+    metrics_registry: Option<Arc<Registry>>,
+}
+
+impl TelnetProviderBuilder {
+    /// Provider-wide configuration, as the host would pass it to `init`
+    pub fn with_config(mut self, config: ProviderConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Open every connection's sockets with `connector`
+    pub fn with_connector(mut self, connector: Arc<dyn TcpConnector>) -> Self {
+        self.connector = Some(connector);
+        self
+    }
+
+    /// Hand every message meant for a component to `sink` instead, so the
+    /// provider runs without a host's wRPC connection
+    pub fn with_message_sink(mut self, sink: Arc<dyn MessageSink>) -> Self {
+        self.message_sink = Some(sink);
+        self
+    }
+
+    /// Reject links beyond `max` concurrent connections
+    pub fn with_max_connections(mut self, max: u32) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Record delivery metrics into `registry`
+    pub fn with_metrics_registry(mut self, registry: Arc<Registry>) -> Self {
+        self.metrics_registry = Some(registry);
+        self
+    }
+
+    /// Create the provider
+    pub fn build(self) -> TelnetProvider {
+        let delivery_limiter = DeliveryLimiter::default();
+        delivery_limiter.set_capacity(self.config.max_inflight_deliveries());
+        let connect_limiter = ConnectLimiter::default();
+        connect_limiter.set_capacity(self.config.max_concurrent_reconnects());
+        TelnetProvider {
+            config: Arc::new(RwLock::new(self.config)),
+            delivery_limiter: Arc::new(delivery_limiter),
+            connect_limiter: Arc::new(connect_limiter),
+            max_connections: self.max_connections.map(|max| max as usize),
+            connector: self.connector,
+            message_sink: self.message_sink,
+            metrics_registry: self.metrics_registry,
+            ..TelnetProvider::default()
+        }
+    }
 }
 
 impl TelnetProvider {
@@ -47,120 +370,1100 @@ impl TelnetProvider {
     }
 
     /// Execute the provider
-    pub async fn run() -> anyhow::Result<()> {
+    ///
+    /// With `wait_for_connections`, the control interface is only served
+    /// once the links the host started the provider with are connected,
+    /// and the provider fails if that takes longer.
+    pub async fn run(wait_for_connections: Option<Duration>) -> anyhow::Result<()> {
+        #[cfg(feature = "tokio-tracing")]
         initialize_observability!(
             Self::name(),
             std::env::var_os("PROVIDER_TELNET_FLAMEGRAPH_PATH")
         );
+        #[cfg(not(feature = "tokio-tracing"))]
+        init_logging();
 
-        let provider = Self::default();
+        // After observability is initialized, so this is the meter provider
+        // it installed
+        let provider = TelnetProviderBuilder::default()
+            .with_metrics_registry(Registry::global())
+            .build();
         let shutdown = run_provider(provider.clone(), Self::name())
             .await
             .context("failed to run provider")?;
 
-        // For this unidirectional provider, we don't export any functions
-        // Just await shutdown
-        shutdown.await;
-        Ok(())
+        if let Some(timeout) = wait_for_connections {
+            provider.warm_up(timeout).await?;
+            info!("All Telnet connections established");
+        }
+
+        // Serve the operator control interface until shutdown
+        let connection = get_connection();
+        let wrpc = connection
+            .get_wrpc_client(connection.provider_key())
+            .await
+            .context("failed to get wrpc client")?;
+        serve_provider_exports(&wrpc, provider, shutdown, bindings::serve)
+            .await
+            .context("failed to serve provider exports")
     }
-}
 
-/// Implement the Provider trait for wasmCloud integration
-impl Provider for TelnetProvider {
-    /// Initialize the provider
-    async fn init(&self, config: impl ProviderInitConfig) -> anyhow::Result<()> {
-        let provider_id = config.get_provider_id();
-        let initial_config = config.get_config();
-        info!(
-            provider_id,
-            ?initial_config,
-            "initializing Telnet provider"
-        );
+    /// Stats of every current link, for metrics export
+    async fn link_stats(&self) -> Vec<(String, Arc<ConnectionStats>)> {
+        self.connections
+            .read()
+            .await
+            .iter()
+            .map(|(source_id, state)| (source_id.clone(), state.stats.clone()))
+            .collect()
+    }
 
-        // Save configuration to provider state
-        *self.config.write().await = ProviderConfig::from(initial_config);
+    /// Link `source_id` to the provider with `config` and `secrets`, as the
+    /// host does through `receive_link_config_as_target`
+    ///
+    /// Only the SDK can build the link config the host passes, so embedders
+    /// and tests running the provider without a host link components here.
+    pub async fn link_component(
+        &self,
+        source_id: &str,
+        config: HashMap<String, String>,
+        secrets: HashMap<String, SecretValue>,
+    ) -> anyhow::Result<()> {
+        info!("Received link configuration from component: {}", source_id);
+        check_component_id(source_id)?;
+        self.open_link(source_id, LinkValues { config, secrets })
+            .await
+    }
 
+    /// Wait until every current link is connected, checking every 100 ms
+    ///
+    /// Links stopped for good (quota or message limit reached) don't count.
+    /// Fails if some are still not connected after `timeout`.
+    pub async fn warm_up(&self, timeout: Duration) -> anyhow::Result<()> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut ticker = tokio::time::interval(WARM_UP_POLL_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let waiting: Vec<String> = self
+                .link_stats()
+                .await
+                .into_iter()
+                .filter(|(_, stats)| {
+                    !stats.is_connected()
+                        && !matches!(
+                            stats.state(),
+                            LinkState::QuotaExceeded | LinkState::MessageLimitReached
+                        )
+                })
+                .map(|(source_id, _)| source_id)
+                .collect();
+            if waiting.is_empty() {
+                return Ok(());
+            }
+            if tokio::time::Instant::now() >= deadline {
+                anyhow::bail!(
+                    "Telnet connections not established after {:?}: {}",
+                    timeout,
+                    waiting.join(", ")
+                );
+            }
+        }
+    }
+
+    /// Start flushing metrics to StatsD if configured
+    ///
+    /// Forwarding doesn't depend on metrics, so a failed setup only disables
+    /// the export unless `metrics_required` is set.
+    async fn start_statsd_exporter(&self, config: &ProviderConfig) -> anyhow::Result<()> {
+        let Some(addr) = config.statsd_addr() else {
+            return Ok(());
+        };
+
+        let mut sink = match StatsdSink::connect(addr, config.statsd_prefix()).await {
+            Ok(sink) => sink,
+            Err(e) if config.metrics_required() => {
+                return Err(e)
+                    .with_context(|| format!("failed to set up StatsD export to {}", addr));
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to set up StatsD export to {}, continuing without it: {}",
+                    addr, e
+                );
+                return Ok(());
+            }
+        };
+        info!("Exporting metrics to StatsD at {}", addr);
+
+        let provider = self.clone();
+        let mut ticker = tokio::time::interval(config.statsd_flush_interval());
+        spawn_named("statsd", "exporter", async move {
+            loop {
+                tokio::select! {
+                    _ = provider.exporters.cancelled() => break,
+                    _ = ticker.tick() => sink.flush(&provider.link_stats().await).await,
+                }
+            }
+        });
         Ok(())
     }
 
-    /// Handle incoming link from a component (component links TO this provider)
-    /// This is where we start the Telnet client
-    async fn receive_link_config_as_target(
+    /// The open connection `handle` stands for, if the handle is the caller's
+    async fn handle_connection(
         &self,
-        SdkLinkConfig {
-            source_id, config, ..
-        }: SdkLinkConfig<'_>,
-    ) -> anyhow::Result<()> {
-        info!("Received link configuration from component: {}", source_id);
+        cx: Option<Context>,
+        handle: &[u8],
+    ) -> Result<Arc<ConnectionState>, String> {
+        let source_id = cx
+            .and_then(|cx| cx.component)
+            .ok_or_else(|| "no such handle".to_string())?;
+        let key = self.connection_handles.get(&source_id, handle)?.key;
+        self.connection(&key)
+            .await
+            .ok_or_else(|| "connection is closed".to_string())
+    }
+
+    /// Drop the resource `handle` of `source_id`, deleting its connection
+    /// as its link being deleted would
+    async fn drop_handle(&self, source_id: &str, handle: &[u8]) -> Result<(), String> {
+        let handle = self.connection_handles.remove(source_id, handle)?;
+        // Already gone if its group was stopped
+        if self.connection(&handle.key).await.is_some() {
+            self.delete_link_as_target(DroppedHandle(&handle.key))
+                .await
+                .map_err(|e| format!("{:#}", e))?;
+        }
+        Ok(())
+    }
+
+    /// The connection serving `source_id`, if any
+    async fn connection(&self, source_id: &str) -> Option<Arc<ConnectionState>> {
+        self.connections.read().await.get(source_id).cloned()
+    }
+
+    /// Stop all connections belonging to `group`, including parked ones
+    ///
+    /// Each is torn down as if its link were deleted: the task gets until
+    /// the teardown deadline to stop before it is aborted, and its
+    /// background deliveries are drained. Returns the number of connections
+    /// that were stopped.
+    async fn stop_group(&self, group: &str) -> u32 {
+        let in_group = |state: &ConnectionState| state.config().group.as_deref() == Some(group);
+        let mut removed = Vec::new();
+        self.connections.write().await.retain(|source_id, state| {
+            if !in_group(state) {
+                return true;
+            }
+            removed.push((source_id.clone(), state.clone()));
+            false
+        });
+        self.parked.write().await.retain(|source_id, parked| {
+            if !in_group(&parked.state) {
+                return true;
+            }
+            parked.reattached.cancel();
+            removed.push((source_id.clone(), parked.state.clone()));
+            false
+        });
+
+        // Signal every member before waiting for any
+        for (source_id, state) in &removed {
+            info!(
+                "Stopping Telnet connection for component {} (group {})",
+                source_id, group
+            );
+            state.stop();
+        }
+        join_all(
+            removed
+                .iter()
+                .map(|(source_id, state)| self.close_link(source_id, state)),
+        )
+        .await;
+
+        info!("Stopped {} connection(s) in group {}", removed.len(), group);
+        removed.len() as u32
+    }
+
+    /// Parse a link's config, merged with its config file, and apply its
+    /// secrets
+    async fn prepare_link_config(
+        &self,
+        source_id: &str,
+        values: &LinkValues,
+        server_type: Option<ServerType>,
+    ) -> anyhow::Result<LinkConfig> {
+        // Values from the config file, if any, override the link's
+        let mut config = values.config.clone();
+        if let Some(path) = config.get("config_file").filter(|v| !v.is_empty()).cloned() {
+            config.extend(read_config_file(Path::new(&path))?);
+        }
+        let secrets = &values.secrets;
 
         // Parse link configuration
-        let link_config = LinkConfig::from_values(config)?;
+        let mut link_config = LinkConfig::from_values(&config)?;
+        for key in SocketTuning::from_config(&link_config).unsupported() {
+            warn!(
+                "{} is not supported on {}; ignoring it for component {}",
+                key,
+                std::env::consts::OS,
+                source_id
+            );
+        }
 
-        info!(
-            "Starting Telnet client for {}:{}",
-            link_config.telnet_host, link_config.telnet_port
+        // Never connect in plaintext to hosts that must be reached over TLS
+        let require_tls_hosts = self.config.read().await.require_tls_hosts()?;
+        if let Some(pattern) = tls_required_by(
+            &require_tls_hosts,
+            &link_config.telnet_host,
+            link_config.telnet_port,
+            link_config.ip_family,
+        )
+        .await
+        {
+            anyhow::bail!(
+                "Host {} requires TLS (require_tls_hosts entry {}), but this provider only connects in plain Telnet; rejecting link from component {}",
+                link_config.telnet_host,
+                pattern,
+                source_id
+            );
+        }
+
+        // Fill in settings suited to the kind of server before applying
+        // secrets, which the re-parsed config would lose. The server is only
+        // asked when the link is created; a reload passes on what it said.
+        if link_config.auto_detect_server_type {
+            let server_type = match server_type {
+                Some(server_type) => server_type,
+                None => {
+                    let mut client =
+                        TelnetClient::new(Arc::new(link_config.clone()), Arc::default())
+                            .with_connect_limiter(self.connect_limiter.clone());
+                    if let Some(connector) = &self.connector {
+                        client = client.with_connector(connector.clone());
+                    }
+                    client.detect_server_type().await
+                }
+            };
+            let applied = server_type.preset().apply(&mut config);
+            if !applied.is_empty() {
+                info!(
+                    "Applied server type preset ({}) for component {}",
+                    applied.join(", "),
+                    source_id
+                );
+                link_config = LinkConfig::from_values(&config)?;
+            }
+            link_config.server_type = Some(server_type);
+        }
+        link_config.provided.add_secrets(secrets.keys());
+        if let Some(login) = &mut link_config.login {
+            if let Some(password) = secrets.get("login_password").and_then(|v| v.as_string()) {
+                login.password = Some(password.to_string());
+            }
+        }
+        link_config.environ.extend_secrets(
+            secrets
+                .iter()
+                .filter_map(|(key, value)| Some((key, value.as_string()?))),
         );
 
-        // Clone what we need for the task
-        let config_clone = link_config.clone();
-        let source_id_clone = source_id.to_string();
+        // Reject pipelines that mangle sample input before they see traffic
+        if self.config.read().await.self_test() {
+            selftest::run(source_id, &link_config)?;
+        }
+        Ok(link_config)
+    }
 
-        // Spawn Telnet client task
-        let task_handle = tokio::spawn(async move {
-            let telnet_client = TelnetClient::new(config_clone.clone());
+    /// Start the tasks of a link, replacing any previous link from the same
+    /// component
+    ///
+    /// `stats` are carried over when the link is restarted with a reloaded
+    /// config. The future is boxed because the config watcher it spawns
+    /// calls it again.
+    fn start_link<'a>(
+        &'a self,
+        source_id: &'a str,
+        link_config: LinkConfig,
+        values: LinkValues,
+        stats: Arc<ConnectionStats>,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'a>> {
+        Box::pin(async move {
+            info!(
+                "Starting Telnet client for {}:{}",
+                link_config.telnet_host, link_config.telnet_port
+            );
 
-            // Create message handler that forwards to the component via wRPC
-            // using the standard wasmcloud:messaging interface
-            let address = config_clone.address();
-            let result = telnet_client
-                .run(move |data| {
-                    // Convert Telnet message to a standard broker-message
-                    let message = create_broker_message(data, &address);
+            // Parsed once and shared by all of the link's tasks
+            let link_config = Arc::new(link_config);
+            let state_values = values.clone();
+            let config_clone = link_config.clone();
+            let cancel = CancellationToken::new();
+            let task_cancel = cancel.clone();
+            let task_stats = stats.clone();
+            let client_stats = stats.clone();
 
-                    // Spawn a task to send message to component
-                    let source = source_id_clone.clone();
-                    tokio::spawn(async move {
-                        if let Err(e) = send_message_to_component(&source, message).await {
-                            error!("Failed to send message to component {}: {}", source, e);
-                        }
-                    });
+            // Register redundant links so only the active member forwards
+            if let Some(group) = &link_config.connection_group {
+                self.connection_groups.join(
+                    group,
+                    source_id,
+                    link_config.connection_group_role,
+                    stats.clone(),
+                );
+            }
+            let connection_groups = self.connection_groups.clone();
+            let transform_rules = self.transform_rules.clone();
+            let delivery_limiter = self.delivery_limiter.clone();
+            let connect_limiter = self.connect_limiter.clone();
+            let connector = self.connector.clone();
+            let metrics_registry = self.metrics_registry.clone();
+            let negotiations = self.negotiations.clone();
+            let audit = self.audit.read().await.for_link(source_id);
+            let writer = SharedWriter::default();
+            let task_writer = writer.clone();
+            let sent_line = SharedSentLine::default();
+            let task_sent_line = sent_line.clone();
+            let component = component_of(source_id);
+            let context = ConnectionContext::new(
+                component,
+                &link_config.address(),
+                &link_config.subject(component),
+            )
+            .with_client(
+                ComponentClient::new(component.into())
+                    .with_recovery(link_config.component_client_recreate_after, stats.clone())
+                    .with_sink(self.message_sink.clone()),
+            );
+            let delivery_tasks =
+                Arc::new(DeliveryTasks::new(context.source_id.clone(), stats.clone()));
+            let msdp = msdp_handler(
+                context.clone(),
+                Router::new(&link_config.routing_rules, context.client.clone()),
+                delivery_tasks.clone(),
+            );
 
-                    Ok(())
-                })
+            // Watch the component's reachability if configured
+            let (hold_tx, hold_rx) = watch::channel(false);
+            if link_config.component_ping_interval_ms > 0 {
+                spawn_named(
+                    source_id,
+                    "keepalive",
+                    link_task(
+                        watchdog::run(
+                            context.clone(),
+                            link_config.clone(),
+                            stats.clone(),
+                            hold_tx,
+                            cancel.clone(),
+                        ),
+                        source_id,
+                        &link_config.telnet_host,
+                        link_config.telnet_port,
+                    ),
+                );
+            }
+
+            // Reconnect stalled connections if configured
+            let (command_tx, command_rx) = mpsc::channel(1);
+            if link_config.watchdog_timeout_ms > 0 {
+                let watchdog = WatchdogTask::new(
+                    context.source_id.clone(),
+                    link_config.watchdog_timeout(),
+                    stats.clone(),
+                    command_tx,
+                );
+                spawn_named(
+                    source_id,
+                    "stall-watchdog",
+                    link_task(
+                        watchdog.run(cancel.clone()),
+                        source_id,
+                        &link_config.telnet_host,
+                        link_config.telnet_port,
+                    ),
+                );
+            }
+
+            // Reload the config whenever its file changes
+            let (config_tx, config_rx) = watch::channel(link_config.clone());
+            let tuned_tx = config_tx.clone();
+            if let Some(path) = &link_config.config_file {
+                match ConfigWatcher::new(Path::new(path)) {
+                    Ok(watcher) => {
+                        spawn_named(
+                            source_id,
+                            "config-watch",
+                            link_task(
+                                self.clone().reload_on_change(
+                                    source_id.to_string(),
+                                    watcher,
+                                    values,
+                                    config_tx.clone(),
+                                    stats.clone(),
+                                    cancel.clone(),
+                                ),
+                                source_id,
+                                &link_config.telnet_host,
+                                link_config.telnet_port,
+                            ),
+                        );
+                    }
+                    Err(e) => warn!("Not watching config_file {} for changes: {:#}", path, e),
+                }
+            }
+
+            // Report reachability right away, independent of the connection task
+            if link_config.reachability_probe {
+                spawn_named(
+                    source_id,
+                    "probe",
+                    link_task(
+                        probe::run(source_id.to_string(), link_config.clone()),
+                        source_id,
+                        &link_config.telnet_host,
+                        link_config.telnet_port,
+                    ),
+                );
+            }
+
+            // Spawn Telnet client task
+            let task = async move {
+                let mut telnet_client = TelnetClient::new(config_clone.clone(), client_stats)
+                    .with_hold(hold_rx)
+                    .with_audit(audit)
+                    .with_writer(task_writer)
+                    .with_sent_line(task_sent_line)
+                    .with_msdp(msdp)
+                    .with_commands(command_rx)
+                    .with_config_updates(config_rx.clone())
+                    .with_connect_limiter(connect_limiter);
+                if let Some(connector) = connector {
+                    telnet_client = telnet_client.with_connector(connector);
+                }
+
+                // Agree on the envelope format before any data is forwarded
+                let component_client = context.client.clone();
+                let protocol_version = negotiate::negotiate(
+                    &negotiations,
+                    &context.source_id,
+                    config_clone.protocol_version,
+                    negotiate::REPLY_TIMEOUT,
+                    |probe| async move { component_client.send(&probe).await },
+                )
                 .await;
+                info!(
+                    "Using protocol version {} for component {}",
+                    protocol_version, context.source_id
+                );
+
+                // Forward each message to the component via wRPC
+                // using the standard wasmcloud:messaging interface
+                let mut forwarder = Forwarder::new(
+                    context,
+                    config_clone,
+                    protocol_version,
+                    task_stats,
+                    task_cancel.clone(),
+                    connection_groups,
+                    transform_rules,
+                )
+                .with_delivery_limiter(delivery_limiter)
+                .with_config_updates(config_rx)
+                .with_tuned_config(tuned_tx);
+                if let Some(registry) = metrics_registry {
+                    forwarder = forwarder.with_metrics_registry(registry);
+                }
+                let result = telnet_client
+                    .run(task_cancel, move |data, received| {
+                        let deliveries = forwarder.forward(data, received);
+                        async move { deliveries?.send().await }
+                    })
+                    .await;
 
-            if let Err(e) = result {
-                error!("Telnet client error: {}", e);
+                if let Err(e) = result {
+                    error!(kind = e.kind().as_str(), "Telnet client error: {}", e);
+                }
+            };
+            let task = link_task(
+                task,
+                source_id,
+                &link_config.telnet_host,
+                link_config.telnet_port,
+            );
+            let task_handle = self
+                .readers
+                .spawn_on(link_config.runtime, source_id, "reader", task);
+
+            // Store connection state, replacing any previous link from the
+            // same component
+            let connection_group = link_config.connection_group.clone();
+            let state = Arc::new(ConnectionState {
+                config: config_tx,
+                cancel,
+                stats,
+                writer,
+                sent_line,
+                values: state_values,
+                delivery_tasks,
+                _task_handle: task_handle,
+            });
+            let previous = self
+                .connections
+                .write()
+                .await
+                .insert(source_id.to_string(), state);
+            if let Some(previous) = previous {
+                info!("Replacing previous connection for component {}", source_id);
+                Self::teardown(source_id, &previous, self.teardown_deadline().await).await;
+                // The new link has already taken over membership of the same group
+                if let Some(group) = &previous.config().connection_group {
+                    if previous.config().connection_group != connection_group {
+                        self.connection_groups.leave(group, source_id);
+                    }
+                }
             }
-        });
 
-        // Store connection state
-        self.connections.write().await.insert(
+            info!("Telnet connection established for component: {}", source_id);
+        })
+    }
+
+    /// Apply changes to a link's config file until the link is stopped
+    ///
+    /// Live keys are handed to the running connection through `updates`;
+    /// any other change restarts the link with the new config. A file that
+    /// can't be read or parsed leaves the link as it is.
+    async fn reload_on_change(
+        self,
+        source_id: String,
+        mut watcher: ConfigWatcher,
+        values: LinkValues,
+        updates: watch::Sender<Arc<LinkConfig>>,
+        stats: Arc<ConnectionStats>,
+        cancel: CancellationToken,
+    ) {
+        loop {
+            tokio::select! {
+                _ = cancel.cancelled() => return,
+                _ = watcher.changed() => {}
+            }
+
+            let server_type = updates.borrow().server_type;
+            let new = match self
+                .prepare_link_config(&source_id, &values, server_type)
+                .await
+            {
+                Ok(new) => new,
+                Err(e) => {
+                    warn!("Ignoring changed config file: {:#}", e);
+                    continue;
+                }
+            };
+            let old = updates.borrow().clone();
+            let changes = LinkConfig::diff(&old, &new);
+            if changes.is_empty() {
+                continue;
+            }
+            for change in &changes {
+                info!(
+                    key = %change.key,
+                    disruptive = change.is_disruptive(),
+                    "Config file changed {} from {:?} to {:?}",
+                    change.key,
+                    change.old,
+                    change.new
+                );
+            }
+
+            if changes.iter().any(ConfigChange::is_disruptive) {
+                // A deleted link isn't brought back by its config file
+                if self.close_parked(&source_id).await {
+                    return;
+                }
+                info!(
+                    "Restarting link from component {} with the new config",
+                    source_id
+                );
+                self.start_link(&source_id, new, values, stats).await;
+                return;
+            }
+            updates.send_replace(Arc::new(new));
+        }
+    }
+
+    /// Deadline for connections being torn down now to stop on their own
+    async fn teardown_deadline(&self) -> tokio::time::Instant {
+        tokio::time::Instant::now() + self.config.read().await.shutdown_timeout()
+    }
+
+    /// Stop a connection, giving its task until `deadline` to finish on its
+    /// own before it is aborted, then drain its background deliveries
+    ///
+    /// Returns whether the task stopped by itself.
+    async fn teardown(
+        source_id: &str,
+        state: &ConnectionState,
+        deadline: tokio::time::Instant,
+    ) -> bool {
+        state.stop();
+        let stopped = tokio::time::timeout_at(deadline, async {
+            state.close().await;
+            state._task_handle.finished().await;
+        })
+        .await
+        .is_ok();
+        if !stopped {
+            let snapshot = state.stats.snapshot();
+            warn!(
+                state = ?snapshot.state,
+                connected = snapshot.connected,
+                last_activity_ms = ?snapshot.last_activity_ms,
+                "Aborting Telnet connection for component {} still running at the teardown deadline",
+                source_id
+            );
+            state._task_handle.abort();
+        }
+        state.delivery_tasks.drain(deadline).await;
+        stopped
+    }
+
+    /// Parse and start a link from `source_id`, replacing its previous one
+    async fn open_link(&self, source_id: &str, values: LinkValues) -> anyhow::Result<()> {
+        if self.reattach(source_id, &values).await {
+            return Ok(());
+        }
+
+        // Refuse new links once the connection limit is reached; a component
+        // replacing its own link doesn't count against it
+        let max_connections = match self.max_connections {
+            Some(max) => Some(max),
+            None => self.config.read().await.max_connections(),
+        };
+        if let Some(max) = max_connections {
+            let connections = self.connections.read().await;
+            if connections.len() >= max && !connections.contains_key(source_id) {
+                anyhow::bail!(
+                    "Connection limit of {} reached, rejecting link from component {}",
+                    max,
+                    source_id
+                );
+            }
+        }
+
+        let link_config = self.prepare_link_config(source_id, &values, None).await?;
+        self.start_link(
+            source_id,
+            link_config,
+            values,
+            Arc::new(ConnectionStats::default()),
+        )
+        .await;
+        Ok(())
+    }
+
+    /// Close a connection that has been removed from the map
+    ///
+    /// Also stops the link's other tasks, such as watchdogs, and waits for
+    /// its background deliveries to finish.
+    async fn close_link(&self, source_id: &str, state: &ConnectionState) {
+        Self::teardown(source_id, state, self.teardown_deadline().await).await;
+        if let Some(group) = &state.config().connection_group {
+            self.connection_groups.leave(group, source_id);
+        }
+    }
+
+    /// Keep the connection of a deleted link open for `grace`, for a
+    /// recreated link to take over, then close it
+    ///
+    /// The connection keeps running meanwhile, so data read while parked
+    /// is still forwarded to the component.
+    async fn park(&self, source_id: &str, state: Arc<ConnectionState>, grace: Duration) {
+        info!(
+            "Keeping connection for component {} open for {:?} in case its link is recreated",
+            source_id, grace
+        );
+        let reattached = CancellationToken::new();
+        let previous = self.parked.write().await.insert(
             source_id.to_string(),
-            ConnectionState {
-                _config: link_config,
-                _task_handle: task_handle,
+            ParkedLink {
+                state,
+                reattached: reattached.clone(),
             },
         );
+        if let Some(previous) = previous {
+            previous.reattached.cancel();
+            self.close_link(source_id, &previous.state).await;
+        }
 
+        let provider = self.clone();
+        let owner = source_id.to_string();
+        spawn_named(source_id, "reuse-grace", async move {
+            tokio::select! {
+                // A reattached connection must not be closed, even if the
+                // window ran out at the same moment
+                biased;
+                _ = reattached.cancelled() => {}
+                _ = tokio::time::sleep(grace) => {
+                    info!(
+                        "Link from component {} wasn't recreated within {:?}; closing its connection",
+                        owner, grace
+                    );
+                    provider.close_parked(&owner).await;
+                }
+            }
+        });
+    }
+
+    /// Hand the parked connection of `source_id` to its recreated link if
+    /// the link was recreated with the same values, or close it otherwise
+    ///
+    /// Returns whether the connection was handed over.
+    async fn reattach(&self, source_id: &str, values: &LinkValues) -> bool {
+        let Some(parked) = self.parked.write().await.remove(source_id) else {
+            return false;
+        };
+        parked.reattached.cancel();
+
+        let mut connections = self.connections.write().await;
+        // A connection opened since the link was deleted takes precedence
+        if !parked.state.values.same_as(values) || connections.contains_key(source_id) {
+            drop(connections);
+            info!(
+                "Link from component {} was recreated with a different config; closing its previous connection",
+                source_id
+            );
+            self.close_link(source_id, &parked.state).await;
+            return false;
+        }
+        connections.insert(source_id.to_string(), parked.state);
         info!(
-            "Telnet connection established for component: {}",
+            "Link from component {} was recreated; reusing its open connection",
             source_id
         );
+        true
+    }
+
+    /// Close the parked connection of `source_id`, if there is one
+    ///
+    /// Returns whether there was one.
+    async fn close_parked(&self, source_id: &str) -> bool {
+        let Some(parked) = self.parked.write().await.remove(source_id) else {
+            return false;
+        };
+        parked.reattached.cancel();
+        self.close_link(source_id, &parked.state).await;
+        true
+    }
+}
+
+/// Operator controls exported via `wasmcloud:telnet/control`
+impl control::Handler<Option<Context>> for TelnetProvider {
+    async fn shutdown_group(&self, _cx: Option<Context>, name: String) -> anyhow::Result<u32> {
+        Ok(self.stop_group(&name).await)
+    }
+
+    async fn status(
+        &self,
+        _cx: Option<Context>,
+        source_id: String,
+    ) -> anyhow::Result<Result<control::LinkStatus, String>> {
+        Ok(self
+            .connection(&source_id)
+            .await
+            .map(|state| state.status())
+            .ok_or_else(|| format!("no connection for component {}", source_id)))
+    }
+
+    async fn effective_config(
+        &self,
+        _cx: Option<Context>,
+        source_id: String,
+    ) -> anyhow::Result<Result<Vec<control::ConfigEntry>, String>> {
+        Ok(self
+            .connection(&source_id)
+            .await
+            .map(|state| {
+                state
+                    .config()
+                    .provided
+                    .effective()
+                    .into_iter()
+                    .map(Into::into)
+                    .collect()
+            })
+            .ok_or_else(|| format!("no connection for component {}", source_id)))
+    }
+}
+
+/// Transformation rules exported via `wasmcloud:telnet/transform`
+impl transform::Handler<Option<Context>> for TelnetProvider {
+    async fn register_rule(
+        &self,
+        cx: Option<Context>,
+        pattern: String,
+        transform: transform::TransformFn,
+    ) -> anyhow::Result<Result<String, String>> {
+        let component = cx.and_then(|cx| cx.component);
+        info!(?component, "Registering transform rule for {}", pattern);
+        Ok(self.transform_rules.register(&pattern, transform.into()))
+    }
+
+    async fn unregister_rule(
+        &self,
+        _cx: Option<Context>,
+        rule_id: String,
+    ) -> anyhow::Result<Result<(), String>> {
+        Ok(self.transform_rules.unregister(&rule_id))
+    }
+}
+
+/// The standard messaging consumer, exported so components can answer
+/// protocol version probes
+///
+/// The provider is no broker: publishing is only accepted on the reply
+/// subject of a probe waiting for the publishing component, and requests
+/// are refused.
+impl consumer::Handler<Option<Context>> for TelnetProvider {
+    async fn request(
+        &self,
+        _cx: Option<Context>,
+        subject: String,
+        _body: Bytes,
+        _timeout_ms: u32,
+    ) -> anyhow::Result<Result<types::BrokerMessage, String>> {
+        Ok(Err(format!(
+            "requests are not supported (subject {})",
+            subject
+        )))
+    }
+
+    async fn publish(
+        &self,
+        cx: Option<Context>,
+        msg: types::BrokerMessage,
+    ) -> anyhow::Result<Result<(), String>> {
+        let component = cx.and_then(|cx| cx.component).unwrap_or_default();
+        if self.negotiations.reply(&component, &msg.subject, msg.body) {
+            Ok(Ok(()))
+        } else {
+            Ok(Err(format!("no reply is expected on {}", msg.subject)))
+        }
+    }
+}
+
+/// Handle-based connections exported via `wasmcloud:telnet/connections`
+///
+/// Each handle stands for a connection of its own, stored under
+/// `<component>#<handle>` next to the component's link, and is only
+/// accepted from the component that opened it. Components may only connect
+/// to hosts allowed by `connect_allowed_hosts` and set the keys in
+/// [`COMPONENT_OPTIONS`](crate::config::COMPONENT_OPTIONS). Dropping a
+/// handle, which `close` does, deletes its connection through
+/// `delete_link_as_target` like any link; deleting the component's link
+/// drops all of its handles. wRPC has no drop call for a handle the
+/// component simply forgets, so such a connection lasts until then.
+impl connections::HandlerTelnetConnection<Option<Context>> for TelnetProvider {
+    async fn connect(
+        &self,
+        cx: Option<Context>,
+        config: connections::ConnectionConfig,
+    ) -> anyhow::Result<Result<ResourceOwn<connections::TelnetConnection>, String>> {
+        let Some(source_id) = cx.and_then(|cx| cx.component) else {
+            return Ok(Err(
+                "connections can only be opened by components".to_string()
+            ));
+        };
+        if let Err(e) = check_component_id(&source_id) {
+            return Ok(Err(e.to_string()));
+        }
+        info!(
+            "Component {} is connecting to {}:{}",
+            source_id, config.host, config.port
+        );
+
+        let (allowed, mut blocklist) = {
+            let provider_config = self.config.read().await;
+            let allowed = provider_config
+                .connect_allowed_hosts()
+                .and_then(|hosts| Ok((hosts, provider_config.connect_allowed_ports()?)));
+            (allowed, provider_config.command_blocklist())
+        };
+        let (allowed_hosts, allowed_ports) = match allowed {
+            Ok(allowed) => allowed,
+            Err(e) => return Ok(Err(format!("{:#}", e))),
+        };
+        if !connect_allowed(&allowed_hosts, &allowed_ports, &config.host, config.port) {
+            warn!(
+                "Refusing connection from component {} to {}:{}, which connect_allowed_hosts doesn't allow",
+                source_id, config.host, config.port
+            );
+            return Ok(Err(format!(
+                "connecting to {}:{} is not allowed",
+                config.host, config.port
+            )));
+        }
+        let mut values: HashMap<String, String> = config.options.into_iter().collect();
+        if let Some(key) = values.keys().find(|key| !is_component_option(key)) {
+            return Ok(Err(format!("{} can't be set by a component", key)));
+        }
+        values.insert("telnet_host".to_string(), config.host);
+        values.insert("telnet_port".to_string(), config.port.to_string());
+        // The operator's blocked commands can be added to but not lifted
+        if let Some(own) = values.get("command_blocklist") {
+            blocklist.push(own.clone());
+        }
+        if !blocklist.is_empty() {
+            values.insert("command_blocklist".to_string(), blocklist.join(","));
+        }
+        let values = LinkValues {
+            config: values,
+            secrets: HashMap::new(),
+        };
+
+        let mut key = String::new();
+        let handle = self.connection_handles.insert(&source_id, |handle| {
+            key = handle_key(&source_id, handle);
+            ConnectionHandle { key: key.clone() }
+        });
+        if let Err(e) = self.open_link(&key, values).await {
+            let _ = self.connection_handles.remove(&source_id, &handle);
+            return Ok(Err(format!("{:#}", e)));
+        }
+        Ok(Ok(ResourceOwn::from(handle)))
+    }
+
+    async fn send(
+        &self,
+        cx: Option<Context>,
+        self_: ResourceBorrow<connections::TelnetConnection>,
+        data: Bytes,
+    ) -> anyhow::Result<Result<(), String>> {
+        let state = match self.handle_connection(cx, &Bytes::from(self_)).await {
+            Ok(state) => state,
+            Err(e) => return Ok(Err(e)),
+        };
+        Ok(state.send(&data).await.map_err(|e| e.to_string()))
+    }
+
+    async fn close(
+        &self,
+        cx: Option<Context>,
+        self_: ResourceBorrow<connections::TelnetConnection>,
+    ) -> anyhow::Result<Result<(), String>> {
+        let Some(source_id) = cx.and_then(|cx| cx.component) else {
+            return Ok(Err("no such handle".to_string()));
+        };
+        Ok(self.drop_handle(&source_id, &Bytes::from(self_)).await)
+    }
+}
+
+impl connections::Handler<Option<Context>> for TelnetProvider {}
+
+impl From<transform::TransformFn> for Transform {
+    fn from(transform: transform::TransformFn) -> Self {
+        match transform {
+            transform::TransformFn::Trim => Transform::Trim,
+            transform::TransformFn::Uppercase => Transform::Uppercase,
+            transform::TransformFn::Lowercase => Transform::Lowercase,
+            transform::TransformFn::Prefix(prefix) => Transform::Prefix(prefix.to_vec()),
+            transform::TransformFn::Suffix(suffix) => Transform::Suffix(suffix.to_vec()),
+            transform::TransformFn::Replace(transform::Replacement { from, to }) => {
+                Transform::Replace {
+                    from: from.to_vec(),
+                    to: to.to_vec(),
+                }
+            }
+            transform::TransformFn::Drop => Transform::Drop,
+        }
+    }
+}
+
+/// Implement the Provider trait for wasmCloud integration
+impl Provider for TelnetProvider {
+    /// Initialize the provider
+    async fn init(&self, config: impl ProviderInitConfig) -> anyhow::Result<()> {
+        let provider_id = config.get_provider_id();
+        let initial_config = config.get_config();
+        info!(provider_id, ?initial_config, "initializing Telnet provider");
+
+        // Save configuration to provider state
+        let provider_config = ProviderConfig::from(initial_config);
+        self.start_statsd_exporter(&provider_config).await?;
+        self.delivery_limiter
+            .set_capacity(provider_config.max_inflight_deliveries());
+        self.connect_limiter
+            .set_capacity(provider_config.max_concurrent_reconnects());
+        let shared_readers = provider_config.shared_reader_tasks();
+        if shared_readers > 0 {
+            info!("Running connections on {} shared tasks", shared_readers);
+            self.readers.start(shared_readers, self.exporters.clone());
+        }
+        if let Some(path) = provider_config.audit_log_path() {
+            info!("Writing audit log to {}", path.display());
+            *self.audit.write().await = AuditLog::open(path, self.exporters.clone()).await?;
+        }
+        *self.config.write().await = provider_config;
+
         Ok(())
     }
 
+    /// Handle incoming link from a component (component links TO this provider)
+    /// This is where we start the Telnet client
+    async fn receive_link_config_as_target(
+        &self,
+        SdkLinkConfig {
+            source_id,
+            config,
+            secrets,
+            ..
+        }: SdkLinkConfig<'_>,
+    ) -> anyhow::Result<()> {
+        self.link_component(source_id, config.clone(), secrets.clone())
+            .await
+    }
+
     /// Handle link deletion
     async fn delete_link_as_target(&self, link: impl LinkDeleteInfo) -> anyhow::Result<()> {
         let source_id = link.get_source_id();
         info!("Deleting link with component: {}", source_id);
 
-        // Remove connection state (task will be cancelled)
-        if let Some(state) = self.connections.write().await.remove(source_id) {
-            info!("Telnet connection closed for component: {}", source_id);
-            state._task_handle.abort();
-        } else {
-            warn!("No connection found for component: {}", source_id);
+        // Remove connection state (task will be cancelled), along with the
+        // connections the component opened through handles
+        let handle_prefix = handle_key(source_id, "");
+        let mut opened = Vec::new();
+        let removed = {
+            let mut connections = self.connections.write().await;
+            connections.retain(|key, state| {
+                if !key.starts_with(&handle_prefix) {
+                    return true;
+                }
+                opened.push((key.clone(), state.clone()));
+                false
+            });
+            connections.remove(source_id)
+        };
+        let handles = self.connection_handles.remove_owner(source_id);
+        if handles > 0 {
+            info!(
+                "Released {} connection handle(s) of component {}",
+                handles, source_id
+            );
+        }
+        join_all(
+            opened
+                .iter()
+                .map(|(key, state)| self.close_link(key, state)),
+        )
+        .await;
+        match removed {
+            Some(state) => match state.config().reuse_grace() {
+                Some(grace) => self.park(source_id, state, grace).await,
+                None => {
+                    info!("Telnet connection closed for component: {}", source_id);
+                    self.close_link(source_id, &state).await;
+                }
+            },
+            None => warn!("No connection found for component: {}", source_id),
         }
 
         Ok(())
@@ -169,12 +1472,54 @@ impl Provider for TelnetProvider {
     /// Handle provider shutdown
     async fn shutdown(&self) -> anyhow::Result<()> {
         info!("Shutting down Telnet provider");
+        self.exporters.cancel();
+        self.connection_handles.clear();
 
-        // Clean up all connections
-        let mut connections = self.connections.write().await;
-        for (source_id, state) in connections.drain() {
-            info!("Closing Telnet connection for component: {}", source_id);
-            state._task_handle.abort();
+        // Signal every connection before waiting for any, then wait for
+        // them together so the deadline bounds the whole shutdown
+        let parked: Vec<_> = self
+            .parked
+            .write()
+            .await
+            .drain()
+            .map(|(source_id, parked)| {
+                parked.reattached.cancel();
+                (source_id, parked.state)
+            })
+            .collect();
+        let connections: Vec<_> = self
+            .connections
+            .write()
+            .await
+            .drain()
+            .chain(parked)
+            .collect();
+        let timeout = self.config.read().await.shutdown_timeout();
+        let deadline = tokio::time::Instant::now() + timeout;
+        info!("Closing {} Telnet connection(s)", connections.len());
+        for (_, state) in &connections {
+            state.stop();
+        }
+        let stopped = join_all(
+            connections
+                .iter()
+                .map(|(source_id, state)| Self::teardown(source_id, state, deadline)),
+        )
+        .await;
+
+        let stragglers: Vec<&str> = connections
+            .iter()
+            .zip(stopped)
+            .filter(|(_, stopped)| !stopped)
+            .map(|((source_id, _), _)| source_id.as_str())
+            .collect();
+        if !stragglers.is_empty() {
+            warn!(
+                "Aborted {} Telnet connection(s) that didn't stop within {:?}: {}",
+                stragglers.len(),
+                timeout,
+                stragglers.join(", ")
+            );
         }
 
         info!("Telnet provider shutdown complete");
@@ -182,41 +1527,771 @@ impl Provider for TelnetProvider {
     }
 }
 
+/// Send each MSDP frame received on a connection to its component, as
+/// routed by `router`, in the background as one of `tasks`
+fn msdp_handler(
+    context: ConnectionContext,
+    router: Router,
+    tasks: Arc<DeliveryTasks>,
+) -> MsdpHandler {
+    Arc::new(move |variables| {
+        let message = msdp::create_msdp_message(&context.msdp_subject, &variables);
+        let client = router.client_for(&message.subject).clone();
+        let tasks = tasks.clone();
+        Box::pin(async move {
+            tasks
+                .spawn(async move {
+                    client
+                        .send(&message)
+                        .await
+                        .context("failed to send MSDP message")
+                })
+                .await;
+        })
+    })
+}
+
 /// Create a broker-message from raw Telnet data
 ///
-/// The subject is set to "telnet.<host>:<port>" so the component knows
-/// which Telnet connection the message originated from.
+/// The subject is the connection's "telnet.<host>:<port>" (see
+/// [`ConnectionContext`]) so the component knows which Telnet connection the
+/// message originated from.
 /// The body contains the raw bytes of the Telnet message.
-fn create_broker_message(data: Vec<u8>, telnet_address: &str) -> types::BrokerMessage {
+pub(crate) fn create_broker_message(data: Bytes, subject: &str) -> types::BrokerMessage {
     types::BrokerMessage {
-        subject: format!("telnet.{}", telnet_address),
-        body: data.into(),
+        subject: subject.to_string(),
+        body: data,
         reply_to: None,
     }
 }
 
-/// Send message to component via wRPC using the standard messaging handler
-async fn send_message_to_component(
-    component_id: &str,
-    message: types::BrokerMessage,
-) -> anyhow::Result<()> {
-    let client = wasmcloud_provider_sdk::get_connection()
-        .get_wrpc_client(component_id)
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use connections::{ConnectionConfig, HandlerTelnetConnection};
+
+    /// A Telnet server that never sends anything, recording what each
+    /// connection wrote and how many were closed by the client
+    struct MockServer {
+        port: u16,
+        accepted: Arc<AtomicUsize>,
+        closed: Arc<AtomicUsize>,
+        received: Arc<std::sync::Mutex<Vec<u8>>>,
+    }
+
+    impl MockServer {
+        async fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let server = Self {
+                port: listener.local_addr().unwrap().port(),
+                accepted: Arc::default(),
+                closed: Arc::default(),
+                received: Arc::default(),
+            };
+            let (accepted, closed, received) = (
+                server.accepted.clone(),
+                server.closed.clone(),
+                server.received.clone(),
+            );
+            tokio::spawn(async move {
+                while let Ok((mut stream, _)) = listener.accept().await {
+                    accepted.fetch_add(1, Ordering::SeqCst);
+                    let (closed, received) = (closed.clone(), received.clone());
+                    tokio::spawn(async move {
+                        let mut buf = [0; 1024];
+                        while let Ok(len @ 1..) = stream.read(&mut buf).await {
+                            received.lock().unwrap().extend_from_slice(&buf[..len]);
+                        }
+                        closed.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+            });
+            server
+        }
+
+        fn received(&self) -> Vec<u8> {
+            self.received.lock().unwrap().clone()
+        }
+    }
+
+    /// Deletion of the link from `0`
+    struct Deleted(&'static str);
+
+    impl LinkDeleteInfo for Deleted {
+        fn get_source_id(&self) -> &str {
+            self.0
+        }
+
+        fn get_target_id(&self) -> &str {
+            "telnet-provider"
+        }
+
+        fn get_link_name(&self) -> &str {
+            "default"
+        }
+    }
+
+    /// Link values connecting to `port`, plus `extra`
+    fn link_values(port: u16, extra: &[(&str, &str)]) -> LinkValues {
+        let mut config = HashMap::from([
+            ("telnet_host".to_string(), "127.0.0.1".to_string()),
+            ("telnet_port".to_string(), port.to_string()),
+            ("reachability_probe".to_string(), "false".to_string()),
+        ]);
+        config.extend(
+            extra
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string())),
+        );
+        LinkValues {
+            config,
+            secrets: HashMap::new(),
+        }
+    }
+
+    fn provider(values: &[(&str, &str)]) -> TelnetProvider {
+        let values: HashMap<String, String> = values
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        TelnetProviderBuilder::default()
+            .with_config(ProviderConfig::from(&values))
+            .build()
+    }
+
+    /// Wait up to 5 seconds for `condition` to hold
+    async fn eventually(condition: impl Fn() -> bool) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !condition() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
         .await
-        .context("failed to get wrpc client")?;
+        .expect("condition not reached within 5s");
+    }
+
+    async fn connected(provider: &TelnetProvider, source_id: &str) -> Arc<ConnectionState> {
+        let state = provider.connection(source_id).await.unwrap();
+        let stats = state.stats.clone();
+        eventually(move || stats.is_connected()).await;
+        state
+    }
 
-    match handler::handle_message(&client, None, &message).await {
-        Ok(Ok(_)) => {
-            info!("Message successfully sent to component {}", component_id);
-            Ok(())
+    #[tokio::test]
+    async fn stop_group_tears_down_members_and_parked_links() {
+        let server = MockServer::start().await;
+        let provider = provider(&[("shutdown_timeout_ms", "1000")]);
+        let group = [("group", "night")];
+        let grace = [("group", "night"), ("reuse_grace_ms", "60000")];
+        for (source_id, extra) in [("a", &group[..]), ("b", &group), ("c", &[]), ("d", &grace)] {
+            provider
+                .open_link(source_id, link_values(server.port, extra))
+                .await
+                .unwrap();
         }
-        Ok(Err(e)) => {
-            error!("Component {} returned error: {}", component_id, e);
-            anyhow::bail!("Component error: {}", e)
+        let states = [
+            connected(&provider, "a").await,
+            connected(&provider, "b").await,
+            connected(&provider, "d").await,
+        ];
+        connected(&provider, "c").await;
+        provider.delete_link_as_target(Deleted("d")).await.unwrap();
+        assert!(provider.parked.read().await.contains_key("d"));
+
+        assert_eq!(provider.stop_group("night").await, 3);
+
+        // Torn down, not just signalled: each task has finished and each
+        // socket was closed
+        for state in &states {
+            tokio::time::timeout(Duration::from_secs(1), state._task_handle.finished())
+                .await
+                .unwrap();
         }
-        Err(e) => {
-            error!("Failed to call component {}: {}", component_id, e);
-            Err(e)
+        let closed = server.closed.clone();
+        eventually(move || closed.load(Ordering::SeqCst) == 3).await;
+        assert!(provider.parked.read().await.is_empty());
+        let remaining: Vec<String> = provider.connections.read().await.keys().cloned().collect();
+        assert_eq!(remaining, ["c"]);
+    }
+
+    fn cx(component: &str) -> Option<Context> {
+        Some(Context {
+            component: Some(component.to_string()),
+            ..Context::default()
+        })
+    }
+
+    fn connection_config(port: u16, options: &[(&str, &str)]) -> ConnectionConfig {
+        ConnectionConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            options: [("reachability_probe", "false")]
+                .iter()
+                .chain(options)
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
         }
     }
+
+    /// Open a connection through a handle, returning the handle
+    async fn connect(
+        provider: &TelnetProvider,
+        component: &str,
+        config: ConnectionConfig,
+    ) -> Result<ResourceOwn<connections::TelnetConnection>, String> {
+        HandlerTelnetConnection::connect(provider, cx(component), config)
+            .await
+            .unwrap()
+    }
+
+    async fn send(
+        provider: &TelnetProvider,
+        component: &str,
+        handle: &ResourceOwn<connections::TelnetConnection>,
+        data: &'static [u8],
+    ) -> Result<(), String> {
+        HandlerTelnetConnection::send(
+            provider,
+            cx(component),
+            handle.as_borrow(),
+            Bytes::from_static(data),
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn keys(provider: &TelnetProvider) -> Vec<String> {
+        let mut keys: Vec<String> = provider.connections.read().await.keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    #[tokio::test]
+    async fn handles_open_connections_alongside_the_link() {
+        let link_server = MockServer::start().await;
+        let handle_server = MockServer::start().await;
+        let provider = provider(&[("connect_allowed_hosts", "127.0.0.1")]);
+        provider
+            .open_link("comp", link_values(link_server.port, &[]))
+            .await
+            .unwrap();
+        let link = connected(&provider, "comp").await;
+
+        let first = connect(
+            &provider,
+            "comp",
+            connection_config(handle_server.port, &[]),
+        )
+        .await
+        .unwrap();
+        let second = connect(
+            &provider,
+            "comp",
+            connection_config(handle_server.port, &[]),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            keys(&provider).await,
+            ["comp", "comp#handle-1", "comp#handle-2"]
+        );
+        connected(&provider, "comp#handle-1").await;
+        connected(&provider, "comp#handle-2").await;
+        // The link is untouched
+        assert!(Arc::ptr_eq(
+            &link,
+            &provider.connection("comp").await.unwrap()
+        ));
+        assert_eq!(link_server.accepted.load(Ordering::SeqCst), 1);
+        assert_eq!(link_server.closed.load(Ordering::SeqCst), 0);
+
+        send(&provider, "comp", &first, b"one\r\n").await.unwrap();
+        send(&provider, "comp", &second, b"two\r\n").await.unwrap();
+        eventually(|| handle_server.received().len() == 10).await;
+        // Only the owner can use a handle
+        assert!(send(&provider, "other", &first, b"three\r\n")
+            .await
+            .is_err());
+
+        HandlerTelnetConnection::close(&provider, cx("comp"), first.as_borrow())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(keys(&provider).await, ["comp", "comp#handle-2"]);
+        assert_eq!(
+            send(&provider, "comp", &first, b"one\r\n").await,
+            Err("no such handle: handle-1".to_string())
+        );
+
+        // Deleting the link closes the component's connections with it
+        provider
+            .delete_link_as_target(Deleted("comp"))
+            .await
+            .unwrap();
+        assert!(keys(&provider).await.is_empty());
+        assert!(send(&provider, "comp", &second, b"two\r\n").await.is_err());
+        let closed = handle_server.closed.clone();
+        eventually(move || closed.load(Ordering::SeqCst) == 2).await;
+    }
+
+    #[tokio::test]
+    async fn component_ids_with_the_handle_separator_are_refused() {
+        assert!(check_component_id("comp").is_ok());
+        assert!(check_component_id("comp#handle-1").is_err());
+
+        let server = MockServer::start().await;
+        let provider = provider(&[("connect_allowed_hosts", "127.0.0.1")]);
+        connect(&provider, "comp", connection_config(server.port, &[]))
+            .await
+            .unwrap();
+        // Would otherwise be stored under the key of comp's connection
+        assert!(connect(
+            &provider,
+            "comp#handle",
+            connection_config(server.port, &[])
+        )
+        .await
+        .is_err());
+        assert_eq!(keys(&provider).await, ["comp#handle-1"]);
+        assert_eq!(component_of("comp#handle-1"), "comp");
+    }
+
+    #[tokio::test]
+    async fn handles_follow_their_connection_across_a_reload() {
+        let server = MockServer::start().await;
+        let provider = provider(&[("connect_allowed_hosts", "127.0.0.1")]);
+        let handle = connect(&provider, "comp", connection_config(server.port, &[]))
+            .await
+            .unwrap();
+        let before = connected(&provider, "comp#handle-1").await;
+
+        // Restart it as a disruptive config change would
+        provider
+            .start_link(
+                "comp#handle-1",
+                (*before.config()).clone(),
+                before.values.clone(),
+                Arc::default(),
+            )
+            .await;
+        let after = connected(&provider, "comp#handle-1").await;
+        assert!(!Arc::ptr_eq(&before, &after));
+        assert_eq!(server.accepted.load(Ordering::SeqCst), 2);
+
+        send(&provider, "comp", &handle, b"still here\r\n")
+            .await
+            .unwrap();
+        eventually(|| server.received() == b"still here\r\n").await;
+
+        // Once the connection is gone, the handle says so
+        let state = provider
+            .connections
+            .write()
+            .await
+            .remove("comp#handle-1")
+            .unwrap();
+        provider.close_link("comp#handle-1", &state).await;
+        assert_eq!(
+            send(&provider, "comp", &handle, b"gone\r\n").await,
+            Err("connection is closed".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn connect_is_limited_to_allowed_hosts_ports_and_options() {
+        let server = MockServer::start().await;
+        let port = server.port.to_string();
+
+        // Nothing is allowed by default
+        let unconfigured = provider(&[]);
+        assert!(
+            connect(&unconfigured, "comp", connection_config(server.port, &[]))
+                .await
+                .is_err()
+        );
+
+        let provider = provider(&[
+            ("connect_allowed_hosts", "10.0.0.0/8,127.0.0.1"),
+            ("connect_allowed_ports", &port),
+        ]);
+        let mut other_port = connection_config(server.port, &[]);
+        other_port.port = server.port.wrapping_add(1);
+        assert!(connect(&provider, "comp", other_port).await.is_err());
+        let mut other_host = connection_config(server.port, &[]);
+        other_host.host = "localhost".to_string();
+        assert!(connect(&provider, "comp", other_host).await.is_err());
+        for key in ["routing_rules", "subject_template", "group", "config_file"] {
+            assert_eq!(
+                connect(
+                    &provider,
+                    "comp",
+                    connection_config(server.port, &[(key, "x")])
+                )
+                .await,
+                Err(format!("{} can't be set by a component", key))
+            );
+        }
+        assert!(keys(&provider).await.is_empty());
+        assert_eq!(server.accepted.load(Ordering::SeqCst), 0);
+
+        connect(
+            &provider,
+            "comp",
+            connection_config(
+                server.port,
+                &[("max_message_size", "512"), ("environ.USER", "me")],
+            ),
+        )
+        .await
+        .unwrap();
+        assert_eq!(keys(&provider).await, ["comp#handle-1"]);
+    }
+
+    /// The connection under `key`, once it is no longer `state`
+    async fn replaced(
+        provider: &TelnetProvider,
+        key: &str,
+        state: &Arc<ConnectionState>,
+    ) -> Arc<ConnectionState> {
+        let connections = provider.connections.clone();
+        let (current, old) = (key.to_string(), state.clone());
+        eventually(move || {
+            connections
+                .try_read()
+                .is_ok_and(|connections| !Arc::ptr_eq(&connections[&current], &old))
+        })
+        .await;
+        connected(provider, key).await
+    }
+
+    #[tokio::test]
+    async fn config_file_changes_apply_live_or_restart_the_link() {
+        let server = MockServer::start().await;
+        let dir =
+            std::env::temp_dir().join(format!("telnet-provider-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("link.json");
+        std::fs::write(&path, r#"{"max_message_size": 1024}"#).unwrap();
+        let provider = provider(&[]);
+        provider
+            .open_link(
+                "a",
+                link_values(server.port, &[("config_file", path.to_str().unwrap())]),
+            )
+            .await
+            .unwrap();
+        let state = connected(&provider, "a").await;
+        assert_eq!(state.config().max_message_size, 1024);
+
+        // A live key is handed to the running connection
+        std::fs::write(&path, r#"{"max_message_size": 2048}"#).unwrap();
+        let live = state.clone();
+        eventually(move || live.config().max_message_size == 2048).await;
+        assert!(Arc::ptr_eq(
+            &state,
+            &provider.connection("a").await.unwrap()
+        ));
+        assert_eq!(server.accepted.load(Ordering::SeqCst), 1);
+
+        // Anything else restarts the link, including a key being removed
+        std::fs::write(
+            &path,
+            r#"{"max_message_size": 2048, "environ.USER": "operator"}"#,
+        )
+        .unwrap();
+        let state = replaced(&provider, "a", &state).await;
+        assert_eq!(server.accepted.load(Ordering::SeqCst), 2);
+        std::fs::write(&path, r#"{"max_message_size": 2048}"#).unwrap();
+        let state = replaced(&provider, "a", &state).await;
+        assert_eq!(state.config().max_message_size, 2048);
+        assert_eq!(server.accepted.load(Ordering::SeqCst), 3);
+
+        provider.delete_link_as_target(Deleted("a")).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn link_churn_does_not_stall_traffic_or_status_reads() {
+        let server = MockServer::start().await;
+        let provider = Arc::new(provider(&[("shutdown_timeout_ms", "500")]));
+        let steady = ["steady-0", "steady-1"];
+        let churned = ["churn-0", "churn-1", "churn-2"];
+        let port = server.port;
+        for source_id in steady {
+            provider
+                .open_link(source_id, link_values(server.port, &[]))
+                .await
+                .unwrap();
+            connected(&provider, source_id).await;
+        }
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(2);
+
+        // Traffic keeps flowing on the steady links
+        let senders: Vec<_> = steady
+            .into_iter()
+            .map(|source_id| {
+                let provider = provider.clone();
+                tokio::spawn(async move {
+                    let mut sent = 0;
+                    while tokio::time::Instant::now() < deadline {
+                        let state = provider.connection(source_id).await.unwrap();
+                        state.send(b"x").await.unwrap();
+                        sent += 1;
+                        tokio::task::yield_now().await;
+                    }
+                    sent
+                })
+            })
+            .collect();
+        // While other links are put and deleted over and over
+        let churners: Vec<_> = churned
+            .into_iter()
+            .map(|source_id| {
+                let provider = provider.clone();
+                tokio::spawn(async move {
+                    let mut cycles = 0;
+                    while tokio::time::Instant::now() < deadline {
+                        provider
+                            .open_link(source_id, link_values(port, &[]))
+                            .await
+                            .unwrap();
+                        provider
+                            .delete_link_as_target(Deleted(source_id))
+                            .await
+                            .unwrap();
+                        cycles += 1;
+                    }
+                    cycles
+                })
+            })
+            .collect();
+        // And status is read for all of them, each read answering promptly
+        let reader = {
+            let provider = provider.clone();
+            tokio::spawn(async move {
+                let mut slowest = Duration::ZERO;
+                while tokio::time::Instant::now() < deadline {
+                    for source_id in steady.into_iter().chain(churned) {
+                        let started = tokio::time::Instant::now();
+                        control::Handler::status(&*provider, None, source_id.to_string())
+                            .await
+                            .unwrap()
+                            .ok();
+                        slowest = slowest.max(started.elapsed());
+                    }
+                    tokio::task::yield_now().await;
+                }
+                slowest
+            })
+        };
+
+        let (sent, cycles, slowest) = tokio::time::timeout(Duration::from_secs(10), async {
+            (
+                join_all(senders).await,
+                join_all(churners).await,
+                reader.await.unwrap(),
+            )
+        })
+        .await
+        .expect("link operations deadlocked");
+        let sent: usize = sent.into_iter().map(Result::unwrap).sum();
+        assert!(cycles.into_iter().all(|cycles| cycles.unwrap() > 0));
+        assert!(
+            slowest < Duration::from_millis(250),
+            "slowest status read took {:?}",
+            slowest
+        );
+        eventually(|| server.received().len() == sent).await;
+        assert_eq!(keys(&provider).await, steady);
+    }
+
+    /// Register a connection as `source_id` whose task ignores being
+    /// stopped, as a link wedged outside any await on its token would
+    fn insert_stuck(provider: &TelnetProvider, source_id: &str) -> Arc<ConnectionState> {
+        let config = Arc::new(LinkConfig::from_values(&link_values(1, &[]).config).unwrap());
+        let stats = Arc::new(ConnectionStats::default());
+        Arc::new(ConnectionState {
+            config: watch::channel(config).0,
+            cancel: CancellationToken::new(),
+            stats: stats.clone(),
+            writer: SharedWriter::default(),
+            sent_line: SharedSentLine::default(),
+            values: link_values(1, &[]),
+            delivery_tasks: Arc::new(DeliveryTasks::new(source_id.into(), stats)),
+            _task_handle: provider
+                .readers
+                .spawn(source_id, "reader", std::future::pending::<()>()),
+        })
+    }
+
+    #[tokio::test]
+    async fn shutdown_aborts_connections_that_outlive_the_timeout() {
+        let server = MockServer::start().await;
+        let provider = provider(&[("shutdown_timeout_ms", "200")]);
+        provider
+            .open_link("polite", link_values(server.port, &[]))
+            .await
+            .unwrap();
+        let polite = connected(&provider, "polite").await;
+        let stuck = insert_stuck(&provider, "stuck");
+        provider
+            .connections
+            .write()
+            .await
+            .insert("stuck".to_string(), stuck.clone());
+
+        let started = tokio::time::Instant::now();
+        Provider::shutdown(&provider).await.unwrap();
+        let elapsed = started.elapsed();
+
+        // The whole shutdown is bounded by one deadline, which the stuck
+        // task had to be aborted at
+        assert!(
+            elapsed >= Duration::from_millis(200) && elapsed < Duration::from_secs(1),
+            "shutdown took {:?}",
+            elapsed
+        );
+        for state in [&polite, &stuck] {
+            tokio::time::timeout(Duration::from_millis(100), state._task_handle.finished())
+                .await
+                .unwrap();
+        }
+        let closed = server.closed.clone();
+        eventually(move || closed.load(Ordering::SeqCst) == 1).await;
+        assert!(keys(&provider).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn shared_reader_tasks_bound_the_task_count() {
+        // The server runs on a runtime of its own, so only the provider's
+        // tasks are counted
+        let (port_tx, port_rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(async {
+                    port_tx.send(MockServer::start().await.port).unwrap();
+                    std::future::pending::<()>().await
+                })
+        });
+        let port = port_rx.recv().unwrap();
+        let provider = provider(&[]);
+        let shutdown = CancellationToken::new();
+        provider.readers.start(2, shutdown.clone());
+        let alive_tasks = || {
+            tokio::runtime::Handle::current()
+                .metrics()
+                .num_alive_tasks()
+        };
+        let workers_only = alive_tasks();
+
+        for i in 0..16 {
+            let source_id = format!("idle-{}", i);
+            provider
+                .open_link(&source_id, link_values(port, &[]))
+                .await
+                .unwrap();
+            connected(&provider, &source_id).await;
+        }
+        // Connected links, reads included, all run on the two workers
+        assert_eq!(alive_tasks(), workers_only);
+        shutdown.cancel();
+    }
+
+    /// The default connector, counting the connections it opens
+    #[derive(Default)]
+    struct CountingConnector(AtomicUsize);
+
+    impl TcpConnector for CountingConnector {
+        fn connect<'a>(
+            &'a self,
+            addrs: &'a [std::net::SocketAddr],
+            tuning: SocketTuning,
+        ) -> Pin<Box<dyn Future<Output = std::io::Result<crate::socket::Socket>> + Send + 'a>>
+        {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            crate::socket::DefaultConnector.connect(addrs, tuning)
+        }
+    }
+
+    #[tokio::test]
+    async fn links_connect_through_the_builders_connector() {
+        let server = MockServer::start().await;
+        let connector = Arc::new(CountingConnector::default());
+        let provider = TelnetProviderBuilder::default()
+            .with_connector(connector.clone())
+            .with_max_connections(1)
+            .build();
+        provider
+            .open_link("comp", link_values(server.port, &[]))
+            .await
+            .unwrap();
+        connected(&provider, "comp").await;
+        assert_eq!(connector.0.load(Ordering::SeqCst), 1);
+
+        // The builder's limit applies without any provider config
+        assert!(provider
+            .open_link("other", link_values(server.port, &[]))
+            .await
+            .is_err());
+        assert_eq!(keys(&provider).await, ["comp"]);
+    }
+
+    #[tokio::test]
+    async fn server_types_are_detected_once_per_link_through_the_connector() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let banner = b"User Access Verification\r\n\r\nUsername: ";
+                    stream.write_all(banner).await.unwrap();
+                    let _ = stream.read_to_end(&mut Vec::new()).await;
+                });
+            }
+        });
+        let dir =
+            std::env::temp_dir().join(format!("telnet-provider-detect-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("link.json");
+        std::fs::write(&path, r#"{"max_message_size": 1024}"#).unwrap();
+        let connector = Arc::new(CountingConnector::default());
+        let provider = TelnetProviderBuilder::default()
+            .with_connector(connector.clone())
+            .build();
+
+        let values = link_values(
+            port,
+            &[
+                ("auto_detect_server_type", "true"),
+                ("config_file", path.to_str().unwrap()),
+            ],
+        );
+        provider.open_link("a", values).await.unwrap();
+        let state = connected(&provider, "a").await;
+        assert_eq!(state.config().server_type, Some(ServerType::Cisco));
+        // The banner was read through the connector, then the link connected
+        assert_eq!(connector.0.load(Ordering::SeqCst), 2);
+
+        // A restart for a changed config file keeps the detected type
+        // without asking the server again
+        std::fs::write(
+            &path,
+            r#"{"max_message_size": 1024, "environ.USER": "operator"}"#,
+        )
+        .unwrap();
+        let state = replaced(&provider, "a", &state).await;
+        assert_eq!(state.config().server_type, Some(ServerType::Cisco));
+        assert_eq!(connector.0.load(Ordering::SeqCst), 3);
+
+        provider.delete_link_as_target(Deleted("a")).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,67 @@
+//! Moving-average message rate
+//!
+//! The instantaneous rate of a bursty feed is noisy; averaging over a window
+//! (10 seconds per link) gives a figure worth graphing and alerting on.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Samples closer together than this are merged, bounding memory at high rates
+const SAMPLE_RESOLUTION: Duration = Duration::from_millis(100);
+
+/// Message rate over a sliding time window
+#[derive(Debug)]
+pub struct MessageRateWindow {
+    /// `(timestamp, cumulative_count)`, oldest first
+    samples: VecDeque<(Instant, u64)>,
+    window: Duration,
+}
+
+impl MessageRateWindow {
+    /// Average over the last `window`
+    pub fn new(window: Duration) -> Self {
+        Self {
+            samples: VecDeque::new(),
+            window,
+        }
+    }
+
+    /// Record the cumulative message count as of now
+    pub fn add_sample(&mut self, count: u64) {
+        let now = Instant::now();
+        match self.samples.back_mut() {
+            Some((at, last)) if now.duration_since(*at) < SAMPLE_RESOLUTION => *last = count,
+            _ => self.samples.push_back((now, count)),
+        }
+        self.expire(now);
+    }
+
+    /// Messages per second from the oldest sample in the window to now
+    pub fn messages_per_second(&self) -> f64 {
+        let now = Instant::now();
+        let mut in_window = self
+            .samples
+            .iter()
+            .filter(|(at, _)| now.duration_since(*at) <= self.window);
+        let (Some((since, first)), Some((_, last))) = (in_window.next(), self.samples.back())
+        else {
+            return 0.0;
+        };
+        let elapsed = now.duration_since(*since).as_secs_f64();
+        if elapsed == 0.0 {
+            return 0.0;
+        }
+        last.saturating_sub(*first) as f64 / elapsed
+    }
+
+    /// Drop samples that have left the window
+    fn expire(&mut self, now: Instant) {
+        while self
+            .samples
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) > self.window)
+        {
+            self.samples.pop_front();
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! Adaptive sizing of a connection's socket reads
+//!
+//! A fixed read size is too large for feeds that trickle a line at a time
+//! and too small for bulk dumps, which then take many reads. Each connection
+//! starts reading `read_buffer_size` bytes at a time, doubles that whenever
+//! consecutive reads fill it, and halves it after a run of reads that use
+//! little of it. The size only decides how much is requested per read; how
+//! the data is framed afterwards doesn't depend on it.
+
+/// Smallest read size the buffer shrinks to
+const MIN_READ_SIZE: usize = 256;
+
+/// Largest read size the buffer grows to, whatever `max_message_size` is
+const MAX_READ_SIZE: usize = 1024 * 1024;
+
+/// Consecutive full reads after which the read size doubles
+const GROW_AFTER: u32 = 2;
+
+/// Consecutive reads using at most a quarter of the size after which it
+/// halves
+const SHRINK_AFTER: u32 = 16;
+
+/// Current read size of a connection and the recent reads it's based on
+#[derive(Debug)]
+pub struct ReadSizer {
+    size: usize,
+    max: usize,
+    full_reads: u32,
+    small_reads: u32,
+}
+
+impl ReadSizer {
+    /// Start at `initial` bytes, growing up to `max_message_size` (and at
+    /// most 1 MiB)
+    pub fn new(initial: usize, max_message_size: usize) -> Self {
+        let max = max_message_size.clamp(MIN_READ_SIZE, MAX_READ_SIZE);
+        Self {
+            size: initial.clamp(MIN_READ_SIZE, max),
+            max,
+            full_reads: 0,
+            small_reads: 0,
+        }
+    }
+
+    /// Change the largest size to `max_message_size` (at most 1 MiB),
+    /// shrinking the current size if needed
+    pub fn set_max(&mut self, max_message_size: usize) {
+        self.max = max_message_size.clamp(MIN_READ_SIZE, MAX_READ_SIZE);
+        self.size = self.size.min(self.max);
+    }
+
+    /// Bytes to request in the next read
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Adjust the size after a read of `n` bytes
+    pub fn record(&mut self, n: usize) {
+        if n >= self.size {
+            self.small_reads = 0;
+            self.full_reads += 1;
+            if self.full_reads >= GROW_AFTER {
+                self.full_reads = 0;
+                self.size = (self.size * 2).min(self.max);
+            }
+        } else if n <= self.size / 4 {
+            self.full_reads = 0;
+            self.small_reads += 1;
+            if self.small_reads >= SHRINK_AFTER {
+                self.small_reads = 0;
+                self.size = (self.size / 2).max(MIN_READ_SIZE);
+            }
+        } else {
+            self.full_reads = 0;
+            self.small_reads = 0;
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//! Recording of the raw bytes a Telnet session receives
+//!
+//! Each connection gets its own file, named after the server and the time
+//! the connection was established. Reads are queued on a bounded channel
+//! and written by a background task, so a slow disk never holds up the
+//! read loop; reads that don't fit are dropped and counted. With
+//! compression on, the file is a zstd stream that `--decompress-session`
+//! turns back into the raw bytes.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context as _;
+use async_compression::tokio::bufread::ZstdDecoder;
+use async_compression::tokio::write::ZstdEncoder;
+use async_compression::Level;
+use bytes::Bytes;
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::instrument::spawn_named;
+
+/// Reads queued before new ones are dropped
+const QUEUE_CAPACITY: usize = 1024;
+
+/// Records the bytes read on one connection; the file is completed by
+/// [`finish`](Self::finish), or in the background once the recorder is
+/// dropped
+pub struct SessionRecorder {
+    sender: mpsc::Sender<Bytes>,
+    dropped: Arc<AtomicU64>,
+    writer: JoinHandle<()>,
+    path: PathBuf,
+}
+
+impl SessionRecorder {
+    /// Create a recording for a connection to `host:port` in `dir`,
+    /// compressed with zstd if `compress` is set
+    pub async fn create(dir: &Path, host: &str, port: u16, compress: bool) -> anyhow::Result<Self> {
+        let path = dir.join(file_name(host, port, compress));
+        let file = tokio::fs::File::create(&path)
+            .await
+            .with_context(|| format!("failed to create session recording {}", path.display()))?;
+        let file = BufWriter::new(file);
+        let writer: Box<dyn AsyncWrite + Send + Unpin> = if compress {
+            // Level 3, zstd's default, keeps up with streaming data
+            Box::new(ZstdEncoder::with_quality(file, Level::Default))
+        } else {
+            Box::new(file)
+        };
+
+        let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let writer = spawn_named(
+            &path.display().to_string(),
+            "session-recorder",
+            write_reads(writer, receiver, dropped.clone(), path.clone()),
+        );
+        info!("Recording session to {}", path.display());
+        Ok(Self {
+            sender,
+            dropped,
+            writer,
+            path,
+        })
+    }
+
+    /// Queue bytes read from the server without waiting; counts them as
+    /// lost if the queue is full
+    pub fn record(&self, data: &[u8]) {
+        if self.sender.try_send(Bytes::copy_from_slice(data)).is_err() {
+            let lost = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            if lost.is_power_of_two() {
+                warn!(
+                    "{} reads left out of session recording {} (writer falling behind)",
+                    lost,
+                    self.path.display()
+                );
+            }
+        }
+    }
+
+    /// Stop recording and wait until the file is complete
+    pub async fn finish(self) -> PathBuf {
+        drop(self.sender);
+        let _ = self.writer.await;
+        self.path
+    }
+}
+
+/// `<host>_<port>-<unix ms>.bin`, with `.zst` appended when compressed
+fn file_name(host: &str, port: u16, compress: bool) -> String {
+    // IPv6 literals and the like can't go into a file name as they are
+    let host: String = host
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let started_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let extension = if compress { "bin.zst" } else { "bin" };
+    format!("{}_{}-{}.{}", host, port, started_ms, extension)
+}
+
+/// Write queued reads until every sender is gone, then complete the file
+async fn write_reads(
+    mut writer: Box<dyn AsyncWrite + Send + Unpin>,
+    mut receiver: mpsc::Receiver<Bytes>,
+    dropped: Arc<AtomicU64>,
+    path: PathBuf,
+) {
+    let mut failed = false;
+    while let Some(data) = receiver.recv().await {
+        if failed {
+            continue;
+        }
+        if let Err(e) = writer.write_all(&data).await {
+            error!(
+                "Failed to write session recording {}: {}",
+                path.display(),
+                e
+            );
+            failed = true;
+        }
+    }
+    // Flushes the buffer and, when compressing, ends the zstd frame
+    if !failed {
+        if let Err(e) = writer.shutdown().await {
+            error!(
+                "Failed to complete session recording {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+    let lost = dropped.load(Ordering::Relaxed);
+    if lost > 0 {
+        warn!(
+            "Session recording {} is missing {} reads",
+            path.display(),
+            lost
+        );
+    }
+}
+
+/// Decompress the recording at `path` to `out`, for `--decompress-session`
+pub async fn decompress<W: AsyncWrite + Unpin>(path: &Path, out: &mut W) -> anyhow::Result<u64> {
+    let file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("failed to open session recording {}", path.display()))?;
+    let mut decoder = ZstdDecoder::new(BufReader::new(file));
+    // Recordings joined with `cat` hold a frame each
+    decoder.multiple_members(true);
+    let written = tokio::io::copy(&mut decoder, out)
+        .await
+        .with_context(|| format!("failed to decompress {}", path.display()))?;
+    out.flush().await?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of its own for one test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "telnet-provider-record-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn records_reads_as_they_arrived() {
+        let dir = test_dir("plain");
+        let recorder = SessionRecorder::create(&dir, "10.0.0.5", 23, false)
+            .await
+            .unwrap();
+        recorder.record(b"\xff\xfb\x01login: ");
+        recorder.record(b"welcome\r\n");
+        let path = recorder.finish().await;
+
+        assert!(path.to_str().unwrap().ends_with(".bin"));
+        assert!(path
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("10.0.0.5_23-"));
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            b"\xff\xfb\x01login: welcome\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn compressed_recordings_decompress_to_the_raw_bytes() {
+        let dir = test_dir("zstd");
+        let recorder = SessionRecorder::create(&dir, "::1", 2323, true)
+            .await
+            .unwrap();
+        let reads: Vec<Vec<u8>> = (0..200)
+            .map(|i| format!("line {} of the session\r\n", i).into_bytes())
+            .collect();
+        for read in &reads {
+            recorder.record(read);
+        }
+        let path = recorder.finish().await;
+
+        assert!(path.to_str().unwrap().ends_with(".bin.zst"));
+        assert!(path
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("__1_2323-"));
+        let raw = reads.concat();
+        assert!(std::fs::metadata(&path).unwrap().len() < raw.len() as u64);
+
+        let mut out = Vec::new();
+        let written = decompress(&path, &mut out).await.unwrap();
+        assert_eq!(written, raw.len() as u64);
+        assert_eq!(out, raw);
+    }
+
+    #[tokio::test]
+    async fn decompressing_an_uncompressed_file_fails() {
+        let dir = test_dir("not-zstd");
+        let recorder = SessionRecorder::create(&dir, "host", 23, false)
+            .await
+            .unwrap();
+        recorder.record(b"plain bytes");
+        let path = recorder.finish().await;
+
+        assert!(decompress(&path, &mut Vec::new()).await.is_err());
+    }
+}
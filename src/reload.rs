@@ -0,0 +1,113 @@
+//! Link configuration reloaded from a file
+//!
+//! Changing a link's config in wasmCloud means deleting and recreating the
+//! link. With `config_file` set, the link's values are merged with those of
+//! a JSON or TOML file (file values win), and the file is watched: whenever
+//! it changes, the merged config is compared with the running one. Changes
+//! to [`LIVE_KEYS`](crate::config::LIVE_KEYS) are handed to the running
+//! connection; any other change restarts the link with the new config.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::Context as _;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+/// How long to wait for a burst of file events (editors often write a file
+/// in several steps) to settle before reading it
+const SETTLE_DELAY: Duration = Duration::from_millis(200);
+
+/// Notifies about changes to one config file
+pub struct ConfigWatcher {
+    /// Stops watching when dropped
+    _watcher: RecommendedWatcher,
+    events: mpsc::Receiver<()>,
+}
+
+impl ConfigWatcher {
+    /// Watch `path`
+    ///
+    /// The file's directory is watched rather than the file, so replacing
+    /// the file (as editors and config management tools do) is noticed too.
+    pub fn new(path: &Path) -> anyhow::Result<Self> {
+        let file_name = path
+            .file_name()
+            .with_context(|| format!("config_file {} is not a file", path.display()))?
+            .to_os_string();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+
+        let (tx, events) = mpsc::channel(1);
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                match event {
+                    Ok(event) => {
+                        let ours = event
+                            .paths
+                            .iter()
+                            .any(|path| path.file_name() == Some(file_name.as_os_str()));
+                        // A full channel already has a change pending
+                        if ours && (event.kind.is_create() || event.kind.is_modify()) {
+                            let _ = tx.try_send(());
+                        }
+                    }
+                    Err(e) => warn!("Error watching config file: {}", e),
+                }
+            })?;
+        watcher
+            .watch(&dir, RecursiveMode::NonRecursive)
+            .with_context(|| format!("failed to watch {}", dir.display()))?;
+        Ok(Self {
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    /// Wait for the file to change and settle
+    pub async fn changed(&mut self) {
+        if self.events.recv().await.is_none() {
+            // The watcher is gone; there will be no more changes
+            std::future::pending::<()>().await;
+        }
+        tokio::time::sleep(SETTLE_DELAY).await;
+        while self.events.try_recv().is_ok() {}
+    }
+}
+
+/// Read a config file as key/value pairs
+///
+/// Files ending in `.toml` are parsed as TOML, anything else as JSON. Both
+/// hold a flat table; numbers and booleans are taken as written.
+pub fn read_config_file(path: &Path) -> anyhow::Result<HashMap<String, String>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let is_toml = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("toml"));
+    let values: HashMap<String, serde_json::Value> = if is_toml {
+        toml::from_str(&text).with_context(|| format!("invalid TOML in {}", path.display()))?
+    } else {
+        serde_json::from_str(&text)
+            .with_context(|| format!("invalid JSON in {}", path.display()))?
+    };
+    values
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                serde_json::Value::String(value) => value,
+                serde_json::Value::Number(_) | serde_json::Value::Bool(_) => value.to_string(),
+                other => anyhow::bail!(
+                    "config_file value of {} must be a string, number or boolean, not {}",
+                    key,
+                    other
+                ),
+            };
+            Ok((key, value))
+        })
+        .collect()
+}
@@ -0,0 +1,270 @@
+//! Aggregated delivery logging for a connection
+//!
+//! At thousands of messages per second, a log line per delivery costs more
+//! than the delivery itself and drowns everything else. Each connection
+//! instead logs one summary per [`REPORT_INTERVAL`] of what it delivered,
+//! and at most one failure and one slow delivery per interval, counting the
+//! ones held back.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::{error, info, warn};
+
+/// How often delivery summaries and repeated failures are logged
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Delivery counts of one connection since its last summary
+pub struct DeliveryReport {
+    source_id: Arc<str>,
+    window: Mutex<ReportWindow>,
+}
+
+struct ReportWindow {
+    started: Instant,
+    messages: u64,
+    bytes: u64,
+    failures: u64,
+    /// When a failure was last logged, if one has been
+    last_failure_logged: Option<Instant>,
+    /// Failures not logged since then
+    suppressed_failures: u64,
+    /// When a slow delivery was last logged, if one has been
+    last_slow_logged: Option<Instant>,
+    /// Slow deliveries not logged since then
+    suppressed_slow: u64,
+}
+
+impl DeliveryReport {
+    pub fn new(source_id: Arc<str>) -> Self {
+        Self {
+            source_id,
+            window: Mutex::new(ReportWindow {
+                started: Instant::now(),
+                messages: 0,
+                bytes: 0,
+                failures: 0,
+                last_failure_logged: None,
+                suppressed_failures: 0,
+                last_slow_logged: None,
+                suppressed_slow: 0,
+            }),
+        }
+    }
+
+    /// Count a message the component handled
+    pub fn delivered(&self, bytes: usize) {
+        self.delivered_at(bytes, Instant::now());
+    }
+
+    fn delivered_at(&self, bytes: usize, now: Instant) {
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        window.messages += 1;
+        window.bytes += bytes as u64;
+        self.summarize_if_due(&mut window, now);
+    }
+
+    /// Count a failed delivery, logging it unless one was logged recently
+    pub fn failed(&self, e: &anyhow::Error) {
+        self.failed_at(e, Instant::now());
+    }
+
+    fn failed_at(&self, e: &anyhow::Error, now: Instant) {
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        window.failures += 1;
+        if window
+            .last_failure_logged
+            .is_some_and(|at| now.duration_since(at) < REPORT_INTERVAL)
+        {
+            window.suppressed_failures += 1;
+        } else {
+            error!(
+                suppressed = window.suppressed_failures,
+                "Failed to send message to component {}: {:#} ({} similar failure(s) not logged)",
+                self.source_id,
+                e,
+                window.suppressed_failures
+            );
+            window.last_failure_logged = Some(now);
+            window.suppressed_failures = 0;
+        }
+        self.summarize_if_due(&mut window, now);
+    }
+
+    /// Note a message the component took `latency` to handle, over
+    /// `threshold`, logging it unless one was logged recently
+    pub fn slow(&self, latency: Duration, threshold: Duration) {
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        if window
+            .last_slow_logged
+            .is_some_and(|at| now.duration_since(at) < REPORT_INTERVAL)
+        {
+            window.suppressed_slow += 1;
+            return;
+        }
+        warn!(
+            latency_ms = latency.as_millis() as u64,
+            suppressed = window.suppressed_slow,
+            "Component {} took {:?} to handle a message, over the slow consumer threshold of {:?} ({} more not logged)",
+            self.source_id,
+            latency,
+            threshold,
+            window.suppressed_slow
+        );
+        window.last_slow_logged = Some(now);
+        window.suppressed_slow = 0;
+    }
+
+    /// Log and reset the counts once the interval has passed
+    fn summarize_if_due(&self, window: &mut ReportWindow, now: Instant) {
+        let elapsed = now.saturating_duration_since(window.started);
+        if elapsed < REPORT_INTERVAL {
+            return;
+        }
+        info!(
+            messages = window.messages,
+            bytes = window.bytes,
+            failures = window.failures,
+            "Delivered {} messages / {} to component {} in the last {}s ({} failed)",
+            window.messages,
+            ByteSize(window.bytes),
+            self.source_id,
+            elapsed.as_secs(),
+            window.failures
+        );
+        window.started = now;
+        window.messages = 0;
+        window.bytes = 0;
+        window.failures = 0;
+    }
+}
+
+/// Byte count formatted with a binary unit
+struct ByteSize(u64);
+
+impl std::fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+        let mut size = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while size >= 1024.0 && unit + 1 < UNITS.len() {
+            size /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    /// Log output collected by a test subscriber
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        /// Lines logged so far, leaving none
+        fn take(&self) -> Vec<String> {
+            let logged = std::mem::take(&mut *self.0.lock().unwrap());
+            String::from_utf8(logged)
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    /// Run `f` with everything it logs going to the capture it is given
+    fn capture(f: impl FnOnce(&Captured)) {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .without_time()
+            .finish();
+        tracing::subscriber::with_default(subscriber, || f(&captured));
+    }
+
+    #[test]
+    fn logs_one_summary_per_interval() {
+        capture(|captured| {
+            let report = DeliveryReport::new("comp".into());
+            let start = Instant::now();
+            for i in 0..1000 {
+                report.delivered_at(1024, start + Duration::from_millis(i * 9));
+            }
+            assert!(captured.take().is_empty());
+
+            report.delivered_at(1024, start + REPORT_INTERVAL);
+            let logged = captured.take();
+            assert_eq!(logged.len(), 1);
+            assert!(
+                logged[0].contains(
+                    "Delivered 1001 messages / 1001.0 KiB to component comp in the last 10s (0 failed)"
+                ),
+                "{}",
+                logged[0]
+            );
+
+            // The counts start over from the summary
+            report.delivered_at(10, start + REPORT_INTERVAL + Duration::from_secs(1));
+            assert!(captured.take().is_empty());
+            report.delivered_at(10, start + 2 * REPORT_INTERVAL);
+            let logged = captured.take();
+            assert_eq!(logged.len(), 1);
+            assert!(logged[0].contains("Delivered 2 messages / 20 B"));
+        });
+    }
+
+    #[test]
+    fn repeated_failures_are_logged_once_per_interval() {
+        capture(|captured| {
+            let report = DeliveryReport::new("comp".into());
+            let start = Instant::now();
+            let e = anyhow::anyhow!("timed out");
+            report.failed_at(&e, start);
+            report.failed_at(&e, start + Duration::from_secs(1));
+            report.failed_at(&e, start + Duration::from_secs(2));
+            let logged = captured.take();
+            assert_eq!(logged.len(), 1);
+            assert!(logged[0].contains("ERROR"));
+            assert!(logged[0].contains("(0 similar failure(s) not logged)"));
+
+            report.failed_at(&e, start + REPORT_INTERVAL);
+            let logged = captured.take();
+            // The failure, then the summary counting all of them
+            assert_eq!(logged.len(), 2);
+            assert!(logged[0].contains("timed out (2 similar failure(s) not logged)"));
+            assert!(logged[1].contains("Delivered 0 messages / 0 B"));
+            assert!(logged[1].contains("(4 failed)"));
+        });
+    }
+
+    #[test]
+    fn formats_byte_sizes_in_binary_units() {
+        assert_eq!(ByteSize(0).to_string(), "0 B");
+        assert_eq!(ByteSize(1023).to_string(), "1023 B");
+        assert_eq!(ByteSize(1536).to_string(), "1.5 KiB");
+        assert_eq!(ByteSize(5 * 1024 * 1024).to_string(), "5.0 MiB");
+        assert_eq!(ByteSize(u64::MAX).to_string(), "16777216.0 TiB");
+    }
+}
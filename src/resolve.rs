@@ -0,0 +1,261 @@
+//! Address resolution restricted to one IP family
+//!
+//! A link resolves its host again for every connection attempt, so a tight
+//! reconnect loop against a flapping host would send the resolver the same
+//! query several times a second. A [`ResolutionCache`] keeps the last answer
+//! for `dns_cache_ms`, so attempts within that window reuse it while longer
+//! gaps resolve again.
+
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use tokio::time::Instant;
+use trust_dns_resolver::TokioAsyncResolver;
+
+use crate::config::IpFamily;
+
+/// Resolve `host` to socket addresses of the requested family
+///
+/// IP literals are used as-is when they match the family. Hostnames are
+/// resolved with an A query for [`IpFamily::Ipv4Only`] and an AAAA query for
+/// [`IpFamily::Ipv6Only`]. [`IpFamily::Any`] leaves ordering to the OS
+/// resolver.
+pub async fn resolve(host: &str, port: u16, family: IpFamily) -> anyhow::Result<Vec<SocketAddr>> {
+    let ips: Vec<IpAddr> = if let Ok(ip) = host.parse::<IpAddr>() {
+        vec![ip]
+    } else {
+        match family {
+            IpFamily::Any => {
+                return Ok(tokio::net::lookup_host((host, port)).await?.collect());
+            }
+            IpFamily::Ipv4Only => resolver()?
+                .ipv4_lookup(host)
+                .await?
+                .iter()
+                .map(|a| IpAddr::V4(a.0))
+                .collect(),
+            IpFamily::Ipv6Only => resolver()?
+                .ipv6_lookup(host)
+                .await?
+                .iter()
+                .map(|aaaa| IpAddr::V6(aaaa.0))
+                .collect(),
+        }
+    };
+
+    addresses_of_family(host, ips, port, family)
+}
+
+/// Socket addresses for those of `host`'s `ips` that belong to `family`
+///
+/// Fails if none do, rather than connecting over the other family.
+fn addresses_of_family(
+    host: &str,
+    ips: Vec<IpAddr>,
+    port: u16,
+    family: IpFamily,
+) -> anyhow::Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = ips
+        .into_iter()
+        .filter(|ip| family.allows(ip))
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect();
+    if addrs.is_empty() {
+        anyhow::bail!("No {:?} addresses found for {}", family, host);
+    }
+    Ok(addrs)
+}
+
+/// Resolver using the system's DNS configuration
+fn resolver() -> anyhow::Result<TokioAsyncResolver> {
+    Ok(TokioAsyncResolver::tokio_from_system_conf()?)
+}
+
+/// Last resolution of a link's host, reused for a short while
+///
+/// Only successful resolutions are stored, so a failed lookup is retried by
+/// the next attempt. After an attempt fails because the cached addresses
+/// refused the connection or couldn't be reached, they are only reused
+/// while more than half of the TTL remains, so a host that moved is found
+/// again promptly.
+pub struct ResolutionCache {
+    ttl: Duration,
+    entry: Option<Resolution>,
+}
+
+struct Resolution {
+    addrs: Vec<SocketAddr>,
+    resolved_at: Instant,
+    /// Whether connecting to `addrs` was refused or unreachable
+    unreachable: bool,
+}
+
+impl ResolutionCache {
+    /// Cache answers for `ttl`; nothing is cached with a zero TTL
+    pub fn new(ttl: Duration) -> Self {
+        Self { ttl, entry: None }
+    }
+
+    /// The cached addresses, if they may still be used at `now`
+    pub fn get(&self, now: Instant) -> Option<Vec<SocketAddr>> {
+        let entry = self.entry.as_ref()?;
+        let age = now.saturating_duration_since(entry.resolved_at);
+        let ttl = if entry.unreachable {
+            self.ttl / 2
+        } else {
+            self.ttl
+        };
+        (age < ttl).then(|| entry.addrs.clone())
+    }
+
+    /// Store the addresses resolved at `now`
+    pub fn insert(&mut self, addrs: Vec<SocketAddr>, now: Instant) {
+        if self.ttl.is_zero() {
+            return;
+        }
+        self.entry = Some(Resolution {
+            addrs,
+            resolved_at: now,
+            unreachable: false,
+        });
+    }
+
+    /// Note how connecting to the cached addresses failed
+    pub fn connect_failed(&mut self, error: &io::Error) {
+        let unreachable = matches!(
+            error.kind(),
+            io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::HostUnreachable
+                | io::ErrorKind::NetworkUnreachable
+        );
+        if let Some(entry) = self.entry.as_mut() {
+            entry.unreachable |= unreachable;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An IPv4 and an IPv6 address of one host
+    fn dual_stack() -> Vec<IpAddr> {
+        vec!["192.0.2.1".parse().unwrap(), "2001:db8::1".parse().unwrap()]
+    }
+
+    #[test]
+    fn ipv4_keeps_only_ipv4_addresses() {
+        assert_eq!(
+            addresses_of_family("console", dual_stack(), 23, IpFamily::Ipv4Only).unwrap(),
+            ["192.0.2.1:23".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn ipv6_keeps_only_ipv6_addresses() {
+        assert_eq!(
+            addresses_of_family("console", dual_stack(), 23, IpFamily::Ipv6Only).unwrap(),
+            ["[2001:db8::1]:23".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn any_keeps_every_address_in_order() {
+        let addrs = addresses_of_family("console", dual_stack(), 2323, IpFamily::Any).unwrap();
+        let ips: Vec<IpAddr> = addrs.iter().map(SocketAddr::ip).collect();
+        assert_eq!(ips, dual_stack());
+        assert!(addrs.iter().all(|addr| addr.port() == 2323));
+    }
+
+    #[test]
+    fn no_address_of_the_family_fails() {
+        let ipv6_only = vec!["2001:db8::1".parse().unwrap()];
+        let error = addresses_of_family("console", ipv6_only, 23, IpFamily::Ipv4Only).unwrap_err();
+        assert!(error.to_string().contains("console"), "{}", error);
+        assert!(addresses_of_family("console", Vec::new(), 23, IpFamily::Any).is_err());
+    }
+
+    #[tokio::test]
+    async fn ip_literals_must_match_the_family() {
+        assert_eq!(
+            resolve("192.0.2.1", 23, IpFamily::Ipv4Only).await.unwrap(),
+            ["192.0.2.1:23".parse().unwrap()]
+        );
+        assert_eq!(
+            resolve("::1", 23, IpFamily::Ipv6Only).await.unwrap(),
+            ["[::1]:23".parse().unwrap()]
+        );
+        assert!(resolve("192.0.2.1", 23, IpFamily::Ipv6Only).await.is_err());
+        assert!(resolve("::1", 23, IpFamily::Ipv4Only).await.is_err());
+    }
+
+    const TTL: Duration = Duration::from_secs(10);
+
+    fn addrs() -> Vec<SocketAddr> {
+        vec!["192.0.2.1:23".parse().unwrap()]
+    }
+
+    #[test]
+    fn reuses_an_answer_within_the_ttl() {
+        let mut cache = ResolutionCache::new(TTL);
+        let now = Instant::now();
+        assert_eq!(cache.get(now), None);
+
+        cache.insert(addrs(), now);
+        assert_eq!(cache.get(now), Some(addrs()));
+        assert_eq!(
+            cache.get(now + TTL - Duration::from_millis(1)),
+            Some(addrs())
+        );
+    }
+
+    #[test]
+    fn expires_an_answer_after_the_ttl() {
+        let mut cache = ResolutionCache::new(TTL);
+        let now = Instant::now();
+        cache.insert(addrs(), now);
+        assert_eq!(cache.get(now + TTL), None);
+    }
+
+    #[test]
+    fn zero_ttl_stores_nothing() {
+        let mut cache = ResolutionCache::new(Duration::ZERO);
+        let now = Instant::now();
+        cache.insert(addrs(), now);
+        assert_eq!(cache.get(now), None);
+    }
+
+    #[test]
+    fn refused_connection_halves_the_ttl() {
+        let mut cache = ResolutionCache::new(TTL);
+        let now = Instant::now();
+        cache.insert(addrs(), now);
+        cache.connect_failed(&io::Error::from(io::ErrorKind::ConnectionRefused));
+
+        assert_eq!(
+            cache.get(now + TTL / 2 - Duration::from_millis(1)),
+            Some(addrs())
+        );
+        assert_eq!(cache.get(now + TTL / 2), None);
+    }
+
+    #[test]
+    fn other_failures_keep_the_full_ttl() {
+        let mut cache = ResolutionCache::new(TTL);
+        let now = Instant::now();
+        cache.insert(addrs(), now);
+        cache.connect_failed(&io::Error::from(io::ErrorKind::TimedOut));
+        assert_eq!(cache.get(now + TTL / 2), Some(addrs()));
+    }
+
+    #[test]
+    fn a_new_answer_clears_the_failure() {
+        let mut cache = ResolutionCache::new(TTL);
+        let now = Instant::now();
+        cache.insert(addrs(), now);
+        cache.connect_failed(&io::Error::from(io::ErrorKind::ConnectionRefused));
+        cache.insert(addrs(), now + TTL);
+        assert_eq!(cache.get(now + TTL + TTL / 2), Some(addrs()));
+    }
+}
@@ -0,0 +1,82 @@
+//! Routing of a link's messages to other components by subject
+//!
+//! A feed can carry several kinds of messages (data, gap and quota events,
+//! MSDP variables, unparsed lines), each on its own subject. With
+//! `routing_rules` set, every message's subject is checked against the rules
+//! in configuration order, and the first matching rule decides which
+//! component receives it. Messages matching no rule go to the linked
+//! component as usual.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::client::ComponentClient;
+use crate::transform::subject_matches;
+
+/// Send messages whose subject matches a glob pattern to another component
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutingRule {
+    /// Glob pattern (`*` and `?`) matched against the whole subject
+    pub subject_pattern: String,
+    /// Component receiving matching messages
+    pub target_component_id: String,
+}
+
+/// Parse `pattern=component` rules separated by commas, keeping their order
+pub fn parse_routing_rules(s: &str) -> anyhow::Result<Vec<RoutingRule>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let (pattern, target) = part
+                .split_once('=')
+                .map(|(pattern, target)| (pattern.trim(), target.trim()))
+                .filter(|(pattern, target)| !pattern.is_empty() && !target.is_empty())
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Invalid routing rule (expected <subject pattern>=<component ID>): {}",
+                        part
+                    )
+                })?;
+            Ok(RoutingRule {
+                subject_pattern: pattern.to_string(),
+                target_component_id: target.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Component clients of one link, chosen per message by its routing rules
+pub struct Router {
+    routes: Vec<(String, Arc<ComponentClient>)>,
+    default: Arc<ComponentClient>,
+}
+
+impl Router {
+    /// Route by `rules`, falling back to `default`; rules sharing a target
+    /// share its client, which recovers like `default`
+    pub fn new(rules: &[RoutingRule], default: Arc<ComponentClient>) -> Self {
+        let mut clients: HashMap<&str, Arc<ComponentClient>> = HashMap::new();
+        let routes = rules
+            .iter()
+            .map(|rule| {
+                let client = clients
+                    .entry(&rule.target_component_id)
+                    .or_insert_with(|| {
+                        Arc::new(default.sibling(rule.target_component_id.as_str().into()))
+                    })
+                    .clone();
+                (rule.subject_pattern.clone(), client)
+            })
+            .collect();
+        Self { routes, default }
+    }
+
+    /// Client of the component receiving messages on `subject`
+    pub fn client_for(&self, subject: &str) -> &Arc<ComponentClient> {
+        self.routes
+            .iter()
+            .find(|(pattern, _)| subject_matches(pattern, subject))
+            .map_or(&self.default, |(_, client)| client)
+    }
+}
@@ -0,0 +1,166 @@
+//! Sampling of high-rate feeds
+//!
+//! Some components only need a representative slice of a feed that arrives
+//! far faster than they can use it. With `sample_rate` set, a link forwards
+//! either one in every N messages (`1/<n>`) or the first message of each
+//! time bucket (`1/<n>ms` or `1/<n>s`), and drops the rest, counting them
+//! in `messages_sampled_out`. Count-based sampling is deterministic: the
+//! first message and every Nth one after it are kept. Time buckets start at
+//! the first sampled message and follow each other back to back, so a
+//! bucket with no messages forwards nothing and the next message after a
+//! quiet spell is always kept.
+
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Which messages a link forwards
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleRate {
+    /// One in this many messages
+    Count(u64),
+    /// The first message of each bucket of this length
+    Interval(Duration),
+}
+
+impl FromStr for SampleRate {
+    type Err = anyhow::Error;
+
+    /// Parse `1/<n>` as one in `n` messages, and `1/<n>ms` or `1/<n>s` as
+    /// one message per `n` milliseconds or seconds
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow::anyhow!("Invalid sample_rate: {}", s);
+        let per = s.trim().strip_prefix("1/").ok_or_else(invalid)?;
+        let (number, unit) = match per.find(|c: char| !c.is_ascii_digit()) {
+            Some(split) => per.split_at(split),
+            None => (per, ""),
+        };
+        let n: u64 = number.parse().map_err(|_| invalid())?;
+        if n == 0 {
+            return Err(invalid());
+        }
+        match unit {
+            "" => Ok(SampleRate::Count(n)),
+            "ms" => Ok(SampleRate::Interval(Duration::from_millis(n))),
+            "s" => Ok(SampleRate::Interval(Duration::from_secs(n))),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Decides which messages of a connection are forwarded
+#[derive(Debug)]
+pub struct Sampler {
+    rate: SampleRate,
+    /// Messages seen, for count-based sampling
+    seen: u64,
+    /// Start of the current time bucket, once a message was kept
+    bucket_start: Option<Instant>,
+}
+
+impl Sampler {
+    pub fn new(rate: SampleRate) -> Self {
+        Self {
+            rate,
+            seen: 0,
+            bucket_start: None,
+        }
+    }
+
+    /// Whether the message received at `now` is forwarded
+    pub fn keep(&mut self, now: Instant) -> bool {
+        match self.rate {
+            SampleRate::Count(n) => {
+                let keep = self.seen.is_multiple_of(n);
+                self.seen = self.seen.wrapping_add(1);
+                keep
+            }
+            SampleRate::Interval(interval) => {
+                let start = match self.bucket_start {
+                    None => now,
+                    Some(start) if now.saturating_duration_since(start) < interval => {
+                        return false;
+                    }
+                    // Start of the bucket `now` falls in
+                    Some(start) => {
+                        let elapsed = now.saturating_duration_since(start).as_nanos();
+                        let into_bucket = elapsed % interval.as_nanos();
+                        now - Duration::from_nanos(into_bucket as u64)
+                    }
+                };
+                self.bucket_start = Some(start);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Indices of the kept messages among `count`, received `gap` apart
+    fn kept(rate: &str, count: u64, gap: Duration) -> Vec<u64> {
+        let mut sampler = Sampler::new(rate.parse().unwrap());
+        let start = Instant::now();
+        (0..count)
+            .filter(|&i| sampler.keep(start + gap * i as u32))
+            .collect()
+    }
+
+    #[test]
+    fn parses_rates() {
+        assert_eq!(
+            "1/100".parse::<SampleRate>().unwrap(),
+            SampleRate::Count(100)
+        );
+        assert_eq!(
+            "1/500ms".parse::<SampleRate>().unwrap(),
+            SampleRate::Interval(Duration::from_millis(500))
+        );
+        assert_eq!(
+            "1/2s".parse::<SampleRate>().unwrap(),
+            SampleRate::Interval(Duration::from_secs(2))
+        );
+        for invalid in ["100", "1/0", "1/", "2/10", "1/10m", "1/x"] {
+            assert!(invalid.parse::<SampleRate>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn keeps_one_in_n() {
+        assert_eq!(kept("1/3", 10, Duration::ZERO), vec![0, 3, 6, 9]);
+    }
+
+    #[test]
+    fn count_sampling_is_deterministic() {
+        let gap = Duration::from_millis(7);
+        assert_eq!(kept("1/4", 100, gap), kept("1/4", 100, gap));
+    }
+
+    #[test]
+    fn one_in_one_keeps_everything() {
+        assert_eq!(kept("1/1", 5, Duration::ZERO), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn keeps_the_first_message_of_each_bucket() {
+        // Messages every 30ms, buckets of 100ms from the first message
+        assert_eq!(
+            kept("1/100ms", 10, Duration::from_millis(30)),
+            vec![0, 4, 7]
+        );
+    }
+
+    #[test]
+    fn buckets_stay_aligned_after_a_quiet_spell() {
+        let mut sampler = Sampler::new(SampleRate::Interval(Duration::from_millis(100)));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert!(sampler.keep(at(0)));
+        // Nothing for three buckets; the next message is kept
+        assert!(sampler.keep(at(350)));
+        // Still in the bucket starting at 300ms
+        assert!(!sampler.keep(at(399)));
+        assert!(sampler.keep(at(400)));
+    }
+}
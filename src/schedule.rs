@@ -0,0 +1,169 @@
+//! Daily time windows in UTC
+
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// One daily window, `start` inclusive and `end` exclusive, in seconds after
+/// midnight UTC; `end < start` wraps past midnight
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Window {
+    start: u64,
+    end: u64,
+}
+
+impl Window {
+    /// Time left in this window at `now` (seconds after midnight), if inside it
+    fn remaining(&self, now: u64) -> Option<u64> {
+        if self.start < self.end {
+            (self.start..self.end)
+                .contains(&now)
+                .then(|| self.end - now)
+        } else if now >= self.start {
+            Some(SECS_PER_DAY - now + self.end)
+        } else if now < self.end {
+            Some(self.end - now)
+        } else {
+            None
+        }
+    }
+}
+
+/// Set of daily windows, e.g. `02:00-04:00,23:30-00:15`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DailySchedule {
+    windows: Vec<Window>,
+}
+
+impl DailySchedule {
+    /// Time until the current window ends, or `None` outside every window
+    pub fn remaining_at(&self, now: SystemTime) -> Option<Duration> {
+        let secs = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() % SECS_PER_DAY;
+        self.windows
+            .iter()
+            .filter_map(|window| window.remaining(secs))
+            .max()
+            .map(Duration::from_secs)
+    }
+
+    /// Time until the current window ends, or `None` outside every window
+    pub fn remaining(&self) -> Option<Duration> {
+        self.remaining_at(SystemTime::now())
+    }
+}
+
+impl FromStr for DailySchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let windows = s
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .map(|part| {
+                let (start, end) = part
+                    .split_once('-')
+                    .ok_or_else(|| anyhow::anyhow!("Invalid time window: {}", part))?;
+                let window = Window {
+                    start: parse_time(start)?,
+                    end: parse_time(end)?,
+                };
+                if window.start == window.end {
+                    anyhow::bail!("Empty time window: {}", part);
+                }
+                Ok(window)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if windows.is_empty() {
+            anyhow::bail!("No time windows in schedule: {}", s);
+        }
+        Ok(Self { windows })
+    }
+}
+
+/// Parse `HH:MM` into seconds after midnight
+fn parse_time(s: &str) -> anyhow::Result<u64> {
+    let invalid = || anyhow::anyhow!("Invalid time of day (expected HH:MM): {}", s);
+    let (hours, minutes) = s.trim().split_once(':').ok_or_else(invalid)?;
+    let hours: u64 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u64 = minutes.parse().map_err(|_| invalid())?;
+    if hours > 23 || minutes > 59 {
+        return Err(invalid());
+    }
+    Ok((hours * 60 + minutes) * 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `HH:MM` on some day, as a point in time
+    fn at(hours: u64, minutes: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(20_000 * SECS_PER_DAY + (hours * 60 + minutes) * 60)
+    }
+
+    fn schedule(s: &str) -> DailySchedule {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn inside_a_window_reports_the_time_left() {
+        let schedule = schedule("02:00-04:00");
+        assert_eq!(
+            schedule.remaining_at(at(2, 0)),
+            Some(Duration::from_secs(2 * 60 * 60))
+        );
+        assert_eq!(
+            schedule.remaining_at(at(3, 30)),
+            Some(Duration::from_secs(30 * 60))
+        );
+    }
+
+    #[test]
+    fn the_end_is_outside_the_window() {
+        let schedule = schedule("02:00-04:00");
+        assert_eq!(schedule.remaining_at(at(1, 59)), None);
+        assert_eq!(schedule.remaining_at(at(4, 0)), None);
+    }
+
+    #[test]
+    fn windows_wrap_past_midnight() {
+        let schedule = schedule("23:30-00:15");
+        assert_eq!(
+            schedule.remaining_at(at(23, 45)),
+            Some(Duration::from_secs(30 * 60))
+        );
+        assert_eq!(
+            schedule.remaining_at(at(0, 5)),
+            Some(Duration::from_secs(10 * 60))
+        );
+        assert_eq!(schedule.remaining_at(at(0, 15)), None);
+        assert_eq!(schedule.remaining_at(at(12, 0)), None);
+    }
+
+    #[test]
+    fn overlapping_windows_report_the_longest_time_left() {
+        let schedule = schedule("02:00-03:00, 02:30-04:00");
+        assert_eq!(
+            schedule.remaining_at(at(2, 45)),
+            Some(Duration::from_secs(75 * 60))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_schedules() {
+        for invalid in [
+            "",
+            " , ",
+            "02:00",
+            "02:00-02:00",
+            "24:00-01:00",
+            "02:60-03:00",
+            "2-3",
+            "aa:bb-cc:dd",
+        ] {
+            assert!(invalid.parse::<DailySchedule>().is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! Startup self-test of a link's processing pipeline
+//!
+//! With `self_test` set in the provider config, every link's pipeline is run
+//! against built-in samples before the link connects, and the link is
+//! rejected if a sample doesn't come out as expected. This catches settings
+//! that are valid on their own but don't work together, such as framing
+//! bytes that mangle the data, before any real traffic depends on them.
+//!
+//! The samples go through a forwarder of their own, so the link's
+//! statistics, quotas and dedupe state are untouched and nothing is sent to
+//! a component. Each check is logged; together they take a few
+//! milliseconds.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::config::LinkConfig;
+use crate::context::ConnectionContext;
+use crate::envelope::EnvelopeFormat;
+use crate::forward::Forwarder;
+use crate::iac::filter_telnet_commands;
+use crate::provider::types;
+
+/// Data interleaved with negotiation (`IAC DO`, a sub-negotiation, an
+/// escaped 0xFF and a trailing `IAC NOP`)
+const IAC_SAMPLE: &[u8] = b"be\xff\xfd\x18fo\xff\xfa\x18\x01\xff\xf0re\xff\xff after\xff\xf1";
+
+/// What the IAC sample must be filtered to
+const IAC_EXPECTED: &[u8] = b"before\xff after";
+
+/// Multibyte UTF-8 text, as a JSON record so that JSON-only stages (NDJSON,
+/// delta records, dedupe keys) accept it too
+const TEXT_SAMPLE: &str = r#"{"msg":"température 21°C ✓ naïve 東京"}"#;
+
+/// Largest `max_message_size` the oversize check builds a record for
+const OVERSIZE_SAMPLE_LIMIT: usize = 16 * 1024 * 1024;
+
+/// A check of a link's pipeline, given its source ID and config
+type SelfCheck = fn(&str, &LinkConfig) -> Check;
+
+/// Run the self-test for `source_id`'s link, failing with every check that
+/// didn't pass
+pub fn run(source_id: &str, config: &LinkConfig) -> anyhow::Result<()> {
+    let checks: [(&str, SelfCheck); 4] = [
+        ("iac", check_iac),
+        ("max_message_size", check_max_message_size),
+        ("multibyte", check_multibyte),
+        ("oversize", check_oversize),
+    ];
+
+    let mut failures = Vec::new();
+    for (name, check) in checks {
+        match check(source_id, config) {
+            Check::Passed => info!("Self-test {} passed for component {}", name, source_id),
+            Check::Skipped(reason) => info!(
+                "Self-test {} skipped for component {}: {}",
+                name, source_id, reason
+            ),
+            Check::Failed(reason) => failures.push(format!("{}: {}", name, reason)),
+        }
+    }
+
+    if !failures.is_empty() {
+        anyhow::bail!(
+            "Self-test failed for component {}: {}",
+            source_id,
+            failures.join("; ")
+        );
+    }
+    Ok(())
+}
+
+/// Outcome of one check
+enum Check {
+    Passed,
+    Skipped(&'static str),
+    Failed(String),
+}
+
+/// Negotiation is removed and escaped 0xFF bytes are kept
+fn check_iac(_source_id: &str, _config: &LinkConfig) -> Check {
+    let filtered = filter_telnet_commands(IAC_SAMPLE);
+    if filtered == IAC_EXPECTED {
+        Check::Passed
+    } else {
+        Check::Failed(format!(
+            "filtered to {:?}, expected {:?}",
+            filtered.escape_ascii().to_string(),
+            IAC_EXPECTED.escape_ascii().to_string()
+        ))
+    }
+}
+
+/// A short record fits in a message
+fn check_max_message_size(_source_id: &str, config: &LinkConfig) -> Check {
+    if config.max_message_size >= TEXT_SAMPLE.len() {
+        Check::Passed
+    } else {
+        Check::Failed(format!(
+            "max_message_size {} drops even a {}-byte record",
+            config.max_message_size,
+            TEXT_SAMPLE.len()
+        ))
+    }
+}
+
+/// Multibyte text comes out intact (or as a valid envelope)
+fn check_multibyte(source_id: &str, config: &LinkConfig) -> Check {
+    if !config.body_required_keywords.is_empty() || !config.body_blocked_keywords.is_empty() {
+        return Check::Skipped("keyword filters may drop the sample");
+    }
+    if config.aggregate_numeric.is_some() {
+        return Check::Skipped("numeric aggregation holds readings for its window");
+    }
+
+    let messages = match forward(source_id, config, frame(config, TEXT_SAMPLE.as_bytes())) {
+        Ok(messages) => messages,
+        Err(e) => return Check::Failed(format!("forwarding failed: {:#}", e)),
+    };
+    if messages.is_empty() {
+        return Check::Failed("the sample produced no message".to_string());
+    }
+
+    for message in &messages {
+        let body = strip_header_lines(&message.body);
+        let intact = match config
+            .envelope_format
+            .filter(|_| config.protocol_version >= 2)
+        {
+            None => body
+                .windows(TEXT_SAMPLE.len())
+                .any(|window| window == TEXT_SAMPLE.as_bytes()),
+            Some(EnvelopeFormat::Json) => body
+                .split(|&byte| byte == b'\n')
+                .filter(|line| !line.is_empty())
+                .all(|line| serde_json::from_slice::<serde_json::Value>(line).is_ok()),
+            Some(_) => !body.is_empty(),
+        };
+        if !intact {
+            return Check::Failed(format!(
+                "the sample came out on {} as {:?}",
+                message.subject,
+                String::from_utf8_lossy(body)
+            ));
+        }
+    }
+    Check::Passed
+}
+
+/// A record over `max_message_size` is dropped by the de-framer
+///
+/// Without framing, oversize reads are dropped by the read loop itself,
+/// which doesn't depend on the pipeline's settings.
+fn check_oversize(source_id: &str, config: &LinkConfig) -> Check {
+    if config.unstuff.is_none() {
+        return Check::Skipped("without framing, oversize reads are dropped before the pipeline");
+    }
+    if config.max_message_size >= OVERSIZE_SAMPLE_LIMIT {
+        return Check::Skipped("max_message_size is too large to build a sample for");
+    }
+    let record = vec![b'x'; config.max_message_size.saturating_add(1)];
+    match forward(source_id, config, frame(config, &record)) {
+        Ok(messages) if messages.is_empty() => Check::Passed,
+        Ok(messages) => Check::Failed(format!(
+            "an oversize record produced {} message(s)",
+            messages.len()
+        )),
+        Err(e) => Check::Failed(format!("forwarding failed: {:#}", e)),
+    }
+}
+
+/// `record` as the server would send it, framed if the link de-frames
+fn frame(config: &LinkConfig, record: &[u8]) -> Vec<u8> {
+    match &config.unstuff {
+        Some(unstuff) => unstuff.stuff(record),
+        None => record.to_vec(),
+    }
+}
+
+/// Run `data` through a fresh forwarder built from the link's config
+///
+/// Settings that make a link hold back data for reasons other than its
+/// content (standby group membership, quotas) are left out.
+fn forward(
+    source_id: &str,
+    config: &LinkConfig,
+    data: Vec<u8>,
+) -> anyhow::Result<Vec<types::BrokerMessage>> {
+    let mut config = config.clone();
+    config.connection_group = None;
+    config.lifetime_byte_quota = None;
+    config.max_messages = None;
+
+    let context = ConnectionContext::new(source_id, &config.address(), &config.subject(source_id));
+    let protocol_version = config.protocol_version;
+    let mut forwarder = Forwarder::new(
+        context,
+        Arc::new(config),
+        protocol_version,
+        Arc::default(),
+        CancellationToken::new(),
+        Arc::default(),
+        Arc::default(),
+    );
+    Ok(forwarder
+        .forward(data.into(), Instant::now())?
+        .into_messages())
+}
+
+/// `body` without its leading `x-telnet-*` header lines
+fn strip_header_lines(mut body: &[u8]) -> &[u8] {
+    while body.starts_with(b"x-telnet-") {
+        match body.windows(2).position(|pair| pair == b"\r\n") {
+            Some(end) => body = &body[end + 2..],
+            None => break,
+        }
+    }
+    body
+}
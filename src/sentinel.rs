@@ -0,0 +1,45 @@
+//! Detection of a server's "please reconnect" sentinel in the data stream
+
+/// Finds a byte sequence in a stream of reads, including across read
+/// boundaries
+pub struct SentinelMatcher {
+    pattern: Vec<u8>,
+    /// End of the previous reads that could begin a match
+    tail: Vec<u8>,
+}
+
+impl SentinelMatcher {
+    pub fn new(pattern: &[u8]) -> Self {
+        Self {
+            pattern: pattern.to_vec(),
+            tail: Vec::new(),
+        }
+    }
+
+    /// Feed the next read, returning where the sentinel lies in `data` if it
+    /// completes there
+    ///
+    /// The range starts at 0 when the sentinel began in an earlier read.
+    pub fn find(&mut self, data: &[u8]) -> Option<std::ops::Range<usize>> {
+        if self.pattern.is_empty() {
+            return None;
+        }
+
+        let carried = self.tail.len();
+        let mut combined = std::mem::take(&mut self.tail);
+        combined.extend_from_slice(data);
+
+        if let Some(pos) = combined
+            .windows(self.pattern.len())
+            .position(|window| window == self.pattern.as_slice())
+        {
+            let end = pos + self.pattern.len() - carried;
+            return Some(pos.saturating_sub(carried)..end);
+        }
+
+        let keep = (self.pattern.len() - 1).min(combined.len());
+        combined.drain(..combined.len() - keep);
+        self.tail = combined;
+        None
+    }
+}
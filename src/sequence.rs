@@ -0,0 +1,150 @@
+//! Detection of lost messages via server-assigned sequence numbers
+//!
+//! Servers that number their records put an `x-telnet-seq: <n>` header line
+//! at the start of each one. A jump of more than one between consecutive
+//! records means messages were lost on the way.
+
+/// Name of the header line carrying a record's sequence number
+pub const SEQUENCE_HEADER: &str = "x-telnet-seq";
+
+/// A run of missing sequence numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceGap {
+    /// Sequence number that should have arrived next
+    pub expected: u64,
+    /// Sequence number that actually arrived
+    pub received: u64,
+    /// Number of missing messages
+    pub gap: u64,
+}
+
+/// Tracks the last sequence number seen on a connection
+#[derive(Debug, Default)]
+pub struct SequenceGapDetector {
+    last_seq: Option<u64>,
+    gap_count: u64,
+}
+
+impl SequenceGapDetector {
+    /// Record `current`, returning the gap before it if any
+    ///
+    /// A number at or below the previous one (a server restart) is taken
+    /// as the new starting point without reporting a gap.
+    pub fn observe(&mut self, current: u64) -> Option<SequenceGap> {
+        let last = self.last_seq.replace(current)?;
+        if current <= last.saturating_add(1) {
+            return None;
+        }
+
+        self.gap_count += 1;
+        Some(SequenceGap {
+            expected: last + 1,
+            received: current,
+            gap: current - last - 1,
+        })
+    }
+
+    /// Number of gaps detected so far
+    pub fn gap_count(&self) -> u64 {
+        self.gap_count
+    }
+}
+
+/// Extract the sequence number from a record's leading header line
+pub fn extract_sequence(data: &[u8]) -> Option<u64> {
+    let end = data.iter().position(|b| *b == b'\n').unwrap_or(data.len());
+    let line = std::str::from_utf8(&data[..end])
+        .ok()?
+        .trim_end_matches('\r');
+    let (name, value) = line.split_once(':')?;
+    if !name.trim().eq_ignore_ascii_case(SEQUENCE_HEADER) {
+        return None;
+    }
+    value.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consecutive_numbers_have_no_gap() {
+        let mut detector = SequenceGapDetector::default();
+        for seq in 1..=5 {
+            assert_eq!(detector.observe(seq), None);
+        }
+        assert_eq!(detector.gap_count(), 0);
+    }
+
+    #[test]
+    fn reports_missing_numbers() {
+        let mut detector = SequenceGapDetector::default();
+        detector.observe(1);
+        assert_eq!(
+            detector.observe(5),
+            Some(SequenceGap {
+                expected: 2,
+                received: 5,
+                gap: 3,
+            })
+        );
+        assert_eq!(detector.observe(6), None);
+        assert_eq!(
+            detector.observe(8),
+            Some(SequenceGap {
+                expected: 7,
+                received: 8,
+                gap: 1,
+            })
+        );
+        assert_eq!(detector.gap_count(), 2);
+    }
+
+    #[test]
+    fn the_first_number_is_a_starting_point() {
+        let mut detector = SequenceGapDetector::default();
+        assert_eq!(detector.observe(1000), None);
+        assert_eq!(detector.observe(1001), None);
+    }
+
+    #[test]
+    fn a_restart_is_not_a_gap() {
+        let mut detector = SequenceGapDetector::default();
+        detector.observe(41);
+        detector.observe(42);
+        // Server restarted and counts from 1 again
+        assert_eq!(detector.observe(1), None);
+        assert_eq!(detector.observe(1), None);
+        assert_eq!(detector.observe(2), None);
+        assert_eq!(detector.gap_count(), 0);
+    }
+
+    #[test]
+    fn the_largest_number_does_not_overflow() {
+        let mut detector = SequenceGapDetector::default();
+        detector.observe(u64::MAX);
+        assert_eq!(detector.observe(u64::MAX), None);
+    }
+
+    #[test]
+    fn extracts_the_header_of_the_first_line() {
+        assert_eq!(extract_sequence(b"x-telnet-seq: 17\r\nbody"), Some(17));
+        assert_eq!(extract_sequence(b"X-Telnet-Seq:42"), Some(42));
+        assert_eq!(extract_sequence(b"  x-telnet-seq :  7  \nrest"), Some(7));
+    }
+
+    #[test]
+    fn ignores_records_without_the_header() {
+        for data in [
+            &b""[..],
+            b"body\nx-telnet-seq: 1",
+            b"x-telnet-seq 1",
+            b"x-other-seq: 1",
+            b"x-telnet-seq: -1",
+            b"x-telnet-seq: one",
+            b"x-telnet-seq: \xff",
+        ] {
+            assert_eq!(extract_sequence(data), None, "{:?}", data);
+        }
+    }
+}
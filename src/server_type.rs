@@ -0,0 +1,124 @@
+//! Recognising the kind of Telnet server from its banner
+//!
+//! Network devices, Unix hosts, MUDs and BBSes each want a few settings of
+//! their own, such as the login prompt to wait for. With
+//! `auto_detect_server_type` set, the provider reads the server's banner
+//! when the link is created and matches it against a few patterns per kind
+//! of server. The kind with the most matching patterns wins, and its
+//! [`ConfigPreset`] fills in link config keys the link leaves unset.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+/// Kind of server behind a link
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ServerType {
+    /// Cisco IOS and similar network devices
+    Cisco,
+    /// Linux or other Unix login
+    Linux,
+    /// Multi-user dungeon
+    Mud,
+    /// Bulletin board system
+    Bbs,
+    /// Nothing recognisable in the banner
+    #[default]
+    Unknown,
+}
+
+/// Patterns per kind of server, in the order ties are decided
+const PATTERNS: &[(ServerType, &str)] = &[
+    (ServerType::Cisco, r"User Access Verification"),
+    (ServerType::Cisco, r"(?m)^[\w.-]+#\s*$"),
+    (ServerType::Linux, r"(?mi)login:\s*$"),
+    (ServerType::Linux, r"(?m)\$\s*$"),
+    (ServerType::Bbs, r"(?i)\bsysop\b"),
+    (ServerType::Bbs, r"\bANSI\b"),
+    (ServerType::Mud, r"(?i)\bwelcome to\b"),
+    (ServerType::Mud, r"\bANSI\b"),
+];
+
+fn patterns() -> &'static [(ServerType, &'static str, Regex)] {
+    static COMPILED: OnceLock<Vec<(ServerType, &'static str, Regex)>> = OnceLock::new();
+    COMPILED.get_or_init(|| {
+        PATTERNS
+            .iter()
+            .map(|&(server_type, pattern)| {
+                let regex = Regex::new(pattern).expect("server type patterns are valid");
+                (server_type, pattern, regex)
+            })
+            .collect()
+    })
+}
+
+impl ServerType {
+    /// Stable lowercase name, used in logs
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServerType::Cisco => "cisco",
+            ServerType::Linux => "linux",
+            ServerType::Mud => "mud",
+            ServerType::Bbs => "bbs",
+            ServerType::Unknown => "unknown",
+        }
+    }
+
+    /// The kind of server that sent `banner`, with the patterns that
+    /// matched it
+    pub fn detect(banner: &[u8]) -> (ServerType, Vec<&'static str>) {
+        let text = String::from_utf8_lossy(banner);
+        let mut best = (ServerType::Unknown, Vec::new());
+        for candidate in [
+            ServerType::Cisco,
+            ServerType::Linux,
+            ServerType::Bbs,
+            ServerType::Mud,
+        ] {
+            let matched: Vec<_> = patterns()
+                .iter()
+                .filter(|(server_type, _, regex)| {
+                    *server_type == candidate && regex.is_match(&text)
+                })
+                .map(|(_, pattern, _)| *pattern)
+                .collect();
+            // Earlier candidates win ties
+            if matched.len() > best.1.len() {
+                best = (candidate, matched);
+            }
+        }
+        best
+    }
+
+    /// Settings for this kind of server
+    pub fn preset(&self) -> ConfigPreset {
+        let values: &'static [(&'static str, &'static str)] = match self {
+            ServerType::Cisco => &[("login_prompt", "username:")],
+            ServerType::Mud => &[("msdp_enabled", "true")],
+            ServerType::Bbs => &[("tokenize_controls", "true")],
+            ServerType::Linux | ServerType::Unknown => &[],
+        };
+        ConfigPreset { values }
+    }
+}
+
+/// Link config values suited to a kind of server
+pub struct ConfigPreset {
+    values: &'static [(&'static str, &'static str)],
+}
+
+impl ConfigPreset {
+    /// Add the preset's values to `config` where it doesn't set them,
+    /// returning the keys that were added
+    pub fn apply(&self, config: &mut HashMap<String, String>) -> Vec<&'static str> {
+        let mut applied = Vec::new();
+        for &(key, value) in self.values {
+            if !config.contains_key(key) {
+                config.insert(key.to_string(), value.to_string());
+                applied.push(key);
+            }
+        }
+        applied
+    }
+}
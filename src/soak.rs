@@ -0,0 +1,336 @@
+//! Soak test mode for tracking memory across reconnect cycles
+//!
+//! Runs a number of Telnet clients against an in-process mock server that
+//! drops every connection after a short interval, forcing continuous
+//! reconnects. Process RSS, live tokio task count, and the links' own
+//! counters are sampled periodically and written as CSV. The run fails if
+//! RSS grows by more than the allowed threshold after the warmup period.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::Context as _;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use crate::config::LinkConfig;
+use crate::instrument::spawn_named;
+use crate::stats::ConnectionStats;
+use crate::telnet::TelnetClient;
+
+/// Soak test parameters, parsed from `--soak` command line flags
+#[derive(Debug, Clone)]
+pub struct SoakOptions {
+    /// Number of concurrent links
+    pub links: usize,
+    /// Total run time
+    pub duration: Duration,
+    /// Time excluded from the growth check while allocations settle
+    pub warmup: Duration,
+    /// Interval between samples
+    pub sample_interval: Duration,
+    /// How long the mock server keeps each connection open
+    pub flap_interval: Duration,
+    /// Maximum allowed RSS growth after warmup, in KiB
+    pub max_growth_kib: u64,
+    /// Path of the CSV report
+    pub output: String,
+}
+
+impl Default for SoakOptions {
+    fn default() -> Self {
+        Self {
+            links: 20,
+            duration: Duration::from_secs(300),
+            warmup: Duration::from_secs(30),
+            sample_interval: Duration::from_secs(5),
+            flap_interval: Duration::from_millis(500),
+            max_growth_kib: 10 * 1024,
+            output: "soak_report.csv".to_string(),
+        }
+    }
+}
+
+impl SoakOptions {
+    /// Parse `--flag value` pairs following `--soak`
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("Missing value for {}", flag))?;
+            let secs = || value.parse().map(Duration::from_secs);
+            match flag.as_str() {
+                "--links" => options.links = value.parse()?,
+                "--duration-secs" => options.duration = secs()?,
+                "--warmup-secs" => options.warmup = secs()?,
+                "--sample-secs" => options.sample_interval = secs()?,
+                "--flap-ms" => options.flap_interval = Duration::from_millis(value.parse()?),
+                "--max-growth-kib" => options.max_growth_kib = value.parse()?,
+                "--output" => options.output = value,
+                other => anyhow::bail!("Unknown soak option: {}", other),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Run the soak test, returning an error if memory growth exceeds the threshold
+pub async fn run(options: SoakOptions) -> anyhow::Result<()> {
+    info!(?options, "starting soak test");
+
+    let cancel = CancellationToken::new();
+    let port = start_flapping_server(options.flap_interval, cancel.clone()).await?;
+
+    let messages = Arc::new(AtomicU64::new(0));
+    let mut link_stats = Vec::with_capacity(options.links);
+    let config = Arc::new(LinkConfig::from_values(&HashMap::from([
+        ("telnet_host".to_string(), "127.0.0.1".to_string()),
+        ("telnet_port".to_string(), port.to_string()),
+        ("initial_reconnect_delay_ms".to_string(), "50".to_string()),
+        ("max_reconnect_delay_ms".to_string(), "200".to_string()),
+    ]))?);
+    for i in 0..options.links {
+        let stats = Arc::new(ConnectionStats::default());
+        link_stats.push(stats.clone());
+
+        let client = TelnetClient::new(config.clone(), stats.clone());
+        let link_cancel = cancel.clone();
+        let link_messages = messages.clone();
+        spawn_named(&format!("soak-{}", i), "reader", async move {
+            let result = client
+                .run(link_cancel, move |data, _received| {
+                    stats.try_forward(data.len() as u64, None);
+                    link_messages.fetch_add(1, Ordering::Relaxed);
+                    std::future::ready(Ok(()))
+                })
+                .await;
+            if let Err(e) = result {
+                error!("Soak link stopped: {}", e);
+            }
+        });
+    }
+
+    let mut report = String::from("elapsed_secs,rss_kib,alive_tasks,messages,bytes_forwarded\n");
+    let started = Instant::now();
+    let mut baseline_rss = None;
+    let mut last_rss = 0;
+    while started.elapsed() < options.duration {
+        sleep(options.sample_interval).await;
+
+        let elapsed = started.elapsed();
+        let rss = rss_kib().unwrap_or_default();
+        let alive_tasks = tokio::runtime::Handle::current()
+            .metrics()
+            .num_alive_tasks();
+        let bytes: u64 = link_stats.iter().map(|s| s.bytes_forwarded()).sum();
+        report.push_str(&format!(
+            "{},{},{},{},{}\n",
+            elapsed.as_secs(),
+            rss,
+            alive_tasks,
+            messages.load(Ordering::Relaxed),
+            bytes
+        ));
+        info!(
+            elapsed_secs = elapsed.as_secs(),
+            rss_kib = rss,
+            alive_tasks,
+            "soak sample"
+        );
+
+        if elapsed >= options.warmup && baseline_rss.is_none() {
+            baseline_rss = Some(rss);
+        }
+        last_rss = rss;
+    }
+
+    cancel.cancel();
+    tokio::fs::write(&options.output, report)
+        .await
+        .with_context(|| format!("failed to write soak report to {}", options.output))?;
+    info!("Soak report written to {}", options.output);
+
+    let growth = last_rss.saturating_sub(baseline_rss.unwrap_or(last_rss));
+    if growth > options.max_growth_kib {
+        anyhow::bail!(
+            "RSS grew by {} KiB after warmup (limit {} KiB)",
+            growth,
+            options.max_growth_kib
+        );
+    }
+    info!("Soak test passed: RSS grew by {} KiB after warmup", growth);
+    Ok(())
+}
+
+/// Start a mock Telnet server that sends lines and drops each connection
+/// after `flap_interval`, returning the port it listens on
+async fn start_flapping_server(
+    flap_interval: Duration,
+    cancel: CancellationToken,
+) -> anyhow::Result<u16> {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .context("failed to bind soak mock server")?;
+    let port = listener.local_addr()?.port();
+
+    spawn_named("soak-server", "listener", async move {
+        loop {
+            let mut stream = tokio::select! {
+                _ = cancel.cancelled() => return,
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        error!("Soak mock server accept failed: {}", e);
+                        continue;
+                    }
+                },
+            };
+
+            spawn_named("soak-server", "connection", async move {
+                // Negotiation first so the IAC filter is exercised every cycle
+                let _ = stream
+                    .write_all(&[0xFF, 0xFB, 0x01, 0xFF, 0xFD, 0x03])
+                    .await;
+                let deadline = Instant::now() + flap_interval;
+                let mut line = 0u64;
+                while Instant::now() < deadline {
+                    line += 1;
+                    let payload = format!("soak line {}\r\n", line);
+                    if stream.write_all(payload.as_bytes()).await.is_err() {
+                        return;
+                    }
+                    sleep(Duration::from_millis(10)).await;
+                }
+            });
+        }
+    });
+
+    Ok(port)
+}
+
+/// Resident set size of this process in KiB (Linux only)
+fn rss_kib() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpStream;
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn options_override_the_defaults() {
+        let options = SoakOptions::from_args(args(&[
+            "--links",
+            "3",
+            "--duration-secs",
+            "60",
+            "--warmup-secs",
+            "10",
+            "--sample-secs",
+            "2",
+            "--flap-ms",
+            "250",
+            "--max-growth-kib",
+            "512",
+            "--output",
+            "out.csv",
+        ]))
+        .unwrap();
+        assert_eq!(options.links, 3);
+        assert_eq!(options.duration, Duration::from_secs(60));
+        assert_eq!(options.warmup, Duration::from_secs(10));
+        assert_eq!(options.sample_interval, Duration::from_secs(2));
+        assert_eq!(options.flap_interval, Duration::from_millis(250));
+        assert_eq!(options.max_growth_kib, 512);
+        assert_eq!(options.output, "out.csv");
+
+        let defaults = SoakOptions::from_args(args(&[])).unwrap();
+        assert_eq!(defaults.links, 20);
+        assert_eq!(defaults.duration, Duration::from_secs(300));
+    }
+
+    #[test]
+    fn bad_options_are_rejected() {
+        assert!(SoakOptions::from_args(args(&["--links"])).is_err());
+        assert!(SoakOptions::from_args(args(&["--links", "many"])).is_err());
+        assert!(SoakOptions::from_args(args(&["--speed", "fast"])).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reads_rss_of_this_process() {
+        assert!(rss_kib().is_some_and(|rss| rss > 0));
+    }
+
+    #[tokio::test]
+    async fn mock_server_negotiates_sends_lines_and_drops_the_connection() {
+        let cancel = CancellationToken::new();
+        let port = start_flapping_server(Duration::from_millis(100), cancel.clone())
+            .await
+            .unwrap();
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut received = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut received))
+            .await
+            .expect("connection was not dropped")
+            .unwrap();
+        assert!(received.starts_with(&[0xFF, 0xFB, 0x01, 0xFF, 0xFD, 0x03, b's']));
+        assert!(received.ends_with(b"\r\n"));
+        let text = String::from_utf8_lossy(&received[6..]);
+        assert!(text.starts_with("soak line 1\r\nsoak line 2\r\n"));
+
+        // Once cancelled it stops accepting
+        cancel.cancel();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(TcpStream::connect(("127.0.0.1", port)).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn short_run_writes_a_sample_per_interval() {
+        let output =
+            std::env::temp_dir().join(format!("telnet-provider-soak-{}.csv", std::process::id()));
+        let options = SoakOptions {
+            links: 2,
+            duration: Duration::from_millis(900),
+            warmup: Duration::ZERO,
+            sample_interval: Duration::from_millis(300),
+            flap_interval: Duration::from_millis(100),
+            output: output.to_str().unwrap().to_string(),
+            ..SoakOptions::default()
+        };
+        run(options).await.unwrap();
+
+        let report = std::fs::read_to_string(&output).unwrap();
+        std::fs::remove_file(&output).unwrap();
+        let mut lines = report.lines();
+        assert_eq!(
+            lines.next(),
+            Some("elapsed_secs,rss_kib,alive_tasks,messages,bytes_forwarded")
+        );
+        let samples: Vec<Vec<u64>> = lines
+            .map(|line| line.split(',').map(|n| n.parse().unwrap()).collect())
+            .collect();
+        assert_eq!(samples.len(), 3);
+        let last = samples.last().unwrap();
+        assert!(last[3] > 0, "no messages were received");
+        assert!(last[4] > 0, "no bytes were forwarded");
+    }
+}
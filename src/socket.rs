@@ -0,0 +1,247 @@
+//! Socket layer under the Telnet protocol code
+//!
+//! Connections read and write through boxed [`AsyncRead`] and
+//! [`AsyncWrite`] halves, so negotiation, framing and delivery don't depend
+//! on how the socket is driven. By default the halves are those of a tokio
+//! `TcpStream`. With the Linux-only `io-uring` feature, sockets are instead
+//! driven by a dedicated thread running a `tokio-uring` runtime, which
+//! submits the reads and writes of every connection through one io_uring.
+//! Each socket is bridged to its connection task by an in-memory pipe, so
+//! the connection tasks keep running on the provider's runtime. Either way,
+//! a link's [`SocketTuning`] is applied to each socket once it connects.
+
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+pub use crate::platform::SocketTuning;
+
+/// Read half of a connection's socket
+pub type SocketReader = Box<dyn AsyncRead + Send + Unpin>;
+
+/// Write half of a connection's socket
+pub type SocketWriter = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// A connected socket, split for concurrent reading and writing
+pub struct Socket {
+    pub reader: SocketReader,
+    pub writer: SocketWriter,
+    /// Address of the server
+    pub peer: SocketAddr,
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(all(feature = "io-uring", target_os = "linux"))] {
+        pub use uring::{connect, BACKEND};
+    } else {
+        pub use tcp::{connect, BACKEND};
+    }
+}
+
+/// Opens the sockets connections run over
+///
+/// Connections use [`DefaultConnector`] unless given another, so tests can
+/// hand them in-memory sockets or count connection attempts.
+pub trait TcpConnector: Send + Sync {
+    /// Connect to the first of `addrs` accepting the connection
+    fn connect<'a>(
+        &'a self,
+        addrs: &'a [SocketAddr],
+        tuning: SocketTuning,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Socket>> + Send + 'a>>;
+}
+
+/// Connects with the socket backend the provider was built with
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultConnector;
+
+impl TcpConnector for DefaultConnector {
+    fn connect<'a>(
+        &'a self,
+        addrs: &'a [SocketAddr],
+        tuning: SocketTuning,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Socket>> + Send + 'a>> {
+        Box::pin(connect(addrs, tuning))
+    }
+}
+
+/// Sockets driven by the provider's tokio runtime
+#[cfg_attr(all(feature = "io-uring", target_os = "linux"), allow(dead_code))]
+mod tcp {
+    use super::*;
+
+    use socket2::SockRef;
+    use tokio::net::TcpStream;
+    use tracing::warn;
+
+    /// Name of the socket backend, as reported by the benchmark
+    pub const BACKEND: &str = "tokio";
+
+    /// Connect to the first of `addrs` accepting the connection
+    pub async fn connect(addrs: &[SocketAddr], tuning: SocketTuning) -> io::Result<Socket> {
+        let stream = TcpStream::connect(addrs).await?;
+        let peer = stream.peer_addr()?;
+        if let Err(e) = tuning.apply(SockRef::from(&stream)) {
+            warn!("Failed to set socket options for {}: {}", peer, e);
+        }
+        let (reader, writer) = stream.into_split();
+        Ok(Socket {
+            reader: Box::new(reader),
+            writer: Box::new(writer),
+            peer,
+        })
+    }
+}
+
+/// Sockets driven by io_uring on a dedicated thread
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring {
+    use super::*;
+
+    use std::net::Shutdown;
+    use std::os::fd::{AsRawFd, BorrowedFd};
+    use std::sync::OnceLock;
+
+    use socket2::SockRef;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+    use tokio::sync::{mpsc, oneshot};
+    use tokio_uring::buf::BoundedBuf;
+    use tracing::{debug, error, warn};
+
+    /// Name of the socket backend, as reported by the benchmark
+    pub const BACKEND: &str = "io-uring";
+
+    /// Bytes buffered in each direction between a socket and its connection
+    /// task, and the size of each io_uring read and write
+    const BRIDGE_BUFFER: usize = 64 * 1024;
+
+    /// A connection for the io_uring thread to establish
+    struct ConnectRequest {
+        addrs: Vec<SocketAddr>,
+        tuning: SocketTuning,
+        reply: oneshot::Sender<io::Result<Socket>>,
+    }
+
+    /// Requests to the io_uring thread, which is started on first use
+    fn requests() -> &'static mpsc::UnboundedSender<ConnectRequest> {
+        static REQUESTS: OnceLock<mpsc::UnboundedSender<ConnectRequest>> = OnceLock::new();
+        REQUESTS.get_or_init(|| {
+            let (tx, mut rx) = mpsc::unbounded_channel::<ConnectRequest>();
+            let started = std::thread::Builder::new()
+                .name("telnet-uring".to_string())
+                .spawn(move || {
+                    tokio_uring::start(async move {
+                        while let Some(request) = rx.recv().await {
+                            tokio_uring::spawn(serve(request));
+                        }
+                    })
+                });
+            if let Err(e) = started {
+                error!("Failed to start the io_uring socket thread: {}", e);
+            }
+            tx
+        })
+    }
+
+    /// Connect to the first of `addrs` accepting the connection
+    pub async fn connect(addrs: &[SocketAddr], tuning: SocketTuning) -> io::Result<Socket> {
+        let unavailable = || io::Error::other("io_uring socket thread is not running");
+        let (reply, socket) = oneshot::channel();
+        requests()
+            .send(ConnectRequest {
+                addrs: addrs.to_vec(),
+                tuning,
+                reply,
+            })
+            .map_err(|_| unavailable())?;
+        socket.await.map_err(|_| unavailable())?
+    }
+
+    /// Connect, hand the connection task its end of the bridge, and move
+    /// data between the socket and the bridge until either side closes it
+    async fn serve(request: ConnectRequest) {
+        let mut last_error =
+            io::Error::new(io::ErrorKind::InvalidInput, "no addresses to connect to");
+        let mut connected = None;
+        for addr in request.addrs {
+            match tokio_uring::net::TcpStream::connect(addr).await {
+                Ok(stream) => {
+                    connected = Some((stream, addr));
+                    break;
+                }
+                Err(e) => last_error = e,
+            }
+        }
+        let Some((stream, peer)) = connected else {
+            let _ = request.reply.send(Err(last_error));
+            return;
+        };
+        // The stream owns the descriptor for as long as it is borrowed here
+        let fd = unsafe { BorrowedFd::borrow_raw(stream.as_raw_fd()) };
+        if let Err(e) = request.tuning.apply(SockRef::from(&fd)) {
+            warn!("Failed to set socket options for {}: {}", peer, e);
+        }
+
+        let (bridge, task_end) = tokio::io::duplex(BRIDGE_BUFFER);
+        let (reader, writer) = tokio::io::split(task_end);
+        let socket = Socket {
+            reader: Box::new(reader),
+            writer: Box::new(writer),
+            peer,
+        };
+        if request.reply.send(Ok(socket)).is_err() {
+            // The connection attempt was abandoned
+            return;
+        }
+
+        let (from_task, to_task) = tokio::io::split(bridge);
+        // Once the server closes the socket or the connection task closes
+        // its end, the socket is dropped, closing it
+        let closed = tokio::select! {
+            result = inbound(&stream, to_task) => result,
+            result = outbound(&stream, from_task) => result,
+        };
+        if let Err(e) = closed {
+            debug!("io_uring socket to {} closed: {}", peer, e);
+        }
+    }
+
+    /// Copy data read from the socket to the connection task
+    async fn inbound(
+        stream: &tokio_uring::net::TcpStream,
+        mut to_task: tokio::io::WriteHalf<DuplexStream>,
+    ) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(BRIDGE_BUFFER);
+        loop {
+            buf.clear();
+            let (result, read) = stream.read(buf).await;
+            buf = read;
+            let n = result?;
+            if n == 0 {
+                return to_task.shutdown().await;
+            }
+            to_task.write_all(&buf[..n]).await?;
+        }
+    }
+
+    /// Write data from the connection task to the socket
+    async fn outbound(
+        stream: &tokio_uring::net::TcpStream,
+        mut from_task: tokio::io::ReadHalf<DuplexStream>,
+    ) -> io::Result<()> {
+        let mut buf = vec![0u8; BRIDGE_BUFFER];
+        loop {
+            let n = from_task.read(&mut buf).await?;
+            if n == 0 {
+                // The connection task closed its write half
+                return stream.shutdown(Shutdown::Write);
+            }
+            let (result, written) = stream.write_all(buf.slice(..n)).await;
+            buf = written.into_inner();
+            result?;
+        }
+    }
+}
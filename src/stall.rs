@@ -0,0 +1,93 @@
+//! Stalled connection watchdog
+//!
+//! A connection can stay up without ever producing data, e.g. when the server
+//! only sends IAC-level keepalives. With `watchdog_timeout_ms` set, a
+//! connected link that has neither received a message nor failed for that
+//! long is told to reconnect.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::time::{interval, Instant, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::stats::ConnectionStats;
+use crate::telnet::ConnectionCommand;
+
+/// Shortest interval between activity checks
+const MIN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Watches one link for stalls and requests reconnects
+pub struct WatchdogTask {
+    source_id: Arc<str>,
+    timeout: Duration,
+    stats: Arc<ConnectionStats>,
+    commands: mpsc::Sender<ConnectionCommand>,
+}
+
+impl WatchdogTask {
+    /// Watchdog reconnecting `source_id`'s connection via `commands` after
+    /// `timeout` without activity
+    pub fn new(
+        source_id: Arc<str>,
+        timeout: Duration,
+        stats: Arc<ConnectionStats>,
+        commands: mpsc::Sender<ConnectionCommand>,
+    ) -> Self {
+        Self {
+            source_id,
+            timeout,
+            stats,
+            commands,
+        }
+    }
+
+    /// Check for stalls until `cancel` is triggered
+    pub async fn run(self, cancel: CancellationToken) {
+        let mut ticker = interval((self.timeout / 4).max(MIN_CHECK_INTERVAL));
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // Time spent disconnected (reconnecting, held, in a blackout) is
+        // not a stall, so the clock restarts whenever the link is down
+        let mut connected_since = Instant::now();
+
+        loop {
+            tokio::select! {
+                _ = cancel.cancelled() => return,
+                _ = ticker.tick() => {}
+            }
+
+            if !self.stats.is_connected() {
+                connected_since = Instant::now();
+                continue;
+            }
+
+            let last_activity = [self.stats.last_message_at(), self.stats.last_error_at()]
+                .into_iter()
+                .flatten()
+                .map(Instant::from_std)
+                .fold(connected_since, Instant::max);
+            let idle = last_activity.elapsed();
+            if idle < self.timeout {
+                continue;
+            }
+
+            warn!(
+                idle_ms = idle.as_millis() as u64,
+                "No activity on the connection for component {} in {:?}, reconnecting",
+                self.source_id,
+                idle
+            );
+            if self
+                .commands
+                .try_send(ConnectionCommand::Reconnect)
+                .is_err()
+            {
+                // A reconnect is already pending, or the connection is gone
+                continue;
+            }
+            connected_since = Instant::now();
+        }
+    }
+}
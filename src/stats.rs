@@ -0,0 +1,731 @@
+//! Runtime state shared between a connection task and the provider
+//!
+//! The read loop and delivery task update these for every message, so
+//! everything they touch is a plain atomic; locks are only taken on
+//! connection changes, errors, and by readers. Counters use `Relaxed`
+//! ordering: each one is exact on its own, but a [`StatsSnapshot`] taken
+//! while messages flow is approximate across counters, e.g. a message may
+//! already count as forwarded but its bytes not yet.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::error::TelnetErrorKind;
+use crate::keywords::KeywordVerdict;
+use crate::rate::MessageRateWindow;
+
+/// Window of the moving-average message rate
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Milliseconds between message rate samples; messages in between only
+/// touch the counter
+const RATE_SAMPLE_INTERVAL_MS: u64 = 100;
+
+/// Lifecycle state of a single link
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkState {
+    /// Forwarding messages normally
+    Active,
+    /// The link's lifetime byte quota is used up and forwarding has stopped
+    QuotaExceeded,
+    /// Disconnected inside a reconnect blackout window, waiting for it to end
+    Blackout,
+    /// `max_messages` messages were forwarded and forwarding has stopped
+    MessageLimitReached,
+}
+
+impl LinkState {
+    fn from_u8(state: u8) -> Self {
+        match state {
+            1 => LinkState::QuotaExceeded,
+            2 => LinkState::Blackout,
+            3 => LinkState::MessageLimitReached,
+            _ => LinkState::Active,
+        }
+    }
+}
+
+/// Point-in-time copy of a link's statistics, for status readers
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatsSnapshot {
+    pub state: LinkState,
+    pub connected: bool,
+    pub component_reachable: bool,
+    /// Connections established, and how many of them were reconnects
+    pub connections: u64,
+    pub reconnects: u64,
+    /// Bytes read from the socket, Telnet negotiation included, and the
+    /// reads that returned them
+    pub bytes_in: u64,
+    pub socket_reads: u64,
+    pub bytes_forwarded: u64,
+    pub messages_forwarded: u64,
+    pub messages_per_second: f64,
+    /// Messages dropped, by reason
+    pub messages_shed: u64,
+    pub messages_dropped: u64,
+    pub messages_sampled_out: u64,
+    pub keyword_missing: u64,
+    pub keyword_blocked: u64,
+    /// Messages the component failed to handle
+    pub delivery_failures: u64,
+    /// Failed messages republished on the dead-letter subject
+    pub messages_dead_lettered: u64,
+    /// Messages the component took longer than the slow consumer threshold
+    /// to handle
+    pub slow_deliveries: u64,
+    pub client_recreations: u64,
+    pub sequence_gaps: u64,
+    /// Last socket read, in milliseconds since the Unix epoch
+    pub last_activity_ms: Option<u64>,
+    pub uptime_secs: u64,
+    pub downtime_secs: u64,
+}
+
+/// Connected and disconnected time of a link across reconnects
+///
+/// Measured on Tokio's clock, so that tests can advance it.
+#[derive(Debug, Default)]
+struct Uptime {
+    /// When the current connection was established, while connected
+    connected_at: Option<(SystemTime, tokio::time::Instant)>,
+    /// When the last connection ended, while disconnected
+    disconnected_at: Option<tokio::time::Instant>,
+    /// Time spent connected, excluding the current connection
+    total_uptime: Duration,
+    /// Time spent reconnecting, excluding the current outage
+    total_downtime: Duration,
+}
+
+/// Counters and state for a single link, shared via `Arc`
+#[derive(Debug)]
+pub struct ConnectionStats {
+    /// Current [`LinkState`], as its discriminant
+    state: AtomicU8,
+    /// Whether the Telnet socket is currently connected
+    connected: AtomicBool,
+    /// Time connected and disconnected, for SLA reporting
+    uptime: Mutex<Uptime>,
+    /// Number of Telnet connections established so far
+    connections: AtomicU64,
+    /// Whether the linked component answered its most recent pings
+    component_reachable: AtomicBool,
+    /// Total body bytes forwarded to the component across all reconnects
+    bytes_forwarded: AtomicU64,
+    /// Total messages forwarded to the component across all reconnects
+    messages_forwarded: AtomicU64,
+    /// Data messages counted against `max_messages` across all reconnects
+    messages_counted: AtomicU64,
+    /// Recent message rate
+    rate: Mutex<MessageRateWindow>,
+    /// Milliseconds after `created` of the last rate sample
+    rate_sampled_at: AtomicU64,
+    /// Total bytes read from the socket across all reconnects
+    bytes_in: AtomicU64,
+    /// Socket reads that returned data across all reconnects
+    socket_reads: AtomicU64,
+    /// Milliseconds since the Unix epoch of the last socket read (0 = none)
+    last_activity_ms: AtomicU64,
+    /// Messages the component failed to handle
+    delivery_failures: AtomicU64,
+    /// Number of sequence number gaps detected
+    gap_count: AtomicU64,
+    /// Bytes requested per socket read, as last adapted
+    read_buffer_size: AtomicU64,
+    /// Messages dropped because the link's delivery share was used up
+    messages_shed: AtomicU64,
+    /// Messages dropped as repeats within the debounce window
+    messages_dropped: AtomicU64,
+    /// Messages left out of the sample of a high-rate feed
+    messages_sampled_out: AtomicU64,
+    /// Failed messages republished on the dead-letter subject
+    messages_dead_lettered: AtomicU64,
+    /// Messages handled slower than the slow consumer threshold
+    slow_deliveries: AtomicU64,
+    /// Component clients recreated after consecutive failed calls
+    client_recreations: AtomicU64,
+    /// Messages dropped for lacking every required keyword
+    keyword_missing: AtomicU64,
+    /// Messages dropped for containing a blocked keyword
+    keyword_blocked: AtomicU64,
+    /// Number of connection failures by kind
+    errors: Mutex<BTreeMap<TelnetErrorKind, u64>>,
+    /// When these stats were created; activity times are offsets from it
+    created: Instant,
+    /// Milliseconds after `created` of the last message received, plus one
+    /// (0 = none yet)
+    last_message_at: AtomicU64,
+    /// Milliseconds after `created` of the last connection failure, plus one
+    /// (0 = none yet)
+    last_error_at: AtomicU64,
+}
+
+impl Default for ConnectionStats {
+    fn default() -> Self {
+        Self {
+            state: AtomicU8::new(LinkState::Active as u8),
+            connected: AtomicBool::new(false),
+            uptime: Mutex::new(Uptime::default()),
+            connections: AtomicU64::new(0),
+            component_reachable: AtomicBool::new(true),
+            bytes_forwarded: AtomicU64::new(0),
+            messages_forwarded: AtomicU64::new(0),
+            messages_counted: AtomicU64::new(0),
+            rate: Mutex::new(MessageRateWindow::new(RATE_WINDOW)),
+            rate_sampled_at: AtomicU64::new(0),
+            bytes_in: AtomicU64::new(0),
+            socket_reads: AtomicU64::new(0),
+            last_activity_ms: AtomicU64::new(0),
+            delivery_failures: AtomicU64::new(0),
+            gap_count: AtomicU64::new(0),
+            read_buffer_size: AtomicU64::new(0),
+            messages_shed: AtomicU64::new(0),
+            messages_dropped: AtomicU64::new(0),
+            messages_sampled_out: AtomicU64::new(0),
+            messages_dead_lettered: AtomicU64::new(0),
+            slow_deliveries: AtomicU64::new(0),
+            client_recreations: AtomicU64::new(0),
+            keyword_missing: AtomicU64::new(0),
+            keyword_blocked: AtomicU64::new(0),
+            errors: Mutex::new(BTreeMap::new()),
+            created: Instant::now(),
+            last_message_at: AtomicU64::new(0),
+            last_error_at: AtomicU64::new(0),
+        }
+    }
+}
+
+impl ConnectionStats {
+    /// Current lifecycle state
+    pub fn state(&self) -> LinkState {
+        LinkState::from_u8(self.state.load(Ordering::Relaxed))
+    }
+
+    /// Transition to a new lifecycle state
+    pub fn set_state(&self, state: LinkState) {
+        self.state.store(state as u8, Ordering::Relaxed);
+    }
+
+    /// Copy of all statistics, each read independently (see module docs)
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let connections = self.connections();
+        StatsSnapshot {
+            state: self.state(),
+            connected: self.is_connected(),
+            component_reachable: self.is_component_reachable(),
+            connections,
+            reconnects: connections.saturating_sub(1),
+            bytes_in: self.bytes_in(),
+            socket_reads: self.socket_reads(),
+            bytes_forwarded: self.bytes_forwarded(),
+            messages_forwarded: self.messages_forwarded(),
+            messages_per_second: self.messages_per_second(),
+            messages_shed: self.messages_shed(),
+            messages_dropped: self.messages_dropped(),
+            messages_sampled_out: self.messages_sampled_out(),
+            keyword_missing: self.keyword_missing(),
+            keyword_blocked: self.keyword_blocked(),
+            delivery_failures: self.delivery_failures.load(Ordering::Relaxed),
+            messages_dead_lettered: self.messages_dead_lettered(),
+            slow_deliveries: self.slow_deliveries.load(Ordering::Relaxed),
+            client_recreations: self.client_recreations(),
+            sequence_gaps: self.gap_count(),
+            last_activity_ms: Some(self.last_activity_ms.load(Ordering::Relaxed))
+                .filter(|ms| *ms > 0),
+            uptime_secs: self.total_uptime_secs(),
+            downtime_secs: self.total_downtime_secs(),
+        }
+    }
+
+    /// Total bytes read from the socket
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+
+    /// Total socket reads that returned data
+    pub fn socket_reads(&self) -> u64 {
+        self.socket_reads.load(Ordering::Relaxed)
+    }
+
+    /// Record a socket read returning `len` bytes
+    pub fn record_bytes_in(&self, len: usize) {
+        self.bytes_in.fetch_add(len as u64, Ordering::Relaxed);
+        self.socket_reads.fetch_add(1, Ordering::Relaxed);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        self.last_activity_ms.store(now, Ordering::Relaxed);
+    }
+
+    /// Record a message the component failed to handle
+    pub fn record_delivery_failure(&self) {
+        self.delivery_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Whether the Telnet socket is currently connected
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Record whether the Telnet socket is connected
+    ///
+    /// On each change, the time since the previous change is added to the
+    /// total uptime or downtime. Downtime only counts from the end of the
+    /// first connection.
+    pub fn set_connected(&self, connected: bool) {
+        let mut uptime = self.uptime.lock().unwrap_or_else(|e| e.into_inner());
+        if self.connected.swap(connected, Ordering::Relaxed) == connected {
+            return;
+        }
+        let now = tokio::time::Instant::now();
+        if connected {
+            if let Some(at) = uptime.disconnected_at.take() {
+                uptime.total_downtime += now.duration_since(at);
+            }
+            uptime.connected_at = Some((SystemTime::now(), now));
+        } else {
+            if let Some((_, at)) = uptime.connected_at.take() {
+                uptime.total_uptime += now.duration_since(at);
+            }
+            uptime.disconnected_at = Some(now);
+        }
+    }
+
+    /// When the current connection was established, if connected
+    pub fn connected_at(&self) -> Option<SystemTime> {
+        let uptime = self.uptime.lock().unwrap_or_else(|e| e.into_inner());
+        uptime.connected_at.map(|(at, _)| at)
+    }
+
+    /// Seconds spent connected across all reconnects, including the
+    /// current connection
+    pub fn total_uptime_secs(&self) -> u64 {
+        let uptime = self.uptime.lock().unwrap_or_else(|e| e.into_inner());
+        let current = uptime.connected_at.map(|(_, at)| at.elapsed());
+        (uptime.total_uptime + current.unwrap_or_default()).as_secs()
+    }
+
+    /// Seconds spent disconnected between connections, including the
+    /// current outage
+    pub fn total_downtime_secs(&self) -> u64 {
+        let uptime = self.uptime.lock().unwrap_or_else(|e| e.into_inner());
+        let current = uptime.disconnected_at.map(|at| at.elapsed());
+        (uptime.total_downtime + current.unwrap_or_default()).as_secs()
+    }
+
+    /// Record a newly established connection, returning its epoch (1-based)
+    pub fn next_epoch(&self) -> u64 {
+        self.connections.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Number of Telnet connections established so far
+    pub fn connections(&self) -> u64 {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    /// Number of sequence number gaps detected so far
+    pub fn gap_count(&self) -> u64 {
+        self.gap_count.load(Ordering::Relaxed)
+    }
+
+    /// Record the total number of sequence number gaps detected
+    pub fn set_gap_count(&self, gaps: u64) {
+        self.gap_count.store(gaps, Ordering::Relaxed);
+    }
+
+    /// Bytes requested per socket read (0 before the first connection)
+    pub fn read_buffer_size(&self) -> u64 {
+        self.read_buffer_size.load(Ordering::Relaxed)
+    }
+
+    /// Record the connection's current read size
+    pub fn set_read_buffer_size(&self, size: usize) {
+        self.read_buffer_size.store(size as u64, Ordering::Relaxed);
+    }
+
+    /// Number of messages shed under delivery backpressure
+    pub fn messages_shed(&self) -> u64 {
+        self.messages_shed.load(Ordering::Relaxed)
+    }
+
+    /// Record a message shed under delivery backpressure
+    pub fn record_shed(&self) {
+        self.messages_shed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of messages dropped as repeats within the debounce window
+    pub fn messages_dropped(&self) -> u64 {
+        self.messages_dropped.load(Ordering::Relaxed)
+    }
+
+    /// Record a message dropped as a repeat within the debounce window
+    pub fn record_dropped(&self) {
+        self.messages_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of messages left out of the sample of a high-rate feed
+    pub fn messages_sampled_out(&self) -> u64 {
+        self.messages_sampled_out.load(Ordering::Relaxed)
+    }
+
+    /// Record a message left out of the sample
+    pub fn record_sampled_out(&self) {
+        self.messages_sampled_out.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of failed messages republished on the dead-letter subject
+    pub fn messages_dead_lettered(&self) -> u64 {
+        self.messages_dead_lettered.load(Ordering::Relaxed)
+    }
+
+    /// Record a failed message republished on the dead-letter subject
+    pub fn record_dead_lettered(&self) {
+        self.messages_dead_lettered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a message handled slower than the slow consumer threshold
+    pub fn record_slow_delivery(&self) {
+        self.slow_deliveries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of component clients recreated after consecutive failed calls
+    pub fn client_recreations(&self) -> u64 {
+        self.client_recreations.load(Ordering::Relaxed)
+    }
+
+    /// Record a component client recreated after consecutive failed calls
+    pub fn record_client_recreation(&self) {
+        self.client_recreations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of messages dropped for lacking every required keyword
+    pub fn keyword_missing(&self) -> u64 {
+        self.keyword_missing.load(Ordering::Relaxed)
+    }
+
+    /// Number of messages dropped for containing a blocked keyword
+    pub fn keyword_blocked(&self) -> u64 {
+        self.keyword_blocked.load(Ordering::Relaxed)
+    }
+
+    /// Record a message dropped by the keyword filter
+    pub fn record_keyword_verdict(&self, verdict: KeywordVerdict) {
+        match verdict {
+            KeywordVerdict::Forward => {}
+            KeywordVerdict::MissingRequired => {
+                self.keyword_missing.fetch_add(1, Ordering::Relaxed);
+            }
+            KeywordVerdict::Blocked => {
+                self.keyword_blocked.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Record that a message was received and passed on for forwarding
+    pub fn record_message(&self) {
+        self.last_message_at.store(self.now(), Ordering::Relaxed);
+    }
+
+    /// When the last message was received, if any
+    pub fn last_message_at(&self) -> Option<Instant> {
+        self.instant(&self.last_message_at)
+    }
+
+    /// When the last connection failure happened, if any
+    pub fn last_error_at(&self) -> Option<Instant> {
+        self.instant(&self.last_error_at)
+    }
+
+    /// Record a connection failure
+    pub fn record_error(&self, kind: TelnetErrorKind) {
+        self.last_error_at.store(self.now(), Ordering::Relaxed);
+        *self
+            .errors
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(kind)
+            .or_default() += 1;
+    }
+
+    /// Number of connection failures so far, by kind
+    pub fn errors(&self) -> Vec<(TelnetErrorKind, u64)> {
+        self.errors
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|(kind, count)| (*kind, *count))
+            .collect()
+    }
+
+    /// Current time as an activity timestamp
+    fn now(&self) -> u64 {
+        self.created.elapsed().as_millis() as u64 + 1
+    }
+
+    fn instant(&self, at: &AtomicU64) -> Option<Instant> {
+        match at.load(Ordering::Relaxed) {
+            0 => None,
+            ms => Some(self.created + Duration::from_millis(ms - 1)),
+        }
+    }
+
+    /// Whether the linked component is considered reachable
+    pub fn is_component_reachable(&self) -> bool {
+        self.component_reachable.load(Ordering::Relaxed)
+    }
+
+    /// Record whether the linked component is reachable
+    pub fn set_component_reachable(&self, reachable: bool) {
+        self.component_reachable.store(reachable, Ordering::Relaxed);
+    }
+
+    /// Total bytes forwarded so far
+    pub fn bytes_forwarded(&self) -> u64 {
+        self.bytes_forwarded.load(Ordering::Relaxed)
+    }
+
+    /// Total messages forwarded so far
+    pub fn messages_forwarded(&self) -> u64 {
+        self.messages_forwarded.load(Ordering::Relaxed)
+    }
+
+    /// Record a message forwarded to the component
+    ///
+    /// The rate window is only sampled every [`RATE_SAMPLE_INTERVAL_MS`], by
+    /// whichever message claims the sample, so other messages take no lock.
+    pub fn record_forwarded_message(&self) {
+        let count = self.messages_forwarded.fetch_add(1, Ordering::Relaxed) + 1;
+        let now = self.now();
+        let sampled_at = self.rate_sampled_at.load(Ordering::Relaxed);
+        if sampled_at != 0 && now.saturating_sub(sampled_at) < RATE_SAMPLE_INTERVAL_MS {
+            return;
+        }
+        // Of messages racing for the same sample, only one takes it
+        if self
+            .rate_sampled_at
+            .compare_exchange(sampled_at, now, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return;
+        }
+        self.rate
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .add_sample(count);
+    }
+
+    /// Messages forwarded per second, averaged over the last 10 seconds
+    pub fn messages_per_second(&self) -> f64 {
+        self.rate
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .messages_per_second()
+    }
+
+    /// Account for `len` more forwarded bytes, respecting an optional quota
+    ///
+    /// Returns `false` without recording anything if forwarding `len` bytes
+    /// would take the total past `quota`.
+    pub fn try_forward(&self, len: u64, quota: Option<u64>) -> bool {
+        match quota {
+            None => {
+                self.bytes_forwarded.fetch_add(len, Ordering::Relaxed);
+                true
+            }
+            Some(quota) => self
+                .bytes_forwarded
+                .try_update(Ordering::Relaxed, Ordering::Relaxed, |total| {
+                    total.checked_add(len).filter(|total| *total <= quota)
+                })
+                .is_ok(),
+        }
+    }
+
+    /// Count one more data message, respecting an optional limit
+    ///
+    /// Returns the number counted so far, including this one, or `None`
+    /// without counting it if `limit` messages were already counted.
+    pub fn try_count_message(&self, limit: Option<u64>) -> Option<u64> {
+        let limit = limit.unwrap_or(u64::MAX);
+        self.messages_counted
+            .try_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                (count < limit).then_some(count + 1)
+            })
+            .ok()
+            .map(|count| count + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::time::advance;
+
+    #[tokio::test(start_paused = true)]
+    async fn accumulates_uptime_and_downtime_across_reconnects() {
+        let stats = ConnectionStats::default();
+        let secs = Duration::from_secs;
+
+        // Connection attempts before the first connection aren't downtime
+        advance(secs(4)).await;
+        stats.set_connected(true);
+        advance(secs(10)).await;
+        stats.set_connected(false);
+        advance(secs(3)).await;
+
+        // First reconnect; a repeated report doesn't restart the connection
+        stats.set_connected(true);
+        advance(secs(15)).await;
+        stats.set_connected(true);
+        advance(secs(5)).await;
+        stats.set_connected(false);
+        advance(secs(6)).await;
+
+        // Second reconnect, still connected
+        stats.set_connected(true);
+        advance(secs(7)).await;
+
+        assert_eq!(stats.total_uptime_secs(), 10 + 20 + 7);
+        assert_eq!(stats.total_downtime_secs(), 3 + 6);
+
+        stats.set_connected(false);
+        advance(secs(2)).await;
+        assert_eq!(stats.total_uptime_secs(), 37);
+        assert_eq!(stats.total_downtime_secs(), 11);
+    }
+
+    /// Counters that only ever grow, from a snapshot
+    fn counters(snapshot: &StatsSnapshot) -> [u64; 8] {
+        [
+            snapshot.connections,
+            snapshot.bytes_in,
+            snapshot.socket_reads,
+            snapshot.bytes_forwarded,
+            snapshot.messages_forwarded,
+            snapshot.messages_dropped,
+            snapshot.delivery_failures,
+            snapshot.keyword_blocked,
+        ]
+    }
+
+    #[test]
+    fn counters_stay_exact_and_monotonic_under_concurrent_updates() {
+        const THREADS: u64 = 8;
+        const UPDATES: u64 = 10_000;
+        let stats = std::sync::Arc::new(ConnectionStats::default());
+        let writers: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let stats = stats.clone();
+                std::thread::spawn(move || {
+                    stats.next_epoch();
+                    for _ in 0..UPDATES {
+                        stats.record_bytes_in(3);
+                        assert!(stats.try_forward(2, None));
+                        stats.record_forwarded_message();
+                        stats.record_dropped();
+                        stats.record_delivery_failure();
+                        stats.record_keyword_verdict(KeywordVerdict::Blocked);
+                    }
+                })
+            })
+            .collect();
+
+        let mut previous = counters(&stats.snapshot());
+        while !writers.iter().all(|writer| writer.is_finished()) {
+            let current = counters(&stats.snapshot());
+            for (before, after) in previous.iter().zip(&current) {
+                assert!(after >= before, "{:?} went back to {:?}", previous, current);
+            }
+            previous = current;
+        }
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let total = THREADS * UPDATES;
+        let snapshot = stats.snapshot();
+        assert_eq!(
+            counters(&snapshot),
+            [
+                THREADS,
+                3 * total,
+                total,
+                2 * total,
+                total,
+                total,
+                total,
+                total
+            ]
+        );
+        assert_eq!(snapshot.reconnects, THREADS - 1);
+        assert!(snapshot.last_activity_ms.is_some());
+    }
+
+    #[test]
+    fn quota_and_message_limit_are_never_overshot() {
+        let stats = std::sync::Arc::new(ConnectionStats::default());
+        let accepted: u64 = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        (0..1000)
+                            .filter(|_| stats.try_forward(7, Some(10_000)))
+                            .count() as u64
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|w| w.join().unwrap()).sum()
+        });
+        assert_eq!(accepted, 10_000 / 7);
+        assert_eq!(stats.bytes_forwarded(), 10_000 / 7 * 7);
+        assert!(!stats.try_forward(7, Some(10_000)));
+        // Without a quota everything counts
+        assert!(stats.try_forward(7, None));
+
+        assert_eq!(stats.try_count_message(Some(2)), Some(1));
+        assert_eq!(stats.try_count_message(Some(2)), Some(2));
+        assert_eq!(stats.try_count_message(Some(2)), None);
+        assert_eq!(stats.try_count_message(None), Some(3));
+    }
+
+    #[test]
+    fn snapshots_report_state_errors_and_activity() {
+        let stats = ConnectionStats::default();
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.state, LinkState::Active);
+        assert_eq!(snapshot.connections, 0);
+        assert_eq!(snapshot.reconnects, 0);
+        assert_eq!(snapshot.last_activity_ms, None);
+        assert!(stats.last_message_at().is_none());
+        assert!(stats.last_error_at().is_none());
+
+        for state in [
+            LinkState::QuotaExceeded,
+            LinkState::Blackout,
+            LinkState::MessageLimitReached,
+            LinkState::Active,
+        ] {
+            stats.set_state(state);
+            assert_eq!(stats.snapshot().state, state);
+        }
+
+        stats.record_error(TelnetErrorKind::Timeout);
+        stats.record_error(TelnetErrorKind::Refused);
+        stats.record_error(TelnetErrorKind::Timeout);
+        let mut errors = stats.errors();
+        errors.sort();
+        assert_eq!(
+            errors,
+            [(TelnetErrorKind::Refused, 1), (TelnetErrorKind::Timeout, 2)]
+        );
+        assert!(stats.last_error_at().is_some());
+        stats.record_message();
+        assert!(stats.last_message_at().is_some());
+    }
+}
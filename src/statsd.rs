@@ -0,0 +1,154 @@
+//! StatsD metrics export over UDP
+//!
+//! Every flush sends one datagram per link with the provider's per-link
+//! metrics, tagged with the link's source ID using DogStatsD tag syntax:
+//!
+//! | Metric | Type | Value |
+//! |--------|------|-------|
+//! | `<prefix>.bytes_in` | counter | Bytes read from the Telnet socket since the last flush |
+//! | `<prefix>.socket_reads` | counter | Socket reads that returned data since the last flush |
+//! | `<prefix>.bytes_forwarded` | counter | Bytes forwarded since the last flush |
+//! | `<prefix>.connections` | counter | Connections established since the last flush |
+//! | `<prefix>.sequence_gaps` | counter | Sequence number gaps detected since the last flush |
+//! | `<prefix>.delivery_failures` | counter | Messages the component failed to handle since the last flush |
+//! | `<prefix>.slow_deliveries` | counter | Messages the component took longer than `slow_consumer_threshold_ms` to handle since the last flush |
+//! | `<prefix>.messages_shed` | counter | Messages dropped under delivery backpressure since the last flush |
+//! | `<prefix>.messages_dropped` | counter | Messages dropped as repeats within the debounce window since the last flush |
+//! | `<prefix>.messages_sampled_out` | counter | Messages left out of the `sample_rate` sample since the last flush |
+//! | `<prefix>.client_recreations` | counter | Component wRPC clients recreated after consecutive failed calls since the last flush |
+//! | `<prefix>.keyword_missing` | counter | Messages dropped for lacking every required keyword since the last flush |
+//! | `<prefix>.keyword_blocked` | counter | Messages dropped for containing a blocked keyword since the last flush |
+//! | `<prefix>.connection_errors` | counter | Connection failures since the last flush, tagged with `kind` |
+//! | `<prefix>.connected` | gauge | `1` while the Telnet socket is connected |
+//! | `<prefix>.component_reachable` | gauge | `1` while the component answers pings |
+//! | `<prefix>.messages_per_second` | gauge | Messages forwarded per second, averaged over 10 seconds |
+//! | `<prefix>.uptime_seconds` | gauge | Seconds connected across all reconnects |
+//! | `<prefix>.downtime_seconds` | gauge | Seconds disconnected between connections |
+//! | `<prefix>.connected_at` | gauge | Unix time the current connection was established (0 while disconnected) |
+//! | `<prefix>.read_buffer_size` | gauge | Bytes requested per socket read, as adapted to the feed |
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+
+use crate::stats::ConnectionStats;
+
+/// Sends link metrics to a StatsD server
+pub struct StatsdSink {
+    socket: UdpSocket,
+    prefix: String,
+    /// Counter values at the previous flush, keyed by metric name and tags
+    previous: HashMap<(&'static str, String), u64>,
+    /// Counter values recorded during the ongoing flush
+    current: HashMap<(&'static str, String), u64>,
+    /// Whether sends are currently failing, so an outage is logged once
+    failing: bool,
+}
+
+impl StatsdSink {
+    /// Bind a local UDP socket sending to `addr`
+    pub async fn connect(addr: &str, prefix: &str) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket.connect(addr).await?;
+        Ok(Self {
+            socket,
+            prefix: prefix.to_string(),
+            previous: HashMap::new(),
+            current: HashMap::new(),
+            failing: false,
+        })
+    }
+
+    /// Send the current metrics of every link
+    pub async fn flush(&mut self, links: &[(String, Arc<ConnectionStats>)]) {
+        for (source_id, stats) in links {
+            let tags = format!("link:{}", source_id);
+            let snapshot = stats.snapshot();
+            let mut lines = vec![
+                self.counter("bytes_in", &tags, snapshot.bytes_in),
+                self.counter("socket_reads", &tags, snapshot.socket_reads),
+                self.counter("bytes_forwarded", &tags, snapshot.bytes_forwarded),
+                self.counter("connections", &tags, snapshot.connections),
+                self.counter("sequence_gaps", &tags, snapshot.sequence_gaps),
+                self.counter("delivery_failures", &tags, snapshot.delivery_failures),
+                self.counter(
+                    "messages_dead_lettered",
+                    &tags,
+                    snapshot.messages_dead_lettered,
+                ),
+                self.counter("slow_deliveries", &tags, snapshot.slow_deliveries),
+                self.counter("messages_shed", &tags, snapshot.messages_shed),
+                self.counter("messages_dropped", &tags, snapshot.messages_dropped),
+                self.counter("messages_sampled_out", &tags, snapshot.messages_sampled_out),
+                self.counter("client_recreations", &tags, snapshot.client_recreations),
+                self.counter("keyword_missing", &tags, snapshot.keyword_missing),
+                self.counter("keyword_blocked", &tags, snapshot.keyword_blocked),
+                self.gauge("connected", &tags, snapshot.connected as u64),
+                self.gauge(
+                    "component_reachable",
+                    &tags,
+                    snapshot.component_reachable as u64,
+                ),
+                self.gauge(
+                    "messages_per_second",
+                    &tags,
+                    format!("{:.2}", snapshot.messages_per_second),
+                ),
+                self.gauge("uptime_seconds", &tags, snapshot.uptime_secs),
+                self.gauge("downtime_seconds", &tags, snapshot.downtime_secs),
+                self.gauge(
+                    "connected_at",
+                    &tags,
+                    stats
+                        .connected_at()
+                        .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+                        .map_or(0, |at| at.as_secs()),
+                ),
+                self.gauge("read_buffer_size", &tags, stats.read_buffer_size()),
+            ];
+            for (kind, count) in stats.errors() {
+                let tags = format!("{},kind:{}", tags, kind.as_str());
+                lines.push(self.counter("connection_errors", &tags, count));
+            }
+            let datagram = lines.join("\n");
+            debug!("Sending StatsD metrics for {}", source_id);
+            match self.socket.send(datagram.as_bytes()).await {
+                Ok(_) if self.failing => {
+                    info!("Sending StatsD metrics again");
+                    self.failing = false;
+                }
+                Ok(_) => {}
+                Err(e) if self.failing => debug!("Failed to send StatsD metrics: {}", e),
+                Err(e) => {
+                    warn!("Failed to send StatsD metrics, will keep retrying: {}", e);
+                    self.failing = true;
+                }
+            }
+        }
+
+        // Links that no longer exist drop out here
+        self.previous = std::mem::take(&mut self.current);
+    }
+
+    /// Format a counter as the increase since the previous flush
+    fn counter(&mut self, name: &'static str, tags: &str, total: u64) -> String {
+        let key = (name, tags.to_string());
+        let previous = self.previous.get(&key).copied().unwrap_or_default();
+        self.current.insert(key, total);
+        format!(
+            "{}.{}:{}|c|#{}",
+            self.prefix,
+            name,
+            total.saturating_sub(previous),
+            tags
+        )
+    }
+
+    /// Format a gauge
+    fn gauge(&self, name: &str, tags: &str, value: impl std::fmt::Display) -> String {
+        format!("{}.{}:{}|g|#{}", self.prefix, name, value, tags)
+    }
+}
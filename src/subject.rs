@@ -0,0 +1,171 @@
+//! Subject a link's data is forwarded on
+//!
+//! The subject is rendered once per link from `subject_template`, which may
+//! refer to `{host}`, `{port}` and `{source_id}`. The component's source ID
+//! can be adjusted with `source_id_transform` first, so subjects follow an
+//! existing naming scheme even when component IDs don't. Event subjects
+//! (`.gap`, `.quota_exceeded`, ...) are appended to the rendered subject.
+
+use std::borrow::Cow;
+
+/// Subject template used when none is configured
+pub const DEFAULT_SUBJECT_TEMPLATE: &str = "telnet.{host}:{port}";
+
+/// Adjustment of the source ID before it's put into the subject
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum SourceIdTransform {
+    /// Use the source ID as is
+    #[default]
+    None,
+    Lowercase,
+    /// Remove this prefix if the source ID starts with it
+    StripPrefix(String),
+}
+
+impl SourceIdTransform {
+    /// The transformed source ID
+    pub fn apply<'a>(&self, source_id: &'a str) -> Cow<'a, str> {
+        match self {
+            SourceIdTransform::None => Cow::Borrowed(source_id),
+            SourceIdTransform::Lowercase => Cow::Owned(source_id.to_lowercase()),
+            SourceIdTransform::StripPrefix(prefix) => {
+                Cow::Borrowed(source_id.strip_prefix(prefix.as_str()).unwrap_or(source_id))
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for SourceIdTransform {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(prefix) = s.strip_prefix("strip_prefix:") {
+            return Ok(SourceIdTransform::StripPrefix(prefix.to_string()));
+        }
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(SourceIdTransform::None),
+            "lowercase" => Ok(SourceIdTransform::Lowercase),
+            other => Err(anyhow::anyhow!("Invalid source_id_transform: {}", other)),
+        }
+    }
+}
+
+/// Fill in the placeholders of a subject template
+///
+/// Characters that can't appear in a subject (whitespace, control
+/// characters and the `*` and `>` wildcards) are replaced by `_` in the
+/// values filled in. The template itself is used as written.
+pub fn render(
+    template: &str,
+    host: &str,
+    port: u16,
+    source_id: &str,
+    transform: &SourceIdTransform,
+) -> String {
+    let mut subject = template
+        .replace("{host}", &sanitize(host))
+        .replace("{port}", &port.to_string());
+    if subject.contains("{source_id}") {
+        subject = subject.replace("{source_id}", &sanitize(&transform.apply(source_id)));
+    }
+    subject
+}
+
+/// `value` with each character that isn't valid in a subject replaced by `_`
+fn sanitize(value: &str) -> Cow<'_, str> {
+    let invalid = |c: char| c.is_whitespace() || c.is_control() || c == '*' || c == '>';
+    if value.contains(invalid) {
+        Cow::Owned(value.replace(invalid, "_"))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_in_each_placeholder() {
+        let none = SourceIdTransform::None;
+        assert_eq!(
+            render(DEFAULT_SUBJECT_TEMPLATE, "10.0.0.5", 23, "comp", &none),
+            "telnet.10.0.0.5:23"
+        );
+        assert_eq!(
+            render(
+                "feeds.{source_id}.{host}",
+                "mud.example",
+                4000,
+                "alarm-handler",
+                &none
+            ),
+            "feeds.alarm-handler.mud.example"
+        );
+        assert_eq!(
+            render("p{port}.{port}", "h", 2323, "c", &none),
+            "p2323.2323"
+        );
+        assert_eq!(render("fixed", "h", 23, "c", &none), "fixed");
+    }
+
+    #[test]
+    fn transforms_only_the_source_id() {
+        let template = "{host}.{source_id}";
+        assert_eq!(
+            render(template, "Host", 23, "Acme_Alarm", &SourceIdTransform::None),
+            "Host.Acme_Alarm"
+        );
+        assert_eq!(
+            render(
+                template,
+                "Host",
+                23,
+                "Acme_Alarm",
+                &SourceIdTransform::Lowercase
+            ),
+            "Host.acme_alarm"
+        );
+        let strip = SourceIdTransform::StripPrefix("Acme_".to_string());
+        assert_eq!(
+            render(template, "Host", 23, "Acme_Alarm", &strip),
+            "Host.Alarm"
+        );
+        // Left alone without the prefix
+        assert_eq!(render(template, "Host", 23, "Other", &strip), "Host.Other");
+    }
+
+    #[test]
+    fn replaces_characters_that_are_invalid_in_a_subject() {
+        let none = SourceIdTransform::None;
+        assert_eq!(
+            render("t.{source_id}", "h", 23, "my comp*>\t\u{7}", &none),
+            "t.my_comp____"
+        );
+        assert_eq!(
+            render("t.{host}", " bad host ", 23, "c", &none),
+            "t._bad_host_"
+        );
+        // Only in the values filled in
+        assert_eq!(render("t.> {host}", "h", 23, "c", &none), "t.> h");
+        assert!(matches!(sanitize("fine.value-1"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn parses_transforms() {
+        assert_eq!(
+            "none".parse::<SourceIdTransform>().unwrap(),
+            SourceIdTransform::None
+        );
+        assert_eq!(
+            "LOWERCASE".parse::<SourceIdTransform>().unwrap(),
+            SourceIdTransform::Lowercase
+        );
+        // The prefix keeps its case
+        assert_eq!(
+            "strip_prefix:Acme_".parse::<SourceIdTransform>().unwrap(),
+            SourceIdTransform::StripPrefix("Acme_".to_string())
+        );
+        assert!("uppercase".parse::<SourceIdTransform>().is_err());
+    }
+}
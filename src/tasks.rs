@@ -0,0 +1,196 @@
+//! Bounded set of a connection's background deliveries
+//!
+//! Data messages are delivered in order by the connection task itself, but
+//! some messages (MSDP variables) are sent from a background task so the
+//! read loop isn't held up by the component. Those tasks are kept in a
+//! per-connection [`JoinSet`] instead of being spawned and forgotten: a
+//! failed or panicked delivery is counted as a delivery failure, at most
+//! [`MAX_TASKS`] run at once (the read loop waits for one to finish when the
+//! set is full, so a stalled component can't pile up tasks), and tasks
+//! still running when the link is deleted may finish until the teardown
+//! deadline before they are aborted.
+
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::task::{JoinError, JoinSet};
+use tokio::time::Instant;
+use tracing::{error, warn};
+
+use crate::instrument::spawn_named_in;
+use crate::stats::ConnectionStats;
+
+/// Background deliveries a connection may have in flight
+const MAX_TASKS: usize = 64;
+
+/// Background deliveries of one connection
+pub struct DeliveryTasks {
+    source_id: Arc<str>,
+    stats: Arc<ConnectionStats>,
+    tasks: Mutex<JoinSet<anyhow::Result<()>>>,
+}
+
+impl DeliveryTasks {
+    pub fn new(source_id: Arc<str>, stats: Arc<ConnectionStats>) -> Self {
+        Self {
+            source_id,
+            stats,
+            tasks: Mutex::new(JoinSet::new()),
+        }
+    }
+
+    /// Start a delivery, first waiting for one to finish if the set is full
+    pub async fn spawn<F>(&self, delivery: F)
+    where
+        F: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let mut tasks = self.tasks.lock().await;
+        while let Some(result) = tasks.try_join_next() {
+            self.finished(result);
+        }
+        while tasks.len() >= MAX_TASKS {
+            match tasks.join_next().await {
+                Some(result) => self.finished(result),
+                None => break,
+            }
+        }
+        spawn_named_in(&mut tasks, &self.source_id, "delivery", delivery);
+    }
+
+    /// Wait for the running deliveries to finish, aborting any still
+    /// running at `deadline`
+    pub async fn drain(&self, deadline: Instant) {
+        let mut tasks = self.tasks.lock().await;
+        let drained = tokio::time::timeout_at(deadline, async {
+            while let Some(result) = tasks.join_next().await {
+                self.finished(result);
+            }
+        })
+        .await;
+        if drained.is_err() {
+            warn!(
+                "Aborting {} delivery task(s) for component {} still running at the teardown deadline",
+                tasks.len(),
+                self.source_id
+            );
+            tasks.shutdown().await;
+        }
+    }
+
+    /// Count a failed or panicked delivery
+    fn finished(&self, result: Result<anyhow::Result<()>, JoinError>) {
+        match result {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                self.stats.record_delivery_failure();
+                error!(
+                    "Failed to send message to component {}: {:#}",
+                    self.source_id, e
+                );
+            }
+            Err(e) if e.is_panic() => {
+                self.stats.record_delivery_failure();
+                error!(
+                    "Delivery task for component {} panicked: {}",
+                    self.source_id, e
+                );
+            }
+            // Aborted on shutdown
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use tokio::sync::Semaphore;
+
+    use super::*;
+
+    fn delivery_tasks() -> (DeliveryTasks, Arc<ConnectionStats>) {
+        let stats = Arc::new(ConnectionStats::default());
+        (DeliveryTasks::new("comp".into(), stats.clone()), stats)
+    }
+
+    #[tokio::test]
+    async fn failed_and_panicked_deliveries_are_counted() {
+        let (tasks, stats) = delivery_tasks();
+        tasks.spawn(async { Ok(()) }).await;
+        tasks
+            .spawn(async { anyhow::bail!("component error") })
+            .await;
+        tasks.spawn(async { panic!("sink panicked") }).await;
+        tasks.drain(Instant::now() + Duration::from_secs(5)).await;
+        assert_eq!(stats.snapshot().delivery_failures, 2);
+    }
+
+    #[tokio::test]
+    async fn spawning_waits_while_the_set_is_full() {
+        let (tasks, _) = delivery_tasks();
+        let tasks = Arc::new(tasks);
+        let gate = Arc::new(Semaphore::new(0));
+        let started = Arc::new(AtomicUsize::new(0));
+        for _ in 0..MAX_TASKS {
+            let (gate, started) = (gate.clone(), started.clone());
+            tasks
+                .spawn(async move {
+                    started.fetch_add(1, Ordering::SeqCst);
+                    let _permit = gate.acquire().await?;
+                    Ok(())
+                })
+                .await;
+        }
+
+        let extra = {
+            let (tasks, started) = (tasks.clone(), started.clone());
+            tokio::spawn(async move {
+                tasks
+                    .spawn(async move {
+                        started.fetch_add(1, Ordering::SeqCst);
+                        Ok(())
+                    })
+                    .await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!extra.is_finished());
+        assert_eq!(started.load(Ordering::SeqCst), MAX_TASKS);
+
+        // One finishing makes room
+        gate.add_permits(1);
+        tokio::time::timeout(Duration::from_secs(5), extra)
+            .await
+            .unwrap()
+            .unwrap();
+        gate.add_permits(MAX_TASKS);
+        tasks.drain(Instant::now() + Duration::from_secs(5)).await;
+        assert_eq!(started.load(Ordering::SeqCst), MAX_TASKS + 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn draining_aborts_deliveries_still_running_at_the_deadline() {
+        let (tasks, stats) = delivery_tasks();
+        let finished = Arc::new(AtomicUsize::new(0));
+        for delay in [1, 2, 60] {
+            let finished = finished.clone();
+            tasks
+                .spawn(async move {
+                    tokio::time::sleep(Duration::from_secs(delay)).await;
+                    finished.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                })
+                .await;
+        }
+
+        let start = Instant::now();
+        tasks.drain(start + Duration::from_secs(5)).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+        assert_eq!(finished.load(Ordering::SeqCst), 2);
+        // Aborting isn't a failed delivery
+        assert_eq!(stats.snapshot().delivery_failures, 0);
+        assert!(tasks.tasks.lock().await.is_empty());
+    }
+}
@@ -1,36 +1,271 @@
-use crate::config::LinkConfig;
-use tokio::io::AsyncReadExt;
-use tokio::net::TcpStream;
-use tokio::time::sleep;
-use tracing::{debug, error, info, warn};
+use crate::audit::{AuditEvent, LinkAudit};
+use crate::backoff::Backoff;
+use crate::blocklist::SentLine;
+use crate::config::{LinkConfig, NegotiationTimeoutAction};
+use crate::error::{TelnetError, TelnetErrorKind};
+use crate::hexdump::{HexDump, Preview};
+use crate::iac::{filter_telnet_commands, filter_telnet_commands_in_place};
+use crate::login::{Expect, LoginConfig};
+use crate::mccp::{self, MccpReader, MccpTransition};
+use crate::msdp::{self, MsdpReader, MsdpVariables};
+use crate::platform::SocketTuning;
+use crate::readbuf::ReadSizer;
+use crate::record::SessionRecorder;
+use crate::resolve::{resolve, ResolutionCache};
+use crate::sentinel::SentinelMatcher;
+use crate::server_type::ServerType;
+use crate::socket::{DefaultConnector, SocketReader, SocketWriter, TcpConnector};
+use crate::stats::{ConnectionStats, LinkState};
+use crate::throttle::ConnectLimiter;
+use crate::verbosity::TraceGate;
+use bytes::{Bytes, BytesMut};
+use futures::future::BoxFuture;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{mpsc, watch, Mutex};
+use tokio::time::{sleep, sleep_until, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, enabled, error, info, trace, warn, Level};
+
+/// Write half of the current connection, shared with the provider so data
+/// can be written while the read loop runs; `None` while disconnected
+pub type SharedWriter = Arc<Mutex<Option<SocketWriter>>>;
+
+/// End of the last line sent on a connection, shared like its writer
+pub type SharedSentLine = Arc<std::sync::Mutex<SentLine>>;
+
+/// Bytes of received data shown in the TRACE preview of each read
+const PREVIEW_MAX_BYTES: usize = 64;
+
+/// How long server type detection waits for the banner to end in a prompt
+const DETECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Request to a running connection from outside its task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionCommand {
+    /// Drop the current connection and reconnect right away
+    Reconnect,
+}
+
+/// Receives the variables of each MSDP frame; the read loop waits for the
+/// returned future before reading on
+pub type MsdpHandler = Arc<dyn Fn(MsdpVariables) -> BoxFuture<'static, ()> + Send + Sync>;
 
 /// Telnet client handler
 pub struct TelnetClient {
-    config: LinkConfig,
+    config: Arc<LinkConfig>,
+    stats: Arc<ConnectionStats>,
+    /// While this signal is `true` the client stays disconnected
+    hold: Option<watch::Receiver<bool>>,
+    /// Audit log of negotiations and connection events, if enabled
+    audit: Option<LinkAudit>,
+    /// Write half of the current connection
+    writer: SharedWriter,
+    /// Unterminated line sent so far, checked with the next send against
+    /// `command_blocklist`
+    sent_line: SharedSentLine,
+    /// Receiver of MSDP variables, if MSDP is enabled
+    msdp: Option<MsdpHandler>,
+    /// Commands for the running connection
+    commands: Option<Mutex<mpsc::Receiver<ConnectionCommand>>>,
+    /// Reloaded config, of which the running connection adopts the live keys
+    updates: Option<watch::Receiver<Arc<LinkConfig>>>,
+    /// Provider-wide limit on concurrent connection attempts, if any
+    connect_limiter: Option<Arc<ConnectLimiter>>,
+    /// Last resolution of the host, reused by quick reconnection attempts
+    resolutions: std::sync::Mutex<ResolutionCache>,
+    /// Opens the connection's sockets
+    connector: Arc<dyn TcpConnector>,
 }
 
 impl TelnetClient {
-    /// Create a new Telnet client
-    pub fn new(config: LinkConfig) -> Self {
-        Self { config }
+    /// Create a new Telnet client reporting into the given stats
+    pub fn new(config: Arc<LinkConfig>, stats: Arc<ConnectionStats>) -> Self {
+        let resolutions = ResolutionCache::new(config.dns_cache());
+        Self {
+            config,
+            stats,
+            hold: None,
+            audit: None,
+            writer: SharedWriter::default(),
+            sent_line: SharedSentLine::default(),
+            msdp: None,
+            commands: None,
+            updates: None,
+            connect_limiter: None,
+            resolutions: std::sync::Mutex::new(resolutions),
+            connector: Arc::new(DefaultConnector),
+        }
+    }
+
+    /// Open sockets with `connector` instead of the default backend
+    pub fn with_connector(mut self, connector: Arc<dyn TcpConnector>) -> Self {
+        self.connector = connector;
+        self
+    }
+
+    /// Disconnect and stay disconnected whenever `hold` is `true`
+    pub fn with_hold(mut self, hold: watch::Receiver<bool>) -> Self {
+        self.hold = Some(hold);
+        self
+    }
+
+    /// Publish the connection's write half in `writer`
+    pub fn with_writer(mut self, writer: SharedWriter) -> Self {
+        self.writer = writer;
+        self
+    }
+
+    /// Check sends against `command_blocklist` together with the end of the
+    /// line sent so far in `sent_line`, shared by all senders on the
+    /// connection
+    pub fn with_sent_line(mut self, sent_line: SharedSentLine) -> Self {
+        self.sent_line = sent_line;
+        self
+    }
+
+    /// Pass the variables of each MSDP frame to `handler`
+    ///
+    /// Only takes effect with `msdp_enabled` set in the link config.
+    pub fn with_msdp(mut self, handler: MsdpHandler) -> Self {
+        self.msdp = Some(handler);
+        self
+    }
+
+    /// Act on commands sent to `commands` while connected
+    pub fn with_commands(mut self, commands: mpsc::Receiver<ConnectionCommand>) -> Self {
+        self.commands = Some(Mutex::new(commands));
+        self
+    }
+
+    /// Adopt changes to live keys ([`LIVE_KEYS`](crate::config::LIVE_KEYS))
+    /// sent to `updates` between reads
+    pub fn with_config_updates(mut self, updates: watch::Receiver<Arc<LinkConfig>>) -> Self {
+        self.updates = Some(updates);
+        self
+    }
+
+    /// Take a permit from `limiter` for each connection attempt
+    pub fn with_connect_limiter(mut self, limiter: Arc<ConnectLimiter>) -> Self {
+        self.connect_limiter = Some(limiter);
+        self
+    }
+
+    /// Record negotiations and connection events in `audit`
+    pub fn with_audit(mut self, audit: LinkAudit) -> Self {
+        self.audit = Some(audit);
+        self
     }
 
     /// Connect to the Telnet server and start receiving messages
-    pub async fn run<F>(&self, mut message_handler: F) -> anyhow::Result<()>
+    ///
+    /// Runs until the connection closes normally, reconnection attempts are
+    /// exhausted, or `cancel` is triggered. Messages are handled in order,
+    /// one handler future at a time. Reading continues while a message is
+    /// handled, up to `read_ahead` messages ahead, so a slow handler only
+    /// slows reading down once those are buffered. The handler gets each
+    /// message with the time of the socket read it came from.
+    pub async fn run<F, Fut>(
+        &self,
+        cancel: CancellationToken,
+        mut message_handler: F,
+    ) -> Result<(), TelnetError>
     where
-        F: FnMut(Vec<u8>) -> anyhow::Result<()> + Send,
+        F: FnMut(Bytes, std::time::Instant) -> Fut + Send,
+        Fut: Future<Output = anyhow::Result<()>> + Send,
     {
         let mut reconnect_attempts = 0u32;
-        let mut current_delay = self.config.initial_reconnect_delay();
+        let mut backoff = Backoff::new(
+            self.config.initial_reconnect_delay(),
+            self.config.max_reconnect_delay(),
+            self.config.backoff_jitter_mode,
+        );
+        let mut hold = self.hold.clone();
 
         loop {
-            match self.connect_and_receive(&mut message_handler).await {
+            // Stay disconnected while held
+            if hold.as_ref().is_some_and(|rx| *rx.borrow()) {
+                info!("Telnet connection held");
+                tokio::select! {
+                    _ = cancel.cancelled() => {
+                        info!("Telnet client stopped while held");
+                        return Ok(());
+                    }
+                    _ = hold_is(&mut hold, false) => {
+                        info!("Telnet connection released");
+                    }
+                }
+            }
+
+            let result = tokio::select! {
+                _ = cancel.cancelled() => {
+                    self.disconnected().await;
+                    info!("Telnet client stopped");
+                    self.audit(AuditEvent::Disconnected { reason: "stopped" });
+                    return Ok(());
+                }
+                _ = hold_is(&mut hold, true) => {
+                    self.disconnected().await;
+                    self.audit(AuditEvent::Disconnected { reason: "held" });
+                    continue;
+                }
+                command = next_command(&self.commands) => match command {
+                    ConnectionCommand::Reconnect => Err(TelnetError::ReconnectRequested),
+                },
+                result = self.connect_and_receive(&mut message_handler) => result,
+            };
+            self.disconnected().await;
+
+            match result {
                 Ok(_) => {
                     info!("Telnet connection closed normally");
                     break Ok(());
                 }
                 Err(e) => {
-                    error!("Telnet connection error: {}", e);
+                    let kind = e.kind();
+                    self.stats.record_error(kind);
+                    self.audit(AuditEvent::Disconnected {
+                        reason: kind.as_str(),
+                    });
+
+                    // Planned outage: wait it out quietly without using up
+                    // reconnection attempts
+                    if let Some(remaining) = self.blackout_remaining() {
+                        debug!(kind = kind.as_str(), "Telnet connection error: {}", e);
+                        info!("Inside reconnect blackout, holding for {:?}", remaining);
+                        self.stats.set_state(LinkState::Blackout);
+                        tokio::select! {
+                            _ = cancel.cancelled() => {
+                                info!("Telnet client stopped during reconnect blackout");
+                                return Ok(());
+                            }
+                            _ = sleep(remaining) => {}
+                        }
+                        self.stats.set_state(LinkState::Active);
+                        info!("Reconnect blackout ended, resuming reconnection");
+                        reconnect_attempts = 0;
+                        backoff.reset();
+                        continue;
+                    }
+
+                    // The server (or the stall watchdog) asked for a fresh
+                    // session: reconnect right away with the backoff reset
+                    if kind == TelnetErrorKind::ReconnectRequested {
+                        info!("Reconnecting on request");
+                        reconnect_attempts = 0;
+                        backoff.reset();
+                        continue;
+                    }
+
+                    if kind == TelnetErrorKind::Idle {
+                        warn!("Telnet connection idle, reconnecting: {}", e);
+                    } else if kind.is_clean_close() {
+                        info!("Telnet connection closed by server");
+                    } else {
+                        error!(kind = kind.as_str(), "Telnet connection error: {}", e);
+                    }
 
                     // Check if we should retry
                     if self.config.max_reconnect_attempts > 0
@@ -43,66 +278,758 @@ impl TelnetClient {
                         return Err(e);
                     }
 
+                    // A connection that closed cleanly did work, so start
+                    // the backoff over
+                    if kind.is_clean_close() {
+                        backoff.reset();
+                    }
+
                     reconnect_attempts += 1;
+                    let delay = backoff.next_delay();
                     warn!(
                         "Attempting reconnection #{} after {:?}",
-                        reconnect_attempts, current_delay
+                        reconnect_attempts, delay
                     );
 
-                    sleep(current_delay).await;
+                    tokio::select! {
+                        _ = cancel.cancelled() => {
+                            info!("Telnet client stopped during reconnection backoff");
+                            return Ok(());
+                        }
+                        _ = sleep(delay) => {}
+                    }
+                }
+            }
+        }
+    }
 
-                    // Exponential backoff with max limit
-                    current_delay =
-                        std::cmp::min(current_delay * 2, self.config.max_reconnect_delay());
+    /// Run the login script, answering each prompt in turn
+    ///
+    /// Output received before the last response is consumed here and not
+    /// forwarded. Each prompt must appear within the login timeout; the
+    /// step delay starts once it has, so it never counts against the timeout.
+    /// Output read past a prompt is kept for the next step, so a `#len:<n>`
+    /// read counts bytes that arrived along with the prompt before it.
+    async fn login(
+        &self,
+        reader: &mut SocketReader,
+        login: &LoginConfig,
+        recorder: Option<&SessionRecorder>,
+        mut mccp: Option<&mut MccpReader>,
+    ) -> Result<(), TelnetError> {
+        let mut buf = vec![0u8; 4096];
+        // Output read but not yet matched by a step, Telnet commands removed
+        let mut seen = Vec::new();
+
+        for step in login.steps() {
+            match step.expect {
+                Expect::Bytes(n) => {
+                    let carried = seen.len().min(*n);
+                    let mut received: Vec<u8> = seen.drain(..carried).collect();
+                    if received.len() < *n {
+                        let rest = self
+                            .receive_exactly(reader, *n - received.len(), login.timeout_ms)
+                            .await?;
+                        if let Some(recorder) = recorder {
+                            recorder.record(&rest);
+                        }
+                        received.extend_from_slice(&rest);
+                    }
+                    debug!(
+                        "Login step received {} bytes ({} carried over)",
+                        received.len(),
+                        carried
+                    );
+                }
+                Expect::Prompt(prompt) => {
+                    let end = tokio::time::timeout(login.timeout(), async {
+                        loop {
+                            if let Some(end) = prompt_end(&seen, prompt) {
+                                return Ok::<_, TelnetError>(end);
+                            }
+                            let n = reader.read(&mut buf).await?;
+                            if n == 0 {
+                                return Err(TelnetError::ServerClosed);
+                            }
+                            if let Some(recorder) = recorder {
+                                recorder.record(&buf[..n]);
+                            }
+                            self.audit_negotiations(&buf[..n]);
+                            // Accepted once logged in, so the script never
+                            // reads compressed data
+                            if let Some(mccp) = mccp.as_deref_mut() {
+                                mccp.note_offers(&buf[..n]);
+                            }
+                            let replies = self.config.environ.replies(&buf[..n]);
+                            self.write(&replies).await?;
+                            seen.extend_from_slice(&filter_telnet_commands(&buf[..n]));
+                        }
+                    })
+                    .await
+                    .inspect_err(|_| warn!("Timed out waiting for login prompt {:?}", prompt))??;
+                    seen.drain(..end);
+                    debug!("Login prompt {:?} seen", prompt);
                 }
             }
+
+            sleep(step.delay).await;
+            self.send_line(step.response).await?;
+        }
+
+        info!("Logged in as {}", login.username);
+        self.audit(AuditEvent::Login {
+            username: login.username.clone(),
+        });
+        Ok(())
+    }
+
+    /// Read exactly `n` raw bytes from the server within `timeout_ms`
+    ///
+    /// Used by the login script for fixed-length handshake fields, where
+    /// matching on text isn't possible. Bytes are returned unfiltered.
+    ///
+    /// Takes the connection's read half rather than reading through
+    /// `&mut self`: the client doesn't own one, since each reconnect makes a
+    /// new connection while the client outlives them all. Errors
+    /// are [`TelnetError`]s so a failed login is classified like any other
+    /// connection failure; they convert into `anyhow::Error` with `?`.
+    ///
+    /// `n` must be between 1 and `max_message_size`, as link config already
+    /// ensures for `#len:<n>`; anything else fails with
+    /// [`std::io::ErrorKind::InvalidInput`] without reading.
+    pub async fn receive_exactly(
+        &self,
+        reader: &mut SocketReader,
+        n: usize,
+        timeout_ms: u64,
+    ) -> Result<Vec<u8>, TelnetError> {
+        if n == 0 || n > self.config.max_message_size {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "cannot read exactly {} bytes: must be between 1 and max_message_size {}",
+                    n, self.config.max_message_size
+                ),
+            )
+            .into());
+        }
+        let mut data = vec![0u8; n];
+        tokio::time::timeout(
+            std::time::Duration::from_millis(timeout_ms),
+            reader.read_exact(&mut data),
+        )
+        .await??;
+        if self.config.debug_hexdump && enabled!(Level::TRACE) {
+            trace!(
+                direction = "in",
+                "read exactly {} bytes\n{}",
+                n,
+                HexDump::new(&data, self.config.hexdump_max_bytes)
+            );
+        }
+        Ok(data)
+    }
+
+    /// Open a separate connection to the server and read its banner
+    ///
+    /// Reads until a blank line (`\r\n\r\n`) ends the banner, the server
+    /// closes the connection, or `timeout_ms` has passed, answering option
+    /// negotiation as the login script does. Returns the banner up to the
+    /// blank line with Telnet commands filtered out, which is empty if the
+    /// server sent no data in time. Fails with a [`TelnetError`] if the
+    /// server can't be reached within the timeout.
+    pub async fn banner_wait(&self, timeout_ms: u64) -> anyhow::Result<Vec<u8>> {
+        let timeout = std::time::Duration::from_millis(timeout_ms);
+        let banner = self
+            .read_greeting(timeout, |banner| {
+                memchr::memmem::find(banner, b"\r\n\r\n").map(|end| end + 4)
+            })
+            .await?;
+        Ok(banner)
+    }
+
+    /// Read the server's banner on a separate connection and guess the kind
+    /// of server from it
+    ///
+    /// The connection is opened like the client's own, through its connector
+    /// and connect limiter. Reads until the banner ends in a prompt, the server closes the
+    /// connection, or [`DETECT_TIMEOUT`] has passed. Returns
+    /// [`ServerType::Unknown`] if nothing matches or the server can't be
+    /// reached.
+    pub async fn detect_server_type(&self) -> ServerType {
+        let greeting = self
+            .read_greeting(DETECT_TIMEOUT, |banner| {
+                ends_with_prompt(banner).then_some(banner.len())
+            })
+            .await;
+        let banner = match greeting {
+            Ok(banner) => banner,
+            Err(e) => {
+                debug!(
+                    "Failed to read banner of {}:{} for server type detection: {}",
+                    self.config.telnet_host, self.config.telnet_port, e
+                );
+                return ServerType::Unknown;
+            }
+        };
+        let (server_type, matched) = ServerType::detect(&banner);
+        info!(
+            "Detected server type {} for {}:{} (matched {:?})",
+            server_type.as_str(),
+            self.config.telnet_host,
+            self.config.telnet_port,
+            matched
+        );
+        server_type
+    }
+
+    /// Connect within `timeout` and read what the server sends, answering
+    /// option negotiation, until `end` returns where the greeting ends, the
+    /// server closes the connection, or the timeout passes
+    async fn read_greeting(
+        &self,
+        timeout: std::time::Duration,
+        end: impl Fn(&[u8]) -> Option<usize>,
+    ) -> Result<Vec<u8>, TelnetError> {
+        let deadline = Instant::now() + timeout;
+        // Held while resolving and connecting only, as for a session
+        let permit = match &self.connect_limiter {
+            Some(limiter) => tokio::time::timeout_at(deadline, limiter.acquire()).await?,
+            None => None,
+        };
+        let addrs = resolve(
+            &self.config.telnet_host,
+            self.config.telnet_port,
+            self.config.ip_family,
+        )
+        .await
+        .map_err(|e| TelnetError::Dns {
+            host: self.config.telnet_host.clone(),
+            reason: format!("{:#}", e),
+        })?;
+        let tuning = SocketTuning::from_config(&self.config);
+        let mut socket =
+            tokio::time::timeout_at(deadline, self.connector.connect(&addrs, tuning)).await??;
+        drop(permit);
+
+        let mut banner = Vec::new();
+        let mut buf = vec![0u8; 4096];
+        // Stop at the deadline with whatever has arrived
+        while let Ok(read) = tokio::time::timeout_at(deadline, socket.reader.read(&mut buf)).await {
+            let n = read?;
+            if n == 0 {
+                break;
+            }
+            let replies = self.config.environ.replies(&buf[..n]);
+            if !replies.is_empty()
+                && tokio::time::timeout_at(deadline, socket.writer.write_all(&replies))
+                    .await
+                    .is_err()
+            {
+                break;
+            }
+            banner.extend_from_slice(&filter_telnet_commands(&buf[..n]));
+            if let Some(end) = end(&banner) {
+                banner.truncate(end);
+                break;
+            }
+        }
+
+        let _ = socket.writer.shutdown().await;
+        Ok(banner)
+    }
+
+    /// Send a line of text to the server
+    ///
+    /// Bare `\n` line breaks become `\r\n` and a missing final line ending
+    /// is added (an empty string sends an empty line). The line is then sent
+    /// with IAC bytes escaped by [`send_bytes`](Self::send_bytes).
+    pub async fn send_line(&self, text: &str) -> Result<(), TelnetError> {
+        self.send_bytes(&crlf_line(text.as_bytes())).await
+    }
+
+    /// Send data to the server with IAC (0xFF) bytes escaped
+    ///
+    /// Fails with [`TelnetError::Blocked`] without writing anything if the
+    /// data contains a command from the link's `command_blocklist`, and
+    /// with [`TelnetError::Timeout`] if the write doesn't complete within
+    /// the link's `write_timeout_ms`.
+    pub async fn send_bytes(&self, data: &[u8]) -> Result<(), TelnetError> {
+        self.check_blocklist(data)?;
+        self.send_escaped(&escape_for_telnet(data)).await
+    }
+
+    /// Refuse data containing a command from `command_blocklist`, on its
+    /// own or completing the line sent so far
+    fn check_blocklist(&self, data: &[u8]) -> Result<(), TelnetError> {
+        let Some(blocklist) = &self.config.command_blocklist else {
+            return Ok(());
+        };
+        match blocklist.check(&mut self.sent_line(), data) {
+            Some(command) => {
+                warn!("Blocked command {:?} to {}", command, self.config.address());
+                Err(TelnetError::Blocked(command.to_string()))
+            }
+            None => Ok(()),
         }
     }
 
+    fn sent_line(&self) -> std::sync::MutexGuard<'_, SentLine> {
+        self.sent_line.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Write already escaped data within the link's `write_timeout_ms`
+    async fn send_escaped(&self, data: &[u8]) -> Result<(), TelnetError> {
+        match self.config.write_timeout() {
+            Some(timeout) => tokio::time::timeout(timeout, self.write(data)).await?,
+            None => self.write(data).await,
+        }
+    }
+
+    /// Write to the current connection; does nothing for empty data
+    async fn write(&self, data: &[u8]) -> Result<(), TelnetError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        match self.writer.lock().await.as_mut() {
+            Some(writer) => Ok(writer.write_all(data).await?),
+            None => Err(TelnetError::ServerClosed),
+        }
+    }
+
+    /// Queue an audit record if auditing is enabled
+    fn audit(&self, event: AuditEvent) {
+        if let Some(audit) = &self.audit {
+            audit.record(event);
+        }
+    }
+
+    /// Record every option negotiation in raw received data
+    fn audit_negotiations(&self, data: &[u8]) {
+        if !self.audit.as_ref().is_some_and(LinkAudit::is_enabled) {
+            return;
+        }
+        for (command, option) in negotiations(data) {
+            let response = self
+                .config
+                .environ
+                .response_to(command, option)
+                .or_else(|| {
+                    self.config
+                        .msdp_enabled
+                        .then(|| msdp::response_to(command, option))
+                        .flatten()
+                })
+                .or_else(|| {
+                    self.config
+                        .mccp_enabled
+                        .then(|| mccp::response_to(command, option))
+                        .flatten()
+                });
+            self.audit(AuditEvent::Negotiation {
+                direction: "received",
+                command: command_name(command),
+                option,
+                response,
+            });
+        }
+    }
+
+    /// Time left in the current reconnect blackout window, if inside one
+    fn blackout_remaining(&self) -> Option<std::time::Duration> {
+        self.config
+            .reconnect_blackout
+            .as_ref()
+            .and_then(|blackout| blackout.remaining())
+    }
+
     /// Connect to Telnet server and receive messages
-    async fn connect_and_receive<F>(&self, message_handler: &mut F) -> anyhow::Result<()>
+    async fn connect_and_receive<F, Fut>(&self, message_handler: &mut F) -> Result<(), TelnetError>
     where
-        F: FnMut(Vec<u8>) -> anyhow::Result<()>,
+        F: FnMut(Bytes, std::time::Instant) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>>,
     {
         let address = self.config.address();
+
+        // Held while resolving and connecting only; released before the
+        // session starts or the attempt's error is returned
+        let permit = match &self.connect_limiter {
+            Some(limiter) => limiter.acquire().await,
+            None => None,
+        };
         info!("Connecting to Telnet server: {}", address);
 
-        let mut stream = TcpStream::connect(&address).await?;
+        let addrs = self.resolve_cached().await?;
+        let tuning = SocketTuning::from_config(&self.config);
+        let socket = match self.connector.connect(&addrs, tuning).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                self.resolutions().connect_failed(&e);
+                return Err(e.into());
+            }
+        };
+        drop(permit);
 
         info!("Telnet connection established to {}", address);
+        self.stats.set_connected(true);
+        let epoch = self.stats.next_epoch();
+        self.audit(AuditEvent::Connected {
+            address: address.clone(),
+            peer: socket.peer.to_string(),
+        });
 
-        let mut buf = vec![0u8; 4096];
+        // The write half is shared for replies and anything else that needs
+        // to send while reading
+        *self.writer.lock().await = Some(socket.writer);
+        self.sent_line().clear();
+        self.receive(socket.reader, epoch, message_handler).await
+    }
+
+    /// Start recording the connection if `session_record_dir` is set; a
+    /// recording that can't be created is logged and the session goes on
+    /// without one
+    async fn session_recorder(&self) -> Option<SessionRecorder> {
+        let dir = self.config.session_record_dir.as_ref()?;
+        match SessionRecorder::create(
+            std::path::Path::new(dir),
+            &self.config.telnet_host,
+            self.config.telnet_port,
+            self.config.session_record_compress,
+        )
+        .await
+        {
+            Ok(recorder) => Some(recorder),
+            Err(e) => {
+                warn!("Not recording session: {:#}", e);
+                None
+            }
+        }
+    }
+
+    /// Resolve the server's host, reusing the last answer within
+    /// `dns_cache_ms`
+    async fn resolve_cached(&self) -> Result<Vec<SocketAddr>, TelnetError> {
+        if let Some(addrs) = self.resolutions().get(Instant::now()) {
+            debug!("Reusing cached resolution of {}", self.config.telnet_host);
+            return Ok(addrs);
+        }
+        let addrs = resolve(
+            &self.config.telnet_host,
+            self.config.telnet_port,
+            self.config.ip_family,
+        )
+        .await
+        .map_err(|e| TelnetError::Dns {
+            host: self.config.telnet_host.clone(),
+            reason: format!("{:#}", e),
+        })?;
+        self.resolutions().insert(addrs.clone(), Instant::now());
+        Ok(addrs)
+    }
+
+    fn resolutions(&self) -> std::sync::MutexGuard<'_, ResolutionCache> {
+        self.resolutions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Mark the connection as gone and release its write half, closing the
+    /// socket even if the read loop was interrupted
+    async fn disconnected(&self) {
+        self.stats.set_connected(false);
+        self.writer.lock().await.take();
+    }
+
+    /// Log in if configured, then read and forward data until the connection
+    /// ends, sending each read, as it arrived, to `recorder`; with
+    /// `mccp_enabled`, reads are decompressed first
+    async fn receive<F, Fut>(
+        &self,
+        mut reader: SocketReader,
+        epoch: u64,
+        message_handler: &mut F,
+    ) -> Result<(), TelnetError>
+    where
+        F: FnMut(Bytes, std::time::Instant) -> Fut,
+        Fut: Future<Output = anyhow::Result<()>>,
+    {
+        let recorder = self.session_recorder().await;
+        let mut mccp = self.config.mccp_enabled.then(MccpReader::new);
+        if let Some(login) = &self.config.login {
+            self.login(&mut reader, login, recorder.as_ref(), mccp.as_mut())
+                .await?;
+        }
+
+        // Reading runs up to `read_ahead` messages ahead of handling, so a
+        // message that takes long to forward doesn't leave the socket unread.
+        // Both stay in this future, so a connection is one task whether it
+        // has a task of its own or shares one with `shared_reader_tasks`.
+        let (messages_tx, mut messages_rx) = mpsc::channel(self.config.read_ahead);
+        let reading = async {
+            let result = self
+                .read_messages(&mut reader, epoch, messages_tx, recorder.as_ref(), mccp)
+                .await;
+            // The recording is complete by the time the session ends
+            if let Some(recorder) = recorder {
+                recorder.finish().await;
+            }
+            result
+        };
+        tokio::pin!(reading);
+        let handling = async {
+            while let Some((message, received)) = messages_rx.recv().await {
+                self.stats.record_message();
+                message_handler(message, received)
+                    .await
+                    .map_err(TelnetError::Handler)?;
+            }
+            Ok::<_, TelnetError>(())
+        };
+        tokio::pin!(handling);
+
+        tokio::select! {
+            read_result = &mut reading => {
+                // Hand off everything read before reading stopped
+                handling.await?;
+                read_result
+            }
+            // Handling finishes first if a message failed, or if reading
+            // closed the channel while finishing the recording, in which
+            // case reading still has to say why
+            result = &mut handling => {
+                result?;
+                reading.await
+            }
+        }
+    }
+
+    /// Read, answer negotiation and filter data until the connection ends,
+    /// sending each message to `messages` in order
+    async fn read_messages(
+        &self,
+        reader: &mut SocketReader,
+        epoch: u64,
+        messages: mpsc::Sender<(Bytes, std::time::Instant)>,
+        recorder: Option<&SessionRecorder>,
+        mut mccp: Option<MccpReader>,
+    ) -> Result<(), TelnetError> {
+        let mut sizer = ReadSizer::new(self.config.read_buffer_size, self.config.max_message_size);
+        self.stats.set_read_buffer_size(sizer.size());
+        let mut buf = BytesMut::with_capacity(sizer.size());
+        let mut sentinel = self
+            .config
+            .reconnect_sentinel
+            .as_ref()
+            .map(|sentinel| SentinelMatcher::new(sentinel.as_bytes()));
+        let mut msdp_reader = self
+            .msdp
+            .as_ref()
+            .filter(|_| self.config.msdp_enabled)
+            .map(|handler| (MsdpReader::new(self.config.max_message_size), handler));
+        let idle_reconnect_after = self.config.idle_reconnect_after();
+        let mut last_received_at = Instant::now();
+        // Negotiation is bounded until the first data arrives
+        let mut negotiation_deadline = self
+            .config
+            .negotiation_timeout()
+            .map(|timeout| (Instant::now() + timeout, timeout));
+        let mut answer_negotiation = true;
+        let mut trace_gate = TraceGate::new();
+        let mut updates = self.updates.clone();
+        let mut live = self.config.clone();
+        // Whether compression was negotiated is settled by the first data
+        let mut compression_settled = mccp.is_none();
+
+        // Answer an offer of compression made while logging in
+        if let Some(reply) = mccp.as_mut().and_then(MccpReader::accept) {
+            info!("Server offered MCCP compression, accepting");
+            self.write(reply).await?;
+        }
 
         // Receive data
         loop {
-            match stream.read(&mut buf).await {
+            if let Some(updates) = updates
+                .as_mut()
+                .filter(|rx| rx.has_changed().unwrap_or(false))
+            {
+                live = updates.borrow_and_update().clone();
+                sizer.set_max(live.max_message_size);
+                if let Some((reader, _)) = &mut msdp_reader {
+                    reader.set_max_frame_size(live.max_message_size);
+                }
+            }
+
+            // Reuses the previous read's allocation once it has been
+            // released, without zeroing it
+            let size = sizer.size();
+            buf.reserve(size);
+            let result = tokio::select! {
+                result = reader.read_buf(&mut buf) => result,
+                _ = idle_deadline(last_received_at, idle_reconnect_after) => {
+                    return Err(TelnetError::Idle(last_received_at.elapsed()));
+                }
+            };
+            match result {
                 Ok(0) => {
-                    info!("Telnet connection closed by server");
-                    return Err(anyhow::anyhow!("Connection closed"));
+                    return Err(TelnetError::ServerClosed);
                 }
                 Ok(n) => {
-                    let data = buf[..n].to_vec();
+                    last_received_at = Instant::now();
+                    // Latency is measured from here, queueing included
+                    let received = last_received_at.into_std();
+                    self.stats.record_bytes_in(n);
+                    sizer.record(n);
+                    if sizer.size() != size {
+                        self.stats.set_read_buffer_size(sizer.size());
+                    }
+                    // Previews and dumps below are only rendered when traced
+                    let traced = trace_gate.enabled();
+                    if traced && live.debug_hexdump {
+                        trace!(
+                            direction = "in",
+                            epoch,
+                            "read {} bytes\n{}",
+                            n,
+                            HexDump::new(&buf, live.hexdump_max_bytes)
+                        );
+                    }
 
-                    // Filter out Telnet negotiation bytes (IAC sequences)
-                    let filtered = filter_telnet_commands(&data);
+                    // Everything below reads the uncompressed stream
+                    if let Some(mccp) = &mut mccp {
+                        let raw = std::mem::take(&mut buf);
+                        let mut plain = Vec::with_capacity(raw.len());
+                        for transition in mccp
+                            .decode(&raw, &mut plain)
+                            .map_err(TelnetError::Compression)?
+                        {
+                            match transition {
+                                MccpTransition::Started => info!("MCCP compression started"),
+                                MccpTransition::Ended => {
+                                    info!("MCCP compression ended by the server, reading uncompressed")
+                                }
+                            }
+                        }
+                        buf = BytesMut::from(&plain[..]);
+                        mccp.note_offers(&buf);
+                    }
+                    if let Some(recorder) = recorder {
+                        recorder.record(&buf);
+                    }
+
+                    // Answer NEW-ENVIRON negotiation if variables are
+                    // configured, and MSDP negotiation if enabled
+                    self.audit_negotiations(&buf);
+                    let mut replies = if answer_negotiation {
+                        self.config.environ.replies(&buf)
+                    } else {
+                        Vec::new()
+                    };
+                    if let Some((reader, handler)) = &mut msdp_reader {
+                        if answer_negotiation {
+                            replies.extend(msdp::replies(&buf));
+                        }
+                        for variables in reader.push(&buf) {
+                            handler(variables).await;
+                        }
+                    }
+                    if let Some(reply) = mccp
+                        .as_mut()
+                        .filter(|_| answer_negotiation)
+                        .and_then(MccpReader::accept)
+                    {
+                        info!("Server offered MCCP compression, accepting");
+                        replies.extend_from_slice(reply);
+                    }
+                    if !replies.is_empty() {
+                        trace!("Sending option negotiation: {} bytes", replies.len());
+                        if traced && live.debug_hexdump {
+                            trace!(
+                                direction = "out",
+                                epoch,
+                                "write {} bytes\n{}",
+                                replies.len(),
+                                HexDump::new(&replies, live.hexdump_max_bytes)
+                            );
+                        }
+                        self.write(&replies).await?;
+                    }
+
+                    // Filter out Telnet negotiation bytes (IAC sequences) in
+                    // the read buffer itself, then hand it off without copying
+                    let len = filter_telnet_commands_in_place(&mut buf);
+                    buf.truncate(len);
+                    let filtered = buf.split().freeze();
+
+                    if !compression_settled && !filtered.is_empty() {
+                        compression_settled = true;
+                        if !mccp.as_ref().is_some_and(MccpReader::offered) {
+                            if self.config.compression_required {
+                                return Err(TelnetError::CompressionNotOffered);
+                            }
+                            info!("Server did not offer MCCP compression, reading uncompressed");
+                        }
+                    }
+
+                    if let Some((deadline, timeout)) = negotiation_deadline {
+                        if !filtered.is_empty() {
+                            negotiation_deadline = None;
+                        } else if Instant::now() >= deadline {
+                            match self.config.negotiation_timeout_action {
+                                NegotiationTimeoutAction::Fail => {
+                                    return Err(TelnetError::NegotiationTimeout(timeout));
+                                }
+                                NegotiationTimeoutAction::Continue => {
+                                    warn!(
+                                        "Option negotiation still going after {:?}, no longer answering it",
+                                        timeout
+                                    );
+                                    negotiation_deadline = None;
+                                    answer_negotiation = false;
+                                }
+                            }
+                        }
+                    }
 
                     if filtered.is_empty() {
-                        debug!("Received Telnet negotiation only, skipping");
+                        trace!("Received Telnet negotiation only, skipping");
                         continue;
                     }
 
-                    debug!("Received data: {} bytes", filtered.len());
+                    if traced {
+                        trace!(
+                            epoch,
+                            preview = %Preview::new(&filtered, PREVIEW_MAX_BYTES),
+                            "Received data: {} bytes",
+                            filtered.len()
+                        );
+                    }
 
-                    if filtered.len() > self.config.max_message_size {
+                    // Drop the session if the server asks for a reconnect;
+                    // anything after the sentinel belongs to the old session
+                    if let Some(range) = sentinel.as_mut().and_then(|s| s.find(&filtered)) {
+                        info!("Reconnect sentinel received");
+                        let end = if self.config.forward_reconnect_sentinel {
+                            range.end
+                        } else {
+                            range.start
+                        };
+                        if end > 0 && end <= live.max_message_size {
+                            let _ = messages.send((filtered.slice(..end), received)).await;
+                        }
+                        return Err(TelnetError::ReconnectRequested);
+                    }
+
+                    if filtered.len() > live.max_message_size {
                         warn!(
                             "Message size {} exceeds limit {}, skipping",
                             filtered.len(),
-                            self.config.max_message_size
+                            live.max_message_size
                         );
                         continue;
                     }
 
-                    message_handler(filtered)?;
+                    if messages.send((filtered, received)).await.is_err() {
+                        // Handling has stopped, and reports why
+                        return Ok(());
+                    }
                 }
                 Err(e) => {
                     error!("Error receiving data: {}", e);
@@ -113,53 +1040,953 @@ impl TelnetClient {
     }
 }
 
-/// Filter out Telnet IAC (Interpret As Command) sequences from raw data.
+/// Resolve once the hold signal equals `held`
 ///
-/// Telnet protocol uses IAC (0xFF) as an escape byte. Common sequences:
-/// - IAC WILL/WONT/DO/DONT <option>: 3 bytes (0xFF, 0xFB-0xFE, <option>)
-/// - IAC SB ... IAC SE: Sub-negotiation (variable length)
-/// - IAC <command>: 2 bytes for other commands
-fn filter_telnet_commands(data: &[u8]) -> Vec<u8> {
-    let mut result = Vec::new();
+/// Never resolves when there is no hold signal or its sender is gone.
+async fn hold_is(hold: &mut Option<watch::Receiver<bool>>, held: bool) {
+    if let Some(rx) = hold {
+        if rx.wait_for(|value| *value == held).await.is_ok() {
+            return;
+        }
+    }
+    std::future::pending().await
+}
+
+/// Resolve once `idle` has passed since `last_received_at`
+///
+/// Never resolves when idle reconnects are disabled.
+async fn idle_deadline(last_received_at: Instant, idle: Option<std::time::Duration>) {
+    match idle {
+        Some(idle) => sleep_until(last_received_at + idle).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Resolve with the next command sent to the connection
+///
+/// Never resolves when there is no command channel or its sender is gone.
+async fn next_command(
+    commands: &Option<Mutex<mpsc::Receiver<ConnectionCommand>>>,
+) -> ConnectionCommand {
+    if let Some(rx) = commands {
+        if let Some(command) = rx.lock().await.recv().await {
+            return command;
+        }
+    }
+    std::future::pending().await
+}
+
+/// Option negotiations (`WILL`/`WONT`/`DO`/`DONT`/`SB` and the option code)
+/// in raw data
+fn negotiations(data: &[u8]) -> Vec<(u8, u8)> {
+    let mut found = Vec::new();
     let mut i = 0;
+    while i + 2 < data.len() {
+        if data[i] == 0xFF && matches!(data[i + 1], 0xFA..=0xFE) {
+            found.push((data[i + 1], data[i + 2]));
+            i += 3;
+        } else if data[i] == 0xFF {
+            i += 2;
+        } else {
+            i += 1;
+        }
+    }
+    found
+}
 
-    while i < data.len() {
-        if data[i] == 0xFF {
-            // IAC byte
-            if i + 1 >= data.len() {
-                break;
+/// Name of a Telnet negotiation command byte
+fn command_name(command: u8) -> &'static str {
+    match command {
+        0xFA => "SB",
+        0xFB => "WILL",
+        0xFC => "WONT",
+        0xFD => "DO",
+        0xFE => "DONT",
+        _ => "UNKNOWN",
+    }
+}
+
+/// Terminate `text` with CRLF, turning bare LF line breaks into CRLF
+fn crlf_line(text: &[u8]) -> Vec<u8> {
+    let mut line = Vec::with_capacity(text.len() + 2);
+    for &byte in text {
+        if byte == b'\n' && line.last() != Some(&b'\r') {
+            line.push(b'\r');
+        }
+        line.push(byte);
+    }
+    if !line.ends_with(b"\r\n") {
+        if line.last() != Some(&b'\r') {
+            line.push(b'\r');
+        }
+        line.push(b'\n');
+    }
+    line
+}
+
+/// Whether `banner` ends in something waiting for input, such as `login:`
+/// or a shell prompt; a line break after it means the server is still
+/// talking
+fn ends_with_prompt(banner: &[u8]) -> bool {
+    let end = banner.iter().rposition(|&byte| byte != b' ');
+    matches!(
+        end.map(|i| banner[i]),
+        Some(b':' | b'#' | b'$' | b'>' | b'%')
+    )
+}
+
+/// Where the first occurrence of `prompt` in `seen` ends, ignoring ASCII
+/// case
+fn prompt_end(seen: &[u8], prompt: &str) -> Option<usize> {
+    let prompt = prompt.as_bytes();
+    if prompt.is_empty() {
+        return Some(0);
+    }
+    seen.windows(prompt.len())
+        .position(|window| window.eq_ignore_ascii_case(prompt))
+        .map(|start| start + prompt.len())
+}
+
+/// Double every IAC (0xFF) byte so the server reads it as data
+fn escape_for_telnet(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &byte in data {
+        if byte == 0xFF {
+            escaped.push(0xFF);
+        }
+        escaped.push(byte);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    /// `text` as sent by [`TelnetClient::send_line`]: terminated with CRLF,
+    /// bare LF line breaks turned into CRLF and IAC (0xFF) bytes doubled
+    fn telnet_line(text: &[u8]) -> Vec<u8> {
+        escape_for_telnet(&crlf_line(text))
+    }
+
+    #[test]
+    fn sends_an_empty_string_as_an_empty_line() {
+        assert_eq!(telnet_line(b""), b"\r\n");
+    }
+
+    #[test]
+    fn completes_a_trailing_carriage_return() {
+        assert_eq!(telnet_line(b"look\r"), b"look\r\n");
+    }
+
+    #[test]
+    fn turns_bare_line_feeds_into_crlf() {
+        assert_eq!(telnet_line(b"north\nsouth\r\n"), b"north\r\nsouth\r\n");
+    }
+
+    #[test]
+    fn escapes_iac_bytes() {
+        assert_eq!(telnet_line(b"a\xffb"), b"a\xff\xffb\r\n");
+        assert_eq!(telnet_line(b"\xff\xff"), b"\xff\xff\xff\xff\r\n");
+    }
+
+    /// A client for a server on `port` with `extra` link config
+    fn client(port: u16, extra: &[(&str, &str)]) -> TelnetClient {
+        let mut values = std::collections::HashMap::from([
+            ("telnet_host".to_string(), "127.0.0.1".to_string()),
+            ("telnet_port".to_string(), port.to_string()),
+        ]);
+        values.extend(
+            extra
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string())),
+        );
+        TelnetClient::new(
+            Arc::new(LinkConfig::from_values(&values).unwrap()),
+            Arc::default(),
+        )
+    }
+
+    /// Serve one connection on a local port with `server`, returning the
+    /// port and the server's task
+    async fn serve_one<F, Fut>(server: F) -> (u16, tokio::task::JoinHandle<Fut::Output>)
+    where
+        F: FnOnce(tokio::net::TcpStream) -> Fut + Send + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: Send + 'static,
+    {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let task = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            server(stream).await
+        });
+        (port, task)
+    }
+
+    /// The next message, waiting up to 5 seconds
+    async fn next(messages: &mut mpsc::UnboundedReceiver<Bytes>) -> Bytes {
+        tokio::time::timeout(std::time::Duration::from_secs(5), messages.recv())
+            .await
+            .expect("no data within 5s")
+            .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn reading_continues_while_a_send_is_blocked() {
+        const SENT: usize = 32 << 20;
+        let (drain_tx, drain) = tokio::sync::oneshot::channel::<()>();
+        let (port, server) = serve_one(|stream| async move {
+            let (mut reader, mut writer) = stream.into_split();
+            // Keep writing, and read nothing until told to, so the client's
+            // send fills the socket buffers and stalls
+            let ticks = tokio::spawn(async move {
+                loop {
+                    if writer.write_all(b"tick\r\n").await.is_err() {
+                        break;
+                    }
+                    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                }
+            });
+            let _ = drain.await;
+            let mut received = 0;
+            let mut buf = vec![0; 64 * 1024];
+            while received < SENT {
+                match reader.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => received += n,
+                }
             }
-            match data[i + 1] {
-                0xFB | 0xFC | 0xFD | 0xFE => {
-                    // WILL, WONT, DO, DONT - skip 3 bytes
-                    i += 3;
-                }
-                0xFA => {
-                    // SB (sub-negotiation) - skip until IAC SE
-                    i += 2;
-                    while i + 1 < data.len() {
-                        if data[i] == 0xFF && data[i + 1] == 0xF0 {
-                            i += 2;
-                            break;
-                        }
-                        i += 1;
+            ticks.abort();
+            received
+        })
+        .await;
+
+        let writer = SharedWriter::default();
+        let reader = client(port, &[]).with_writer(writer.clone());
+        let cancel = CancellationToken::new();
+        let (messages_tx, mut messages) = mpsc::unbounded_channel();
+        let running = tokio::spawn({
+            let cancel = cancel.clone();
+            async move {
+                reader
+                    .run(cancel, move |data, _| {
+                        let _ = messages_tx.send(data);
+                        async { Ok(()) }
+                    })
+                    .await
+            }
+        });
+        assert_eq!(next(&mut messages).await, &b"tick\r\n"[..]);
+
+        let sender = client(port, &[]).with_writer(writer);
+        let send = tokio::spawn(async move { sender.send_bytes(&vec![b'x'; SENT]).await });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert!(!send.is_finished(), "the send should be stalled");
+        // The read loop doesn't wait for the write half
+        while messages.try_recv().is_ok() {}
+        for _ in 0..3 {
+            assert_eq!(next(&mut messages).await, &b"tick\r\n"[..]);
+        }
+        assert!(!send.is_finished(), "the send should still be stalled");
+
+        // Once the server reads, the send completes in full
+        drain_tx.send(()).unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(10), send)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(server.await.unwrap(), SENT);
+        cancel.cancel();
+        running.await.unwrap().unwrap();
+    }
+
+    const IAC_NOP: &[u8] = &[0xFF, 0xF1];
+    const DO_NEW_ENVIRON: &[u8] = &[0xFF, 0xFD, 0x27];
+
+    #[tokio::test]
+    async fn negotiating_past_the_timeout_fails_the_connection() {
+        let (port, _) = serve_one(|mut stream| async move {
+            // Negotiate forever
+            while stream.write_all(IAC_NOP).await.is_ok() {
+                sleep(std::time::Duration::from_millis(20)).await;
+            }
+        })
+        .await;
+
+        let client = client(
+            port,
+            &[
+                ("negotiation_timeout_ms", "200"),
+                ("negotiation_timeout_action", "fail"),
+            ],
+        );
+        let started = Instant::now();
+        let result = tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            client.connect_and_receive(&mut |_, _| async { Ok(()) }),
+        )
+        .await
+        .expect("negotiation was not cut off");
+        assert!(matches!(
+            result,
+            Err(TelnetError::NegotiationTimeout(timeout))
+                if timeout == std::time::Duration::from_millis(200)
+        ));
+        assert!(started.elapsed() >= std::time::Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn negotiation_is_no_longer_answered_past_the_timeout() {
+        let (port, server) = serve_one(|mut stream| async move {
+            // Answered while within the timeout
+            stream.write_all(DO_NEW_ENVIRON).await.unwrap();
+            let mut reply = [0; 3];
+            stream.read_exact(&mut reply).await.unwrap();
+            assert_eq!(reply, [0xFF, 0xFB, 0x27]);
+            // Past it, then asked again before any data
+            sleep(std::time::Duration::from_millis(300)).await;
+            stream.write_all(IAC_NOP).await.unwrap();
+            sleep(std::time::Duration::from_millis(50)).await;
+            stream.write_all(DO_NEW_ENVIRON).await.unwrap();
+            sleep(std::time::Duration::from_millis(50)).await;
+            stream.write_all(b"hello\r\n").await.unwrap();
+            let mut unanswered = Vec::new();
+            stream.read_to_end(&mut unanswered).await.unwrap();
+            unanswered
+        })
+        .await;
+
+        let client = client(
+            port,
+            &[("negotiation_timeout_ms", "100"), ("environ.USER", "me")],
+        );
+        let mut forwarded = Vec::new();
+        let result = client
+            .connect_and_receive(&mut |data: Bytes, _| {
+                forwarded.push(data);
+                async { Err(anyhow::anyhow!("done")) }
+            })
+            .await;
+        assert!(matches!(result, Err(TelnetError::Handler(_))));
+        assert_eq!(forwarded, [&b"hello\r\n"[..]]);
+
+        client
+            .writer
+            .lock()
+            .await
+            .take()
+            .unwrap()
+            .shutdown()
+            .await
+            .unwrap();
+        assert_eq!(server.await.unwrap(), b"");
+    }
+
+    #[tokio::test]
+    async fn data_before_the_timeout_lifts_the_limit() {
+        let (port, _) = serve_one(|mut stream| async move {
+            stream.write_all(b"hi\r\n").await.unwrap();
+            for _ in 0..20 {
+                sleep(std::time::Duration::from_millis(20)).await;
+                stream.write_all(IAC_NOP).await.unwrap();
+            }
+            stream.write_all(b"bye\r\n").await.unwrap();
+            let _ = stream.read(&mut [0; 1]).await;
+        })
+        .await;
+
+        let client = client(
+            port,
+            &[
+                ("negotiation_timeout_ms", "100"),
+                ("negotiation_timeout_action", "fail"),
+            ],
+        );
+        let mut forwarded = Vec::new();
+        let result = client
+            .connect_and_receive(&mut |data: Bytes, _| {
+                let done = data.as_ref() == b"bye\r\n";
+                forwarded.push(data);
+                async move {
+                    if done {
+                        anyhow::bail!("done");
                     }
+                    Ok(())
                 }
-                0xFF => {
-                    // Escaped 0xFF - output single 0xFF
-                    result.push(0xFF);
-                    i += 2;
+            })
+            .await;
+        assert!(matches!(result, Err(TelnetError::Handler(_))));
+        assert_eq!(forwarded, [&b"hi\r\n"[..], b"bye\r\n"]);
+    }
+
+    /// `data` as a zlib stream, ended with `finish` or left open
+    fn zlib(data: &[u8], finish: bool) -> Vec<u8> {
+        use flate2::{Compress, Compression, FlushCompress};
+        let mut compress = Compress::new(Compression::default(), true);
+        let mut out = Vec::with_capacity(data.len() + 64);
+        let flush = if finish {
+            FlushCompress::Finish
+        } else {
+            FlushCompress::Sync
+        };
+        compress.compress_vec(data, &mut out, flush).unwrap();
+        out
+    }
+
+    const WILL_COMPRESS2: &[u8] = &[0xFF, 0xFB, 86];
+    const MCCP_START: &[u8] = &[0xFF, 0xFA, 86, 0xFF, 0xF0];
+
+    #[tokio::test]
+    async fn reads_are_decompressed_once_compression_starts() {
+        let (port, _) = serve_one(|mut stream| async move {
+            stream.write_all(WILL_COMPRESS2).await.unwrap();
+            let mut reply = [0; 3];
+            stream.read_exact(&mut reply).await.unwrap();
+            assert_eq!(reply, [0xFF, 0xFD, 86]);
+
+            stream.write_all(b"plain\r\n").await.unwrap();
+            sleep(std::time::Duration::from_millis(20)).await;
+            stream.write_all(MCCP_START).await.unwrap();
+            stream
+                .write_all(&zlib(b"compressed\r\n", false))
+                .await
+                .unwrap();
+            let _ = stream.read(&mut [0; 1]).await;
+        })
+        .await;
+
+        let client = client(port, &[("mccp_enabled", "true")]);
+        let mut forwarded = Vec::new();
+        let result = client
+            .connect_and_receive(&mut |data: Bytes, _| {
+                forwarded.extend_from_slice(&data);
+                let done = forwarded.ends_with(b"compressed\r\n");
+                async move {
+                    if done {
+                        anyhow::bail!("done");
+                    }
+                    Ok(())
                 }
-                _ => {
-                    // Other IAC command - skip 2 bytes
-                    i += 2;
+            })
+            .await;
+        assert!(matches!(result, Err(TelnetError::Handler(_))));
+        assert_eq!(forwarded, b"plain\r\ncompressed\r\n");
+    }
+
+    #[tokio::test]
+    async fn reads_follow_the_server_turning_compression_on_and_off() {
+        let (port, _) = serve_one(|mut stream| async move {
+            stream.write_all(WILL_COMPRESS2).await.unwrap();
+            let mut reply = [0; 3];
+            stream.read_exact(&mut reply).await.unwrap();
+            assert_eq!(reply, [0xFF, 0xFD, 86]);
+
+            // Compressed, then off mid-stream, then on again
+            stream.write_all(MCCP_START).await.unwrap();
+            stream.write_all(&zlib(b"one\r\n", true)).await.unwrap();
+            sleep(std::time::Duration::from_millis(20)).await;
+            stream.write_all(b"two\r\n").await.unwrap();
+            sleep(std::time::Duration::from_millis(20)).await;
+            stream.write_all(MCCP_START).await.unwrap();
+            stream.write_all(&zlib(b"three\r\n", false)).await.unwrap();
+            let _ = stream.read(&mut [0; 1]).await;
+        })
+        .await;
+
+        let client = client(port, &[("compression_required", "true")]);
+        let mut forwarded = Vec::new();
+        let result = client
+            .connect_and_receive(&mut |data: Bytes, _| {
+                forwarded.extend_from_slice(&data);
+                let done = forwarded.ends_with(b"three\r\n");
+                async move {
+                    if done {
+                        anyhow::bail!("done");
+                    }
+                    Ok(())
                 }
+            })
+            .await;
+        assert!(matches!(result, Err(TelnetError::Handler(_))));
+        assert_eq!(forwarded, b"one\r\ntwo\r\nthree\r\n");
+    }
+
+    #[tokio::test]
+    async fn compression_required_fails_a_server_that_does_not_offer_it() {
+        let (port, _) = serve_one(|mut stream| async move {
+            stream.write_all(b"hello\r\n").await.unwrap();
+            let _ = stream.read(&mut [0; 1]).await;
+        })
+        .await;
+
+        let client = client(port, &[("compression_required", "true")]);
+        let mut forwarded = Vec::new();
+        let result = client
+            .connect_and_receive(&mut |data: Bytes, _| {
+                forwarded.push(data);
+                async { Ok(()) }
+            })
+            .await;
+        assert!(matches!(result, Err(TelnetError::CompressionNotOffered)));
+        assert!(forwarded.is_empty());
+    }
+
+    #[tokio::test]
+    async fn compression_is_not_accepted_unless_enabled() {
+        let (port, server) = serve_one(|mut stream| async move {
+            stream.write_all(WILL_COMPRESS2).await.unwrap();
+            stream.write_all(b"hello\r\n").await.unwrap();
+            let mut answered = Vec::new();
+            stream.read_to_end(&mut answered).await.unwrap();
+            answered
+        })
+        .await;
+
+        let client = client(port, &[]);
+        let result = client
+            .connect_and_receive(&mut |_, _| async { Err(anyhow::anyhow!("done")) })
+            .await;
+        assert!(matches!(result, Err(TelnetError::Handler(_))));
+        client
+            .writer
+            .lock()
+            .await
+            .take()
+            .unwrap()
+            .shutdown()
+            .await
+            .unwrap();
+        assert_eq!(server.await.unwrap(), b"");
+    }
+
+    #[tokio::test]
+    async fn sessions_are_recorded_as_received() {
+        let (port, _) = serve_one(|mut stream| async move {
+            stream.write_all(IAC_NOP).await.unwrap();
+            stream.write_all(b"hello\r\n").await.unwrap();
+        })
+        .await;
+
+        let dir =
+            std::env::temp_dir().join(format!("telnet-provider-session-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let client = client(
+            port,
+            &[
+                ("session_record_dir", dir.to_str().unwrap()),
+                ("session_record_compress", "true"),
+            ],
+        );
+        let result = client.connect_and_receive(&mut |_, _| async { Ok(()) }).await;
+        assert!(matches!(result, Err(TelnetError::ServerClosed)));
+
+        // Negotiation included
+        let recordings: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(recordings.len(), 1);
+        assert!(recordings[0].to_str().unwrap().ends_with(".bin.zst"));
+        let mut raw = Vec::new();
+        crate::record::decompress(&recordings[0], &mut raw)
+            .await
+            .unwrap();
+        assert_eq!(raw, [IAC_NOP, b"hello\r\n"].concat());
+    }
+
+    #[test]
+    fn the_client_shares_the_link_config() {
+        let config = Arc::new(
+            LinkConfig::from_values(&std::collections::HashMap::from([(
+                "telnet_host".to_string(),
+                "127.0.0.1".to_string(),
+            )]))
+            .unwrap(),
+        );
+        let client = TelnetClient::new(config.clone(), Arc::default());
+        assert!(Arc::ptr_eq(&client.config, &config));
+    }
+
+    #[tokio::test]
+    async fn the_read_loop_adopts_a_reloaded_max_message_size() {
+        let (reloaded_tx, reloaded_rx) = tokio::sync::oneshot::channel();
+        let (port, _) = serve_one(|mut stream| async move {
+            let long = format!("{}\r\n", "x".repeat(30));
+            stream.write_all(long.as_bytes()).await.unwrap();
+            reloaded_rx.await.unwrap();
+            // A read already waiting when the config changed uses the old
+            // one; the reads after it use the new one
+            for data in [&b"ok\r\n"[..], long.as_bytes(), b"end\r\n"] {
+                sleep(std::time::Duration::from_millis(50)).await;
+                stream.write_all(data).await.unwrap();
             }
-        } else {
-            result.push(data[i]);
-            i += 1;
+            let _ = stream.read(&mut [0; 1]).await;
+        })
+        .await;
+
+        let client = client(port, &[]);
+        let (updates_tx, updates) = watch::channel(client.config.clone());
+        let client = client.with_config_updates(updates);
+        let mut reloaded_tx = Some(reloaded_tx);
+        let mut forwarded = Vec::new();
+        let result = client
+            .connect_and_receive(&mut |data: Bytes, _| {
+                let done = data.as_ref() == b"end\r\n";
+                forwarded.push(data);
+                if let Some(reloaded_tx) = reloaded_tx.take() {
+                    let mut config = (**updates_tx.borrow()).clone();
+                    config.max_message_size = 10;
+                    updates_tx.send(Arc::new(config)).unwrap();
+                    reloaded_tx.send(()).unwrap();
+                }
+                async move {
+                    if done {
+                        anyhow::bail!("done");
+                    }
+                    Ok(())
+                }
+            })
+            .await;
+        assert!(matches!(result, Err(TelnetError::Handler(_))));
+        assert_eq!(forwarded.len(), 3);
+        assert_eq!(forwarded[0].len(), 32);
+        assert_eq!(forwarded[1..], [&b"ok\r\n"[..], b"end\r\n"]);
+    }
+
+    #[tokio::test]
+    async fn blocked_commands_never_reach_the_server() {
+        let (port, server) = serve_one(|mut stream| async move {
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).await.unwrap();
+            received
+        })
+        .await;
+
+        let stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap();
+        let (_reader, writer) = stream.into_split();
+        let writer: SharedWriter = Arc::new(Mutex::new(Some(Box::new(writer))));
+        let client = client(port, &[("command_blocklist", "reload, delete")])
+            .with_writer(writer.clone());
+
+        client.send_bytes(b"show version\r\n").await.unwrap();
+        for blocked in [&b"reload\r\n"[..], b"show clock\r\nRELOAD in 5\r\n"] {
+            assert!(matches!(
+                client.send_bytes(blocked).await,
+                Err(TelnetError::Blocked(command)) if command == "reload"
+            ));
         }
+        assert!(matches!(
+            client.send_line("delete flash:").await,
+            Err(TelnetError::Blocked(_))
+        ));
+        client.send_line("exit").await.unwrap();
+
+        writer.lock().await.take().unwrap().shutdown().await.unwrap();
+        assert_eq!(server.await.unwrap(), b"show version\r\nexit\r\n");
     }
 
-    result
+    #[tokio::test]
+    async fn blocked_commands_split_across_sends_are_not_completed() {
+        let (port, server) = serve_one(|mut stream| async move {
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).await.unwrap();
+            received
+        })
+        .await;
+
+        let stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .unwrap();
+        let (_reader, writer) = stream.into_split();
+        let writer: SharedWriter = Arc::new(Mutex::new(Some(Box::new(writer))));
+        let sent_line = SharedSentLine::default();
+        // A client per send, like the provider's sends for components
+        let sender = || {
+            client(port, &[("command_blocklist", "reload")])
+                .with_writer(writer.clone())
+                .with_sent_line(sent_line.clone())
+        };
+
+        sender().send_bytes(b"show version\r\nre").await.unwrap();
+        sender().send_bytes(b"lo").await.unwrap();
+        assert!(matches!(
+            sender().send_bytes(b"ad\r\n").await,
+            Err(TelnetError::Blocked(command)) if command == "reload"
+        ));
+        sender().send_bytes(b"\r\nshow reloads\r\n").await.unwrap();
+
+        writer
+            .lock()
+            .await
+            .take()
+            .unwrap()
+            .shutdown()
+            .await
+            .unwrap();
+        assert_eq!(
+            server.await.unwrap(),
+            b"show version\r\nrelo\r\nshow reloads\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn reading_continues_while_handling_waits() {
+        let (port, _) = serve_one(|mut stream| async move {
+            stream.write_all(b"first\r\n").await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            stream.write_all(b"second\r\n").await.unwrap();
+            // Hold the connection open until the client is done
+            let _ = stream.read(&mut [0; 1]).await;
+        })
+        .await;
+
+        let stats = Arc::new(ConnectionStats::default());
+        let client = TelnetClient::new(client(port, &[]).config, stats.clone());
+        let cancel = CancellationToken::new();
+        let mut handled = Vec::new();
+        client
+            .run(cancel.clone(), |data, _| {
+                handled.push(data);
+                if handled.len() == 2 {
+                    cancel.cancel();
+                }
+                // Wait in the handler on the first message until the second
+                // has been read, or fail after 5s
+                let first = handled.len() == 1;
+                let stats = stats.clone();
+                async move {
+                    let read = async {
+                        while stats.bytes_in() < 15 {
+                            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                        }
+                    };
+                    if first {
+                        tokio::time::timeout(std::time::Duration::from_secs(5), read)
+                            .await
+                            .map_err(|_| {
+                                anyhow::anyhow!("second message not read while handling the first")
+                            })?;
+                    }
+                    Ok(())
+                }
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(handled, [&b"first\r\n"[..], b"second\r\n"]);
+    }
+
+    /// Connector handing out the client end of an in-memory pipe per
+    /// attempt, recording the addresses it was asked for
+    #[derive(Default)]
+    struct PipeConnector {
+        servers: std::sync::Mutex<Vec<tokio::io::DuplexStream>>,
+        attempts: std::sync::Mutex<Vec<Vec<SocketAddr>>>,
+    }
+
+    impl TcpConnector for PipeConnector {
+        fn connect<'a>(
+            &'a self,
+            addrs: &'a [SocketAddr],
+            _tuning: SocketTuning,
+        ) -> std::pin::Pin<
+            Box<
+                dyn std::future::Future<Output = std::io::Result<crate::socket::Socket>>
+                    + Send
+                    + 'a,
+            >,
+        > {
+            self.attempts.lock().unwrap().push(addrs.to_vec());
+            let (client, server) = tokio::io::duplex(1024);
+            self.servers.lock().unwrap().push(server);
+            let (reader, writer) = tokio::io::split(client);
+            Box::pin(std::future::ready(Ok(crate::socket::Socket {
+                reader: Box::new(reader),
+                writer: Box::new(writer),
+                peer: addrs[0],
+            })))
+        }
+    }
+
+    #[tokio::test]
+    async fn connects_through_the_given_connector() {
+        let connector = Arc::new(PipeConnector::default());
+        // Nothing listens on port 9; the connector doesn't need it to
+        let client = client(9, &[]).with_connector(connector.clone());
+        let cancel = CancellationToken::new();
+        let running = {
+            let cancel = cancel.clone();
+            let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let handled = received.clone();
+            let task = tokio::spawn(async move {
+                client
+                    .run(cancel, move |data, _| {
+                        handled.lock().unwrap().push(data);
+                        async { Ok(()) }
+                    })
+                    .await
+            });
+            (task, received)
+        };
+
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while connector.servers.lock().unwrap().is_empty() {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        let mut server = connector.servers.lock().unwrap().pop().unwrap();
+        server.write_all(b"over the pipe\r\n").await.unwrap();
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while running.1.lock().unwrap().is_empty() {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        cancel.cancel();
+        running.0.await.unwrap().unwrap();
+
+        assert_eq!(*running.1.lock().unwrap(), [&b"over the pipe\r\n"[..]]);
+        assert_eq!(
+            *connector.attempts.lock().unwrap(),
+            [vec![SocketAddr::from(([127, 0, 0, 1], 9))]]
+        );
+    }
+
+    /// A server writing each of `writes` in turn, 20ms apart, then holding
+    /// the connection open until the client leaves
+    async fn greeting_server(writes: &'static [&'static [u8]]) -> u16 {
+        let (port, _) = serve_one(move |mut stream| async move {
+            for write in writes {
+                stream.write_all(write).await.unwrap();
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+            let _ = stream.read_to_end(&mut Vec::new()).await;
+        })
+        .await;
+        port
+    }
+
+    #[tokio::test]
+    async fn banner_wait_stops_at_the_blank_line() {
+        let port = greeting_server(&[
+            b"\xff\xfb\x01Welcome\r\n",
+            b"Authorized use only\r\n\r\nlogin: ",
+            b"never read",
+        ])
+        .await;
+        let started = Instant::now();
+        let banner = client(port, &[]).banner_wait(5000).await.unwrap();
+        assert_eq!(banner, b"Welcome\r\nAuthorized use only\r\n\r\n");
+        assert!(started.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn banner_wait_returns_what_arrived_by_the_timeout() {
+        let port = greeting_server(&[b"Welcome\r\n"]).await;
+        let started = Instant::now();
+        let banner = client(port, &[]).banner_wait(200).await.unwrap();
+        let elapsed = started.elapsed();
+        assert_eq!(banner, b"Welcome\r\n");
+        assert!(
+            elapsed >= std::time::Duration::from_millis(200)
+                && elapsed < std::time::Duration::from_secs(1),
+            "took {:?}",
+            elapsed
+        );
+
+        // Nothing at all is an empty banner rather than an error
+        let silent = greeting_server(&[]).await;
+        assert!(client(silent, &[])
+            .banner_wait(100)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_fixed_length_login_step_counts_bytes_sent_with_the_prompt() {
+        use tokio::io::AsyncBufReadExt;
+
+        let (port, server) = serve_one(|stream| async move {
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = tokio::io::BufReader::new(reader).lines();
+            // Four bytes of the 6-byte field arrive with the prompt
+            writer.write_all(b"LOGIN:\x01\x02\x03\x04").await.unwrap();
+            let username = lines.next_line().await.unwrap().unwrap();
+            writer.write_all(b"\x05\x06").await.unwrap();
+            let password = lines.next_line().await.unwrap().unwrap();
+            writer.write_all(b"ready\r\n").await.unwrap();
+            let _ = lines.next_line().await;
+            (username, password)
+        })
+        .await;
+
+        let cancel = CancellationToken::new();
+        let (messages_tx, mut messages) = mpsc::unbounded_channel();
+        let running = tokio::spawn({
+            let client = client(
+                port,
+                &[
+                    ("login_username", "admin"),
+                    ("login_password", "secret"),
+                    ("password_prompt", "#len:6"),
+                    ("login_timeout_ms", "5000"),
+                ],
+            );
+            let cancel = cancel.clone();
+            async move {
+                client
+                    .run(cancel, move |data, _| {
+                        let _ = messages_tx.send(data);
+                        async { Ok(()) }
+                    })
+                    .await
+            }
+        });
+        assert_eq!(next(&mut messages).await, &b"ready\r\n"[..]);
+        cancel.cancel();
+        running.await.unwrap().unwrap();
+        let (username, password) = server.await.unwrap();
+        assert_eq!(username, "admin");
+        assert_eq!(password, "secret");
+    }
+
+    #[tokio::test]
+    async fn receive_exactly_rejects_empty_and_oversized_reads() {
+        let client = client(1, &[("max_message_size", "4")]);
+        for n in [0, 5] {
+            let mut reader: SocketReader = Box::new(tokio::io::empty());
+            let e = client
+                .receive_exactly(&mut reader, n, 1000)
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(&e, TelnetError::Io(e) if e.kind() == std::io::ErrorKind::InvalidInput),
+                "{:?}",
+                e
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn banner_wait_fails_for_an_unreachable_server() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let e = client(port, &[]).banner_wait(1000).await.unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<TelnetError>().map(TelnetError::kind),
+            Some(crate::error::TelnetErrorKind::Refused)
+        );
+    }
 }
@@ -0,0 +1,139 @@
+//! Provider-wide limit on concurrent connection attempts
+//!
+//! After a network blip every link reconnects at about the same time, and
+//! hundreds of simultaneous DNS lookups and TCP handshakes can overwhelm the
+//! resolver or trip a console server's SYN flood protection. Each link takes
+//! a permit for the connect phase only (resolving and connecting), so at
+//! most `max_concurrent_reconnects` attempts run at once and the rest queue
+//! up. Permits are released as soon as the socket connects or the attempt
+//! fails, so neither the read loop nor the backoff sleep between attempts
+//! holds one.
+
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Concurrent connection attempts allowed when the provider config doesn't
+/// say
+pub const DEFAULT_MAX_CONCURRENT_RECONNECTS: usize = 16;
+
+/// Hands out permits to connect, shared by all links
+#[derive(Debug)]
+pub struct ConnectLimiter {
+    /// Permits of the current capacity; `None` when unlimited
+    permits: Mutex<Option<Arc<Semaphore>>>,
+}
+
+impl Default for ConnectLimiter {
+    fn default() -> Self {
+        let limiter = Self {
+            permits: Mutex::new(None),
+        };
+        limiter.set_capacity(DEFAULT_MAX_CONCURRENT_RECONNECTS);
+        limiter
+    }
+}
+
+impl ConnectLimiter {
+    /// Set the maximum concurrent connection attempts (0 = unlimited)
+    ///
+    /// Attempts already holding or waiting for a permit finish under the
+    /// old capacity.
+    pub fn set_capacity(&self, capacity: usize) {
+        let permits = (capacity > 0).then(|| Arc::new(Semaphore::new(capacity)));
+        *self.permits.lock().unwrap_or_else(|e| e.into_inner()) = permits;
+    }
+
+    /// Wait for a permit to connect, or `None` if attempts are unlimited
+    ///
+    /// Dropping the future gives up the place in the queue, so a link
+    /// stopped while waiting doesn't take a permit.
+    pub async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        let permits = self
+            .permits
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()?;
+        // The semaphore is never closed
+        permits.acquire_owned().await.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn allows_the_default_number_of_attempts_at_once() {
+        let limiter = ConnectLimiter::default();
+        let mut permits = Vec::new();
+        for _ in 0..DEFAULT_MAX_CONCURRENT_RECONNECTS {
+            permits.push(limiter.acquire().await.unwrap());
+        }
+        let waiting = tokio::time::timeout(Duration::from_millis(50), limiter.acquire()).await;
+        assert!(waiting.is_err(), "got a permit past the default capacity");
+
+        permits.pop();
+        assert!(limiter.acquire().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn queued_attempts_never_exceed_the_capacity() {
+        let limiter = Arc::new(ConnectLimiter::default());
+        limiter.set_capacity(3);
+        let (running, peak) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let attempts: Vec<_> = (0..20)
+            .map(|_| {
+                let (limiter, running, peak) = (limiter.clone(), running.clone(), peak.clone());
+                tokio::spawn(async move {
+                    let _permit = limiter.acquire().await.unwrap();
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for attempt in attempts {
+            attempt.await.unwrap();
+        }
+        assert_eq!(peak.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn zero_capacity_is_unlimited() {
+        let limiter = ConnectLimiter::default();
+        limiter.set_capacity(0);
+        assert!(limiter.acquire().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn new_capacity_applies_to_later_attempts_only() {
+        let limiter = ConnectLimiter::default();
+        limiter.set_capacity(1);
+        let held = limiter.acquire().await.unwrap();
+
+        limiter.set_capacity(2);
+        let first = limiter.acquire().await.unwrap();
+        let second = limiter.acquire().await.unwrap();
+        let waiting = tokio::time::timeout(Duration::from_millis(50), limiter.acquire()).await;
+        assert!(waiting.is_err(), "got a permit past the new capacity");
+        drop((held, first, second));
+    }
+
+    #[tokio::test]
+    async fn giving_up_a_queued_attempt_keeps_its_permit_free() {
+        let limiter = ConnectLimiter::default();
+        limiter.set_capacity(1);
+        let held = limiter.acquire().await.unwrap();
+        let gave_up = tokio::time::timeout(Duration::from_millis(20), limiter.acquire()).await;
+        assert!(gave_up.is_err());
+
+        drop(held);
+        let next = tokio::time::timeout(Duration::from_millis(50), limiter.acquire()).await;
+        assert!(next.unwrap().is_some());
+    }
+}
@@ -0,0 +1,222 @@
+//! Declarative message transformations registered by components
+//!
+//! Components register rules through `wasmcloud:telnet/transform`. Each rule
+//! pairs a subject glob pattern with a transformation; every forwarded message
+//! whose subject matches has the rule applied to its body, in registration
+//! order, before it is delivered.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
+
+use bytes::Bytes;
+use tracing::info;
+
+/// A transformation applied to a message body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transform {
+    /// Strip leading and trailing ASCII whitespace
+    Trim,
+    /// Convert ASCII letters to uppercase
+    Uppercase,
+    /// Convert ASCII letters to lowercase
+    Lowercase,
+    /// Prepend bytes
+    Prefix(Vec<u8>),
+    /// Append bytes
+    Suffix(Vec<u8>),
+    /// Replace every occurrence of `from` with `to`
+    Replace { from: Vec<u8>, to: Vec<u8> },
+    /// Don't forward the message at all
+    Drop,
+}
+
+impl Transform {
+    /// Apply the transformation, returning `None` if the message is dropped
+    fn apply(&self, body: Vec<u8>) -> Option<Vec<u8>> {
+        match self {
+            Transform::Trim => Some(body.trim_ascii().to_vec()),
+            Transform::Uppercase => Some(body.to_ascii_uppercase()),
+            Transform::Lowercase => Some(body.to_ascii_lowercase()),
+            Transform::Prefix(prefix) => Some([prefix.as_slice(), &body].concat()),
+            Transform::Suffix(suffix) => Some([body.as_slice(), suffix].concat()),
+            Transform::Replace { from, to } => Some(replace_all(&body, from, to)),
+            Transform::Drop => None,
+        }
+    }
+}
+
+/// A registered transformation rule
+struct Rule {
+    id: String,
+    pattern: String,
+    transform: Transform,
+}
+
+/// All transformation rules registered with the provider
+#[derive(Default)]
+pub struct TransformRules {
+    rules: RwLock<Vec<Rule>>,
+    next_id: AtomicU64,
+}
+
+impl TransformRules {
+    /// Register a rule, returning its ID
+    pub fn register(&self, pattern: &str, transform: Transform) -> Result<String, String> {
+        if pattern.is_empty() {
+            return Err("subject pattern must not be empty".to_string());
+        }
+        if let Transform::Replace { from, .. } = &transform {
+            if from.is_empty() {
+                return Err("replace pattern must not be empty".to_string());
+            }
+        }
+
+        let id = format!("rule-{}", self.next_id.fetch_add(1, Ordering::Relaxed) + 1);
+        info!(
+            "Registered transform rule {} for {}: {:?}",
+            id, pattern, transform
+        );
+        self.rules
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Rule {
+                id: id.clone(),
+                pattern: pattern.to_string(),
+                transform,
+            });
+        Ok(id)
+    }
+
+    /// Remove a previously registered rule
+    pub fn unregister(&self, id: &str) -> Result<(), String> {
+        let mut rules = self.rules.write().unwrap_or_else(|e| e.into_inner());
+        let before = rules.len();
+        rules.retain(|rule| rule.id != id);
+        if rules.len() == before {
+            return Err(format!("no transform rule with ID {}", id));
+        }
+        info!("Unregistered transform rule {}", id);
+        Ok(())
+    }
+
+    /// Apply every rule matching `subject` to `body`
+    ///
+    /// Returns `None` if a rule dropped the message.
+    pub fn apply(&self, subject: &str, body: Bytes) -> Option<Bytes> {
+        let rules = self.rules.read().unwrap_or_else(|e| e.into_inner());
+        let mut matching = rules
+            .iter()
+            .filter(|rule| subject_matches(&rule.pattern, subject))
+            .peekable();
+
+        // Pass the body through untouched (and uncopied) if no rule applies
+        if matching.peek().is_none() {
+            return Some(body);
+        }
+        matching
+            .try_fold(Vec::from(body), |body, rule| rule.transform.apply(body))
+            .map(Bytes::from)
+    }
+}
+
+/// Match a subject against a glob pattern
+///
+/// `*` matches any run of characters (including none) and `?` matches exactly
+/// one character; everything else matches literally.
+pub fn subject_matches(pattern: &str, subject: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let subject = subject.as_bytes();
+    let (mut p, mut s) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while s < subject.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, s));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == subject[s] => {
+                p += 1;
+                s += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` absorb one more character and retry
+                Some((star, matched)) => {
+                    p = star + 1;
+                    s = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Replace every non-overlapping occurrence of `from` in `body` with `to`
+fn replace_all(body: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(body.len());
+    let mut i = 0;
+    while i < body.len() {
+        if body[i..].starts_with(from) {
+            result.extend_from_slice(to);
+            i += from.len();
+        } else {
+            result.push(body[i]);
+            i += 1;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_bodies_no_rule_matches_through_uncopied() {
+        let rules = TransformRules::default();
+        rules.register("telnet.other:*", Transform::Drop).unwrap();
+        let body = Bytes::from(b"reading".to_vec());
+        let applied = rules.apply("telnet.host:23", body.clone()).unwrap();
+        assert_eq!(applied.as_ptr(), body.as_ptr());
+        assert_eq!(applied, body);
+    }
+
+    #[test]
+    fn applies_matching_rules_in_registration_order() {
+        let rules = TransformRules::default();
+        rules.register("telnet.*", Transform::Trim).unwrap();
+        rules
+            .register("telnet.host:?3", Transform::Prefix(b"> ".to_vec()))
+            .unwrap();
+        rules.register("telnet.*", Transform::Uppercase).unwrap();
+        rules.register("other.*", Transform::Drop).unwrap();
+        let applied = rules.apply("telnet.host:23", Bytes::from_static(b"  ok \r\n"));
+        assert_eq!(applied.unwrap(), &b"> OK"[..]);
+    }
+
+    #[test]
+    fn drops_bodies_until_the_rule_is_unregistered() {
+        let rules = TransformRules::default();
+        let id = rules.register("*", Transform::Drop).unwrap();
+        assert!(rules
+            .apply("telnet.host:23", Bytes::from_static(b"x"))
+            .is_none());
+
+        rules.unregister(&id).unwrap();
+        assert!(rules.unregister(&id).is_err());
+        let applied = rules.apply("telnet.host:23", Bytes::from_static(b"x"));
+        assert_eq!(applied.unwrap(), &b"x"[..]);
+    }
+
+    #[test]
+    fn replaces_every_occurrence() {
+        let replace = Transform::Replace {
+            from: b"ab".to_vec(),
+            to: b"c".to_vec(),
+        };
+        assert_eq!(replace.apply(b"abab-aab".to_vec()).unwrap(), b"cc-ac");
+    }
+}
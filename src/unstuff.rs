@@ -0,0 +1,351 @@
+//! Byte-stuffed record framing (SLIP-style)
+//!
+//! Binary protocols carried over Telnet often delimit records with a framing
+//! byte and escape occurrences of it inside payloads. The [`Unstuffer`]
+//! de-frames such a stream into records and removes the escaping, carrying
+//! partial records and escape state across reads.
+//!
+//! Telnet itself stuffs carriage returns: outside binary mode a bare CR is
+//! sent as `CR NUL` (RFC 854). The [`CrUnstuffer`] turns those back into a
+//! bare CR and leaves `CR LF` line endings alone.
+
+use std::str::FromStr;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// Framing and escape bytes of a byte-stuffed protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnstuffConfig {
+    /// Byte terminating each record
+    pub end: u8,
+    /// Escape byte introducing a two-byte sequence
+    pub esc: u8,
+    /// Byte following `esc` that stands for a literal `end`
+    pub esc_end: u8,
+    /// Byte following `esc` that stands for a literal `esc`
+    pub esc_esc: u8,
+}
+
+impl UnstuffConfig {
+    /// SLIP (RFC 1055) framing
+    pub const SLIP: Self = Self {
+        end: 0xC0,
+        esc: 0xDB,
+        esc_end: 0xDC,
+        esc_esc: 0xDD,
+    };
+
+    /// `record` escaped and terminated as the server would send it
+    pub fn stuff(&self, record: &[u8]) -> Vec<u8> {
+        let mut framed = Vec::with_capacity(record.len() + 2);
+        framed.push(self.end);
+        for &byte in record {
+            if byte == self.end {
+                framed.extend_from_slice(&[self.esc, self.esc_end]);
+            } else if byte == self.esc {
+                framed.extend_from_slice(&[self.esc, self.esc_esc]);
+            } else {
+                framed.push(byte);
+            }
+        }
+        framed.push(self.end);
+        framed
+    }
+}
+
+impl FromStr for UnstuffConfig {
+    type Err = anyhow::Error;
+
+    /// Parse `slip`, or a comma-separated list overriding SLIP's bytes, e.g.
+    /// `end=0x7E,esc=0x7D,esc_end=0x5E,esc_esc=0x5D`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = Self::SLIP;
+        if s.eq_ignore_ascii_case("slip") {
+            return Ok(config);
+        }
+
+        for part in s.split(',') {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("Invalid unstuff entry: {}", part))?;
+            let value = value.trim();
+            let byte = match value
+                .strip_prefix("0x")
+                .or_else(|| value.strip_prefix("0X"))
+            {
+                Some(hex) => u8::from_str_radix(hex, 16),
+                None => value.parse(),
+            }
+            .map_err(|_| anyhow::anyhow!("Invalid unstuff byte: {}", value))?;
+
+            match key.trim() {
+                "end" => config.end = byte,
+                "esc" => config.esc = byte,
+                "esc_end" => config.esc_end = byte,
+                "esc_esc" => config.esc_esc = byte,
+                other => anyhow::bail!("Unknown unstuff key: {}", other),
+            }
+        }
+
+        if config.end == config.esc {
+            anyhow::bail!("unstuff end and esc bytes must differ");
+        }
+        Ok(config)
+    }
+}
+
+/// Incremental de-framer for a byte-stuffed stream
+pub struct Unstuffer {
+    config: UnstuffConfig,
+    /// Largest record kept; longer records are discarded
+    max_record_size: usize,
+    /// Record being assembled
+    record: BytesMut,
+    /// Whether the previous byte was an unconsumed escape
+    escaped: bool,
+    /// Whether the current record exceeded `max_record_size`
+    overflowed: bool,
+}
+
+impl Unstuffer {
+    /// Create a de-framer discarding records longer than `max_record_size`
+    pub fn new(config: UnstuffConfig, max_record_size: usize) -> Self {
+        Self {
+            config,
+            max_record_size,
+            record: BytesMut::new(),
+            escaped: false,
+            overflowed: false,
+        }
+    }
+
+    /// Change the largest record kept from now on
+    pub fn set_max_record_size(&mut self, max_record_size: usize) {
+        self.max_record_size = max_record_size;
+    }
+
+    /// Feed received bytes, returning every record completed by them
+    ///
+    /// Empty records (back-to-back framing bytes) are skipped. Records that
+    /// grew past the size limit are dropped and counted in the second value.
+    pub fn push(&mut self, data: &[u8]) -> (Vec<Bytes>, usize) {
+        let mut records = Vec::new();
+        let mut dropped = 0;
+
+        for &byte in data {
+            let decoded = if self.escaped {
+                self.escaped = false;
+                match byte {
+                    b if b == self.config.esc_end => self.config.end,
+                    b if b == self.config.esc_esc => self.config.esc,
+                    // Not a valid escape; keep the byte as-is like RFC 1055
+                    b => b,
+                }
+            } else if byte == self.config.end {
+                if self.overflowed {
+                    dropped += 1;
+                } else if !self.record.is_empty() {
+                    records.push(self.record.split().freeze());
+                }
+                self.record.clear();
+                self.overflowed = false;
+                continue;
+            } else if byte == self.config.esc {
+                self.escaped = true;
+                continue;
+            } else {
+                byte
+            };
+
+            if self.record.len() >= self.max_record_size {
+                self.overflowed = true;
+                self.record.clear();
+            }
+            if !self.overflowed {
+                self.record.put_u8(decoded);
+            }
+        }
+
+        (records, dropped)
+    }
+}
+
+/// Removes the NUL that Telnet sends after a bare carriage return
+#[derive(Debug, Default)]
+pub struct CrUnstuffer {
+    /// Whether the last byte fed was a CR, whose NUL may start the next read
+    after_cr: bool,
+}
+
+impl CrUnstuffer {
+    /// Feed received bytes, returning them with `CR NUL` turned into `CR`
+    ///
+    /// The CR itself is passed on at once; only the NUL is dropped, even
+    /// when it arrives in the next read. Data without a NUL is returned as
+    /// is.
+    pub fn push(&mut self, data: Bytes) -> Bytes {
+        let after_cr = std::mem::replace(&mut self.after_cr, data.last() == Some(&b'\r'));
+        let Some(first_nul) = memchr::memchr(0, &data) else {
+            return data;
+        };
+
+        let mut out = BytesMut::with_capacity(data.len());
+        out.extend_from_slice(&data[..first_nul]);
+        let mut previous_cr = match first_nul {
+            0 => after_cr,
+            i => data[i - 1] == b'\r',
+        };
+        for &byte in &data[first_nul..] {
+            if !(byte == 0 && previous_cr) {
+                out.put_u8(byte);
+            }
+            previous_cr = byte == b'\r';
+        }
+        out.freeze()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records completed by feeding `reads` in turn
+    fn records(config: UnstuffConfig, reads: &[&[u8]]) -> Vec<Vec<u8>> {
+        let mut unstuffer = Unstuffer::new(config, 1024);
+        reads
+            .iter()
+            .flat_map(|read| unstuffer.push(read).0)
+            .map(|record| record.to_vec())
+            .collect()
+    }
+
+    /// Output of feeding `reads` to one [`CrUnstuffer`] in turn
+    fn cr_unstuffed(reads: &[&[u8]]) -> Vec<Vec<u8>> {
+        let mut unstuffer = CrUnstuffer::default();
+        reads
+            .iter()
+            .map(|read| unstuffer.push(Bytes::copy_from_slice(read)).to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn decodes_escaped_framing_bytes_inside_payloads() {
+        let record = [b'a', 0xC0, b'b', 0xDB, b'c'];
+        let framed = UnstuffConfig::SLIP.stuff(&record);
+        assert_eq!(
+            framed,
+            [0xC0, b'a', 0xDB, 0xDC, b'b', 0xDB, 0xDD, b'c', 0xC0]
+        );
+        assert_eq!(records(UnstuffConfig::SLIP, &[&framed]), [record]);
+    }
+
+    #[test]
+    fn carries_records_and_escapes_across_reads() {
+        // Split inside the record and between ESC and ESC_END
+        let reads: [&[u8]; 3] = [&[0xC0, b'a', 0xDB], &[0xDC, b'b'], &[0xC0, b'c', 0xC0]];
+        assert_eq!(
+            records(UnstuffConfig::SLIP, &reads),
+            [vec![b'a', 0xC0, b'b'], vec![b'c']]
+        );
+    }
+
+    #[test]
+    fn skips_empty_records_and_keeps_invalid_escapes() {
+        let reads: [&[u8]; 1] = [&[0xC0, 0xC0, 0xDB, b'x', 0xC0]];
+        assert_eq!(records(UnstuffConfig::SLIP, &reads), [vec![b'x']]);
+    }
+
+    #[test]
+    fn drops_records_over_the_size_limit() {
+        let mut unstuffer = Unstuffer::new(UnstuffConfig::SLIP, 3);
+        let mut data = UnstuffConfig::SLIP.stuff(b"abcd");
+        data.extend(UnstuffConfig::SLIP.stuff(b"abc"));
+        let (records, dropped) = unstuffer.push(&data);
+        assert_eq!(records, [Bytes::from_static(b"abc")]);
+        assert_eq!(dropped, 1);
+    }
+
+    #[test]
+    fn parses_configs() {
+        assert_eq!(
+            "slip".parse::<UnstuffConfig>().unwrap(),
+            UnstuffConfig::SLIP
+        );
+        assert_eq!(
+            "end=0x7E,esc=0x7D,esc_end=0x5E,esc_esc=0x5D"
+                .parse::<UnstuffConfig>()
+                .unwrap(),
+            UnstuffConfig {
+                end: 0x7E,
+                esc: 0x7D,
+                esc_end: 0x5E,
+                esc_esc: 0x5D,
+            }
+        );
+        assert_eq!("end=10".parse::<UnstuffConfig>().unwrap().end, 10);
+        for invalid in ["end", "end=0x1FF", "end=x", "start=1", "end=0xDB"] {
+            assert!(invalid.parse::<UnstuffConfig>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn custom_bytes_round_trip() {
+        let config: UnstuffConfig = "end=0x7E,esc=0x7D,esc_end=0x5E,esc_esc=0x5D"
+            .parse()
+            .unwrap();
+        let record = [0x7E, 0x7D, 0x00, 0x7E];
+        assert_eq!(records(config, &[&config.stuff(&record)]), [record]);
+    }
+
+    #[test]
+    fn cr_nul_becomes_a_bare_cr() {
+        assert_eq!(
+            cr_unstuffed(&[b"50%\r\0done\r\n"]),
+            [b"50%\rdone\r\n".to_vec()]
+        );
+    }
+
+    #[test]
+    fn cr_lf_and_other_nuls_are_kept() {
+        assert_eq!(
+            cr_unstuffed(&[b"a\r\nb\0c\r\r\0"]),
+            [b"a\r\nb\0c\r\r".to_vec()]
+        );
+        assert_eq!(cr_unstuffed(&[b"\0\0"]), [b"\0\0".to_vec()]);
+    }
+
+    #[test]
+    fn cr_nul_split_across_reads() {
+        assert_eq!(
+            cr_unstuffed(&[b"progress\r", b"\0done\r\n"]),
+            [b"progress\r".to_vec(), b"done\r\n".to_vec()]
+        );
+    }
+
+    #[test]
+    fn cr_lf_split_across_reads() {
+        assert_eq!(
+            cr_unstuffed(&[b"line\r", b"\nnext\0"]),
+            [b"line\r".to_vec(), b"\nnext\0".to_vec()]
+        );
+    }
+
+    #[test]
+    fn a_nul_only_follows_the_cr_of_the_previous_read() {
+        assert_eq!(
+            cr_unstuffed(&[b"a\r", b"b", b"\0c"]),
+            [b"a\r".to_vec(), b"b".to_vec(), b"\0c".to_vec()]
+        );
+        assert_eq!(
+            cr_unstuffed(&[b"a\r", b"", b"\0c"]),
+            [b"a\r".to_vec(), b"".to_vec(), b"\0c".to_vec()]
+        );
+    }
+
+    #[test]
+    fn data_without_a_nul_is_not_copied() {
+        let data = Bytes::from_static(b"plain\r\n");
+        let out = CrUnstuffer::default().push(data.clone());
+        assert_eq!(out.as_ptr(), data.as_ptr());
+    }
+}
@@ -0,0 +1,45 @@
+//! Cheap per-read check whether TRACE events would be recorded
+//!
+//! Payload previews, negotiation summaries and hexdumps do string and UTF-8
+//! work for every read, so they are only built when TRACE is enabled. Asking
+//! the subscriber for every read adds up on busy links, so each connection
+//! keeps a [`TraceGate`] that asks at most once per refresh interval; a log
+//! level change takes effect on open connections within that interval.
+
+use std::time::{Duration, Instant};
+
+use tracing::{enabled, Level};
+
+/// How long a TRACE check is reused before the subscriber is asked again
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Cached answer to whether TRACE events are enabled
+#[derive(Debug)]
+pub struct TraceGate {
+    enabled: bool,
+    checked_at: Instant,
+}
+
+impl TraceGate {
+    pub fn new() -> Self {
+        Self {
+            enabled: enabled!(Level::TRACE),
+            checked_at: Instant::now(),
+        }
+    }
+
+    /// Whether TRACE is enabled, as of at most the refresh interval ago
+    pub fn enabled(&mut self) -> bool {
+        if self.checked_at.elapsed() >= REFRESH_INTERVAL {
+            self.enabled = enabled!(Level::TRACE);
+            self.checked_at = Instant::now();
+        }
+        self.enabled
+    }
+}
+
+impl Default for TraceGate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,86 @@
+//! Component reachability watchdog
+//!
+//! Periodically pings the linked component with a `telnet.ping` message. Once
+//! the component has been unreachable for the configured threshold it is
+//! marked down, and with the `disconnect` action the Telnet connection is held
+//! closed until a ping succeeds again.
+
+use std::sync::Arc;
+
+use tokio::sync::watch;
+use tokio::time::{interval, timeout, Instant, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::config::{ComponentDownAction, LinkConfig};
+use crate::context::ConnectionContext;
+use crate::provider::types;
+use crate::stats::ConnectionStats;
+
+/// Subject of the reachability ping sent to components
+const PING_SUBJECT: &str = "telnet.ping";
+
+/// Ping the component until `cancel` is triggered
+///
+/// `hold` is set to `true` while the component is down and the configured
+/// action is to disconnect.
+pub async fn run(
+    context: ConnectionContext,
+    config: Arc<LinkConfig>,
+    stats: Arc<ConnectionStats>,
+    hold: watch::Sender<bool>,
+    cancel: CancellationToken,
+) {
+    let source_id = &context.source_id;
+    let ping_interval = config.component_ping_interval();
+    let mut ticker = interval(ping_interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut last_reachable = Instant::now();
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => return,
+            _ = ticker.tick() => {}
+        }
+
+        let ping = types::BrokerMessage {
+            subject: PING_SUBJECT.to_string(),
+            body: Vec::new().into(),
+            reply_to: None,
+        };
+        let reachable = match timeout(ping_interval, context.client.send(&ping)).await {
+            Ok(result) => result.is_ok(),
+            Err(_) => {
+                // A ping that never returns counts towards client recreation
+                context.client.record_failure().await;
+                false
+            }
+        };
+
+        if reachable {
+            last_reachable = Instant::now();
+            if !stats.is_component_reachable() {
+                info!("Component {} is reachable again", source_id);
+                stats.set_component_reachable(true);
+                hold.send_replace(false);
+            }
+            continue;
+        }
+
+        let down_for = last_reachable.elapsed();
+        debug!(
+            "Component {} did not answer ping (unreachable for {:?})",
+            source_id, down_for
+        );
+        if stats.is_component_reachable() && down_for >= config.component_down_threshold() {
+            warn!(
+                "Component {} unreachable for {:?}, marking down ({:?})",
+                source_id, down_for, config.component_down_action
+            );
+            stats.set_component_reachable(false);
+            if config.component_down_action == ComponentDownAction::Disconnect {
+                hold.send_replace(true);
+            }
+        }
+    }
+}
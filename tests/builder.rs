@@ -0,0 +1,114 @@
+//! A provider built with `TelnetProviderBuilder`, driven without a host
+//!
+//! The provider gets its config, connector and connection limit from the
+//! builder, links through `link_component`, and loses them through the
+//! SDK's `Provider` trait, as under a host.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpListener;
+use wasmcloud_provider_sdk::{LinkDeleteInfo, Provider};
+use wasmcloud_provider_telnet::config::ProviderConfig;
+use wasmcloud_provider_telnet::provider::TelnetProviderBuilder;
+use wasmcloud_provider_telnet::socket::{DefaultConnector, Socket, SocketTuning, TcpConnector};
+
+/// The default connector, counting the connections it opens
+#[derive(Default)]
+struct CountingConnector(AtomicUsize);
+
+impl TcpConnector for CountingConnector {
+    fn connect<'a>(
+        &'a self,
+        addrs: &'a [SocketAddr],
+        tuning: SocketTuning,
+    ) -> Pin<Box<dyn Future<Output = std::io::Result<Socket>> + Send + 'a>> {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        DefaultConnector.connect(addrs, tuning)
+    }
+}
+
+/// Deletion of the link from `0`
+struct Deleted(&'static str);
+
+impl LinkDeleteInfo for Deleted {
+    fn get_source_id(&self) -> &str {
+        self.0
+    }
+
+    fn get_target_id(&self) -> &str {
+        "telnet-provider"
+    }
+
+    fn get_link_name(&self) -> &str {
+        "default"
+    }
+}
+
+/// A Telnet server that never sends anything, holding each connection
+/// until the client closes it
+async fn silent_server() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let _ = stream.read_to_end(&mut Vec::new()).await;
+            });
+        }
+    });
+    port
+}
+
+#[tokio::test]
+async fn a_built_provider_connects_through_its_connector_within_its_limit() {
+    let port = silent_server().await;
+    let connector = Arc::new(CountingConnector::default());
+    let provider = TelnetProviderBuilder::default()
+        .with_config(ProviderConfig::from(&HashMap::from([(
+            "shutdown_timeout_ms".to_string(),
+            "1000".to_string(),
+        )])))
+        .with_connector(connector.clone())
+        .with_max_connections(1)
+        .build();
+    let config = HashMap::from([
+        ("telnet_host".to_string(), "127.0.0.1".to_string()),
+        ("telnet_port".to_string(), port.to_string()),
+        ("reachability_probe".to_string(), "false".to_string()),
+    ]);
+
+    provider
+        .link_component("weather", config.clone(), HashMap::new())
+        .await
+        .unwrap();
+    provider.warm_up(Duration::from_secs(5)).await.unwrap();
+    assert_eq!(connector.0.load(Ordering::SeqCst), 1);
+
+    // The builder's limit applies without a host's config
+    assert!(provider
+        .link_component("alerts", config.clone(), HashMap::new())
+        .await
+        .is_err());
+    provider
+        .delete_link_as_target(Deleted("weather"))
+        .await
+        .unwrap();
+    provider
+        .link_component("alerts", config, HashMap::new())
+        .await
+        .unwrap();
+    provider.warm_up(Duration::from_secs(5)).await.unwrap();
+    assert_eq!(connector.0.load(Ordering::SeqCst), 2);
+
+    provider
+        .delete_link_as_target(Deleted("alerts"))
+        .await
+        .unwrap();
+}
@@ -0,0 +1,37 @@
+//! Short soak run checking for memory growth across reconnect cycles
+//!
+//! Runs the provider binary in `--soak` mode, where its links connect to an
+//! in-process mock server that keeps dropping them. Ignored by default as it
+//! takes over a minute; run it with `cargo test --release -- --ignored`.
+//! `SOAK_REPORT` sets the CSV report path and `SOAK_ARGS` adds soak flags,
+//! which override the defaults below.
+
+use std::path::PathBuf;
+use std::process::Command;
+
+#[test]
+#[ignore = "takes over a minute; run with --ignored"]
+fn memory_stays_flat_across_reconnects() {
+    let report = std::env::var_os("SOAK_REPORT")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| std::env::temp_dir().join("soak_report.csv"));
+    let extra = std::env::var("SOAK_ARGS").unwrap_or_default();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_wasmcloud-provider-telnet"))
+        .arg("--soak")
+        .args(["--links", "10"])
+        .args(["--duration-secs", "60"])
+        .args(["--warmup-secs", "15"])
+        .args(["--sample-secs", "5"])
+        .arg("--output")
+        .arg(&report)
+        .args(extra.split_whitespace())
+        .status()
+        .expect("failed to start the provider binary");
+
+    match std::fs::read_to_string(&report) {
+        Ok(csv) => println!("{}", csv),
+        Err(e) => println!("No soak report at {}: {}", report.display(), e),
+    }
+    assert!(status.success(), "soak run failed: {}", status);
+}
@@ -0,0 +1,162 @@
+//! End-to-end test against a real telnetd running in Docker
+//!
+//! The mock server in tests/telnet_server.py encodes our own assumptions
+//! about the protocol; this test points a link at BusyBox telnetd instead,
+//! which performs real option negotiation before sending its login banner.
+//! A message sink stands in for the component, so no host is needed.
+//!
+//! Ignored by default; run it with `cargo test --test telnetd -- --ignored`.
+//! It passes without checking anything when Docker isn't available. The
+//! container can be changed through `TELNETD_IMAGE`, `TELNETD_USER`,
+//! `TELNETD_PASSWORD` and `TELNETD_BANNER`.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use testcontainers::bollard::Docker;
+use testcontainers::core::{IntoContainerPort, WaitFor};
+use testcontainers::runners::AsyncRunner;
+use testcontainers::{GenericImage, ImageExt};
+use wasmcloud_provider_sdk::{LinkDeleteInfo, Provider};
+use wasmcloud_provider_telnet::config::ProviderConfig;
+use wasmcloud_provider_telnet::provider::{MessageSink, TelnetProviderBuilder};
+
+/// Provisions the container and starts telnetd, printing the line the test
+/// waits for once it's about to listen
+const TELNETD_SCRIPT: &str = r#"
+apk add --no-cache busybox-extras > /dev/null &&
+adduser -D -s /bin/sh "$TELNETD_USER" &&
+echo "$TELNETD_USER:$TELNETD_PASSWORD" | chpasswd &&
+echo "$TELNETD_BANNER" > /etc/issue &&
+echo "telnetd ready" &&
+exec telnetd -F -p 23 -l /bin/login -f /etc/issue
+"#;
+
+/// Messages handed to the component, in order
+#[derive(Default)]
+struct Received(Mutex<Vec<(String, String, Vec<u8>)>>);
+
+impl Received {
+    /// Everything the component got, as one text
+    fn text(&self) -> String {
+        let messages = self.0.lock().unwrap();
+        let body: Vec<u8> = messages
+            .iter()
+            .flat_map(|(_, _, body)| body.clone())
+            .collect();
+        String::from_utf8_lossy(&body).into_owned()
+    }
+}
+
+impl MessageSink for Received {
+    fn handle_message<'a>(
+        &'a self,
+        component_id: &'a str,
+        subject: &'a str,
+        body: &'a [u8],
+        _reply_to: Option<&'a str>,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send + 'a>> {
+        self.0
+            .lock()
+            .unwrap()
+            .push((component_id.to_string(), subject.to_string(), body.to_vec()));
+        Box::pin(std::future::ready(Ok(())))
+    }
+}
+
+/// Deletion of the test component's link
+struct Deleted;
+
+impl LinkDeleteInfo for Deleted {
+    fn get_source_id(&self) -> &str {
+        "test-component"
+    }
+
+    fn get_target_id(&self) -> &str {
+        "telnet-provider"
+    }
+
+    fn get_link_name(&self) -> &str {
+        "default"
+    }
+}
+
+fn env_or(key: &str, default: &str) -> String {
+    std::env::var(key).unwrap_or_else(|_| default.to_string())
+}
+
+#[tokio::test]
+#[ignore = "needs Docker; run with --ignored"]
+async fn negotiation_is_filtered_from_a_real_telnetd() {
+    let docker_up = match Docker::connect_with_defaults() {
+        Ok(docker) => docker.ping().await.is_ok(),
+        Err(_) => false,
+    };
+    if !docker_up {
+        println!("Docker not available, skipping real telnetd test");
+        return;
+    }
+
+    let image = env_or("TELNETD_IMAGE", "alpine:3");
+    let (name, tag) = image.split_once(':').unwrap_or((&image, "latest"));
+    let banner = env_or("TELNETD_BANNER", "wasmCloud telnetd test");
+    let container = GenericImage::new(name, tag)
+        .with_exposed_port(23.tcp())
+        .with_wait_for(WaitFor::message_on_stdout("telnetd ready"))
+        .with_env_var("TELNETD_USER", env_or("TELNETD_USER", "telnet"))
+        .with_env_var("TELNETD_PASSWORD", env_or("TELNETD_PASSWORD", "telnet"))
+        .with_env_var("TELNETD_BANNER", &banner)
+        .with_cmd(["sh", "-c", TELNETD_SCRIPT])
+        .with_startup_timeout(Duration::from_secs(60))
+        .start()
+        .await
+        .expect("failed to start the telnetd container");
+    let host = container.get_host().await.unwrap().to_string();
+    let port = container.get_host_port_ipv4(23.tcp()).await.unwrap();
+
+    let received = Arc::new(Received::default());
+    let provider = TelnetProviderBuilder::default()
+        .with_config(ProviderConfig::from(&HashMap::from([(
+            "shutdown_timeout_ms".to_string(),
+            "1000".to_string(),
+        )])))
+        .with_message_sink(received.clone())
+        .build();
+    let config = HashMap::from([
+        ("telnet_host".to_string(), host),
+        ("telnet_port".to_string(), port.to_string()),
+        ("max_reconnect_attempts".to_string(), "3".to_string()),
+        ("initial_reconnect_delay_ms".to_string(), "1000".to_string()),
+    ]);
+    provider
+        .link_component("test-component", config, HashMap::new())
+        .await
+        .unwrap();
+
+    // telnetd sends the banner and prompt once negotiation is done
+    for _ in 0..30 {
+        let text = received.text();
+        if text.contains(&banner) && text.contains("login:") {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    provider.delete_link_as_target(Deleted).await.unwrap();
+
+    let text = received.text();
+    assert!(text.contains(&banner), "no banner in {:?}", text);
+    assert!(text.contains("login:"), "no login prompt in {:?}", text);
+    let messages = received.0.lock().unwrap();
+    assert!(messages
+        .iter()
+        .all(|(component, _, _)| component == "test-component"));
+    // IAC (0xFF) starts every negotiation command; none may reach the component
+    assert!(
+        messages.iter().all(|(_, _, body)| !body.contains(&0xFF)),
+        "negotiation leaked into {:?}",
+        messages
+    );
+}